@@ -0,0 +1,103 @@
+/*
+ * Runs tests/wat/embedding.wat through the C API:
+ *
+ *     cargo build --lib
+ *     cc examples/c/embed.c -Iinclude -Ltarget/debug -lauto_parallel_wasm \
+ *         -Wl,-rpath,target/debug -o embed
+ *     ./embed embedding.wasm
+ */
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+#include "auto_parallel_wasm.h"
+
+static void log_i32(int32_t value) { printf("log: %d\n", value); }
+
+static double scale(double value) { return value * 2.0; }
+
+static int fail(const char *what) {
+    fprintf(stderr, "%s: %s\n", what, apw_last_error());
+    return 1;
+}
+
+static uint8_t *read_file(const char *path, size_t *len) {
+    FILE *file = fopen(path, "rb");
+    if (!file) return NULL;
+    fseek(file, 0, SEEK_END);
+    *len = (size_t)ftell(file);
+    rewind(file);
+    uint8_t *bytes = malloc(*len);
+    if (bytes && fread(bytes, 1, *len, file) != *len) {
+        free(bytes);
+        bytes = NULL;
+    }
+    fclose(file);
+    return bytes;
+}
+
+int main(int argc, char **argv) {
+    if (argc != 2) {
+        fprintf(stderr, "usage: %s <module.wasm>\n", argv[0]);
+        return 2;
+    }
+    size_t len;
+    uint8_t *bytes = read_file(argv[1], &len);
+    if (!bytes) {
+        fprintf(stderr, "cannot read %s\n", argv[1]);
+        return 2;
+    }
+
+    apw_config_t *config = apw_config_new();
+    if (!apw_config_set_opt_level(config, "2")) return fail("config");
+    apw_engine_t *engine = apw_engine_new(config);
+    apw_config_delete(config);
+    apw_module_t *module = apw_module_new(engine, bytes, len);
+    free(bytes);
+    if (!module) return fail("compile");
+
+    apw_imports_t *imports = apw_imports_new();
+    apw_valkind_t i32 = APW_I32, f64 = APW_F64;
+    if (!apw_imports_add_func(imports, "env", "log", &i32, 1, NULL, 0, (apw_func_t)log_i32) ||
+        !apw_imports_add_func(imports, "env", "scale", &f64, 1, &f64, 1, (apw_func_t)scale))
+        return fail("imports");
+    apw_instance_t *instance = apw_instance_new(module, imports);
+    apw_imports_delete(imports);
+    if (!instance) return fail("instantiate");
+
+    apw_val_t args[2] = {{.kind = APW_I32, .of.i32 = 2}, {.kind = APW_I32, .of.i32 = 3}};
+    apw_val_t result;
+    if (!apw_instance_call(instance, "add", args, 2, &result, 1)) return fail("add");
+    printf("add(2, 3) = %d\n", result.of.i32);
+
+    apw_val_t x = {.kind = APW_F64, .of.f64 = 1.25};
+    if (!apw_instance_call(instance, "scaled", &x, 1, &result, 1)) return fail("scaled");
+    printf("scaled(1.25) = %g\n", result.of.f64);
+
+    if (!apw_instance_call(instance, "bump", NULL, 0, &result, 1)) return fail("bump");
+    printf("bump() = %d\n", result.of.i32);
+
+    apw_val_t one = {.kind = APW_I32, .of.i32 = 1};
+    if (apw_instance_call(instance, "check", &one, 1, &result, 1)) {
+        fprintf(stderr, "check(1) should trap\n");
+        return 1;
+    }
+    printf("check(1) %s: %s\n", apw_last_error_is_trap() ? "trapped" : "failed",
+           apw_last_error());
+
+    uint8_t written[4] = {1, 2, 3, 4}, read[4];
+    uint64_t size;
+    if (!apw_instance_memory_write(instance, "memory", 16, written, 4) ||
+        !apw_instance_memory_read(instance, "memory", 16, read, 4) ||
+        !apw_instance_memory_size(instance, "memory", &size))
+        return fail("memory");
+    printf("memory: %llu bytes, round trip %s\n", (unsigned long long)size,
+           memcmp(written, read, 4) == 0 ? "ok" : "mismatch");
+    if (!apw_instance_memory_read(instance, "memory", size - 2, read, 4))
+        printf("out of bounds read: %s\n", apw_last_error());
+
+    apw_instance_delete(instance);
+    apw_module_delete(module);
+    apw_engine_delete(engine);
+    return 0;
+}
@@ -0,0 +1,117 @@
+/*
+ * C API of auto-parallel-wasm: compile WebAssembly modules to native code,
+ * instantiate them and call their exports.
+ *
+ * Functions that can fail return NULL or false and leave a message that
+ * apw_last_error() returns on the same thread. Handles are owned by the
+ * caller and released with the matching *_delete function; deleting NULL is
+ * a no-op. An instance must not outlive its module, and a module must not
+ * outlive its engine.
+ *
+ * Engines and modules may be shared between threads, for example to
+ * instantiate one module on several threads at once. Other handles are not
+ * thread-safe: use and delete each one only on the thread that created it.
+ *
+ * Generated from src/capi.rs; do not edit.
+ */
+#ifndef AUTO_PARALLEL_WASM_H
+#define AUTO_PARALLEL_WASM_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct apw_config apw_config_t;
+typedef struct apw_engine apw_engine_t;
+typedef struct apw_module apw_module_t;
+typedef struct apw_imports apw_imports_t;
+typedef struct apw_instance apw_instance_t;
+
+typedef uint32_t apw_valkind_t;
+#define APW_I32 ((apw_valkind_t)0)
+#define APW_I64 ((apw_valkind_t)1)
+#define APW_F32 ((apw_valkind_t)2)
+#define APW_F64 ((apw_valkind_t)3)
+
+typedef struct apw_val {
+    apw_valkind_t kind;
+    union {
+        int32_t i32;
+        int64_t i64;
+        float f32;
+        double f64;
+    } of;
+} apw_val_t;
+
+/*
+ * A host function. It takes the import's parameters and returns its result
+ * (if any) as native C values, e.g. `double scale(double x)`. Cast it to
+ * apw_func_t when passing it to apw_imports_add_func.
+ */
+typedef void (*apw_func_t)(void);
+
+/*
+ * The message of the last failed call on this thread, or NULL. The string
+ * stays valid until the next failing call on this thread.
+ */
+const char *apw_last_error(void);
+/* Whether the last failed call on this thread failed because of a trap. */
+bool apw_last_error_is_trap(void);
+
+/* Compiler options, defaulting to those of the command line. */
+apw_config_t *apw_config_new(void);
+void apw_config_delete(apw_config_t *config);
+/* Takes one of "0", "1", "2", "3" or "s". */
+bool apw_config_set_opt_level(apw_config_t *config, const char *level);
+void apw_config_set_debug_info(apw_config_t *config, bool enable);
+/* Threads for compiling large modules; 0 uses all cores. */
+void apw_config_set_jobs(apw_config_t *config, size_t jobs);
+
+/* An engine compiling with a copy of `config`, or the defaults if NULL. */
+apw_engine_t *apw_engine_new(const apw_config_t *config);
+void apw_engine_delete(apw_engine_t *engine);
+
+/* Parses, validates and compiles a binary module. */
+apw_module_t *apw_module_new(const apw_engine_t *engine, const uint8_t *bytes, size_t len);
+void apw_module_delete(apw_module_t *module);
+
+apw_imports_t *apw_imports_new(void);
+void apw_imports_delete(apw_imports_t *imports);
+/* Binds the function import `module`.`name` to `func`. */
+bool apw_imports_add_func(apw_imports_t *imports, const char *module, const char *name,
+                          const apw_valkind_t *params, size_t num_params,
+                          const apw_valkind_t *results, size_t num_results, apw_func_t func);
+
+/*
+ * Instantiates `module`, binding its function imports from `imports` (which
+ * may be NULL if it has none), and runs its start function.
+ */
+apw_instance_t *apw_instance_new(const apw_module_t *module, const apw_imports_t *imports);
+void apw_instance_delete(apw_instance_t *instance);
+
+/*
+ * Calls the function export `name`. `results` must have room for exactly as
+ * many values as the function returns. Fails if the arguments do not match
+ * its parameters or if it traps.
+ */
+bool apw_instance_call(apw_instance_t *instance, const char *name, const apw_val_t *args,
+                       size_t num_args, apw_val_t *results, size_t num_results);
+
+/* The size in bytes of the memory export `memory`. */
+bool apw_instance_memory_size(apw_instance_t *instance, const char *memory, uint64_t *size);
+/* Copies `len` bytes at `offset` of the memory export `memory` to `buffer`. */
+bool apw_instance_memory_read(apw_instance_t *instance, const char *memory, uint64_t offset,
+                              uint8_t *buffer, size_t len);
+/* Copies `len` bytes from `data` to `offset` of the memory export `memory`. */
+bool apw_instance_memory_write(apw_instance_t *instance, const char *memory, uint64_t offset,
+                               const uint8_t *data, size_t len);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* AUTO_PARALLEL_WASM_H */
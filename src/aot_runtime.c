@@ -0,0 +1,122 @@
+/*
+ * Host functions linked into shared libraries and executables produced by
+ * `compile --emit so|exe`. The JIT resolves the same imports to the functions
+ * in compiler.rs and runtime.rs instead.
+ */
+#include <inttypes.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+
+#ifdef __linux__
+#include <errno.h>
+#include <linux/futex.h>
+#include <sys/syscall.h>
+#include <time.h>
+#include <unistd.h>
+#endif
+
+void assert_eq32(int32_t actual, int32_t expected) {
+    if (actual != expected) {
+        fprintf(stderr, "assert_eq32 failed: expected %" PRId32 ", got %" PRId32 "\n",
+                expected, actual);
+        abort();
+    }
+}
+
+void assert_eq64(int64_t actual, int64_t expected) {
+    if (actual != expected) {
+        fprintf(stderr, "assert_eq64 failed: expected %" PRId64 ", got %" PRId64 "\n",
+                expected, actual);
+        abort();
+    }
+}
+
+/* Results of memory.atomic.wait32/wait64. */
+enum { WAIT_OK = 0, WAIT_NOT_EQUAL = 1, WAIT_TIMED_OUT = 2 };
+
+#ifdef __linux__
+static int64_t monotonic_ns(void) {
+    struct timespec now;
+    clock_gettime(CLOCK_MONOTONIC, &now);
+    return (int64_t)now.tv_sec * 1000000000 + now.tv_nsec;
+}
+
+static int32_t futex_wait(const void *word, uint32_t expected, int64_t timeout_ns) {
+    int64_t deadline = timeout_ns >= 0 ? monotonic_ns() + timeout_ns : -1;
+    for (;;) {
+        /* A signal interrupts the wait, so restart it with whatever is left
+         * of the timeout. */
+        struct timespec timeout;
+        struct timespec *timeout_ptr = NULL;
+        if (deadline >= 0) {
+            int64_t remaining = deadline - monotonic_ns();
+            if (remaining < 0) {
+                remaining = 0;
+            }
+            timeout.tv_sec = remaining / 1000000000;
+            timeout.tv_nsec = remaining % 1000000000;
+            timeout_ptr = &timeout;
+        }
+        if (syscall(SYS_futex, word, FUTEX_WAIT | FUTEX_PRIVATE_FLAG, expected, timeout_ptr) == 0) {
+            return WAIT_OK;
+        }
+        switch (errno) {
+        case EINTR:
+            continue;
+        case EAGAIN:
+            return WAIT_NOT_EQUAL;
+        case ETIMEDOUT:
+            return WAIT_TIMED_OUT;
+        default:
+            return WAIT_OK;
+        }
+    }
+}
+
+static uint32_t futex_wake(const void *word, uint32_t count) {
+    if (count > INT32_MAX) {
+        count = INT32_MAX;
+    }
+    long woken = syscall(SYS_futex, word, FUTEX_WAKE | FUTEX_PRIVATE_FLAG, count);
+    return woken < 0 ? 0 : (uint32_t)woken;
+}
+#else
+static int32_t futex_wait(const void *word, uint32_t expected, int64_t timeout_ns) {
+    (void)word;
+    (void)expected;
+    (void)timeout_ns;
+    fprintf(stderr, "memory.atomic.wait is only supported on Linux\n");
+    abort();
+}
+
+static uint32_t futex_wake(const void *word, uint32_t count) {
+    (void)word;
+    (void)count;
+    return 0;
+}
+#endif
+
+int32_t wasm_memory_atomic_wait32(const int32_t *addr, int32_t expected, int64_t timeout_ns) {
+    if (__atomic_load_n(addr, __ATOMIC_SEQ_CST) != expected) {
+        return WAIT_NOT_EQUAL;
+    }
+    return futex_wait(addr, (uint32_t)expected, timeout_ns);
+}
+
+/* Futexes compare 32 bits, so the kernel re-checks only the first word. */
+int32_t wasm_memory_atomic_wait64(const int64_t *addr, int64_t expected, int64_t timeout_ns) {
+    if (__atomic_load_n(addr, __ATOMIC_SEQ_CST) != expected) {
+        return WAIT_NOT_EQUAL;
+    }
+#if __BYTE_ORDER__ == __ORDER_LITTLE_ENDIAN__
+    uint32_t first_word = (uint32_t)expected;
+#else
+    uint32_t first_word = (uint32_t)((uint64_t)expected >> 32);
+#endif
+    return futex_wait(addr, first_word, timeout_ns);
+}
+
+uint32_t wasm_memory_atomic_notify(const int32_t *addr, uint32_t count) {
+    return futex_wake(addr, count);
+}
@@ -0,0 +1,347 @@
+//! On-disk cache of compiled modules for `exec`.
+//!
+//! Entries hold the optimized bitcode of a module, keyed by a hash of the
+//! module bytes, the compiler options, the compiler build and the host, so a
+//! cached artifact is only reused by a compiler that would have produced the
+//! same one.
+
+use std::env;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::SystemTime;
+
+use anyhow::{Result, anyhow};
+use inkwell::targets::TargetMachine;
+
+use crate::options::CompilerOptions;
+
+/// Overrides the cache directory.
+pub const CACHE_DIR_VAR: &str = "AUTO_PARALLEL_WASM_CACHE_DIR";
+
+const ENTRY_EXTENSION: &str = "bc";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CacheKey(u128);
+
+impl CacheKey {
+    pub fn new(wasm_bytes: &[u8], options: &CompilerOptions) -> Self {
+        let mut hasher = Fnv128::new();
+        hasher.write_field(env!("CARGO_PKG_VERSION").as_bytes());
+        hasher.write_field(build_id().as_bytes());
+        hasher.write_field(TargetMachine::get_default_triple().as_str().to_bytes());
+        hasher.write_field(TargetMachine::get_host_cpu_name().to_bytes());
+        // The thread count does not change the output.
+        let options = CompilerOptions {
+            jobs: None,
+            ..options.clone()
+        };
+        hasher.write_field(format!("{options:?}").as_bytes());
+        hasher.write_field(wasm_bytes);
+        Self(hasher.finish())
+    }
+}
+
+impl fmt::Display for CacheKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:032x}", self.0)
+    }
+}
+
+/// Identifies the running compiler binary by its path, size and modification
+/// time, so that rebuilding the compiler without bumping the version does not
+/// pick up artifacts of the previous build.
+fn build_id() -> String {
+    let Ok(exe) = env::current_exe() else {
+        return String::new();
+    };
+    let Ok(metadata) = fs::metadata(&exe) else {
+        return String::new();
+    };
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(SystemTime::UNIX_EPOCH).ok())
+        .unwrap_or_default();
+    format!(
+        "{}:{}:{}",
+        exe.display(),
+        metadata.len(),
+        modified.as_nanos()
+    )
+}
+
+/// 128-bit FNV-1a. Keys only need to be stable across runs of the same
+/// build, and 128 bits make accidental collisions between modules moot.
+struct Fnv128(u128);
+
+impl Fnv128 {
+    const OFFSET_BASIS: u128 = 0x6c62272e07bb014262b821756295c58d;
+    const PRIME: u128 = 0x0000000001000000000000000000013b;
+
+    fn new() -> Self {
+        Self(Self::OFFSET_BASIS)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 ^= byte as u128;
+            self.0 = self.0.wrapping_mul(Self::PRIME);
+        }
+    }
+
+    /// Hashes `bytes` prefixed with their length, so that adjacent fields
+    /// cannot trade bytes.
+    fn write_field(&mut self, bytes: &[u8]) {
+        self.write(&(bytes.len() as u64).to_le_bytes());
+        self.write(bytes);
+    }
+
+    fn finish(&self) -> u128 {
+        self.0
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CacheEntry {
+    pub key: String,
+    pub size: u64,
+    pub last_used: SystemTime,
+}
+
+pub struct Cache {
+    dir: PathBuf,
+}
+
+impl Cache {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// The cache in `$AUTO_PARALLEL_WASM_CACHE_DIR`, or in the user's cache
+    /// directory per the XDG base directory spec.
+    pub fn open_default() -> Result<Self> {
+        if let Some(dir) = env::var_os(CACHE_DIR_VAR) {
+            return Ok(Self::new(dir));
+        }
+        let base = match env::var_os("XDG_CACHE_HOME") {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => env::var_os("HOME")
+                .map(|home| Path::new(&home).join(".cache"))
+                .ok_or(anyhow!(
+                    "Cannot locate a cache directory; set {}",
+                    CACHE_DIR_VAR
+                ))?,
+        };
+        Ok(Self::new(base.join(env!("CARGO_PKG_NAME"))))
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    fn entry_path(&self, key: CacheKey) -> PathBuf {
+        self.dir.join(format!("{key}.{ENTRY_EXTENSION}"))
+    }
+
+    /// The artifact stored under `key`, marking it as recently used.
+    pub fn get(&self, key: CacheKey) -> Option<Vec<u8>> {
+        let path = self.entry_path(key);
+        let bytes = fs::read(&path).ok()?;
+        if let Ok(file) = File::options().append(true).open(&path) {
+            file.set_modified(SystemTime::now()).ok();
+        }
+        Some(bytes)
+    }
+
+    /// Stores `bytes` under `key`. The entry is written to a temporary file
+    /// and renamed into place, so concurrent readers never see a partial
+    /// artifact.
+    pub fn put(&self, key: CacheKey, bytes: &[u8]) -> Result<()> {
+        fs::create_dir_all(&self.dir).map_err(|e| {
+            anyhow!(
+                "Failed to create cache directory {}: {}",
+                self.dir.display(),
+                e
+            )
+        })?;
+        let path = self.entry_path(key);
+        let temp = self.dir.join(format!("{key}.{}.tmp", process::id()));
+        fs::write(&temp, bytes)
+            .and_then(|()| fs::rename(&temp, &path))
+            .map_err(|e| {
+                fs::remove_file(&temp).ok();
+                anyhow!("Failed to write cache entry {}: {}", path.display(), e)
+            })
+    }
+
+    /// All entries, least recently used first.
+    pub fn entries(&self) -> Result<Vec<CacheEntry>> {
+        let read_dir = match fs::read_dir(&self.dir) {
+            Ok(read_dir) => read_dir,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => {
+                return Err(anyhow!(
+                    "Failed to read cache directory {}: {}",
+                    self.dir.display(),
+                    e
+                ));
+            }
+        };
+
+        let mut entries = Vec::new();
+        for dir_entry in read_dir {
+            let path = dir_entry?.path();
+            if path.extension().is_none_or(|ext| ext != ENTRY_EXTENSION) {
+                continue;
+            }
+            let Some(key) = path.file_stem().and_then(|stem| stem.to_str()) else {
+                continue;
+            };
+            let metadata = fs::metadata(&path)?;
+            entries.push(CacheEntry {
+                key: key.to_string(),
+                size: metadata.len(),
+                last_used: metadata.modified()?,
+            });
+        }
+        entries.sort_by(|a, b| a.last_used.cmp(&b.last_used).then(a.key.cmp(&b.key)));
+        Ok(entries)
+    }
+
+    fn remove(&self, entry: &CacheEntry) -> Result<()> {
+        let path = self.dir.join(format!("{}.{ENTRY_EXTENSION}", entry.key));
+        match fs::remove_file(&path) {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(anyhow!("Failed to remove {}: {}", path.display(), e)),
+        }
+    }
+
+    /// Removes every entry and returns how many there were.
+    pub fn clear(&self) -> Result<usize> {
+        let entries = self.entries()?;
+        for entry in &entries {
+            self.remove(entry)?;
+        }
+        Ok(entries.len())
+    }
+
+    /// Removes least recently used entries until the cache holds at most
+    /// `max_size` bytes, and returns the removed entries.
+    pub fn prune(&self, max_size: u64) -> Result<Vec<CacheEntry>> {
+        let entries = self.entries()?;
+        let mut total: u64 = entries.iter().map(|entry| entry.size).sum();
+        let mut removed = Vec::new();
+        for entry in entries {
+            if total <= max_size {
+                break;
+            }
+            self.remove(&entry)?;
+            total -= entry.size;
+            removed.push(entry);
+        }
+        Ok(removed)
+    }
+}
+
+/// Parses a byte count with an optional `K`, `M` or `G` suffix (powers of
+/// 1024), as taken by `cache prune`.
+pub fn parse_size(s: &str) -> Result<u64> {
+    let (digits, multiplier) = match s.char_indices().last() {
+        Some((i, 'K' | 'k')) => (&s[..i], 1 << 10),
+        Some((i, 'M' | 'm')) => (&s[..i], 1 << 20),
+        Some((i, 'G' | 'g')) => (&s[..i], 1 << 30),
+        _ => (s, 1),
+    };
+    digits
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(multiplier))
+        .ok_or(anyhow!("Invalid size: {}", s))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options::OptLevel;
+    use std::time::Duration;
+
+    fn temp_cache(name: &str) -> Cache {
+        let dir =
+            env::temp_dir().join(format!("auto-parallel-wasm-cache-{name}-{}", process::id()));
+        fs::remove_dir_all(&dir).ok();
+        Cache::new(dir)
+    }
+
+    #[test]
+    fn test_key_covers_module_and_options() {
+        let options = CompilerOptions::default();
+        let key = CacheKey::new(b"\0asm", &options);
+
+        assert_eq!(key, CacheKey::new(b"\0asm", &options));
+        assert_ne!(key, CacheKey::new(b"\0asn", &options));
+        let optimized = CompilerOptions {
+            opt_level: OptLevel::O2,
+            ..CompilerOptions::default()
+        };
+        assert_ne!(key, CacheKey::new(b"\0asm", &optimized));
+        assert_eq!(key.to_string().len(), 32);
+    }
+
+    #[test]
+    fn test_put_get_and_clear() {
+        let cache = temp_cache("put-get");
+        let key = CacheKey::new(b"module", &CompilerOptions::default());
+
+        assert!(cache.get(key).is_none());
+        assert!(cache.entries().unwrap().is_empty());
+        cache.put(key, b"bitcode").unwrap();
+        assert_eq!(cache.get(key).unwrap(), b"bitcode");
+
+        let entries = cache.entries().unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].key, key.to_string());
+        assert_eq!(entries[0].size, 7);
+
+        assert_eq!(cache.clear().unwrap(), 1);
+        assert!(cache.get(key).is_none());
+        fs::remove_dir_all(cache.dir()).ok();
+    }
+
+    #[test]
+    fn test_prune_removes_least_recently_used() {
+        let cache = temp_cache("prune");
+        let options = CompilerOptions::default();
+        let old = CacheKey::new(b"old", &options);
+        let new = CacheKey::new(b"new", &options);
+        cache.put(old, &[0; 100]).unwrap();
+        cache.put(new, &[0; 100]).unwrap();
+        let an_hour_ago = SystemTime::now() - Duration::from_secs(3600);
+        File::options()
+            .append(true)
+            .open(cache.entry_path(old))
+            .unwrap()
+            .set_modified(an_hour_ago)
+            .unwrap();
+
+        let removed = cache.prune(150).unwrap();
+        assert_eq!(removed.len(), 1);
+        assert_eq!(removed[0].key, old.to_string());
+        assert!(cache.get(new).is_some());
+        assert!(cache.prune(150).unwrap().is_empty());
+        fs::remove_dir_all(cache.dir()).ok();
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("512").unwrap(), 512);
+        assert_eq!(parse_size("4K").unwrap(), 4096);
+        assert_eq!(parse_size("100M").unwrap(), 100 << 20);
+        assert_eq!(parse_size("2g").unwrap(), 2 << 30);
+        assert!(parse_size("M").is_err());
+        assert!(parse_size("-1").is_err());
+    }
+}
@@ -0,0 +1,871 @@
+//! C API over `crate::embed`, declared in `include/auto_parallel_wasm.h`,
+//! which `c_header` generates.
+//!
+//! Handles are boxed Rust values. Functions that can fail return NULL or
+//! false and leave a message for `apw_last_error` on the calling thread.
+
+// The pointer contracts are documented once, in the C header.
+#![allow(clippy::missing_safety_doc)]
+
+use std::cell::RefCell;
+use std::ffi::{CStr, CString, c_char};
+use std::ptr;
+use std::slice;
+
+use anyhow::{Result, anyhow};
+use wasmparser::{FuncType, ValType};
+
+use crate::embed::{Engine, Imports, Instance, Module, Val};
+use crate::options::CompilerOptions;
+use crate::trap::Trap;
+
+const APW_I32: u32 = 0;
+const APW_I64: u32 = 1;
+const APW_F32: u32 = 2;
+const APW_F64: u32 = 3;
+
+/// `apw_val_t`.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct RawVal {
+    kind: u32,
+    of: RawValue,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+union RawValue {
+    i32: i32,
+    i64: i64,
+    f32: f32,
+    f64: f64,
+}
+
+impl RawVal {
+    fn to_val(self) -> Result<Val> {
+        unsafe {
+            match self.kind {
+                APW_I32 => Ok(Val::I32(self.of.i32)),
+                APW_I64 => Ok(Val::I64(self.of.i64)),
+                APW_F32 => Ok(Val::F32(self.of.f32)),
+                APW_F64 => Ok(Val::F64(self.of.f64)),
+                kind => Err(anyhow!("Invalid value kind: {}", kind)),
+            }
+        }
+    }
+}
+
+impl From<Val> for RawVal {
+    fn from(val: Val) -> Self {
+        match val {
+            Val::I32(i32) => RawVal {
+                kind: APW_I32,
+                of: RawValue { i32 },
+            },
+            Val::I64(i64) => RawVal {
+                kind: APW_I64,
+                of: RawValue { i64 },
+            },
+            Val::F32(f32) => RawVal {
+                kind: APW_F32,
+                of: RawValue { f32 },
+            },
+            Val::F64(f64) => RawVal {
+                kind: APW_F64,
+                of: RawValue { f64 },
+            },
+        }
+    }
+}
+
+fn val_type(kind: u32) -> Result<ValType> {
+    match kind {
+        APW_I32 => Ok(ValType::I32),
+        APW_I64 => Ok(ValType::I64),
+        APW_F32 => Ok(ValType::F32),
+        APW_F64 => Ok(ValType::F64),
+        kind => Err(anyhow!("Invalid value kind: {}", kind)),
+    }
+}
+
+thread_local! {
+    /// Message of the last error on this thread, and whether it was a trap.
+    static LAST_ERROR: RefCell<Option<(CString, bool)>> = const { RefCell::new(None) };
+}
+
+/// Runs `f`, recording its error for `apw_last_error`.
+fn catch<T>(f: impl FnOnce() -> Result<T>) -> Option<T> {
+    match f() {
+        Ok(value) => Some(value),
+        Err(error) => {
+            let is_trap = error.downcast_ref::<Trap>().is_some();
+            let message = CString::new(format!("{error:#}").replace('\0', " ")).unwrap();
+            LAST_ERROR.set(Some((message, is_trap)));
+            None
+        }
+    }
+}
+
+unsafe fn c_str<'a>(ptr: *const c_char, what: &str) -> Result<&'a str> {
+    if ptr.is_null() {
+        return Err(anyhow!("{} is NULL", what));
+    }
+    unsafe { CStr::from_ptr(ptr) }
+        .to_str()
+        .map_err(|_| anyhow!("{} is not valid UTF-8", what))
+}
+
+unsafe fn c_slice<'a, T>(ptr: *const T, len: usize) -> Result<&'a [T]> {
+    match len {
+        0 => Ok(&[]),
+        _ if ptr.is_null() => Err(anyhow!("Array of {} elements is NULL", len)),
+        _ => Ok(unsafe { slice::from_raw_parts(ptr, len) }),
+    }
+}
+
+unsafe fn handle<'a, T>(ptr: *const T, what: &str) -> Result<&'a T> {
+    unsafe { ptr.as_ref() }.ok_or(anyhow!("{} is NULL", what))
+}
+
+fn into_handle<T>(value: Option<T>) -> *mut T {
+    value.map_or(ptr::null_mut(), |value| Box::into_raw(Box::new(value)))
+}
+
+unsafe fn delete<T>(ptr: *mut T) {
+    if !ptr.is_null() {
+        drop(unsafe { Box::from_raw(ptr) });
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn apw_last_error() -> *const c_char {
+    LAST_ERROR.with_borrow(|error| {
+        error
+            .as_ref()
+            .map_or(ptr::null(), |(message, _)| message.as_ptr())
+    })
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn apw_last_error_is_trap() -> bool {
+    LAST_ERROR.with_borrow(|error| error.as_ref().is_some_and(|(_, is_trap)| *is_trap))
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn apw_config_new() -> *mut CompilerOptions {
+    into_handle(Some(CompilerOptions::default()))
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn apw_config_delete(config: *mut CompilerOptions) {
+    unsafe { delete(config) }
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn apw_config_set_opt_level(
+    config: *mut CompilerOptions,
+    level: *const c_char,
+) -> bool {
+    catch(|| {
+        let config = unsafe { config.as_mut() }.ok_or(anyhow!("config is NULL"))?;
+        config.opt_level = unsafe { c_str(level, "level") }?.parse()?;
+        Ok(())
+    })
+    .is_some()
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn apw_config_set_debug_info(config: *mut CompilerOptions, enable: bool) {
+    if let Some(config) = unsafe { config.as_mut() } {
+        config.debug_info = enable;
+    }
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn apw_config_set_jobs(config: *mut CompilerOptions, jobs: usize) {
+    if let Some(config) = unsafe { config.as_mut() } {
+        config.jobs = (jobs > 0).then_some(jobs);
+    }
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn apw_engine_new(config: *const CompilerOptions) -> *mut Engine {
+    let config = unsafe { config.as_ref() }.cloned().unwrap_or_default();
+    into_handle(Some(Engine::new(config)))
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn apw_engine_delete(engine: *mut Engine) {
+    unsafe { delete(engine) }
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn apw_module_new(
+    engine: *const Engine,
+    bytes: *const u8,
+    len: usize,
+) -> *mut Module {
+    into_handle(catch(|| {
+        let engine = unsafe { handle(engine, "engine") }?;
+        Module::from_bytes(engine, unsafe { c_slice(bytes, len) }?)
+    }))
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn apw_module_delete(module: *mut Module) {
+    unsafe { delete(module) }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn apw_imports_new() -> *mut Imports {
+    into_handle(Some(Imports::new()))
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn apw_imports_delete(imports: *mut Imports) {
+    unsafe { delete(imports) }
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn apw_imports_add_func(
+    imports: *mut Imports,
+    module: *const c_char,
+    name: *const c_char,
+    params: *const u32,
+    num_params: usize,
+    results: *const u32,
+    num_results: usize,
+    func: Option<unsafe extern "C" fn()>,
+) -> bool {
+    catch(|| {
+        let imports = unsafe { imports.as_mut() }.ok_or(anyhow!("imports is NULL"))?;
+        let module = unsafe { c_str(module, "module") }?;
+        let name = unsafe { c_str(name, "name") }?;
+        let kinds = |ptr, len| -> Result<Vec<ValType>> {
+            unsafe { c_slice(ptr, len) }?
+                .iter()
+                .map(|kind| val_type(*kind))
+                .collect()
+        };
+        let func_type = FuncType::new(kinds(params, num_params)?, kinds(results, num_results)?);
+        let func = func.ok_or(anyhow!("Host function for {}::{} is NULL", module, name))?;
+        imports.func_raw(module, name, func_type, func as usize);
+        Ok(())
+    })
+    .is_some()
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn apw_instance_new(
+    module: *const Module,
+    imports: *const Imports,
+) -> *mut Instance {
+    into_handle(catch(|| {
+        let module = unsafe { handle(module, "module") }?;
+        match unsafe { imports.as_ref() } {
+            Some(imports) => Instance::new(module, imports),
+            None => Instance::new(module, &Imports::new()),
+        }
+    }))
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn apw_instance_delete(instance: *mut Instance) {
+    unsafe { delete(instance) }
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn apw_instance_call(
+    instance: *mut Instance,
+    name: *const c_char,
+    args: *const RawVal,
+    num_args: usize,
+    results: *mut RawVal,
+    num_results: usize,
+) -> bool {
+    catch(|| {
+        let instance = unsafe { handle(instance, "instance") }?;
+        let name = unsafe { c_str(name, "name") }?;
+        let func = instance
+            .get_func(name)
+            .ok_or(anyhow!("Unknown function export: {}", name))?;
+        if num_results != func.ty().results().len() {
+            return Err(anyhow!(
+                "{} returns {} values, got room for {}",
+                name,
+                func.ty().results().len(),
+                num_results
+            ));
+        }
+        if num_results > 0 && results.is_null() {
+            return Err(anyhow!("results is NULL"));
+        }
+        let args = unsafe { c_slice(args, num_args) }?
+            .iter()
+            .map(|arg| arg.to_val())
+            .collect::<Result<Vec<_>>>()?;
+        if let Some(result) = func.call(&args)? {
+            unsafe { results.write(result.into()) };
+        }
+        Ok(())
+    })
+    .is_some()
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn apw_instance_memory_size(
+    instance: *mut Instance,
+    memory: *const c_char,
+    size: *mut u64,
+) -> bool {
+    catch(|| {
+        let instance = unsafe { handle(instance, "instance") }?;
+        let memory = unsafe { c_str(memory, "memory") }?;
+        let memory = instance
+            .get_memory(memory)
+            .ok_or(anyhow!("Unknown memory export: {}", memory))?;
+        unsafe { size.write(memory.data_size() as u64) };
+        Ok(())
+    })
+    .is_some()
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn apw_instance_memory_read(
+    instance: *mut Instance,
+    memory: *const c_char,
+    offset: u64,
+    buffer: *mut u8,
+    len: usize,
+) -> bool {
+    catch(|| {
+        let instance = unsafe { handle(instance, "instance") }?;
+        let memory = unsafe { c_str(memory, "memory") }?;
+        let memory = instance
+            .get_memory(memory)
+            .ok_or(anyhow!("Unknown memory export: {}", memory))?;
+        let buffer = match len {
+            0 => &mut [],
+            _ if buffer.is_null() => return Err(anyhow!("buffer is NULL")),
+            _ => unsafe { slice::from_raw_parts_mut(buffer, len) },
+        };
+        memory.read(usize::try_from(offset)?, buffer)
+    })
+    .is_some()
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn apw_instance_memory_write(
+    instance: *mut Instance,
+    memory: *const c_char,
+    offset: u64,
+    data: *const u8,
+    len: usize,
+) -> bool {
+    catch(|| {
+        let instance = unsafe { handle(instance, "instance") }?;
+        let memory = unsafe { c_str(memory, "memory") }?;
+        let memory = instance
+            .get_memory(memory)
+            .ok_or(anyhow!("Unknown memory export: {}", memory))?;
+        memory.write(usize::try_from(offset)?, unsafe { c_slice(data, len) }?)
+    })
+    .is_some()
+}
+
+/// Generates `include/auto_parallel_wasm.h`. Every function is declared in
+/// C next to the Rust function it declares, whose parameter and result types
+/// are checked against the C spelling; `test_header_is_generated` keeps the
+/// checked-in header in sync.
+#[cfg(test)]
+mod c_header {
+    use std::any::TypeId;
+
+    use super::*;
+
+    const COLUMNS: usize = 100;
+
+    const PROLOGUE: &str = "\
+/*
+ * C API of auto-parallel-wasm: compile WebAssembly modules to native code,
+ * instantiate them and call their exports.
+ *
+ * Functions that can fail return NULL or false and leave a message that
+ * apw_last_error() returns on the same thread. Handles are owned by the
+ * caller and released with the matching *_delete function; deleting NULL is
+ * a no-op. An instance must not outlive its module, and a module must not
+ * outlive its engine.
+ *
+ * Engines and modules may be shared between threads, for example to
+ * instantiate one module on several threads at once. Other handles are not
+ * thread-safe: use and delete each one only on the thread that created it.
+ *
+ * Generated from src/capi.rs; do not edit.
+ */
+#ifndef AUTO_PARALLEL_WASM_H
+#define AUTO_PARALLEL_WASM_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern \"C\" {
+#endif
+
+typedef struct apw_config apw_config_t;
+typedef struct apw_engine apw_engine_t;
+typedef struct apw_module apw_module_t;
+typedef struct apw_imports apw_imports_t;
+typedef struct apw_instance apw_instance_t;
+
+typedef uint32_t apw_valkind_t;
+";
+
+    const TYPES: &str = "
+typedef struct apw_val {
+    apw_valkind_t kind;
+    union {
+        int32_t i32;
+        int64_t i64;
+        float f32;
+        double f64;
+    } of;
+} apw_val_t;
+
+/*
+ * A host function. It takes the import's parameters and returns its result
+ * (if any) as native C values, e.g. `double scale(double x)`. Cast it to
+ * apw_func_t when passing it to apw_imports_add_func.
+ */
+typedef void (*apw_func_t)(void);
+
+";
+
+    const EPILOGUE: &str = "\
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* AUTO_PARALLEL_WASM_H */
+";
+
+    /// A Rust type of the C API, spelled in C.
+    trait CType: 'static {
+        fn is_spelled(c: &str) -> bool;
+    }
+
+    macro_rules! c_types {
+        ($($ty:ty => $c:literal,)*) => {$(
+            impl CType for $ty {
+                fn is_spelled(c: &str) -> bool {
+                    c == $c
+                }
+            }
+        )*};
+    }
+
+    c_types! {
+        () => "void",
+        bool => "bool",
+        u32 => "apw_valkind_t",
+        u64 => "uint64_t",
+        usize => "size_t",
+        RawVal => "apw_val_t",
+        CompilerOptions => "apw_config_t",
+        Engine => "apw_engine_t",
+        Module => "apw_module_t",
+        Imports => "apw_imports_t",
+        Instance => "apw_instance_t",
+        Option<unsafe extern "C" fn()> => "apw_func_t",
+    }
+
+    // `c_char` is `i8` or `u8` depending on the platform.
+    impl CType for i8 {
+        fn is_spelled(c: &str) -> bool {
+            c == "char" && TypeId::of::<c_char>() == TypeId::of::<i8>()
+        }
+    }
+
+    impl CType for u8 {
+        fn is_spelled(c: &str) -> bool {
+            c == "uint8_t" || c == "char" && TypeId::of::<c_char>() == TypeId::of::<u8>()
+        }
+    }
+
+    impl<T: CType> CType for *const T {
+        fn is_spelled(c: &str) -> bool {
+            c.strip_prefix("const ")
+                .and_then(|c| c.strip_suffix(" *"))
+                .is_some_and(T::is_spelled)
+        }
+    }
+
+    impl<T: CType> CType for *mut T {
+        fn is_spelled(c: &str) -> bool {
+            c.strip_suffix(" *")
+                .filter(|c| !c.starts_with("const "))
+                .is_some_and(T::is_spelled)
+        }
+    }
+
+    /// Checks of the parameter and result types of a function pointer type
+    /// against C spellings.
+    pub(super) struct Signature {
+        params: Vec<fn(&str) -> bool>,
+        result: fn(&str) -> bool,
+    }
+
+    trait CSignature {
+        fn signature() -> Signature;
+    }
+
+    macro_rules! c_signatures {
+        ($(($($param:ident),*))*) => {$(
+            impl<$($param: CType,)* R: CType> CSignature for unsafe extern "C" fn($($param),*) -> R {
+                fn signature() -> Signature {
+                    Signature {
+                        params: vec![$($param::is_spelled),*],
+                        result: R::is_spelled,
+                    }
+                }
+            }
+        )*};
+    }
+
+    c_signatures! {
+        ()
+        (A)
+        (A, B)
+        (A, B, C)
+        (A, B, C, D)
+        (A, B, C, D, E)
+        (A, B, C, D, E, F)
+        (A, B, C, D, E, F, G)
+        (A, B, C, D, E, F, G, H)
+    }
+
+    fn signature_of<F: CSignature>(_: F) -> Signature {
+        F::signature()
+    }
+
+    pub(super) struct Declaration {
+        doc: Vec<&'static str>,
+        result: &'static str,
+        pub(super) name: &'static str,
+        params: Vec<(&'static str, &'static str)>,
+        signature: Signature,
+    }
+
+    impl Declaration {
+        /// Panics unless the Rust function has the declared signature.
+        pub(super) fn check_signature(&self) {
+            assert!(
+                (self.signature.result)(self.result),
+                "{} does not return {}",
+                self.name,
+                self.result
+            );
+            assert_eq!(
+                self.params.len(),
+                self.signature.params.len(),
+                "{} takes {} parameters",
+                self.name,
+                self.signature.params.len()
+            );
+            for ((ty, name), is_spelled) in self.params.iter().zip(&self.signature.params) {
+                assert!(is_spelled(ty), "{}({name}) is not a {ty}", self.name);
+            }
+        }
+
+        fn write(&self, header: &mut String) {
+            match self.doc.as_slice() {
+                [] => {}
+                [line] => header.push_str(&format!("/* {line} */\n")),
+                lines => {
+                    header.push_str("/*\n");
+                    for line in lines {
+                        header.push_str(&format!(" * {line}\n"));
+                    }
+                    header.push_str(" */\n");
+                }
+            }
+
+            // Wrap parameters the way clang-format does, aligned after the
+            // opening parenthesis.
+            let mut line = format!("{}(", spelled(self.result, self.name));
+            let indent = line.len();
+            let params: Vec<String> = match self.params.as_slice() {
+                [] => vec!["void".to_string()],
+                params => params.iter().map(|(ty, name)| spelled(ty, name)).collect(),
+            };
+            for (i, param) in params.iter().enumerate() {
+                let end = if i + 1 == params.len() { ");" } else { "," };
+                let first = line.len() == indent;
+                if !first && line.len() + 1 + param.len() + end.len() > COLUMNS {
+                    header.push_str(&line);
+                    header.push('\n');
+                    line = " ".repeat(indent);
+                } else if !first {
+                    line.push(' ');
+                }
+                line.push_str(param);
+                line.push_str(end);
+            }
+            header.push_str(&line);
+            header.push('\n');
+        }
+    }
+
+    /// `name` declared with type `ty`, such as `const char *name`.
+    fn spelled(ty: &str, name: &str) -> String {
+        if ty.ends_with('*') {
+            format!("{ty}{name}")
+        } else {
+            format!("{ty} {name}")
+        }
+    }
+
+    /// Declares Rust function `$name` in C, with `$doc` as its comment.
+    macro_rules! c_fn {
+        ($(#[doc = $doc:literal])* $result:literal $name:ident($($param:ident: $ty:literal),*)) => {
+            Declaration {
+                doc: vec![$($doc.trim()),*],
+                result: $result,
+                name: stringify!($name),
+                params: vec![$(($ty, stringify!($param))),*],
+                signature: signature_of(
+                    $name as unsafe extern "C" fn($(c_fn!(@infer $param)),*) -> _,
+                ),
+            }
+        };
+        (@infer $param:ident) => { _ };
+    }
+
+    /// The functions of the C API, in groups separated by blank lines.
+    pub(super) fn declarations() -> Vec<Vec<Declaration>> {
+        vec![
+            vec![
+                c_fn! {
+                    /// The message of the last failed call on this thread, or NULL. The string
+                    /// stays valid until the next failing call on this thread.
+                    "const char *" apw_last_error()
+                },
+                c_fn! {
+                    /// Whether the last failed call on this thread failed because of a trap.
+                    "bool" apw_last_error_is_trap()
+                },
+            ],
+            vec![
+                c_fn! {
+                    /// Compiler options, defaulting to those of the command line.
+                    "apw_config_t *" apw_config_new()
+                },
+                c_fn! {
+                    "void" apw_config_delete(config: "apw_config_t *")
+                },
+                c_fn! {
+                    /// Takes one of "0", "1", "2", "3" or "s".
+                    "bool" apw_config_set_opt_level(config: "apw_config_t *", level: "const char *")
+                },
+                c_fn! {
+                    "void" apw_config_set_debug_info(config: "apw_config_t *", enable: "bool")
+                },
+                c_fn! {
+                    /// Threads for compiling large modules; 0 uses all cores.
+                    "void" apw_config_set_jobs(config: "apw_config_t *", jobs: "size_t")
+                },
+            ],
+            vec![
+                c_fn! {
+                    /// An engine compiling with a copy of `config`, or the defaults if NULL.
+                    "apw_engine_t *" apw_engine_new(config: "const apw_config_t *")
+                },
+                c_fn! {
+                    "void" apw_engine_delete(engine: "apw_engine_t *")
+                },
+            ],
+            vec![
+                c_fn! {
+                    /// Parses, validates and compiles a binary module.
+                    "apw_module_t *" apw_module_new(
+                        engine: "const apw_engine_t *",
+                        bytes: "const uint8_t *",
+                        len: "size_t"
+                    )
+                },
+                c_fn! {
+                    "void" apw_module_delete(module: "apw_module_t *")
+                },
+            ],
+            vec![
+                c_fn! {
+                    "apw_imports_t *" apw_imports_new()
+                },
+                c_fn! {
+                    "void" apw_imports_delete(imports: "apw_imports_t *")
+                },
+                c_fn! {
+                    /// Binds the function import `module`.`name` to `func`.
+                    "bool" apw_imports_add_func(
+                        imports: "apw_imports_t *",
+                        module: "const char *",
+                        name: "const char *",
+                        params: "const apw_valkind_t *",
+                        num_params: "size_t",
+                        results: "const apw_valkind_t *",
+                        num_results: "size_t",
+                        func: "apw_func_t"
+                    )
+                },
+            ],
+            vec![
+                c_fn! {
+                    /// Instantiates `module`, binding its function imports from `imports` (which
+                    /// may be NULL if it has none), and runs its start function.
+                    "apw_instance_t *" apw_instance_new(
+                        module: "const apw_module_t *",
+                        imports: "const apw_imports_t *"
+                    )
+                },
+                c_fn! {
+                    "void" apw_instance_delete(instance: "apw_instance_t *")
+                },
+            ],
+            vec![c_fn! {
+                /// Calls the function export `name`. `results` must have room for exactly as
+                /// many values as the function returns. Fails if the arguments do not match
+                /// its parameters or if it traps.
+                "bool" apw_instance_call(
+                    instance: "apw_instance_t *",
+                    name: "const char *",
+                    args: "const apw_val_t *",
+                    num_args: "size_t",
+                    results: "apw_val_t *",
+                    num_results: "size_t"
+                )
+            }],
+            vec![
+                c_fn! {
+                    /// The size in bytes of the memory export `memory`.
+                    "bool" apw_instance_memory_size(
+                        instance: "apw_instance_t *",
+                        memory: "const char *",
+                        size: "uint64_t *"
+                    )
+                },
+                c_fn! {
+                    /// Copies `len` bytes at `offset` of the memory export `memory` to `buffer`.
+                    "bool" apw_instance_memory_read(
+                        instance: "apw_instance_t *",
+                        memory: "const char *",
+                        offset: "uint64_t",
+                        buffer: "uint8_t *",
+                        len: "size_t"
+                    )
+                },
+                c_fn! {
+                    /// Copies `len` bytes from `data` to `offset` of the memory export `memory`.
+                    "bool" apw_instance_memory_write(
+                        instance: "apw_instance_t *",
+                        memory: "const char *",
+                        offset: "uint64_t",
+                        data: "const uint8_t *",
+                        len: "size_t"
+                    )
+                },
+            ],
+        ]
+    }
+
+    pub(super) fn generate() -> String {
+        let mut header = PROLOGUE.to_string();
+        for (name, kind) in [
+            ("APW_I32", APW_I32),
+            ("APW_I64", APW_I64),
+            ("APW_F32", APW_F32),
+            ("APW_F64", APW_F64),
+        ] {
+            header.push_str(&format!("#define {name} ((apw_valkind_t){kind})\n"));
+        }
+        header.push_str(TYPES);
+        for group in declarations() {
+            for declaration in group {
+                declaration.write(&mut header);
+            }
+            header.push('\n');
+        }
+        header.push_str(EPILOGUE);
+        header
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_values_round_trip() {
+        for val in [
+            Val::I32(-1),
+            Val::I64(1 << 40),
+            Val::F32(0.5),
+            Val::F64(-2.25),
+        ] {
+            assert_eq!(RawVal::from(val).to_val().unwrap(), val);
+        }
+        let invalid = RawVal {
+            kind: 4,
+            of: RawValue { i64: 0 },
+        };
+        assert!(invalid.to_val().is_err());
+    }
+
+    #[test]
+    fn test_errors_are_kept_for_the_thread() {
+        let engine = unsafe { apw_engine_new(ptr::null()) } as *const Engine;
+        let module = unsafe { apw_module_new(engine, b"\0asm".as_ptr(), 4) };
+        assert!(module.is_null());
+        let message = unsafe { CStr::from_ptr(apw_last_error()) };
+        assert!(!message.to_str().unwrap().is_empty());
+        assert!(!apw_last_error_is_trap());
+        unsafe { apw_engine_delete(engine as *mut Engine) };
+    }
+
+    #[test]
+    fn test_header_is_generated() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/include/auto_parallel_wasm.h");
+        let generated = c_header::generate();
+        if std::env::var_os("UPDATE_C_HEADER").is_some() {
+            std::fs::write(path, &generated).unwrap();
+        }
+        assert_eq!(
+            std::fs::read_to_string(path).unwrap(),
+            generated,
+            "{path} is out of date; rerun this test with UPDATE_C_HEADER=1"
+        );
+    }
+
+    #[test]
+    fn test_header_matches_the_rust_signatures() {
+        let declarations: Vec<_> = c_header::declarations().into_iter().flatten().collect();
+        for declaration in &declarations {
+            declaration.check_signature();
+        }
+
+        // Every exported function is declared, and nothing else.
+        let source = include_str!("capi.rs");
+        let mut exported: Vec<&str> = source
+            .split("#[unsafe(no_mangle)]\n")
+            .skip(1)
+            .map(|item| {
+                let name = &item[item.find("fn ").unwrap() + 3..];
+                &name[..name.find('(').unwrap()]
+            })
+            .collect();
+        let mut declared: Vec<&str> = declarations.iter().map(|d| d.name).collect();
+        exported.sort_unstable();
+        declared.sort_unstable();
+        assert_eq!(exported, declared);
+    }
+}
@@ -3,16 +3,81 @@ use inkwell::basic_block::BasicBlock;
 use inkwell::builder::Builder;
 use inkwell::context::Context;
 use inkwell::execution_engine::ExecutionEngine;
-use inkwell::module::Module;
-use inkwell::targets::{InitializationConfig, Target};
-use inkwell::types::{BasicMetadataTypeEnum, BasicType, BasicTypeEnum};
+use inkwell::memory_buffer::MemoryBuffer;
+use inkwell::module::{Linkage, Module};
+use inkwell::passes::PassBuilderOptions;
+use inkwell::targets::{
+    CodeModel, FileType, InitializationConfig, Target, TargetData, TargetMachine, TargetTriple,
+};
+use inkwell::types::{BasicMetadataTypeEnum, BasicType, BasicTypeEnum, IntType, StructType};
 use inkwell::values::{
-    BasicValue, BasicValueEnum, FloatValue, FunctionValue, GlobalValue, IntValue, PointerValue,
+    BasicMetadataValueEnum, BasicValue, BasicValueEnum, FloatValue, FunctionValue, GlobalValue,
+    InstructionValue, IntValue, PointerValue,
 };
-use inkwell::{FloatPredicate, IntPredicate, OptimizationLevel};
-use wasmparser::{Operator, ValType};
+use inkwell::{AtomicOrdering, AtomicRMWBinOp, FloatPredicate, IntPredicate};
+use wasmparser::{ExternalKind, GlobalType, MemoryType, Operator, TableType, ValType};
+
+use crate::debug_info::DebugInfo;
+use crate::header;
+use crate::limits::{DEFAULT_MAX_STACK, ExecutionLimits, InterruptHandle, stack_limit};
+use crate::linker;
+use crate::options::{CompilerOptions, EmitKind, OptLevel, RelocModel};
+use crate::profile::{self, FunctionCounters, JitFunction, ProfileEntry};
+use crate::runtime;
+use crate::trap::Trap;
+use crate::vmctx::VmctxLayout;
+use crate::wasm_parser::{ElementSegment, ElementSegmentKind, Function, WasmModule};
+use inkwell::debug_info::DISubprogram;
+use std::cell::{Cell, OnceCell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+/// Initializes only the LLVM backend needed for `triple`, falling back to every
+/// available backend for architectures we don't recognize.
+fn initialize_target(triple: &TargetTriple) {
+    let config = InitializationConfig::default();
+    let triple = triple.as_str().to_string_lossy();
+    let arch = triple.split('-').next().unwrap_or_default();
+
+    match arch {
+        "x86_64" | "i386" | "i486" | "i586" | "i686" => Target::initialize_x86(&config),
+        "aarch64" | "aarch64_be" | "arm64" => Target::initialize_aarch64(&config),
+        a if a.starts_with("arm") || a.starts_with("thumb") => Target::initialize_arm(&config),
+        a if a.starts_with("riscv") => Target::initialize_riscv(&config),
+        a if a.starts_with("wasm") => Target::initialize_webassembly(&config),
+        a if a.starts_with("powerpc") || a.starts_with("ppc") => {
+            Target::initialize_power_pc(&config)
+        }
+        a if a.starts_with("mips") => Target::initialize_mips(&config),
+        "s390x" => Target::initialize_system_z(&config),
+        "loongarch64" => Target::initialize_loongarch(&config),
+        _ => Target::initialize_all(&config),
+    }
+}
+
+/// Makes a Wasm name usable as an LLVM symbol or value name: names may hold
+/// arbitrary UTF-8, which assemblers and debuggers handle poorly.
+fn mangle_name(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_graphic() && c != '"' && c != '\\' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
 
-use crate::wasm_parser::{Function, WasmModule};
+const PAGE_SIZE: u64 = 65536;
+const MAX_PAGES: u64 = 65536;
+const MEMORY_GROW_SYMBOL: &str = "wasm_memory_grow";
+const LAZY_COMPILE_SYMBOL: &str = "wasm_lazy_compile";
+const LIMITED_MAIN_SYMBOL: &str = "wasm_limited_main";
+const NO_INTERRUPT_SYMBOL: &str = "wasm_no_interrupt";
 
 extern "C" fn assert_eq32_wrapper(actual: i32, expected: i32) {
     if actual != expected {
@@ -26,6 +91,43 @@ extern "C" fn assert_eq64_wrapper(actual: i64, expected: i64) {
     }
 }
 
+/// The lazily compiled module running on this thread, for `lazy_compile`.
+struct LazyJit<'a, 'ctx> {
+    compiler: &'a Compiler<'ctx>,
+    wasm_module: &'a WasmModule,
+    /// Why the first body that failed to compile did, reported by
+    /// `run_main_lazy` in place of the trap it caused.
+    error: RefCell<Option<anyhow::Error>>,
+}
+
+thread_local! {
+    static LAZY_JIT: Cell<*const ()> = const { Cell::new(std::ptr::null()) };
+}
+
+/// Called by the stubs of a lazily compiled module to compile function
+/// `func_idx` at `tier`. Returns the address of the compiled body, or null
+/// if it cannot be compiled, on which the stub traps with
+/// `Trap::CompileFailed`. Unwinding out of here would abort the process.
+extern "C" fn lazy_compile(func_idx: u32, tier: u32) -> *const u8 {
+    let state = LAZY_JIT.with(Cell::get);
+    if state.is_null() {
+        return std::ptr::null();
+    }
+    let jit = unsafe { &*(state as *const LazyJit) };
+    match jit
+        .compiler
+        .compile_lazy_body(jit.wasm_module, func_idx, tier)
+    {
+        Ok(address) => address as *const u8,
+        Err(e) => {
+            jit.error
+                .borrow_mut()
+                .get_or_insert_with(|| e.context(format!("Failed to compile func {func_idx}")));
+            std::ptr::null()
+        }
+    }
+}
+
 #[derive(Clone)]
 struct ControlBlock<'ctx> {
     block_type: ControlBlockType,
@@ -40,78 +142,708 @@ enum ControlBlockType {
     If,
 }
 
+/// Where in the module the compiler currently is, for error messages and
+/// debug locations.
+#[derive(Default)]
+struct SourcePosition {
+    func_index: u32,
+    func_name: Option<String>,
+    offset: Option<usize>,
+}
+
+impl fmt::Display for SourcePosition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "func {}", self.func_index)?;
+        if let Some(name) = &self.func_name {
+            write!(f, " ({name})")?;
+        }
+        if let Some(offset) = self.offset {
+            write!(f, " at offset 0x{offset:x}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Number of functions compiled together in one LLVM context when a module
+/// is compiled in parallel. Partitions depend only on the module, never on
+/// the number of threads, so the output is the same for any thread count.
+const PARTITION_SIZE: usize = 256;
+
+/// What part of a Wasm module a compiler's LLVM module holds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ModuleRole {
+    /// The whole module.
+    Whole,
+    /// Everything but the function bodies, which are compiled in partitions
+    /// and linked in.
+    PartitionMain,
+    /// The bodies of one partition of the functions.
+    Partition,
+    /// Everything but the function bodies, with a stub in place of each
+    /// function that compiles it on its first call.
+    LazyMain,
+    /// The body of one lazily compiled function, added to the execution
+    /// engine of the `LazyMain` module.
+    LazyBody,
+}
+
+/// What a worker needs from the main compiler besides the Wasm module.
+struct PartitionSetup {
+    module_name: String,
+    options: CompilerOptions,
+    function_symbols: HashMap<u32, String>,
+    embedded: bool,
+    triple: String,
+    data_layout: String,
+}
+
 pub struct Compiler<'ctx> {
     context: &'ctx Context,
+    // Declared before `module` so the debug info builder is disposed first.
+    debug_info: Option<DebugInfo<'ctx>>,
     module: Module<'ctx>,
     builder: Builder<'ctx>,
-    execution_engine: ExecutionEngine<'ctx>,
-    memory: Option<GlobalValue<'ctx>>,
-    globals: Vec<(GlobalValue<'ctx>, ValType)>,
-    function_tables: Vec<GlobalValue<'ctx>>,
-    table_sizes: Vec<u32>,
+    /// Absent in partition workers, whose modules are only serialized.
+    execution_engine: Option<ExecutionEngine<'ctx>>,
+    memory: Option<MemoryType>,
+    globals: Vec<GlobalType>,
+    tables: Vec<TableType>,
+    element_segments: Vec<ElementSegment>,
+    /// LLVM symbols of functions named by the name section or an export.
+    function_symbols: HashMap<u32, String>,
+    options: CompilerOptions,
+    role: ModuleRole,
+    /// Compiling for the embedding API: imported functions are called
+    /// through host function slots in the vmctx, and the start function is
+    /// exported as `wasm_instance_start`.
+    embedded: bool,
+    /// Number of host function slots in the vmctx.
+    host_imports: usize,
+    position: RefCell<SourcePosition>,
+    subprogram: Cell<Option<DISubprogram<'ctx>>>,
+    /// Counters of `CompilerOptions::profile`, mapped into the JIT-compiled
+    /// code along with the host functions.
+    profile_counters: OnceCell<Vec<FunctionCounters>>,
 }
 
 impl<'ctx> Compiler<'ctx> {
     pub fn new(context: &'ctx Context, module_name: &str) -> Result<Self> {
-        Target::initialize_native(&InitializationConfig::default())
-            .map_err(|e| anyhow!("Failed to initialize native target: {}", e))?;
+        Self::with_options(context, module_name, CompilerOptions::default())
+    }
+
+    pub fn with_options(
+        context: &'ctx Context,
+        module_name: &str,
+        options: CompilerOptions,
+    ) -> Result<Self> {
+        Self::create(context, module_name, options, true)
+    }
 
+    fn create(
+        context: &'ctx Context,
+        module_name: &str,
+        options: CompilerOptions,
+        jit: bool,
+    ) -> Result<Self> {
         let module = context.create_module(module_name);
-        let execution_engine = module
-            .create_jit_execution_engine(OptimizationLevel::None)
-            .map_err(|e| anyhow!("Failed to create execution engine: {}", e))?;
+        let debug_info = options.debug_info.then(|| {
+            DebugInfo::new(
+                context,
+                &module,
+                &format!("{module_name}.wasm"),
+                options.opt_level != OptLevel::O0,
+            )
+        });
+        Self::from_module(context, module, debug_info, options, jit)
+    }
+
+    /// A compiler for `crate::embed`, which binds imports to host functions
+    /// when instantiating rather than at link time.
+    pub(crate) fn for_embedding(context: &'ctx Context, options: CompilerOptions) -> Result<Self> {
+        if options.lazy {
+            return Err(anyhow!(
+                "Lazy compilation is not supported by the embedding API"
+            ));
+        }
+        let mut compiler = Self::with_options(context, "wasm_module", options)?;
+        compiler.embedded = true;
+        Ok(compiler)
+    }
+
+    /// A compiler for a module compiled earlier and serialized with
+    /// `to_bitcode`, ready to `run_main` without recompiling.
+    pub fn from_bitcode(
+        context: &'ctx Context,
+        module_name: &str,
+        bitcode: &[u8],
+        options: CompilerOptions,
+    ) -> Result<Self> {
+        let buffer = MemoryBuffer::create_from_memory_range_copy(bitcode, module_name);
+        let module = Module::parse_bitcode_from_buffer(&buffer, context)
+            .map_err(|e| anyhow!("Failed to load bitcode: {}", e))?;
+        Self::from_module(context, module, None, options, true)
+    }
+
+    fn from_module(
+        context: &'ctx Context,
+        module: Module<'ctx>,
+        debug_info: Option<DebugInfo<'ctx>>,
+        options: CompilerOptions,
+        jit: bool,
+    ) -> Result<Self> {
+        let execution_engine = if jit {
+            Target::initialize_native(&InitializationConfig::default())
+                .map_err(|e| anyhow!("Failed to initialize native target: {}", e))?;
+            let execution_engine = module
+                .create_jit_execution_engine(options.opt_level.codegen_level())
+                .map_err(|e| anyhow!("Failed to create execution engine: {}", e))?;
+            // The engine replaces the module's data layout with the JIT's,
+            // which leaves inkwell's cached copy dangling; set it again.
+            module.set_data_layout(&execution_engine.get_target_data().get_data_layout());
+            Some(execution_engine)
+        } else {
+            None
+        };
 
         Ok(Self {
             context,
+            debug_info,
             module,
             builder: context.create_builder(),
             execution_engine,
             memory: None,
             globals: Vec::new(),
-            function_tables: Vec::new(),
-            table_sizes: Vec::new(),
+            tables: Vec::new(),
+            element_segments: Vec::new(),
+            function_symbols: HashMap::new(),
+            options,
+            role: ModuleRole::Whole,
+            embedded: false,
+            host_imports: 0,
+            position: RefCell::new(SourcePosition::default()),
+            subprogram: Cell::new(None),
+            profile_counters: OnceCell::new(),
         })
     }
 
+    pub fn options(&self) -> &CompilerOptions {
+        &self.options
+    }
+
+    /// Compiles `wasm_module` into this compiler's LLVM module. Modules with
+    /// more than `PARTITION_SIZE` functions have their function bodies
+    /// compiled and optimized in parallel, on `CompilerOptions::jobs`
+    /// threads. With
+    /// `CompilerOptions::lazy`, function bodies are left for `run_main_lazy`
+    /// to compile as they are called.
     pub fn compile_module(&mut self, wasm_module: &WasmModule) -> Result<()> {
+        if self.options.target.is_some() {
+            let target_machine = self.create_target_machine()?;
+            self.module.set_triple(&target_machine.get_triple());
+            self.module
+                .set_data_layout(&target_machine.get_target_data().get_data_layout());
+        }
+        if self.options.lazy && (self.options.profile || self.options.perf_map) {
+            return Err(anyhow!("Profiling is not supported with lazy compilation"));
+        }
+        if wasm_module.imported_global_count > 0 {
+            return Err(anyhow!("Global imports are not supported"));
+        }
+        header::check_export_symbols(wasm_module)?;
+        if self.options.lazy {
+            self.role = ModuleRole::LazyMain;
+        } else if wasm_module.functions.len() > PARTITION_SIZE {
+            self.role = ModuleRole::PartitionMain;
+        }
+
+        self.prepare_module(wasm_module)?;
+        for function in &wasm_module.functions {
+            self.declare_function(function)?;
+        }
+
+        let mut partitions = Vec::new();
+        match self.role {
+            ModuleRole::PartitionMain => partitions = self.compile_partitions(wasm_module)?,
+            ModuleRole::LazyMain => {
+                for function in &wasm_module.functions {
+                    self.create_lazy_stub(function);
+                }
+            }
+            _ => {
+                for function in &wasm_module.functions {
+                    self.compile_function(function, &wasm_module.function_types, wasm_module)?;
+                }
+            }
+        }
+
+        self.create_instance_functions(wasm_module)?;
+        self.create_export_wrappers(wasm_module)?;
+        self.create_export_accessors(wasm_module)?;
+        if self.embedded {
+            self.create_array_call_trampolines(wasm_module)?;
+        }
+
+        if let Some(start_idx) = wasm_module.start_func_idx {
+            self.create_main(wasm_module, start_idx)?;
+            if self.has_limited_main() {
+                self.create_limited_main(wasm_module, start_idx)?;
+            }
+            if self.embedded {
+                self.create_start_export(wasm_module, start_idx)?;
+            }
+        }
+
+        if let Some(debug_info) = &self.debug_info {
+            debug_info.finalize();
+        }
+        self.run_pass_pipeline()?;
+        if self.role == ModuleRole::PartitionMain {
+            // The partitions were optimized by the workers. Once linked, all
+            // that is left is dropping functions inlined into all callers.
+            self.link_partitions(&partitions)?;
+            self.internalize(wasm_module);
+            self.run_passes("globaldce")?;
+        }
+        Ok(())
+    }
+
+    /// Sets up what compiling function bodies relies on: memory, globals,
+    /// tables and the symbols of functions.
+    fn prepare_module(&mut self, wasm_module: &WasmModule) -> Result<()> {
         if !wasm_module.memories.is_empty() {
             self.create_memory(&wasm_module.memories[0])?;
         }
 
         self.create_globals(&wasm_module.globals)?;
+        self.tables = wasm_module.tables.clone();
+        self.element_segments = wasm_module.element_segments.clone();
+        if self.embedded {
+            self.host_imports = wasm_module.imported_functions.len();
+        }
+        if let Some(debug_info) = &mut self.debug_info {
+            debug_info.load_source_lines(&wasm_module.dwarf);
+        }
 
-        for table in &wasm_module.tables {
-            let ptr_type = self.context.ptr_type(inkwell::AddressSpace::default());
-            let table_size = table.initial as u32;
-            let table_type = ptr_type.array_type(table_size);
+        if wasm_module.has_assert_eq32_import || wasm_module.has_assert_eq64_import {
+            self.declare_assert_functions();
+        }
 
-            let table_global = self.module.add_global(
-                table_type,
-                None,
-                &format!("table_{}", self.function_tables.len()),
+        let computes_symbols = matches!(
+            self.role,
+            ModuleRole::Whole | ModuleRole::PartitionMain | ModuleRole::LazyMain
+        );
+        if computes_symbols && !self.options.stable_names {
+            self.function_symbols = Self::function_symbols(wasm_module);
+        }
+        Ok(())
+    }
+
+    /// Compiles and optimizes the function bodies partition by partition on
+    /// worker threads, each in its own LLVM context, and returns the
+    /// partitions as bitcode in order. Machine code generation still runs on
+    /// the linked module.
+    fn compile_partitions(&self, wasm_module: &WasmModule) -> Result<Vec<Vec<u8>>> {
+        let partitions: Vec<&[Function]> = wasm_module.functions.chunks(PARTITION_SIZE).collect();
+        let setup = PartitionSetup {
+            module_name: self.module.get_name().to_string_lossy().into_owned(),
+            options: self.options.clone(),
+            function_symbols: self.function_symbols.clone(),
+            embedded: self.embedded,
+            triple: self
+                .module
+                .get_triple()
+                .as_str()
+                .to_string_lossy()
+                .into_owned(),
+            data_layout: self
+                .module
+                .get_data_layout()
+                .as_str()
+                .to_string_lossy()
+                .into_owned(),
+        };
+        let threads = self
+            .options
+            .jobs
+            .unwrap_or_else(|| thread::available_parallelism().map_or(1, |n| n.get()))
+            .clamp(1, partitions.len());
+
+        let next_partition = AtomicUsize::new(0);
+        let mut results: Vec<(usize, Result<Vec<u8>>)> = thread::scope(|scope| {
+            let workers: Vec<_> = (0..threads)
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut results = Vec::new();
+                        loop {
+                            let index = next_partition.fetch_add(1, Ordering::Relaxed);
+                            let Some(partition) = partitions.get(index) else {
+                                break;
+                            };
+                            let result = Self::compile_partition(wasm_module, partition, &setup);
+                            results.push((index, result));
+                        }
+                        results
+                    })
+                })
+                .collect();
+            workers
+                .into_iter()
+                .flat_map(|worker| worker.join().expect("Partition worker panicked"))
+                .collect()
+        });
+        results.sort_by_key(|(index, _)| *index);
+        results.into_iter().map(|(_, bitcode)| bitcode).collect()
+    }
+
+    /// Links the partitions returned by `compile_partitions` into this
+    /// module in order.
+    fn link_partitions(&self, partitions: &[Vec<u8>]) -> Result<()> {
+        let module_name = self.module.get_name().to_string_lossy().into_owned();
+        for (index, bitcode) in partitions.iter().enumerate() {
+            let buffer = MemoryBuffer::create_from_memory_range_copy(
+                bitcode,
+                &format!("{module_name}.part{index}"),
             );
-            let null_initializer = table_type.const_zero();
-            table_global.set_initializer(&null_initializer);
+            let partition = Module::parse_bitcode_from_buffer(&buffer, self.context)
+                .map_err(|e| anyhow!("Failed to load partition {}: {}", index, e))?;
+            self.module
+                .link_in_module(partition)
+                .map_err(|e| anyhow!("Failed to link partition {}: {}", index, e))?;
+        }
+        Ok(())
+    }
 
-            self.function_tables.push(table_global);
-            self.table_sizes.push(table_size);
+    /// Compiles the bodies of `partition` in a fresh context, runs the
+    /// optimization pipeline on them and returns them as bitcode. The other
+    /// functions are declared so that calls resolve when the partition is
+    /// linked; calls between partitions are not inlined.
+    fn compile_partition(
+        wasm_module: &WasmModule,
+        partition: &[Function],
+        setup: &PartitionSetup,
+    ) -> Result<Vec<u8>> {
+        let context = Context::create();
+        let mut compiler =
+            Compiler::create(&context, &setup.module_name, setup.options.clone(), false)?;
+        compiler.role = ModuleRole::Partition;
+        compiler.embedded = setup.embedded;
+        compiler.function_symbols = setup.function_symbols.clone();
+        compiler
+            .module
+            .set_triple(&TargetTriple::create(&setup.triple));
+        if !setup.data_layout.is_empty() {
+            let target_data = TargetData::create(&setup.data_layout);
+            compiler
+                .module
+                .set_data_layout(&target_data.get_data_layout());
         }
 
-        if wasm_module.has_assert_eq32_import || wasm_module.has_assert_eq64_import {
-            self.declare_assert_functions();
+        compiler.prepare_module(wasm_module)?;
+        for function in &wasm_module.functions {
+            compiler.declare_function(function)?;
         }
+        for function in partition {
+            compiler.compile_function(function, &wasm_module.function_types, wasm_module)?;
+        }
+
+        if let Some(debug_info) = &compiler.debug_info {
+            debug_info.finalize();
+        }
+        compiler.run_pass_pipeline()?;
+        Ok(compiler.to_bitcode())
+    }
 
+    /// Gives the symbols shared between partitions the linkage they have in a
+    /// module compiled as a whole, once all partitions are linked.
+    fn internalize(&self, wasm_module: &WasmModule) {
         for function in &wasm_module.functions {
-            self.compile_function(function, &wasm_module.function_types, wasm_module)?;
+            if let Some(llvm_func) = self.module.get_function(&self.function_symbol(function)) {
+                llvm_func.set_linkage(Linkage::Internal);
+            }
+        }
+        if let Some(grow_fn) = self.module.get_function(MEMORY_GROW_SYMBOL) {
+            grow_fn.set_linkage(Linkage::Internal);
+        }
+        for global in self.module.get_globals() {
+            if global.get_linkage() == Linkage::LinkOnceODR {
+                global.set_linkage(Linkage::Private);
+            }
         }
+    }
 
-        self.initialize_function_tables(&wasm_module.element_segments, &wasm_module.functions)?;
+    /// Linkage of functions internal to the module. Partitions refer to each
+    /// other's functions, so they stay external until `internalize`.
+    fn local_linkage(&self) -> Linkage {
+        match self.role {
+            ModuleRole::Whole => Linkage::Internal,
+            ModuleRole::PartitionMain
+            | ModuleRole::Partition
+            | ModuleRole::LazyMain
+            | ModuleRole::LazyBody => Linkage::External,
+        }
+    }
 
-        if let Some(start_idx) = wasm_module.start_func_idx {
-            self.create_main(start_idx)?;
+    /// Defines the stub standing in for `function` until it is compiled. The
+    /// stub keeps the address of the compiled body in a slot, which is empty
+    /// until the first call asks the lazy JIT to compile the body. With
+    /// tiering enabled, the stub also counts calls and has the body
+    /// recompiled at a higher optimization level once it is hot. A body
+    /// that fails to compile traps with `Trap::CompileFailed`.
+    fn create_lazy_stub(&self, function: &Function) {
+        let ptr_type = self.context.ptr_type(inkwell::AddressSpace::default());
+        let i32_type = self.context.i32_type();
+        let symbol = self.function_symbol(function);
+        let stub = self.module.get_function(&symbol).unwrap();
+        let compile_fn = self.get_runtime_function(
+            LAZY_COMPILE_SYMBOL,
+            ptr_type.fn_type(&[i32_type.into(), i32_type.into()], false),
+        );
+
+        let slot = self
+            .module
+            .add_global(ptr_type, None, &format!("{symbol}.slot"));
+        slot.set_initializer(&ptr_type.const_null());
+        slot.set_linkage(Linkage::Internal);
+
+        let entry_block = self.context.append_basic_block(stub, "entry");
+        let compile_block = self.context.append_basic_block(stub, "compile");
+        let call_block = self.context.append_basic_block(stub, "call");
+        let failed_block = self.context.append_basic_block(stub, "compile_failed");
+        let func_idx = i32_type.const_int(function.idx as u64, false);
+        let compile = |tier: u64, name: &str| {
+            let target = self
+                .builder
+                .build_call(
+                    compile_fn,
+                    &[func_idx.into(), i32_type.const_int(tier, false).into()],
+                    name,
+                )
+                .unwrap()
+                .try_as_basic_value()
+                .left()
+                .unwrap()
+                .into_pointer_value();
+            let failed = self.builder.build_is_null(target, "failed").unwrap();
+            let compiled_block = self
+                .context
+                .append_basic_block(stub, &format!("{name}_compiled"));
+            self.builder
+                .build_conditional_branch(failed, failed_block, compiled_block)
+                .unwrap();
+            self.builder.position_at_end(compiled_block);
+            self.builder
+                .build_store(slot.as_pointer_value(), target)
+                .unwrap();
+            self.builder.build_unconditional_branch(call_block).unwrap();
+            (target, compiled_block)
+        };
+
+        self.builder.position_at_end(entry_block);
+        let target = self
+            .builder
+            .build_load(ptr_type, slot.as_pointer_value(), "target")
+            .unwrap()
+            .into_pointer_value();
+        let compiled = self.builder.build_is_not_null(target, "compiled").unwrap();
+        let mut incoming = Vec::new();
+        match self.options.tier_up_calls {
+            Some(threshold) => {
+                let count_block = self.context.append_basic_block(stub, "count");
+                let tier_up_block = self.context.append_basic_block(stub, "tier_up");
+                self.builder
+                    .build_conditional_branch(compiled, count_block, compile_block)
+                    .unwrap();
+
+                self.builder.position_at_end(count_block);
+                let counter = self
+                    .module
+                    .add_global(i32_type, None, &format!("{symbol}.calls"));
+                counter.set_initializer(&i32_type.const_zero());
+                counter.set_linkage(Linkage::Internal);
+                let calls = self
+                    .builder
+                    .build_load(i32_type, counter.as_pointer_value(), "calls")
+                    .unwrap()
+                    .into_int_value();
+                let calls = self
+                    .builder
+                    .build_int_add(calls, i32_type.const_int(1, false), "calls_next")
+                    .unwrap();
+                self.builder
+                    .build_store(counter.as_pointer_value(), calls)
+                    .unwrap();
+                let hot = self
+                    .builder
+                    .build_int_compare(
+                        IntPredicate::EQ,
+                        calls,
+                        i32_type.const_int(threshold as u64, false),
+                        "hot",
+                    )
+                    .unwrap();
+                self.builder
+                    .build_conditional_branch(hot, tier_up_block, call_block)
+                    .unwrap();
+                incoming.push((target, count_block));
+
+                self.builder.position_at_end(tier_up_block);
+                incoming.push(compile(2, "optimized"));
+            }
+            None => {
+                self.builder
+                    .build_conditional_branch(compiled, call_block, compile_block)
+                    .unwrap();
+                incoming.push((target, entry_block));
+            }
         }
 
-        Ok(())
+        self.builder.position_at_end(compile_block);
+        incoming.push(compile(1, "first"));
+
+        self.builder.position_at_end(failed_block);
+        self.build_trap(Trap::CompileFailed);
+
+        self.builder.position_at_end(call_block);
+        let callee = self.builder.build_phi(ptr_type, "callee").unwrap();
+        for (value, block) in &incoming {
+            callee.add_incoming(&[(value, *block)]);
+        }
+        let args: Vec<BasicMetadataValueEnum> =
+            stub.get_param_iter().map(|param| param.into()).collect();
+        let call = self
+            .builder
+            .build_indirect_call(
+                self.create_llvm_function_type(&function.func_type),
+                callee.as_basic_value().into_pointer_value(),
+                &args,
+                "result",
+            )
+            .unwrap();
+        call.set_tail_call(true);
+        match call.try_as_basic_value().left() {
+            Some(result) => self.builder.build_return(Some(&result)).unwrap(),
+            None => self.builder.build_return(None).unwrap(),
+        };
+    }
+
+    /// Compiles the body of function `func_idx` of a lazily compiled module
+    /// at `tier` into a module of its own, adds it to the execution engine
+    /// and returns the address of the body. Tier 2 optimizes at `-O3`.
+    fn compile_lazy_body(
+        &self,
+        wasm_module: &WasmModule,
+        func_idx: u32,
+        tier: u32,
+    ) -> Result<usize> {
+        let function = wasm_module
+            .functions
+            .iter()
+            .find(|f| f.idx == func_idx)
+            .ok_or(anyhow!("Invalid function index: {}", func_idx))?;
+        let execution_engine = self
+            .execution_engine
+            .as_ref()
+            .ok_or(anyhow!("Compiler has no execution engine"))?;
+        let symbol = format!("{}.tier{tier}", self.function_symbol(function));
+        let options = CompilerOptions {
+            opt_level: if tier >= 2 {
+                OptLevel::O3
+            } else {
+                self.options.opt_level
+            },
+            ..self.options.clone()
+        };
+
+        let module_name = self.module.get_name().to_string_lossy().into_owned();
+        let mut body = Compiler::create(self.context, &module_name, options, false)?;
+        body.role = ModuleRole::LazyBody;
+        body.function_symbols = self.function_symbols.clone();
+        body.function_symbols.insert(func_idx, symbol.clone());
+        body.module.set_triple(&self.module.get_triple());
+        body.module.set_data_layout(&self.module.get_data_layout());
+        body.prepare_module(wasm_module)?;
+        for function in &wasm_module.functions {
+            body.declare_function(function)?;
+        }
+        body.compile_function(function, &wasm_module.function_types, wasm_module)?;
+        if let Some(debug_info) = &body.debug_info {
+            debug_info.finalize();
+        }
+        body.run_pass_pipeline()?;
+
+        // The execution engine takes ownership of the module, so its machine
+        // code outlives `body`.
+        execution_engine
+            .add_module(&body.module)
+            .map_err(|()| anyhow!("Failed to add {} to the execution engine", symbol))?;
+        execution_engine
+            .get_function_address(&symbol)
+            .map_err(|e| anyhow!("Failed to compile {}: {}", symbol, e))
+    }
+
+    /// Linkage of constants created on demand, such as funcref descriptors,
+    /// which partitions may each create; the linker keeps one copy. Lazily
+    /// compiled bodies are never linked and keep private copies.
+    fn constant_linkage(&self) -> Linkage {
+        match self.role {
+            ModuleRole::Whole | ModuleRole::LazyMain | ModuleRole::LazyBody => Linkage::Private,
+            ModuleRole::PartitionMain | ModuleRole::Partition => Linkage::LinkOnceODR,
+        }
+    }
+
+    fn run_pass_pipeline(&self) -> Result<()> {
+        self.run_passes(self.options.opt_level.pass_pipeline())
+    }
+
+    /// Runs the passes described by `pipeline` unless optimizations are off.
+    fn run_passes(&self, pipeline: &str) -> Result<()> {
+        if self.options.opt_level == OptLevel::O0 {
+            return Ok(());
+        }
+
+        let target_machine = self.create_target_machine()?;
+        self.module
+            .run_passes(pipeline, &target_machine, PassBuilderOptions::create())
+            .map_err(|e| anyhow!("Failed to run optimization passes: {}", e))
+    }
+
+    fn create_target_machine(&self) -> Result<TargetMachine> {
+        self.create_target_machine_with_reloc(self.options.reloc)
+    }
+
+    fn create_target_machine_with_reloc(&self, reloc: RelocModel) -> Result<TargetMachine> {
+        let target_triple = match &self.options.target {
+            Some(triple) => TargetMachine::normalize_triple(&TargetTriple::create(triple)),
+            None => TargetMachine::get_default_triple(),
+        };
+        initialize_target(&target_triple);
+        let target = Target::from_triple(&target_triple)
+            .map_err(|e| anyhow!("Failed to get target: {}", e))?;
+
+        let (cpu, features) = match self.options.cpu.as_deref() {
+            Some("native") => (
+                TargetMachine::get_host_cpu_name().to_string(),
+                TargetMachine::get_host_cpu_features().to_string(),
+            ),
+            Some(cpu) => (cpu.to_string(), String::new()),
+            None => ("generic".to_string(), String::new()),
+        };
+        let features = match &self.options.features {
+            Some(extra) if features.is_empty() => extra.clone(),
+            Some(extra) => format!("{features},{extra}"),
+            None => features,
+        };
+
+        target
+            .create_target_machine(
+                &target_triple,
+                &cpu,
+                &features,
+                self.options.opt_level.codegen_level(),
+                reloc.reloc_mode(),
+                CodeModel::Default,
+            )
+            .ok_or_else(|| anyhow!("Failed to create target machine for {}", target_triple))
     }
 
     fn build_binary_arithmetic_op<F>(
@@ -122,106 +854,324 @@ impl<'ctx> Compiler<'ctx> {
     where
         F: FnOnce(IntValue<'ctx>, IntValue<'ctx>) -> IntValue<'ctx>,
     {
-        let rhs = value_stack
-            .pop()
-            .ok_or(anyhow!("Stack underflow"))?
-            .into_int_value();
-        let lhs = value_stack
-            .pop()
-            .ok_or(anyhow!("Stack underflow"))?
-            .into_int_value();
+        let rhs = Self::pop_int_value(value_stack)?;
+        let lhs = Self::pop_int_value(value_stack)?;
         let result = op_builder(lhs, rhs);
         value_stack.push(result.into());
         Ok(())
     }
 
-    fn build_comparison_op(
-        &self,
-        value_stack: &mut Vec<BasicValueEnum<'ctx>>,
-        predicate: IntPredicate,
-        op_name: &str,
-    ) -> Result<()> {
-        let rhs = value_stack
-            .pop()
-            .ok_or(anyhow!("Stack underflow"))?
-            .into_int_value();
-        let lhs = value_stack
-            .pop()
-            .ok_or(anyhow!("Stack underflow"))?
-            .into_int_value();
-        let result = self
+    /// Traps if `rhs` is zero and, with `check_overflow`, if `lhs / rhs`
+    /// overflows, which only `INT_MIN / -1` does.
+    fn build_divisor_check(&self, lhs: IntValue<'ctx>, rhs: IntValue<'ctx>, check_overflow: bool) {
+        let int_type = rhs.get_type();
+        let is_zero = self
             .builder
-            .build_int_compare(predicate, lhs, rhs, op_name)
+            .build_int_compare(IntPredicate::EQ, rhs, int_type.const_zero(), "div_by_zero")
             .unwrap();
-        let extended = self
+        self.build_trap_if(is_zero, Trap::IntegerDivideByZero);
+        if !check_overflow {
+            return;
+        }
+        let min = int_type.const_int(1 << (int_type.get_bit_width() - 1), false);
+        let lhs_is_min = self
             .builder
-            .build_int_z_extend(result, self.context.i32_type(), &format!("{op_name}_ext"))
+            .build_int_compare(IntPredicate::EQ, lhs, min, "lhs_is_min")
             .unwrap();
-        value_stack.push(extended.into());
-        Ok(())
+        let rhs_is_minus_one = self
+            .builder
+            .build_int_compare(
+                IntPredicate::EQ,
+                rhs,
+                int_type.const_all_ones(),
+                "rhs_is_minus_one",
+            )
+            .unwrap();
+        let overflows = self
+            .builder
+            .build_and(lhs_is_min, rhs_is_minus_one, "div_overflow")
+            .unwrap();
+        self.build_trap_if(overflows, Trap::IntegerOverflow);
     }
 
-    fn build_binary_float_arithmetic_op<F>(
-        &self,
-        value_stack: &mut Vec<BasicValueEnum<'ctx>>,
-        op_builder: F,
-    ) -> Result<()>
-    where
-        F: FnOnce(FloatValue<'ctx>, FloatValue<'ctx>) -> FloatValue<'ctx>,
-    {
-        let rhs = value_stack
-            .pop()
-            .ok_or(anyhow!("Stack underflow"))?
-            .into_float_value();
-        let lhs = value_stack
-            .pop()
-            .ok_or(anyhow!("Stack underflow"))?
-            .into_float_value();
-        let result = op_builder(lhs, rhs);
-        value_stack.push(result.into());
-        Ok(())
+    /// The divisor to give `srem` for `rem_s` by `rhs`. A remainder by -1 is
+    /// always 0 in Wasm, but `srem` overflows for `INT_MIN % -1`, so -1 is
+    /// replaced by 1, which gives the same remainder.
+    fn build_signed_rem_divisor(&self, rhs: IntValue<'ctx>) -> IntValue<'ctx> {
+        let int_type = rhs.get_type();
+        let is_minus_one = self
+            .builder
+            .build_int_compare(
+                IntPredicate::EQ,
+                rhs,
+                int_type.const_all_ones(),
+                "rem_by_minus_one",
+            )
+            .unwrap();
+        self.builder
+            .build_select(
+                is_minus_one,
+                int_type.const_int(1, false),
+                rhs,
+                "rem_divisor",
+            )
+            .unwrap()
+            .into_int_value()
     }
 
-    fn build_float_comparison_op(
+    /// Lowers `trunc`, which traps on NaN and on values whose integer part
+    /// does not fit in `int_type`, where `fptosi` and `fptoui` give poison.
+    fn build_trunc(
         &self,
         value_stack: &mut Vec<BasicValueEnum<'ctx>>,
-        predicate: FloatPredicate,
-        op_name: &str,
+        int_type: IntType<'ctx>,
+        signed: bool,
+        name: &str,
     ) -> Result<()> {
-        let rhs = value_stack
-            .pop()
-            .ok_or(anyhow!("Stack underflow"))?
-            .into_float_value();
-        let lhs = value_stack
-            .pop()
-            .ok_or(anyhow!("Stack underflow"))?
-            .into_float_value();
-        let result = self
+        let value = Self::pop_float_value(value_stack)?;
+        let float_type = value.get_type();
+        let is_nan = self
             .builder
-            .build_float_compare(predicate, lhs, rhs, op_name)
+            .build_float_compare(FloatPredicate::UNO, value, value, "trunc_is_nan")
             .unwrap();
-        let extended = self
+        self.build_trap_if(is_nan, Trap::InvalidConversionToInteger);
+
+        // Values in the open interval (lower, upper) truncate to a valid
+        // integer. When lower itself is not representable, the closed bound
+        // one above it is used instead.
+        let bits = int_type.get_bit_width();
+        let mantissa_bits = if float_type == self.context.f32_type() {
+            24
+        } else {
+            53
+        };
+        let (lower_predicate, lower, upper) = if !signed {
+            (FloatPredicate::OGT, -1.0, 2f64.powi(bits as i32))
+        } else if bits <= mantissa_bits {
+            let min = -(2f64.powi(bits as i32 - 1));
+            (FloatPredicate::OGT, min - 1.0, -min)
+        } else {
+            let min = -(2f64.powi(bits as i32 - 1));
+            (FloatPredicate::OGE, min, -min)
+        };
+        let above_lower = self
             .builder
-            .build_int_z_extend(result, self.context.i32_type(), &format!("{op_name}_ext"))
+            .build_float_compare(
+                lower_predicate,
+                value,
+                float_type.const_float(lower),
+                "trunc_above_min",
+            )
             .unwrap();
-        value_stack.push(extended.into());
+        let below_upper = self
+            .builder
+            .build_float_compare(
+                FloatPredicate::OLT,
+                value,
+                float_type.const_float(upper),
+                "trunc_below_max",
+            )
+            .unwrap();
+        let in_range = self
+            .builder
+            .build_and(above_lower, below_upper, "trunc_in_range")
+            .unwrap();
+        let overflows = self.builder.build_not(in_range, "trunc_overflow").unwrap();
+        self.build_trap_if(overflows, Trap::IntegerOverflow);
+
+        let result = if signed {
+            self.builder
+                .build_float_to_signed_int(value, int_type, name)
+                .unwrap()
+        } else {
+            self.builder
+                .build_float_to_unsigned_int(value, int_type, name)
+                .unwrap()
+        };
+        value_stack.push(result.into());
         Ok(())
     }
 
-    fn pop_single_value(
+    /// Lowers `trunc_sat` to `llvm.fpto[su]i.sat`, which saturates and maps
+    /// NaN to 0 exactly like Wasm.
+    fn build_trunc_sat(
+        &self,
         value_stack: &mut Vec<BasicValueEnum<'ctx>>,
-    ) -> Result<BasicValueEnum<'ctx>> {
-        value_stack.pop().ok_or(anyhow!("Stack underflow"))
-    }
-
+        int_type: IntType<'ctx>,
+        signed: bool,
+        name: &str,
+    ) -> Result<()> {
+        let value = Self::pop_float_value(value_stack)?;
+        let float_bits = if value.get_type() == self.context.f32_type() {
+            32
+        } else {
+            64
+        };
+        let intrinsic_fn = self.get_intrinsic_function(
+            &format!(
+                "llvm.fpto{}i.sat.i{}.f{float_bits}",
+                if signed { "s" } else { "u" },
+                int_type.get_bit_width()
+            ),
+            &[value.get_type().into()],
+            int_type.into(),
+        )?;
+        let result = self
+            .builder
+            .build_call(intrinsic_fn, &[value.into()], name)
+            .unwrap()
+            .try_as_basic_value()
+            .left()
+            .unwrap();
+        value_stack.push(result);
+        Ok(())
+    }
+
+    /// Lowers `extendN_s`, which sign-extends the low `bits` of an integer.
+    fn build_sign_extend_low(
+        &self,
+        value_stack: &mut Vec<BasicValueEnum<'ctx>>,
+        bits: u32,
+        name: &str,
+    ) -> Result<()> {
+        let value = Self::pop_int_value(value_stack)?;
+        let truncated = self
+            .builder
+            .build_int_truncate(
+                value,
+                self.context.custom_width_int_type(bits),
+                &format!("{name}_low"),
+            )
+            .unwrap();
+        let result = self
+            .builder
+            .build_int_s_extend(truncated, value.get_type(), name)
+            .unwrap();
+        value_stack.push(result.into());
+        Ok(())
+    }
+
+    /// Lowers `reinterpret`, which keeps the bits of a value as another type.
+    fn build_reinterpret(
+        &self,
+        value_stack: &mut Vec<BasicValueEnum<'ctx>>,
+        ty: BasicTypeEnum<'ctx>,
+    ) -> Result<()> {
+        let value = Self::pop_single_value(value_stack)?;
+        if value.get_type().is_int_type() == ty.is_int_type() {
+            return Err(Self::type_mismatch(
+                if ty.is_int_type() { "float" } else { "integer" },
+                value,
+            ));
+        }
+        let result = self
+            .builder
+            .build_bit_cast(value, ty, "reinterpret")
+            .unwrap();
+        value_stack.push(result);
+        Ok(())
+    }
+
+    fn build_comparison_op(
+        &self,
+        value_stack: &mut Vec<BasicValueEnum<'ctx>>,
+        predicate: IntPredicate,
+        op_name: &str,
+    ) -> Result<()> {
+        let rhs = Self::pop_int_value(value_stack)?;
+        let lhs = Self::pop_int_value(value_stack)?;
+        let result = self
+            .builder
+            .build_int_compare(predicate, lhs, rhs, op_name)
+            .unwrap();
+        let extended = self
+            .builder
+            .build_int_z_extend(result, self.context.i32_type(), &format!("{op_name}_ext"))
+            .unwrap();
+        value_stack.push(extended.into());
+        Ok(())
+    }
+
+    fn build_binary_float_arithmetic_op<F>(
+        &self,
+        value_stack: &mut Vec<BasicValueEnum<'ctx>>,
+        op_builder: F,
+    ) -> Result<()>
+    where
+        F: FnOnce(FloatValue<'ctx>, FloatValue<'ctx>) -> FloatValue<'ctx>,
+    {
+        let rhs = Self::pop_float_value(value_stack)?;
+        let lhs = Self::pop_float_value(value_stack)?;
+        let result = op_builder(lhs, rhs);
+        value_stack.push(result.into());
+        Ok(())
+    }
+
+    fn build_float_comparison_op(
+        &self,
+        value_stack: &mut Vec<BasicValueEnum<'ctx>>,
+        predicate: FloatPredicate,
+        op_name: &str,
+    ) -> Result<()> {
+        let rhs = Self::pop_float_value(value_stack)?;
+        let lhs = Self::pop_float_value(value_stack)?;
+        let result = self
+            .builder
+            .build_float_compare(predicate, lhs, rhs, op_name)
+            .unwrap();
+        let extended = self
+            .builder
+            .build_int_z_extend(result, self.context.i32_type(), &format!("{op_name}_ext"))
+            .unwrap();
+        value_stack.push(extended.into());
+        Ok(())
+    }
+
+    fn pop_single_value(
+        value_stack: &mut Vec<BasicValueEnum<'ctx>>,
+    ) -> Result<BasicValueEnum<'ctx>> {
+        value_stack.pop().ok_or(anyhow!("Stack underflow"))
+    }
+
+    fn pop_int_value(value_stack: &mut Vec<BasicValueEnum<'ctx>>) -> Result<IntValue<'ctx>> {
+        match Self::pop_single_value(value_stack)? {
+            BasicValueEnum::IntValue(value) => Ok(value),
+            value => Err(Self::type_mismatch("integer", value)),
+        }
+    }
+
+    fn pop_float_value(value_stack: &mut Vec<BasicValueEnum<'ctx>>) -> Result<FloatValue<'ctx>> {
+        match Self::pop_single_value(value_stack)? {
+            BasicValueEnum::FloatValue(value) => Ok(value),
+            value => Err(Self::type_mismatch("float", value)),
+        }
+    }
+
+    fn pop_pointer_value(
+        value_stack: &mut Vec<BasicValueEnum<'ctx>>,
+    ) -> Result<PointerValue<'ctx>> {
+        match Self::pop_single_value(value_stack)? {
+            BasicValueEnum::PointerValue(value) => Ok(value),
+            value => Err(Self::type_mismatch("reference", value)),
+        }
+    }
+
+    fn type_mismatch(expected: &str, found: BasicValueEnum<'ctx>) -> anyhow::Error {
+        anyhow!(
+            "Type mismatch: expected {} operand, found {}",
+            expected,
+            found.get_type().print_to_string().to_string()
+        )
+    }
+
     fn build_load_op(
         &self,
         value_stack: &mut Vec<BasicValueEnum<'ctx>>,
         load_type: BasicTypeEnum<'ctx>,
         memarg: &wasmparser::MemArg,
     ) -> Result<()> {
-        let offset = Self::pop_single_value(value_stack)?.into_int_value();
-        let ptr = self.get_memory_ptr(offset, memarg.offset)?;
+        let offset = Self::pop_int_value(value_stack)?;
+        let ptr = self.get_memory_ptr(offset, memarg)?;
         let value = self.builder.build_load(load_type, ptr, "load").unwrap();
         value_stack.push(value);
         Ok(())
@@ -233,8 +1183,8 @@ impl<'ctx> Compiler<'ctx> {
         memarg: &wasmparser::MemArg,
     ) -> Result<()> {
         let value = Self::pop_single_value(value_stack)?;
-        let offset = Self::pop_single_value(value_stack)?.into_int_value();
-        let ptr = self.get_memory_ptr(offset, memarg.offset)?;
+        let offset = Self::pop_int_value(value_stack)?;
+        let ptr = self.get_memory_ptr(offset, memarg)?;
         self.builder.build_store(ptr, value).unwrap();
         Ok(())
     }
@@ -247,8 +1197,8 @@ impl<'ctx> Compiler<'ctx> {
         signed: bool,
         memarg: &wasmparser::MemArg,
     ) -> Result<()> {
-        let offset = Self::pop_single_value(value_stack)?.into_int_value();
-        let ptr = self.get_memory_ptr(offset, memarg.offset)?;
+        let offset = Self::pop_int_value(value_stack)?;
+        let ptr = self.get_memory_ptr(offset, memarg)?;
         let loaded_value = self.builder.build_load(load_type, ptr, "load").unwrap();
 
         let extended_value = if signed {
@@ -279,19 +1229,238 @@ impl<'ctx> Compiler<'ctx> {
         store_type: BasicTypeEnum<'ctx>,
         memarg: &wasmparser::MemArg,
     ) -> Result<()> {
-        let value = Self::pop_single_value(value_stack)?;
-        let offset = Self::pop_single_value(value_stack)?.into_int_value();
-        let ptr = self.get_memory_ptr(offset, memarg.offset)?;
+        let value = Self::pop_int_value(value_stack)?;
+        let offset = Self::pop_int_value(value_stack)?;
+        let ptr = self.get_memory_ptr(offset, memarg)?;
 
         let truncated_value = self
             .builder
-            .build_int_truncate(value.into_int_value(), store_type.into_int_type(), "trunc")
+            .build_int_truncate(value, store_type.into_int_type(), "trunc")
             .unwrap();
 
         self.builder.build_store(ptr, truncated_value).unwrap();
         Ok(())
     }
 
+    fn atomic_access_type(&self, memarg: &wasmparser::MemArg) -> IntType<'ctx> {
+        self.context.custom_width_int_type(8 << memarg.max_align)
+    }
+
+    /// Makes a plain load or store sequentially consistent. Atomic accesses
+    /// are always naturally aligned, which `get_atomic_ptr` has checked.
+    fn make_atomic(
+        &self,
+        instruction: InstructionValue<'ctx>,
+        memarg: &wasmparser::MemArg,
+    ) -> Result<()> {
+        instruction
+            .set_atomic_ordering(AtomicOrdering::SequentiallyConsistent)
+            .map_err(|e| anyhow!("Failed to make access atomic: {}", e))?;
+        instruction
+            .set_alignment(1 << memarg.max_align)
+            .map_err(|e| anyhow!("Failed to align atomic access: {}", e))?;
+        Ok(())
+    }
+
+    fn build_atomic_load_op(
+        &self,
+        value_stack: &mut Vec<BasicValueEnum<'ctx>>,
+        result_type: IntType<'ctx>,
+        memarg: &wasmparser::MemArg,
+    ) -> Result<()> {
+        let offset = Self::pop_int_value(value_stack)?;
+        let ptr = self.get_atomic_ptr(offset, memarg)?;
+        let access_type = self.atomic_access_type(memarg);
+        let loaded = self
+            .builder
+            .build_load(access_type, ptr, "atomic_load")
+            .unwrap()
+            .into_int_value();
+        self.make_atomic(loaded.as_instruction().unwrap(), memarg)?;
+        let value = if access_type == result_type {
+            loaded
+        } else {
+            self.builder
+                .build_int_z_extend(loaded, result_type, "zext")
+                .unwrap()
+        };
+        value_stack.push(value.into());
+        Ok(())
+    }
+
+    fn build_atomic_store_op(
+        &self,
+        value_stack: &mut Vec<BasicValueEnum<'ctx>>,
+        memarg: &wasmparser::MemArg,
+    ) -> Result<()> {
+        let value = Self::pop_int_value(value_stack)?;
+        let offset = Self::pop_int_value(value_stack)?;
+        let ptr = self.get_atomic_ptr(offset, memarg)?;
+        let value = self.truncate_to_access_type(value, memarg);
+        let store = self.builder.build_store(ptr, value).unwrap();
+        self.make_atomic(store, memarg)
+    }
+
+    fn build_atomic_rmw_op(
+        &self,
+        value_stack: &mut Vec<BasicValueEnum<'ctx>>,
+        op: AtomicRMWBinOp,
+        memarg: &wasmparser::MemArg,
+    ) -> Result<()> {
+        let value = Self::pop_int_value(value_stack)?;
+        let offset = Self::pop_int_value(value_stack)?;
+        let result_type = value.get_type();
+        let ptr = self.get_atomic_ptr(offset, memarg)?;
+        let operand = self.truncate_to_access_type(value, memarg);
+        let old = self
+            .builder
+            .build_atomicrmw(op, ptr, operand, AtomicOrdering::SequentiallyConsistent)
+            .unwrap();
+        value_stack.push(self.extend_from_access_type(old, result_type).into());
+        Ok(())
+    }
+
+    fn build_atomic_cmpxchg_op(
+        &self,
+        value_stack: &mut Vec<BasicValueEnum<'ctx>>,
+        memarg: &wasmparser::MemArg,
+    ) -> Result<()> {
+        let replacement = Self::pop_int_value(value_stack)?;
+        let expected = Self::pop_int_value(value_stack)?;
+        let offset = Self::pop_int_value(value_stack)?;
+        let result_type = replacement.get_type();
+        let ptr = self.get_atomic_ptr(offset, memarg)?;
+        let expected = self.truncate_to_access_type(expected, memarg);
+        let replacement = self.truncate_to_access_type(replacement, memarg);
+        let cmpxchg = self
+            .builder
+            .build_cmpxchg(
+                ptr,
+                expected,
+                replacement,
+                AtomicOrdering::SequentiallyConsistent,
+                AtomicOrdering::SequentiallyConsistent,
+            )
+            .unwrap();
+        let old = self
+            .builder
+            .build_extract_value(cmpxchg, 0, "cmpxchg_old")
+            .unwrap()
+            .into_int_value();
+        value_stack.push(self.extend_from_access_type(old, result_type).into());
+        Ok(())
+    }
+
+    /// Lowers `memory.atomic.wait32`/`wait64` to a runtime call. Waiting on
+    /// unshared memory can never be woken, so the spec makes it a trap.
+    fn build_atomic_wait_op(
+        &self,
+        value_stack: &mut Vec<BasicValueEnum<'ctx>>,
+        memarg: &wasmparser::MemArg,
+    ) -> Result<()> {
+        let timeout = Self::pop_int_value(value_stack)?;
+        let expected = Self::pop_int_value(value_stack)?;
+        let offset = Self::pop_int_value(value_stack)?;
+        let ptr = self.get_atomic_ptr(offset, memarg)?;
+        let i32_type = self.context.i32_type();
+
+        if !self.has_shared_memory() {
+            let function = self.current_function();
+            self.build_trap(Trap::WaitOnUnsharedMemory);
+            let after_trap = self.context.append_basic_block(function, "after_wait_trap");
+            self.builder.position_at_end(after_trap);
+            value_stack.push(i32_type.get_undef().into());
+            return Ok(());
+        }
+
+        let ptr_type = self.context.ptr_type(inkwell::AddressSpace::default());
+        let symbol = if expected.get_type().get_bit_width() == 64 {
+            runtime::WAIT64_SYMBOL
+        } else {
+            runtime::WAIT32_SYMBOL
+        };
+        let wait_fn = self.get_runtime_function(
+            symbol,
+            i32_type.fn_type(
+                &[
+                    ptr_type.into(),
+                    expected.get_type().into(),
+                    self.context.i64_type().into(),
+                ],
+                false,
+            ),
+        );
+        let result = self
+            .builder
+            .build_call(
+                wait_fn,
+                &[ptr.into(), expected.into(), timeout.into()],
+                "wait_result",
+            )
+            .unwrap();
+        value_stack.push(result.try_as_basic_value().left().unwrap());
+        Ok(())
+    }
+
+    /// Lowers `memory.atomic.notify`. Nothing can wait on unshared memory, so
+    /// no waiters are ever woken there.
+    fn build_atomic_notify_op(
+        &self,
+        value_stack: &mut Vec<BasicValueEnum<'ctx>>,
+        memarg: &wasmparser::MemArg,
+    ) -> Result<()> {
+        let count = Self::pop_int_value(value_stack)?;
+        let offset = Self::pop_int_value(value_stack)?;
+        let ptr = self.get_atomic_ptr(offset, memarg)?;
+        let i32_type = self.context.i32_type();
+
+        if !self.has_shared_memory() {
+            value_stack.push(i32_type.const_zero().into());
+            return Ok(());
+        }
+
+        let ptr_type = self.context.ptr_type(inkwell::AddressSpace::default());
+        let notify_fn = self.get_runtime_function(
+            runtime::NOTIFY_SYMBOL,
+            i32_type.fn_type(&[ptr_type.into(), i32_type.into()], false),
+        );
+        let result = self
+            .builder
+            .build_call(notify_fn, &[ptr.into(), count.into()], "notify_result")
+            .unwrap();
+        value_stack.push(result.try_as_basic_value().left().unwrap());
+        Ok(())
+    }
+
+    fn truncate_to_access_type(
+        &self,
+        value: IntValue<'ctx>,
+        memarg: &wasmparser::MemArg,
+    ) -> IntValue<'ctx> {
+        let access_type = self.atomic_access_type(memarg);
+        if value.get_type() == access_type {
+            value
+        } else {
+            self.builder
+                .build_int_truncate(value, access_type, "trunc")
+                .unwrap()
+        }
+    }
+
+    fn extend_from_access_type(
+        &self,
+        value: IntValue<'ctx>,
+        result_type: IntType<'ctx>,
+    ) -> IntValue<'ctx> {
+        if value.get_type() == result_type {
+            value
+        } else {
+            self.builder
+                .build_int_z_extend(value, result_type, "zext")
+                .unwrap()
+        }
+    }
+
     fn get_branch_target(
         &self,
         control_stack: &[ControlBlock<'ctx>],
@@ -310,35 +1479,58 @@ impl<'ctx> Compiler<'ctx> {
         }
     }
 
+    /// Compiles the body of `function`, reporting errors at the position of
+    /// the operator that caused them.
     fn compile_function(
         &self,
         function: &Function,
         function_types: &[wasmparser::FuncType],
         wasm_module: &WasmModule,
     ) -> Result<FunctionValue<'ctx>> {
-        let param_types: Vec<BasicMetadataTypeEnum> = function
-            .func_type
-            .params()
-            .iter()
-            .map(|vt| self.val_type_to_llvm_type(*vt).into())
-            .collect();
-
-        let return_type = if function.func_type.results().is_empty() {
-            None
-        } else if function.func_type.results().len() == 1 {
-            Some(self.val_type_to_llvm_type(function.func_type.results()[0]))
-        } else {
-            return Err(anyhow!("Multiple return values not supported"));
+        *self.position.borrow_mut() = SourcePosition {
+            func_index: function.idx,
+            func_name: wasm_module
+                .names
+                .functions
+                .get(&function.idx)
+                .or(function.name.as_ref())
+                .cloned(),
+            offset: None,
         };
+        let result = self.compile_function_body(function, function_types, wasm_module);
+        self.builder.unset_current_debug_location();
+        self.subprogram.set(None);
+        result.map_err(|e| anyhow!("{}: {}", self.position.borrow(), e))
+    }
 
-        let fn_type = match return_type {
-            Some(ret_type) => ret_type.fn_type(&param_types, false),
-            None => self.context.void_type().fn_type(&param_types, false),
-        };
+    /// Records the offset of the operator about to be compiled and points
+    /// the debug location of the instructions it produces at it.
+    fn set_source_offset(&self, offset: Option<usize>) {
+        self.position.borrow_mut().offset = offset;
+        if let (Some(debug_info), Some(subprogram)) = (&self.debug_info, self.subprogram.get()) {
+            let line = offset.unwrap_or(0) as u32;
+            let location = debug_info.location(self.context, subprogram, line);
+            self.builder.set_current_debug_location(location);
+        }
+    }
 
-        let default_name = format!("func_{}", function.idx);
-        let func_name = function.name.as_ref().unwrap_or(&default_name);
-        let llvm_func = self.module.add_function(func_name, fn_type, None);
+    fn compile_function_body(
+        &self,
+        function: &Function,
+        function_types: &[wasmparser::FuncType],
+        wasm_module: &WasmModule,
+    ) -> Result<FunctionValue<'ctx>> {
+        let llvm_func = match self.module.get_function(&self.function_symbol(function)) {
+            Some(llvm_func) => llvm_func,
+            None => self.declare_function(function)?,
+        };
+        if let Some(debug_info) = &self.debug_info {
+            let name = self.position.borrow().func_name.clone();
+            let name = name.unwrap_or_else(|| format!("func_{}", function.idx));
+            let offset = function.body.offsets.first().copied().unwrap_or(0) as u32;
+            self.subprogram
+                .set(Some(debug_info.create_function(llvm_func, &name, offset)));
+        }
 
         let entry_block = self.context.append_basic_block(llvm_func, "entry");
         self.builder.position_at_end(entry_block);
@@ -347,34 +1539,39 @@ impl<'ctx> Compiler<'ctx> {
         let mut locals: Vec<BasicValueEnum<'ctx>> = Vec::new();
         let mut control_stack: Vec<ControlBlock<'ctx>> = Vec::new();
 
+        let local_names = wasm_module.names.locals.get(&function.idx);
+        let local_name = |index: usize| local_names.and_then(|names| names.get(&(index as u32)));
+
         for (i, _) in function.func_type.params().iter().enumerate() {
-            locals.push(llvm_func.get_nth_param(i as u32).unwrap());
+            let param = llvm_func.get_nth_param(i as u32 + 1).unwrap();
+            if let Some(name) = self.value_name(local_name(i)) {
+                param.set_name(&name);
+            }
+            locals.push(param);
         }
 
+        // Wasm locals start out as zero, which an uninitialized alloca is not.
         for local_type in &function.body.locals {
-            let alloca = match local_type {
-                ValType::I32 => self
-                    .builder
-                    .build_alloca(self.context.i32_type(), "local")
-                    .unwrap(),
-                ValType::I64 => self
-                    .builder
-                    .build_alloca(self.context.i64_type(), "local")
-                    .unwrap(),
-                ValType::F32 => self
-                    .builder
-                    .build_alloca(self.context.f32_type(), "local")
-                    .unwrap(),
-                ValType::F64 => self
-                    .builder
-                    .build_alloca(self.context.f64_type(), "local")
-                    .unwrap(),
-                _ => return Err(anyhow!("Unsupported local type: {:?}", local_type)),
-            };
+            let llvm_type = self.val_type_to_llvm_type(*local_type);
+            let name = self.value_name(local_name(locals.len()));
+            let alloca = self
+                .builder
+                .build_alloca(llvm_type, name.as_deref().unwrap_or("local"))
+                .unwrap();
+            self.builder
+                .build_store(alloca, llvm_type.const_zero())
+                .unwrap();
             locals.push(alloca.as_basic_value_enum());
         }
-
-        for operator in &function.body.operators {
+        let param_count = function.func_type.params().len();
+        self.build_stack_check();
+        self.build_limit_checks();
+        let profile_counters = self.declare_profile_counters(function);
+        self.build_profile_count(profile_counters, 0);
+        let mut loops = 0;
+
+        for (index, operator) in function.body.operators.iter().enumerate() {
+            self.set_source_offset(function.body.offsets.get(index).copied());
             match operator {
                 Operator::I32Const { value } => {
                     value_stack.push(
@@ -417,6 +1614,7 @@ impl<'ctx> Compiler<'ctx> {
                 }
                 Operator::I64DivS => {
                     self.build_binary_arithmetic_op(&mut value_stack, |lhs, rhs| {
+                        self.build_divisor_check(lhs, rhs, true);
                         self.builder
                             .build_int_signed_div(lhs, rhs, "div64")
                             .unwrap()
@@ -424,6 +1622,7 @@ impl<'ctx> Compiler<'ctx> {
                 }
                 Operator::I64DivU => {
                     self.build_binary_arithmetic_op(&mut value_stack, |lhs, rhs| {
+                        self.build_divisor_check(lhs, rhs, false);
                         self.builder
                             .build_int_unsigned_div(lhs, rhs, "divu64")
                             .unwrap()
@@ -431,6 +1630,8 @@ impl<'ctx> Compiler<'ctx> {
                 }
                 Operator::I64RemS => {
                     self.build_binary_arithmetic_op(&mut value_stack, |lhs, rhs| {
+                        self.build_divisor_check(lhs, rhs, false);
+                        let rhs = self.build_signed_rem_divisor(rhs);
                         self.builder
                             .build_int_signed_rem(lhs, rhs, "rem64")
                             .unwrap()
@@ -438,6 +1639,7 @@ impl<'ctx> Compiler<'ctx> {
                 }
                 Operator::I64RemU => {
                     self.build_binary_arithmetic_op(&mut value_stack, |lhs, rhs| {
+                        self.build_divisor_check(lhs, rhs, false);
                         self.builder
                             .build_int_unsigned_rem(lhs, rhs, "remu64")
                             .unwrap()
@@ -450,7 +1652,7 @@ impl<'ctx> Compiler<'ctx> {
                     self.build_comparison_op(&mut value_stack, IntPredicate::NE, "ne64")?;
                 }
                 Operator::I64Eqz => {
-                    let value = Self::pop_single_value(&mut value_stack)?.into_int_value();
+                    let value = Self::pop_int_value(&mut value_stack)?;
                     let zero = self.context.i64_type().const_zero();
                     let result = self
                         .builder
@@ -521,8 +1723,8 @@ impl<'ctx> Compiler<'ctx> {
                     })?;
                 }
                 Operator::I64Rotl => {
-                    let rhs = Self::pop_single_value(&mut value_stack)?.into_int_value();
-                    let lhs = Self::pop_single_value(&mut value_stack)?.into_int_value();
+                    let rhs = Self::pop_int_value(&mut value_stack)?;
+                    let lhs = Self::pop_int_value(&mut value_stack)?;
                     let i64_type = self.context.i64_type();
                     let bits = i64_type.const_int(64, false);
                     let masked_rhs = self
@@ -545,8 +1747,8 @@ impl<'ctx> Compiler<'ctx> {
                     value_stack.push(result.into());
                 }
                 Operator::I64Rotr => {
-                    let rhs = Self::pop_single_value(&mut value_stack)?.into_int_value();
-                    let lhs = Self::pop_single_value(&mut value_stack)?.into_int_value();
+                    let rhs = Self::pop_int_value(&mut value_stack)?;
+                    let lhs = Self::pop_int_value(&mut value_stack)?;
                     let i64_type = self.context.i64_type();
                     let bits = i64_type.const_int(64, false);
                     let masked_rhs = self
@@ -661,11 +1863,13 @@ impl<'ctx> Compiler<'ctx> {
                 }
                 Operator::I32DivS => {
                     self.build_binary_arithmetic_op(&mut value_stack, |lhs, rhs| {
+                        self.build_divisor_check(lhs, rhs, true);
                         self.builder.build_int_signed_div(lhs, rhs, "div").unwrap()
                     })?;
                 }
                 Operator::I32DivU => {
                     self.build_binary_arithmetic_op(&mut value_stack, |lhs, rhs| {
+                        self.build_divisor_check(lhs, rhs, false);
                         self.builder
                             .build_int_unsigned_div(lhs, rhs, "divu")
                             .unwrap()
@@ -673,11 +1877,14 @@ impl<'ctx> Compiler<'ctx> {
                 }
                 Operator::I32RemS => {
                     self.build_binary_arithmetic_op(&mut value_stack, |lhs, rhs| {
+                        self.build_divisor_check(lhs, rhs, false);
+                        let rhs = self.build_signed_rem_divisor(rhs);
                         self.builder.build_int_signed_rem(lhs, rhs, "rem").unwrap()
                     })?;
                 }
                 Operator::I32RemU => {
                     self.build_binary_arithmetic_op(&mut value_stack, |lhs, rhs| {
+                        self.build_divisor_check(lhs, rhs, false);
                         self.builder
                             .build_int_unsigned_rem(lhs, rhs, "remu")
                             .unwrap()
@@ -714,7 +1921,7 @@ impl<'ctx> Compiler<'ctx> {
                     self.build_comparison_op(&mut value_stack, IntPredicate::NE, "ne")?;
                 }
                 Operator::I32Eqz => {
-                    let value = Self::pop_single_value(&mut value_stack)?.into_int_value();
+                    let value = Self::pop_int_value(&mut value_stack)?;
                     let zero = self.context.i32_type().const_zero();
                     let result = self
                         .builder
@@ -761,8 +1968,8 @@ impl<'ctx> Compiler<'ctx> {
                     })?;
                 }
                 Operator::I32Rotl => {
-                    let rhs = Self::pop_single_value(&mut value_stack)?.into_int_value();
-                    let lhs = Self::pop_single_value(&mut value_stack)?.into_int_value();
+                    let rhs = Self::pop_int_value(&mut value_stack)?;
+                    let lhs = Self::pop_int_value(&mut value_stack)?;
                     let i32_type = self.context.i32_type();
                     let bits = i32_type.const_int(32, false);
                     let masked_rhs = self
@@ -785,8 +1992,8 @@ impl<'ctx> Compiler<'ctx> {
                     value_stack.push(result.into());
                 }
                 Operator::I32Rotr => {
-                    let rhs = Self::pop_single_value(&mut value_stack)?.into_int_value();
-                    let lhs = Self::pop_single_value(&mut value_stack)?.into_int_value();
+                    let rhs = Self::pop_int_value(&mut value_stack)?;
+                    let lhs = Self::pop_int_value(&mut value_stack)?;
                     let i32_type = self.context.i32_type();
                     let bits = i32_type.const_int(32, false);
                     let masked_rhs = self
@@ -812,29 +2019,19 @@ impl<'ctx> Compiler<'ctx> {
                     let local_ptr = locals
                         .get(*local_index as usize)
                         .ok_or(anyhow!("Invalid local index: {}", local_index))?;
-                    if local_ptr.is_pointer_value() {
-                        let ptr = local_ptr.into_pointer_value();
-                        let param_count = function.func_type.params().len();
-                        if (*local_index as usize) < param_count {
-                            let param_type = function.func_type.params()[*local_index as usize];
-                            let llvm_type = self.val_type_to_llvm_type(param_type);
-                            let loaded = self
-                                .builder
-                                .build_load(llvm_type, ptr, "local_load")
-                                .unwrap();
-                            value_stack.push(loaded);
-                        } else {
-                            let local_idx = *local_index as usize - param_count;
-                            let local_type = function.body.locals[local_idx];
-                            let llvm_type = self.val_type_to_llvm_type(local_type);
-                            let loaded = self
-                                .builder
-                                .build_load(llvm_type, ptr, "local_load")
-                                .unwrap();
-                            value_stack.push(loaded);
-                        }
-                    } else {
+                    if (*local_index as usize) < param_count {
                         value_stack.push(*local_ptr);
+                    } else {
+                        let local_idx = *local_index as usize - param_count;
+                        let local_type = function.body.locals[local_idx];
+                        let llvm_type = self.val_type_to_llvm_type(local_type);
+                        let name = self.value_name(local_name(*local_index as usize));
+                        let name = format!("{}_load", name.as_deref().unwrap_or("local"));
+                        let loaded = self
+                            .builder
+                            .build_load(llvm_type, local_ptr.into_pointer_value(), &name)
+                            .unwrap();
+                        value_stack.push(loaded);
                     }
                 }
                 Operator::LocalSet { local_index } => {
@@ -842,7 +2039,7 @@ impl<'ctx> Compiler<'ctx> {
                     let local_ptr = locals
                         .get(*local_index as usize)
                         .ok_or(anyhow!("Invalid local index: {}", local_index))?;
-                    if local_ptr.is_pointer_value() {
+                    if (*local_index as usize) >= param_count {
                         let ptr = local_ptr.into_pointer_value();
                         self.builder.build_store(ptr, value).unwrap();
                     } else {
@@ -854,7 +2051,7 @@ impl<'ctx> Compiler<'ctx> {
                     let local_ptr = locals
                         .get(*local_index as usize)
                         .ok_or(anyhow!("Invalid local index: {}", local_index))?;
-                    if local_ptr.is_pointer_value() {
+                    if (*local_index as usize) >= param_count {
                         let ptr = local_ptr.into_pointer_value();
                         self.builder.build_store(ptr, value).unwrap();
                         value_stack.push(value);
@@ -863,26 +2060,22 @@ impl<'ctx> Compiler<'ctx> {
                     }
                 }
                 Operator::GlobalGet { global_index } => {
-                    let (global_var, val_type) = self
-                        .globals
-                        .get(*global_index as usize)
-                        .ok_or(anyhow!("Invalid global index: {}", global_index))?;
-                    let llvm_type = self.val_type_to_llvm_type(*val_type);
+                    let (global_ptr, global_type) =
+                        self.get_global_ptr(self.current_vmctx(), *global_index)?;
+                    let llvm_type = self.val_type_to_llvm_type(global_type.content_type);
+                    let name = self.value_name(wasm_module.names.globals.get(global_index));
+                    let name = format!("{}_load", name.as_deref().unwrap_or("global"));
                     let loaded = self
                         .builder
-                        .build_load(llvm_type, global_var.as_pointer_value(), "global_load")
+                        .build_load(llvm_type, global_ptr, &name)
                         .unwrap();
                     value_stack.push(loaded);
                 }
                 Operator::GlobalSet { global_index } => {
                     let value = Self::pop_single_value(&mut value_stack)?;
-                    let (global_var, _val_type) = self
-                        .globals
-                        .get(*global_index as usize)
-                        .ok_or(anyhow!("Invalid global index: {}", global_index))?;
-                    self.builder
-                        .build_store(global_var.as_pointer_value(), value)
-                        .unwrap();
+                    let (global_ptr, _global_type) =
+                        self.get_global_ptr(self.current_vmctx(), *global_index)?;
+                    self.builder.build_store(global_ptr, value).unwrap();
                 }
                 Operator::Return => {
                     if function.func_type.results().is_empty() {
@@ -891,7 +2084,8 @@ impl<'ctx> Compiler<'ctx> {
                         let return_value = Self::pop_single_value(&mut value_stack)?;
                         self.builder.build_return(Some(&return_value)).unwrap();
                     }
-                    return Ok(llvm_func);
+                    let after_return = self.context.append_basic_block(llvm_func, "after_return");
+                    self.builder.position_at_end(after_return);
                 }
                 Operator::Drop => {
                     Self::pop_single_value(&mut value_stack)?;
@@ -903,16 +2097,12 @@ impl<'ctx> Compiler<'ctx> {
                     self.build_store_op(&mut value_stack, memarg)?;
                 }
                 Operator::I32Store8 { memarg } => {
-                    let value = Self::pop_single_value(&mut value_stack)?;
-                    let offset = Self::pop_single_value(&mut value_stack)?.into_int_value();
-                    let ptr = self.get_memory_ptr(offset, memarg.offset)?;
+                    let value = Self::pop_int_value(&mut value_stack)?;
+                    let offset = Self::pop_int_value(&mut value_stack)?;
+                    let ptr = self.get_memory_ptr(offset, memarg)?;
                     let value_i8 = self
                         .builder
-                        .build_int_truncate(
-                            value.into_int_value(),
-                            self.context.i8_type(),
-                            "trunc_i8",
-                        )
+                        .build_int_truncate(value, self.context.i8_type(), "trunc_i8")
                         .unwrap();
                     self.builder.build_store(ptr, value_i8).unwrap();
                 }
@@ -1057,10 +2247,105 @@ impl<'ctx> Compiler<'ctx> {
                     value_stack.push(pages.into());
                 }
                 Operator::MemoryGrow { .. } => {
-                    let delta = Self::pop_single_value(&mut value_stack)?.into_int_value();
+                    let delta = Self::pop_int_value(&mut value_stack)?;
                     let result = self.grow_memory(delta)?;
                     value_stack.push(result.into());
                 }
+                Operator::I32AtomicLoad { memarg }
+                | Operator::I32AtomicLoad8U { memarg }
+                | Operator::I32AtomicLoad16U { memarg } => {
+                    self.build_atomic_load_op(&mut value_stack, self.context.i32_type(), memarg)?;
+                }
+                Operator::I64AtomicLoad { memarg }
+                | Operator::I64AtomicLoad8U { memarg }
+                | Operator::I64AtomicLoad16U { memarg }
+                | Operator::I64AtomicLoad32U { memarg } => {
+                    self.build_atomic_load_op(&mut value_stack, self.context.i64_type(), memarg)?;
+                }
+                Operator::I32AtomicStore { memarg }
+                | Operator::I32AtomicStore8 { memarg }
+                | Operator::I32AtomicStore16 { memarg }
+                | Operator::I64AtomicStore { memarg }
+                | Operator::I64AtomicStore8 { memarg }
+                | Operator::I64AtomicStore16 { memarg }
+                | Operator::I64AtomicStore32 { memarg } => {
+                    self.build_atomic_store_op(&mut value_stack, memarg)?;
+                }
+                Operator::I32AtomicRmwAdd { memarg }
+                | Operator::I32AtomicRmw8AddU { memarg }
+                | Operator::I32AtomicRmw16AddU { memarg }
+                | Operator::I64AtomicRmwAdd { memarg }
+                | Operator::I64AtomicRmw8AddU { memarg }
+                | Operator::I64AtomicRmw16AddU { memarg }
+                | Operator::I64AtomicRmw32AddU { memarg } => {
+                    self.build_atomic_rmw_op(&mut value_stack, AtomicRMWBinOp::Add, memarg)?;
+                }
+                Operator::I32AtomicRmwSub { memarg }
+                | Operator::I32AtomicRmw8SubU { memarg }
+                | Operator::I32AtomicRmw16SubU { memarg }
+                | Operator::I64AtomicRmwSub { memarg }
+                | Operator::I64AtomicRmw8SubU { memarg }
+                | Operator::I64AtomicRmw16SubU { memarg }
+                | Operator::I64AtomicRmw32SubU { memarg } => {
+                    self.build_atomic_rmw_op(&mut value_stack, AtomicRMWBinOp::Sub, memarg)?;
+                }
+                Operator::I32AtomicRmwAnd { memarg }
+                | Operator::I32AtomicRmw8AndU { memarg }
+                | Operator::I32AtomicRmw16AndU { memarg }
+                | Operator::I64AtomicRmwAnd { memarg }
+                | Operator::I64AtomicRmw8AndU { memarg }
+                | Operator::I64AtomicRmw16AndU { memarg }
+                | Operator::I64AtomicRmw32AndU { memarg } => {
+                    self.build_atomic_rmw_op(&mut value_stack, AtomicRMWBinOp::And, memarg)?;
+                }
+                Operator::I32AtomicRmwOr { memarg }
+                | Operator::I32AtomicRmw8OrU { memarg }
+                | Operator::I32AtomicRmw16OrU { memarg }
+                | Operator::I64AtomicRmwOr { memarg }
+                | Operator::I64AtomicRmw8OrU { memarg }
+                | Operator::I64AtomicRmw16OrU { memarg }
+                | Operator::I64AtomicRmw32OrU { memarg } => {
+                    self.build_atomic_rmw_op(&mut value_stack, AtomicRMWBinOp::Or, memarg)?;
+                }
+                Operator::I32AtomicRmwXor { memarg }
+                | Operator::I32AtomicRmw8XorU { memarg }
+                | Operator::I32AtomicRmw16XorU { memarg }
+                | Operator::I64AtomicRmwXor { memarg }
+                | Operator::I64AtomicRmw8XorU { memarg }
+                | Operator::I64AtomicRmw16XorU { memarg }
+                | Operator::I64AtomicRmw32XorU { memarg } => {
+                    self.build_atomic_rmw_op(&mut value_stack, AtomicRMWBinOp::Xor, memarg)?;
+                }
+                Operator::I32AtomicRmwXchg { memarg }
+                | Operator::I32AtomicRmw8XchgU { memarg }
+                | Operator::I32AtomicRmw16XchgU { memarg }
+                | Operator::I64AtomicRmwXchg { memarg }
+                | Operator::I64AtomicRmw8XchgU { memarg }
+                | Operator::I64AtomicRmw16XchgU { memarg }
+                | Operator::I64AtomicRmw32XchgU { memarg } => {
+                    self.build_atomic_rmw_op(&mut value_stack, AtomicRMWBinOp::Xchg, memarg)?;
+                }
+                Operator::I32AtomicRmwCmpxchg { memarg }
+                | Operator::I32AtomicRmw8CmpxchgU { memarg }
+                | Operator::I32AtomicRmw16CmpxchgU { memarg }
+                | Operator::I64AtomicRmwCmpxchg { memarg }
+                | Operator::I64AtomicRmw8CmpxchgU { memarg }
+                | Operator::I64AtomicRmw16CmpxchgU { memarg }
+                | Operator::I64AtomicRmw32CmpxchgU { memarg } => {
+                    self.build_atomic_cmpxchg_op(&mut value_stack, memarg)?;
+                }
+                Operator::MemoryAtomicWait32 { memarg }
+                | Operator::MemoryAtomicWait64 { memarg } => {
+                    self.build_atomic_wait_op(&mut value_stack, memarg)?;
+                }
+                Operator::MemoryAtomicNotify { memarg } => {
+                    self.build_atomic_notify_op(&mut value_stack, memarg)?;
+                }
+                Operator::AtomicFence => {
+                    self.builder
+                        .build_fence(AtomicOrdering::SequentiallyConsistent, 0, "")
+                        .unwrap();
+                }
                 Operator::Call { function_index } => {
                     let import_count = wasm_module.import_count;
                     let mut assert_eq32_idx = None;
@@ -1079,7 +2364,13 @@ impl<'ctx> Compiler<'ctx> {
                         }
                     }
 
-                    if *function_index < import_count {
+                    if *function_index < import_count && self.embedded {
+                        self.build_host_import_call(
+                            &mut value_stack,
+                            *function_index,
+                            wasm_module,
+                        )?;
+                    } else if *function_index < import_count {
                         if Some(*function_index) == assert_eq32_idx {
                             let expected = Self::pop_single_value(&mut value_stack)?;
                             let actual = Self::pop_single_value(&mut value_stack)?;
@@ -1106,17 +2397,23 @@ impl<'ctx> Compiler<'ctx> {
                             }
                         }
                     } else {
-                        let adjusted_index = function_index - import_count;
-                        let func_name = format!("func_{adjusted_index}");
+                        let func_name = wasm_module
+                            .functions
+                            .iter()
+                            .find(|f| f.idx == *function_index)
+                            .map(|f| self.function_symbol(f))
+                            .unwrap_or_else(|| format!("func_{function_index}"));
                         if let Some(func) = self.module.get_function(&func_name) {
-                            let param_count = func.get_type().get_param_types().len();
+                            let param_count = func.count_params() - 1;
                             let mut args = Vec::new();
                             for _ in 0..param_count {
                                 let arg = Self::pop_single_value(&mut value_stack)?;
                                 args.push(arg.into());
                             }
+                            args.push(self.current_vmctx().into());
                             args.reverse();
                             let call_result = self.builder.build_call(func, &args, "call").unwrap();
+                            self.build_trap_propagation();
                             if func.get_type().get_return_type().is_some() {
                                 value_stack.push(call_result.try_as_basic_value().left().unwrap());
                             }
@@ -1129,25 +2426,41 @@ impl<'ctx> Compiler<'ctx> {
                     type_index,
                     table_index,
                 } => {
-                    let func_idx = Self::pop_single_value(&mut value_stack)?.into_int_value();
+                    let func_idx = Self::pop_int_value(&mut value_stack)?;
 
-                    if (*table_index as usize) >= self.function_tables.len()
+                    if (*table_index as usize) >= self.tables.len()
                         || (*type_index as usize) >= function_types.len()
                     {
-                        self.builder.build_unreachable().unwrap();
+                        self.build_trap(Trap::BadIndirectCall);
                     } else {
                         let func_type = &function_types[*type_index as usize];
-                        let table = self.function_tables[*table_index as usize];
                         let ptr_type = self.context.ptr_type(inkwell::AddressSpace::default());
                         let i32_type = self.context.i32_type();
 
-                        let table_size = self.table_sizes[*table_index as usize];
+                        let vmctx = self.current_vmctx();
+                        let layout = self.layout();
+                        let table_base = self
+                            .load_vmctx_field(
+                                vmctx,
+                                layout.table_base(*table_index),
+                                ptr_type.into(),
+                                "table_base",
+                            )
+                            .into_pointer_value();
+                        let table_size = self
+                            .load_vmctx_field(
+                                vmctx,
+                                layout.table_size(*table_index),
+                                i32_type.into(),
+                                "table_size",
+                            )
+                            .into_int_value();
                         let bounds_check = self
                             .builder
                             .build_int_compare(
                                 IntPredicate::ULT,
                                 func_idx,
-                                i32_type.const_int(table_size as u64, false),
+                                table_size,
                                 "bounds_check",
                             )
                             .unwrap();
@@ -1161,46 +2474,76 @@ impl<'ctx> Compiler<'ctx> {
                             .unwrap();
 
                         self.builder.position_at_end(trap_block);
-                        self.builder.build_unreachable().unwrap();
+                        self.build_trap(Trap::BadIndirectCall);
 
                         self.builder.position_at_end(valid_block);
 
-                        let table_ptr = table.as_pointer_value();
-                        let func_ptr_ptr = unsafe {
+                        let func_ref_ptr = unsafe {
                             self.builder
-                                .build_gep(
-                                    ptr_type.array_type(table_size),
-                                    table_ptr,
-                                    &[i32_type.const_zero(), func_idx],
-                                    "func_ptr_ptr",
-                                )
+                                .build_gep(ptr_type, table_base, &[func_idx], "func_ref_ptr")
                                 .unwrap()
                         };
 
-                        let func_ptr = self
+                        let func_ref = self
                             .builder
-                            .build_load(ptr_type, func_ptr_ptr, "func_ptr")
+                            .build_load(ptr_type, func_ref_ptr, "func_ref")
                             .unwrap()
                             .into_pointer_value();
 
                         let null_check =
-                            self.builder.build_is_null(func_ptr, "null_check").unwrap();
+                            self.builder.build_is_null(func_ref, "null_check").unwrap();
+                        let type_check_block =
+                            self.context.append_basic_block(llvm_func, "check_type");
                         let call_block = self.context.append_basic_block(llvm_func, "do_call");
 
                         self.builder
-                            .build_conditional_branch(null_check, trap_block, call_block)
+                            .build_conditional_branch(null_check, trap_block, type_check_block)
+                            .unwrap();
+
+                        self.builder.position_at_end(type_check_block);
+                        let func_ref_type = self.func_ref_type();
+                        let signature_ptr = self
+                            .builder
+                            .build_struct_gep(func_ref_type, func_ref, 1, "signature_ptr")
+                            .unwrap();
+                        let signature = self
+                            .builder
+                            .build_load(i32_type, signature_ptr, "signature")
+                            .unwrap()
+                            .into_int_value();
+                        let expected = Self::signature_id(function_types, func_type);
+                        let signature_check = self
+                            .builder
+                            .build_int_compare(
+                                IntPredicate::EQ,
+                                signature,
+                                i32_type.const_int(expected as u64, false),
+                                "signature_check",
+                            )
+                            .unwrap();
+                        self.builder
+                            .build_conditional_branch(signature_check, call_block, trap_block)
                             .unwrap();
 
                         self.builder.position_at_end(call_block);
+                        let func_ptr = self
+                            .builder
+                            .build_load(ptr_type, func_ref, "func_ptr")
+                            .unwrap()
+                            .into_pointer_value();
 
                         let mut args = Vec::new();
                         for param_type in func_type.params().iter().rev() {
-                            let arg = Self::pop_single_value(&mut value_stack)?;
                             let converted_arg = match param_type {
-                                ValType::I32 => arg.into_int_value().into(),
-                                ValType::I64 => arg.into_int_value().into(),
-                                ValType::F32 => arg.into_float_value().into(),
-                                ValType::F64 => arg.into_float_value().into(),
+                                ValType::I32 | ValType::I64 => {
+                                    Self::pop_int_value(&mut value_stack)?.into()
+                                }
+                                ValType::F32 | ValType::F64 => {
+                                    Self::pop_float_value(&mut value_stack)?.into()
+                                }
+                                ValType::Ref(_) => {
+                                    Self::pop_pointer_value(&mut value_stack)?.into()
+                                }
                                 _ => {
                                     return Err(anyhow!(
                                         "Unsupported parameter type: {param_type:?}"
@@ -1209,6 +2552,7 @@ impl<'ctx> Compiler<'ctx> {
                             };
                             args.push(converted_arg);
                         }
+                        args.push(vmctx.into());
                         args.reverse();
 
                         let call_type = self.create_llvm_function_type(func_type);
@@ -1216,11 +2560,12 @@ impl<'ctx> Compiler<'ctx> {
                             .builder
                             .build_indirect_call(call_type, func_ptr, &args, "indirect_call")
                             .unwrap();
+                        self.build_trap_propagation();
 
-                        if !func_type.results().is_empty() {
-                            if let Some(result) = call_result.try_as_basic_value().left() {
-                                value_stack.push(result);
-                            }
+                        if !func_type.results().is_empty()
+                            && let Some(result) = call_result.try_as_basic_value().left()
+                        {
+                            value_stack.push(result);
                         }
 
                         self.builder
@@ -1230,10 +2575,7 @@ impl<'ctx> Compiler<'ctx> {
                     }
                 }
                 Operator::If { .. } => {
-                    let condition = value_stack
-                        .pop()
-                        .ok_or(anyhow!("Stack underflow for if condition"))?
-                        .into_int_value();
+                    let condition = Self::pop_int_value(&mut value_stack)?;
 
                     let then_block = self.context.append_basic_block(llvm_func, "if_then");
                     let else_block = self.context.append_basic_block(llvm_func, "if_else");
@@ -1258,14 +2600,14 @@ impl<'ctx> Compiler<'ctx> {
                     self.builder.position_at_end(then_block);
                 }
                 Operator::Else => {
-                    if let Some(control_block) = control_stack.last() {
-                        if matches!(control_block.block_type, ControlBlockType::If) {
-                            self.builder
-                                .build_unconditional_branch(control_block.end_block)
-                                .unwrap();
-                            if let Some(else_block) = control_block.continue_block {
-                                self.builder.position_at_end(else_block);
-                            }
+                    if let Some(control_block) = control_stack.last()
+                        && matches!(control_block.block_type, ControlBlockType::If)
+                    {
+                        self.builder
+                            .build_unconditional_branch(control_block.end_block)
+                            .unwrap();
+                        if let Some(else_block) = control_block.continue_block {
+                            self.builder.position_at_end(else_block);
                         }
                     }
                 }
@@ -1290,6 +2632,9 @@ impl<'ctx> Compiler<'ctx> {
                         .build_unconditional_branch(loop_header)
                         .unwrap();
                     self.builder.position_at_end(loop_header);
+                    self.build_limit_checks();
+                    loops += 1;
+                    self.build_profile_count(profile_counters, loops);
 
                     control_stack.push(ControlBlock {
                         block_type: ControlBlockType::Loop,
@@ -1311,7 +2656,7 @@ impl<'ctx> Compiler<'ctx> {
                     }
                 }
                 Operator::BrIf { relative_depth } => {
-                    let condition = Self::pop_single_value(&mut value_stack)?.into_int_value();
+                    let condition = Self::pop_int_value(&mut value_stack)?;
 
                     if let Some(branch_target) =
                         self.get_branch_target(&control_stack, *relative_depth)
@@ -1333,7 +2678,7 @@ impl<'ctx> Compiler<'ctx> {
                     }
                 }
                 Operator::I32WrapI64 => {
-                    let value = Self::pop_single_value(&mut value_stack)?.into_int_value();
+                    let value = Self::pop_int_value(&mut value_stack)?;
                     let result = self
                         .builder
                         .build_int_truncate(value, self.context.i32_type(), "wrap_i64")
@@ -1341,7 +2686,7 @@ impl<'ctx> Compiler<'ctx> {
                     value_stack.push(result.into());
                 }
                 Operator::I64ExtendI32S => {
-                    let value = Self::pop_single_value(&mut value_stack)?.into_int_value();
+                    let value = Self::pop_int_value(&mut value_stack)?;
                     let result = self
                         .builder
                         .build_int_s_extend(value, self.context.i64_type(), "extend_i32_s")
@@ -1349,7 +2694,7 @@ impl<'ctx> Compiler<'ctx> {
                     value_stack.push(result.into());
                 }
                 Operator::I64ExtendI32U => {
-                    let value = Self::pop_single_value(&mut value_stack)?.into_int_value();
+                    let value = Self::pop_int_value(&mut value_stack)?;
                     let result = self
                         .builder
                         .build_int_z_extend(value, self.context.i64_type(), "extend_i32_u")
@@ -1357,7 +2702,7 @@ impl<'ctx> Compiler<'ctx> {
                     value_stack.push(result.into());
                 }
                 Operator::F32ConvertI32S => {
-                    let value = Self::pop_single_value(&mut value_stack)?.into_int_value();
+                    let value = Self::pop_int_value(&mut value_stack)?;
                     let result = self
                         .builder
                         .build_signed_int_to_float(value, self.context.f32_type(), "convert_i32_s")
@@ -1365,7 +2710,7 @@ impl<'ctx> Compiler<'ctx> {
                     value_stack.push(result.into());
                 }
                 Operator::F32ConvertI32U => {
-                    let value = Self::pop_single_value(&mut value_stack)?.into_int_value();
+                    let value = Self::pop_int_value(&mut value_stack)?;
                     let result = self
                         .builder
                         .build_unsigned_int_to_float(
@@ -1377,7 +2722,7 @@ impl<'ctx> Compiler<'ctx> {
                     value_stack.push(result.into());
                 }
                 Operator::F64ConvertI32S => {
-                    let value = Self::pop_single_value(&mut value_stack)?.into_int_value();
+                    let value = Self::pop_int_value(&mut value_stack)?;
                     let result = self
                         .builder
                         .build_signed_int_to_float(value, self.context.f64_type(), "convert_i32_s")
@@ -1385,7 +2730,7 @@ impl<'ctx> Compiler<'ctx> {
                     value_stack.push(result.into());
                 }
                 Operator::F64ConvertI32U => {
-                    let value = Self::pop_single_value(&mut value_stack)?.into_int_value();
+                    let value = Self::pop_int_value(&mut value_stack)?;
                     let result = self
                         .builder
                         .build_unsigned_int_to_float(
@@ -1396,40 +2741,176 @@ impl<'ctx> Compiler<'ctx> {
                         .unwrap();
                     value_stack.push(result.into());
                 }
-                Operator::I32TruncF32S => {
-                    let value = Self::pop_single_value(&mut value_stack)?.into_float_value();
+                Operator::F32ConvertI64S => {
+                    let value = Self::pop_int_value(&mut value_stack)?;
                     let result = self
                         .builder
-                        .build_float_to_signed_int(value, self.context.i32_type(), "trunc_f32_s")
+                        .build_signed_int_to_float(value, self.context.f32_type(), "convert_i64_s")
                         .unwrap();
                     value_stack.push(result.into());
                 }
-                Operator::I32TruncF32U => {
-                    let value = Self::pop_single_value(&mut value_stack)?.into_float_value();
+                Operator::F32ConvertI64U => {
+                    let value = Self::pop_int_value(&mut value_stack)?;
                     let result = self
                         .builder
-                        .build_float_to_unsigned_int(value, self.context.i32_type(), "trunc_f32_u")
+                        .build_unsigned_int_to_float(
+                            value,
+                            self.context.f32_type(),
+                            "convert_i64_u",
+                        )
                         .unwrap();
                     value_stack.push(result.into());
                 }
-                Operator::I32TruncF64S => {
-                    let value = Self::pop_single_value(&mut value_stack)?.into_float_value();
+                Operator::F64ConvertI64S => {
+                    let value = Self::pop_int_value(&mut value_stack)?;
                     let result = self
                         .builder
-                        .build_float_to_signed_int(value, self.context.i32_type(), "trunc_f64_s")
+                        .build_signed_int_to_float(value, self.context.f64_type(), "convert_i64_s")
                         .unwrap();
                     value_stack.push(result.into());
                 }
-                Operator::I32TruncF64U => {
-                    let value = Self::pop_single_value(&mut value_stack)?.into_float_value();
+                Operator::F64ConvertI64U => {
+                    let value = Self::pop_int_value(&mut value_stack)?;
                     let result = self
                         .builder
-                        .build_float_to_unsigned_int(value, self.context.i32_type(), "trunc_f64_u")
+                        .build_unsigned_int_to_float(
+                            value,
+                            self.context.f64_type(),
+                            "convert_i64_u",
+                        )
                         .unwrap();
                     value_stack.push(result.into());
                 }
+                Operator::I32TruncF32S => {
+                    self.build_trunc(
+                        &mut value_stack,
+                        self.context.i32_type(),
+                        true,
+                        "trunc_f32_s",
+                    )?;
+                }
+                Operator::I32TruncF32U => {
+                    self.build_trunc(
+                        &mut value_stack,
+                        self.context.i32_type(),
+                        false,
+                        "trunc_f32_u",
+                    )?;
+                }
+                Operator::I32TruncF64S => {
+                    self.build_trunc(
+                        &mut value_stack,
+                        self.context.i32_type(),
+                        true,
+                        "trunc_f64_s",
+                    )?;
+                }
+                Operator::I32TruncF64U => {
+                    self.build_trunc(
+                        &mut value_stack,
+                        self.context.i32_type(),
+                        false,
+                        "trunc_f64_u",
+                    )?;
+                }
+                Operator::I64TruncF32S => {
+                    self.build_trunc(
+                        &mut value_stack,
+                        self.context.i64_type(),
+                        true,
+                        "trunc_f32_s",
+                    )?;
+                }
+                Operator::I64TruncF32U => {
+                    self.build_trunc(
+                        &mut value_stack,
+                        self.context.i64_type(),
+                        false,
+                        "trunc_f32_u",
+                    )?;
+                }
+                Operator::I64TruncF64S => {
+                    self.build_trunc(
+                        &mut value_stack,
+                        self.context.i64_type(),
+                        true,
+                        "trunc_f64_s",
+                    )?;
+                }
+                Operator::I64TruncF64U => {
+                    self.build_trunc(
+                        &mut value_stack,
+                        self.context.i64_type(),
+                        false,
+                        "trunc_f64_u",
+                    )?;
+                }
+                Operator::I32TruncSatF32S => {
+                    self.build_trunc_sat(
+                        &mut value_stack,
+                        self.context.i32_type(),
+                        true,
+                        "trunc_sat_f32_s",
+                    )?;
+                }
+                Operator::I32TruncSatF32U => {
+                    self.build_trunc_sat(
+                        &mut value_stack,
+                        self.context.i32_type(),
+                        false,
+                        "trunc_sat_f32_u",
+                    )?;
+                }
+                Operator::I32TruncSatF64S => {
+                    self.build_trunc_sat(
+                        &mut value_stack,
+                        self.context.i32_type(),
+                        true,
+                        "trunc_sat_f64_s",
+                    )?;
+                }
+                Operator::I32TruncSatF64U => {
+                    self.build_trunc_sat(
+                        &mut value_stack,
+                        self.context.i32_type(),
+                        false,
+                        "trunc_sat_f64_u",
+                    )?;
+                }
+                Operator::I64TruncSatF32S => {
+                    self.build_trunc_sat(
+                        &mut value_stack,
+                        self.context.i64_type(),
+                        true,
+                        "trunc_sat_f32_s",
+                    )?;
+                }
+                Operator::I64TruncSatF32U => {
+                    self.build_trunc_sat(
+                        &mut value_stack,
+                        self.context.i64_type(),
+                        false,
+                        "trunc_sat_f32_u",
+                    )?;
+                }
+                Operator::I64TruncSatF64S => {
+                    self.build_trunc_sat(
+                        &mut value_stack,
+                        self.context.i64_type(),
+                        true,
+                        "trunc_sat_f64_s",
+                    )?;
+                }
+                Operator::I64TruncSatF64U => {
+                    self.build_trunc_sat(
+                        &mut value_stack,
+                        self.context.i64_type(),
+                        false,
+                        "trunc_sat_f64_u",
+                    )?;
+                }
                 Operator::F64PromoteF32 => {
-                    let value = Self::pop_single_value(&mut value_stack)?.into_float_value();
+                    let value = Self::pop_float_value(&mut value_stack)?;
                     let result = self
                         .builder
                         .build_float_ext(value, self.context.f64_type(), "promote_f32")
@@ -1437,13 +2918,25 @@ impl<'ctx> Compiler<'ctx> {
                     value_stack.push(result.into());
                 }
                 Operator::F32DemoteF64 => {
-                    let value = Self::pop_single_value(&mut value_stack)?.into_float_value();
+                    let value = Self::pop_float_value(&mut value_stack)?;
                     let result = self
                         .builder
                         .build_float_trunc(value, self.context.f32_type(), "demote_f64")
                         .unwrap();
                     value_stack.push(result.into());
                 }
+                Operator::I32ReinterpretF32 => {
+                    self.build_reinterpret(&mut value_stack, self.context.i32_type().into())?;
+                }
+                Operator::I64ReinterpretF64 => {
+                    self.build_reinterpret(&mut value_stack, self.context.i64_type().into())?;
+                }
+                Operator::F32ReinterpretI32 => {
+                    self.build_reinterpret(&mut value_stack, self.context.f32_type().into())?;
+                }
+                Operator::F64ReinterpretI64 => {
+                    self.build_reinterpret(&mut value_stack, self.context.f64_type().into())?;
+                }
                 Operator::End => {
                     if let Some(control_block) = control_stack.pop() {
                         match control_block.block_type {
@@ -1451,6 +2944,16 @@ impl<'ctx> Compiler<'ctx> {
                                 self.builder
                                     .build_unconditional_branch(control_block.end_block)
                                     .unwrap();
+                                // Without an `else`, the false edge goes
+                                // straight to the end.
+                                if let Some(else_block) = control_block.continue_block
+                                    && else_block.get_terminator().is_none()
+                                {
+                                    self.builder.position_at_end(else_block);
+                                    self.builder
+                                        .build_unconditional_branch(control_block.end_block)
+                                        .unwrap();
+                                }
                                 self.builder.position_at_end(control_block.end_block);
                             }
                             ControlBlockType::Block | ControlBlockType::Loop => {
@@ -1462,13 +2965,16 @@ impl<'ctx> Compiler<'ctx> {
                         }
                     } else if function.func_type.results().is_empty() {
                         self.builder.build_return(None).unwrap();
-                    } else {
-                        let return_val = Self::pop_single_value(&mut value_stack)?;
+                    } else if let Some(return_val) = value_stack.pop() {
                         self.builder.build_return(Some(&return_val)).unwrap();
+                    } else {
+                        // Only code after a branch, return or trap, which is
+                        // never reached, ends with an empty stack.
+                        self.builder.build_unreachable().unwrap();
                     }
                 }
-                Operator::Select => {
-                    let condition = Self::pop_single_value(&mut value_stack)?.into_int_value();
+                Operator::Select | Operator::TypedSelect { .. } => {
+                    let condition = Self::pop_int_value(&mut value_stack)?;
                     let val2 = Self::pop_single_value(&mut value_stack)?;
                     let val1 = Self::pop_single_value(&mut value_stack)?;
 
@@ -1484,12 +2990,12 @@ impl<'ctx> Compiler<'ctx> {
 
                     let result = self
                         .builder
-                        .build_select(condition_bool, val2, val1, "select")
+                        .build_select(condition_bool, val1, val2, "select")
                         .unwrap();
                     value_stack.push(result);
                 }
                 Operator::I32Clz => {
-                    let value = Self::pop_single_value(&mut value_stack)?.into_int_value();
+                    let value = Self::pop_int_value(&mut value_stack)?;
                     let clz_fn = self.get_intrinsic_function(
                         "llvm.ctlz.i32",
                         &[
@@ -1509,7 +3015,7 @@ impl<'ctx> Compiler<'ctx> {
                     value_stack.push(result.try_as_basic_value().left().unwrap());
                 }
                 Operator::I32Ctz => {
-                    let value = Self::pop_single_value(&mut value_stack)?.into_int_value();
+                    let value = Self::pop_int_value(&mut value_stack)?;
                     let ctz_fn = self.get_intrinsic_function(
                         "llvm.cttz.i32",
                         &[
@@ -1529,7 +3035,7 @@ impl<'ctx> Compiler<'ctx> {
                     value_stack.push(result.try_as_basic_value().left().unwrap());
                 }
                 Operator::I32Popcnt => {
-                    let value = Self::pop_single_value(&mut value_stack)?.into_int_value();
+                    let value = Self::pop_int_value(&mut value_stack)?;
                     let popcnt_fn = self.get_intrinsic_function(
                         "llvm.ctpop.i32",
                         &[self.context.i32_type().into()],
@@ -1542,7 +3048,7 @@ impl<'ctx> Compiler<'ctx> {
                     value_stack.push(result.try_as_basic_value().left().unwrap());
                 }
                 Operator::I64Clz => {
-                    let value = Self::pop_single_value(&mut value_stack)?.into_int_value();
+                    let value = Self::pop_int_value(&mut value_stack)?;
                     let clz_fn = self.get_intrinsic_function(
                         "llvm.ctlz.i64",
                         &[
@@ -1562,7 +3068,7 @@ impl<'ctx> Compiler<'ctx> {
                     value_stack.push(result.try_as_basic_value().left().unwrap());
                 }
                 Operator::I64Ctz => {
-                    let value = Self::pop_single_value(&mut value_stack)?.into_int_value();
+                    let value = Self::pop_int_value(&mut value_stack)?;
                     let ctz_fn = self.get_intrinsic_function(
                         "llvm.cttz.i64",
                         &[
@@ -1582,7 +3088,7 @@ impl<'ctx> Compiler<'ctx> {
                     value_stack.push(result.try_as_basic_value().left().unwrap());
                 }
                 Operator::I64Popcnt => {
-                    let value = Self::pop_single_value(&mut value_stack)?.into_int_value();
+                    let value = Self::pop_int_value(&mut value_stack)?;
                     let popcnt_fn = self.get_intrinsic_function(
                         "llvm.ctpop.i64",
                         &[self.context.i64_type().into()],
@@ -1595,7 +3101,7 @@ impl<'ctx> Compiler<'ctx> {
                     value_stack.push(result.try_as_basic_value().left().unwrap());
                 }
                 Operator::F32Abs => {
-                    let value = Self::pop_single_value(&mut value_stack)?.into_float_value();
+                    let value = Self::pop_float_value(&mut value_stack)?;
                     let abs_fn = self.get_intrinsic_function(
                         "llvm.fabs.f32",
                         &[self.context.f32_type().into()],
@@ -1608,12 +3114,12 @@ impl<'ctx> Compiler<'ctx> {
                     value_stack.push(result.try_as_basic_value().left().unwrap());
                 }
                 Operator::F32Neg => {
-                    let value = Self::pop_single_value(&mut value_stack)?.into_float_value();
+                    let value = Self::pop_float_value(&mut value_stack)?;
                     let result = self.builder.build_float_neg(value, "neg").unwrap();
                     value_stack.push(result.into());
                 }
                 Operator::F32Sqrt => {
-                    let value = Self::pop_single_value(&mut value_stack)?.into_float_value();
+                    let value = Self::pop_float_value(&mut value_stack)?;
                     let sqrt_fn = self.get_intrinsic_function(
                         "llvm.sqrt.f32",
                         &[self.context.f32_type().into()],
@@ -1626,7 +3132,7 @@ impl<'ctx> Compiler<'ctx> {
                     value_stack.push(result.try_as_basic_value().left().unwrap());
                 }
                 Operator::F32Ceil => {
-                    let value = Self::pop_single_value(&mut value_stack)?.into_float_value();
+                    let value = Self::pop_float_value(&mut value_stack)?;
                     let ceil_fn = self.get_intrinsic_function(
                         "llvm.ceil.f32",
                         &[self.context.f32_type().into()],
@@ -1639,7 +3145,7 @@ impl<'ctx> Compiler<'ctx> {
                     value_stack.push(result.try_as_basic_value().left().unwrap());
                 }
                 Operator::F32Floor => {
-                    let value = Self::pop_single_value(&mut value_stack)?.into_float_value();
+                    let value = Self::pop_float_value(&mut value_stack)?;
                     let floor_fn = self.get_intrinsic_function(
                         "llvm.floor.f32",
                         &[self.context.f32_type().into()],
@@ -1652,7 +3158,7 @@ impl<'ctx> Compiler<'ctx> {
                     value_stack.push(result.try_as_basic_value().left().unwrap());
                 }
                 Operator::F32Trunc => {
-                    let value = Self::pop_single_value(&mut value_stack)?.into_float_value();
+                    let value = Self::pop_float_value(&mut value_stack)?;
                     let trunc_fn = self.get_intrinsic_function(
                         "llvm.trunc.f32",
                         &[self.context.f32_type().into()],
@@ -1665,7 +3171,7 @@ impl<'ctx> Compiler<'ctx> {
                     value_stack.push(result.try_as_basic_value().left().unwrap());
                 }
                 Operator::F32Nearest => {
-                    let value = Self::pop_single_value(&mut value_stack)?.into_float_value();
+                    let value = Self::pop_float_value(&mut value_stack)?;
                     let nearbyint_fn = self.get_intrinsic_function(
                         "llvm.nearbyint.f32",
                         &[self.context.f32_type().into()],
@@ -1678,8 +3184,8 @@ impl<'ctx> Compiler<'ctx> {
                     value_stack.push(result.try_as_basic_value().left().unwrap());
                 }
                 Operator::F32Min => {
-                    let rhs = Self::pop_single_value(&mut value_stack)?.into_float_value();
-                    let lhs = Self::pop_single_value(&mut value_stack)?.into_float_value();
+                    let rhs = Self::pop_float_value(&mut value_stack)?;
+                    let lhs = Self::pop_float_value(&mut value_stack)?;
                     let minnum_fn = self.get_intrinsic_function(
                         "llvm.minnum.f32",
                         &[
@@ -1695,8 +3201,8 @@ impl<'ctx> Compiler<'ctx> {
                     value_stack.push(result.try_as_basic_value().left().unwrap());
                 }
                 Operator::F32Max => {
-                    let rhs = Self::pop_single_value(&mut value_stack)?.into_float_value();
-                    let lhs = Self::pop_single_value(&mut value_stack)?.into_float_value();
+                    let rhs = Self::pop_float_value(&mut value_stack)?;
+                    let lhs = Self::pop_float_value(&mut value_stack)?;
                     let maxnum_fn = self.get_intrinsic_function(
                         "llvm.maxnum.f32",
                         &[
@@ -1712,8 +3218,8 @@ impl<'ctx> Compiler<'ctx> {
                     value_stack.push(result.try_as_basic_value().left().unwrap());
                 }
                 Operator::F32Copysign => {
-                    let rhs = Self::pop_single_value(&mut value_stack)?.into_float_value();
-                    let lhs = Self::pop_single_value(&mut value_stack)?.into_float_value();
+                    let rhs = Self::pop_float_value(&mut value_stack)?;
+                    let lhs = Self::pop_float_value(&mut value_stack)?;
                     let copysign_fn = self.get_intrinsic_function(
                         "llvm.copysign.f32",
                         &[
@@ -1729,7 +3235,7 @@ impl<'ctx> Compiler<'ctx> {
                     value_stack.push(result.try_as_basic_value().left().unwrap());
                 }
                 Operator::F64Abs => {
-                    let value = Self::pop_single_value(&mut value_stack)?.into_float_value();
+                    let value = Self::pop_float_value(&mut value_stack)?;
                     let abs_fn = self.get_intrinsic_function(
                         "llvm.fabs.f64",
                         &[self.context.f64_type().into()],
@@ -1742,12 +3248,12 @@ impl<'ctx> Compiler<'ctx> {
                     value_stack.push(result.try_as_basic_value().left().unwrap());
                 }
                 Operator::F64Neg => {
-                    let value = Self::pop_single_value(&mut value_stack)?.into_float_value();
+                    let value = Self::pop_float_value(&mut value_stack)?;
                     let result = self.builder.build_float_neg(value, "neg64").unwrap();
                     value_stack.push(result.into());
                 }
                 Operator::F64Sqrt => {
-                    let value = Self::pop_single_value(&mut value_stack)?.into_float_value();
+                    let value = Self::pop_float_value(&mut value_stack)?;
                     let sqrt_fn = self.get_intrinsic_function(
                         "llvm.sqrt.f64",
                         &[self.context.f64_type().into()],
@@ -1760,7 +3266,7 @@ impl<'ctx> Compiler<'ctx> {
                     value_stack.push(result.try_as_basic_value().left().unwrap());
                 }
                 Operator::F64Ceil => {
-                    let value = Self::pop_single_value(&mut value_stack)?.into_float_value();
+                    let value = Self::pop_float_value(&mut value_stack)?;
                     let ceil_fn = self.get_intrinsic_function(
                         "llvm.ceil.f64",
                         &[self.context.f64_type().into()],
@@ -1773,7 +3279,7 @@ impl<'ctx> Compiler<'ctx> {
                     value_stack.push(result.try_as_basic_value().left().unwrap());
                 }
                 Operator::F64Floor => {
-                    let value = Self::pop_single_value(&mut value_stack)?.into_float_value();
+                    let value = Self::pop_float_value(&mut value_stack)?;
                     let floor_fn = self.get_intrinsic_function(
                         "llvm.floor.f64",
                         &[self.context.f64_type().into()],
@@ -1786,7 +3292,7 @@ impl<'ctx> Compiler<'ctx> {
                     value_stack.push(result.try_as_basic_value().left().unwrap());
                 }
                 Operator::F64Trunc => {
-                    let value = Self::pop_single_value(&mut value_stack)?.into_float_value();
+                    let value = Self::pop_float_value(&mut value_stack)?;
                     let trunc_fn = self.get_intrinsic_function(
                         "llvm.trunc.f64",
                         &[self.context.f64_type().into()],
@@ -1799,7 +3305,7 @@ impl<'ctx> Compiler<'ctx> {
                     value_stack.push(result.try_as_basic_value().left().unwrap());
                 }
                 Operator::F64Nearest => {
-                    let value = Self::pop_single_value(&mut value_stack)?.into_float_value();
+                    let value = Self::pop_float_value(&mut value_stack)?;
                     let nearbyint_fn = self.get_intrinsic_function(
                         "llvm.nearbyint.f64",
                         &[self.context.f64_type().into()],
@@ -1812,8 +3318,8 @@ impl<'ctx> Compiler<'ctx> {
                     value_stack.push(result.try_as_basic_value().left().unwrap());
                 }
                 Operator::F64Min => {
-                    let rhs = Self::pop_single_value(&mut value_stack)?.into_float_value();
-                    let lhs = Self::pop_single_value(&mut value_stack)?.into_float_value();
+                    let rhs = Self::pop_float_value(&mut value_stack)?;
+                    let lhs = Self::pop_float_value(&mut value_stack)?;
                     let minnum_fn = self.get_intrinsic_function(
                         "llvm.minnum.f64",
                         &[
@@ -1829,8 +3335,8 @@ impl<'ctx> Compiler<'ctx> {
                     value_stack.push(result.try_as_basic_value().left().unwrap());
                 }
                 Operator::F64Max => {
-                    let rhs = Self::pop_single_value(&mut value_stack)?.into_float_value();
-                    let lhs = Self::pop_single_value(&mut value_stack)?.into_float_value();
+                    let rhs = Self::pop_float_value(&mut value_stack)?;
+                    let lhs = Self::pop_float_value(&mut value_stack)?;
                     let maxnum_fn = self.get_intrinsic_function(
                         "llvm.maxnum.f64",
                         &[
@@ -1846,8 +3352,8 @@ impl<'ctx> Compiler<'ctx> {
                     value_stack.push(result.try_as_basic_value().left().unwrap());
                 }
                 Operator::F64Copysign => {
-                    let rhs = Self::pop_single_value(&mut value_stack)?.into_float_value();
-                    let lhs = Self::pop_single_value(&mut value_stack)?.into_float_value();
+                    let rhs = Self::pop_float_value(&mut value_stack)?;
+                    let lhs = Self::pop_float_value(&mut value_stack)?;
                     let copysign_fn = self.get_intrinsic_function(
                         "llvm.copysign.f64",
                         &[
@@ -1863,16 +3369,16 @@ impl<'ctx> Compiler<'ctx> {
                     value_stack.push(result.try_as_basic_value().left().unwrap());
                 }
                 Operator::MemoryCopy { .. } => {
-                    let size = Self::pop_single_value(&mut value_stack)?.into_int_value();
-                    let src = Self::pop_single_value(&mut value_stack)?.into_int_value();
-                    let dest = Self::pop_single_value(&mut value_stack)?.into_int_value();
+                    let size = Self::pop_int_value(&mut value_stack)?;
+                    let src = Self::pop_int_value(&mut value_stack)?;
+                    let dest = Self::pop_int_value(&mut value_stack)?;
 
                     self.build_memory_copy(dest, src, size)?;
                 }
                 Operator::MemoryFill { .. } => {
-                    let size = Self::pop_single_value(&mut value_stack)?.into_int_value();
-                    let value = Self::pop_single_value(&mut value_stack)?.into_int_value();
-                    let dest = Self::pop_single_value(&mut value_stack)?.into_int_value();
+                    let size = Self::pop_int_value(&mut value_stack)?;
+                    let value = Self::pop_int_value(&mut value_stack)?;
+                    let dest = Self::pop_int_value(&mut value_stack)?;
 
                     self.build_memory_fill(dest, value, size)?;
                 }
@@ -1881,15 +3387,19 @@ impl<'ctx> Compiler<'ctx> {
                         .context
                         .ptr_type(inkwell::AddressSpace::default())
                         .const_null();
-                    let alloca = self
-                        .builder
-                        .build_alloca(null_ptr.get_type(), "ref_slot")
-                        .unwrap();
-                    self.builder.build_store(alloca, null_ptr).unwrap();
                     value_stack.push(null_ptr.into());
                 }
+                Operator::RefFunc { function_index } => {
+                    let func_ref = self
+                        .function_reference(wasm_module, *function_index)
+                        .ok_or(anyhow!(
+                            "Unsupported reference to imported function {}",
+                            function_index
+                        ))?;
+                    value_stack.push(func_ref.into());
+                }
                 Operator::RefIsNull => {
-                    let value = Self::pop_single_value(&mut value_stack)?.into_pointer_value();
+                    let value = Self::pop_pointer_value(&mut value_stack)?;
                     let null_ptr = value.get_type().const_null();
                     let result = self
                         .builder
@@ -1899,15 +3409,110 @@ impl<'ctx> Compiler<'ctx> {
                         .builder
                         .build_int_z_extend(result, self.context.i32_type(), "ref_is_null_ext")
                         .unwrap();
-                    let alloca = self
+                    value_stack.push(extended.into());
+                }
+                Operator::TableGet { table } => {
+                    let index = Self::pop_int_value(&mut value_stack)?;
+                    let (base, size) = self.load_table(*table)?;
+                    self.build_index_check(index, size);
+                    let elem_ptr = self.table_element_ptr(base, index);
+                    let ptr_type = self.context.ptr_type(inkwell::AddressSpace::default());
+                    let value = self
                         .builder
-                        .build_alloca(self.context.i32_type(), "result_slot")
+                        .build_load(ptr_type, elem_ptr, "table_get")
                         .unwrap();
-                    self.builder.build_store(alloca, extended).unwrap();
-                    value_stack.push(extended.into());
+                    value_stack.push(value);
+                }
+                Operator::TableSet { table } => {
+                    let value = Self::pop_pointer_value(&mut value_stack)?;
+                    let index = Self::pop_int_value(&mut value_stack)?;
+                    let (base, size) = self.load_table(*table)?;
+                    self.build_index_check(index, size);
+                    let elem_ptr = self.table_element_ptr(base, index);
+                    self.builder.build_store(elem_ptr, value).unwrap();
+                }
+                Operator::TableSize { table } => {
+                    let (_, size) = self.load_table(*table)?;
+                    value_stack.push(size.into());
+                }
+                Operator::TableGrow { table } => {
+                    let delta = Self::pop_int_value(&mut value_stack)?;
+                    let init = Self::pop_pointer_value(&mut value_stack)?;
+                    let old_size = self.build_table_grow(*table, init, delta)?;
+                    value_stack.push(old_size.into());
+                }
+                Operator::TableFill { table } => {
+                    let count = Self::pop_int_value(&mut value_stack)?;
+                    let value = Self::pop_pointer_value(&mut value_stack)?;
+                    let start = Self::pop_int_value(&mut value_stack)?;
+                    let (base, size) = self.load_table(*table)?;
+                    self.build_range_check(start, count, size);
+                    let end = self
+                        .builder
+                        .build_int_add(start, count, "fill_end")
+                        .unwrap();
+                    self.build_table_fill(base, start, end, value);
+                }
+                Operator::TableCopy {
+                    dst_table,
+                    src_table,
+                } => {
+                    let count = Self::pop_int_value(&mut value_stack)?;
+                    let src = Self::pop_int_value(&mut value_stack)?;
+                    let dest = Self::pop_int_value(&mut value_stack)?;
+                    let (dest_base, dest_size) = self.load_table(*dst_table)?;
+                    let (src_base, src_size) = self.load_table(*src_table)?;
+                    self.build_range_check(src, count, src_size);
+                    self.build_range_check(dest, count, dest_size);
+                    self.build_table_move(
+                        self.table_element_ptr(dest_base, dest),
+                        self.table_element_ptr(src_base, src),
+                        count,
+                    );
+                }
+                Operator::TableInit { elem_index, table } => {
+                    let count = Self::pop_int_value(&mut value_stack)?;
+                    let src = Self::pop_int_value(&mut value_stack)?;
+                    let dest = Self::pop_int_value(&mut value_stack)?;
+                    let (base, size) = self.load_table(*table)?;
+                    let i32_type = self.context.i32_type();
+                    let passive_index = self.passive_segment_index(*elem_index)?;
+                    let segment_len = match passive_index {
+                        Some(passive_index) => self
+                            .load_vmctx_field(
+                                self.current_vmctx(),
+                                self.layout().element_segment(passive_index),
+                                i32_type.into(),
+                                "elem_len",
+                            )
+                            .into_int_value(),
+                        None => i32_type.const_zero(),
+                    };
+                    self.build_range_check(src, count, segment_len);
+                    self.build_range_check(dest, count, size);
+                    // Other segments are empty by now, so the checks leave
+                    // nothing to copy.
+                    if passive_index.is_some() {
+                        let items = self.element_segment_items(wasm_module, *elem_index)?;
+                        self.build_table_move(
+                            self.table_element_ptr(base, dest),
+                            self.table_element_ptr(items, src),
+                            count,
+                        );
+                    }
+                }
+                Operator::ElemDrop { elem_index } => {
+                    if let Some(passive_index) = self.passive_segment_index(*elem_index)? {
+                        self.store_vmctx_field(
+                            self.current_vmctx(),
+                            self.layout().element_segment(passive_index),
+                            self.context.i32_type().const_zero().into(),
+                            "elem_len",
+                        );
+                    }
                 }
                 Operator::I32Extend8S => {
-                    let value = Self::pop_single_value(&mut value_stack)?.into_int_value();
+                    let value = Self::pop_int_value(&mut value_stack)?;
                     let masked = self
                         .builder
                         .build_and(
@@ -1926,8 +3531,21 @@ impl<'ctx> Compiler<'ctx> {
                         .unwrap();
                     value_stack.push(extended.into());
                 }
+                Operator::I32Extend16S => {
+                    self.build_sign_extend_low(&mut value_stack, 16, "extend16s")?;
+                }
+                Operator::I64Extend8S => {
+                    self.build_sign_extend_low(&mut value_stack, 8, "extend8s")?;
+                }
+                Operator::I64Extend16S => {
+                    self.build_sign_extend_low(&mut value_stack, 16, "extend16s")?;
+                }
+                Operator::I64Extend32S => {
+                    self.build_sign_extend_low(&mut value_stack, 32, "extend32s")?;
+                }
+                Operator::Nop => {}
                 Operator::Unreachable => {
-                    self.builder.build_unreachable().unwrap();
+                    self.build_trap(Trap::Unreachable);
                     let unreachable_block = self
                         .context
                         .append_basic_block(llvm_func, "after_unreachable");
@@ -1940,103 +3558,920 @@ impl<'ctx> Compiler<'ctx> {
         Ok(llvm_func)
     }
 
-    fn create_main(&self, start_func_idx: u32) -> Result<()> {
-        let i32_type = self.context.i32_type();
-        let main_fn_type = i32_type.fn_type(&[], false);
-        let main_func = self.module.add_function("main", main_fn_type, None);
-
-        let entry_block = self.context.append_basic_block(main_func, "entry");
-        self.builder.position_at_end(entry_block);
+    fn function_symbol(&self, function: &Function) -> String {
+        if let Some(symbol) = self.function_symbols.get(&function.idx) {
+            return symbol.clone();
+        }
+        function
+            .name
+            .clone()
+            .unwrap_or_else(|| format!("func_{}", function.idx))
+    }
 
-        let start_func_name = format!("func_{start_func_idx}");
-        if let Some(start_func) = self.module.get_function(&start_func_name) {
-            self.builder
-                .build_call(start_func, &[], "call_start")
-                .unwrap();
-        } else {
-            let start_func_name = "_start";
-            if let Some(start_func) = self.module.get_function(start_func_name) {
-                self.builder
-                    .build_call(start_func, &[], "call_start")
-                    .unwrap();
+    /// Picks a symbol for every function with a name in the name section or,
+    /// failing that, an export. Symbols are prefixed with `$` as in the text
+    /// format, which keeps them apart from export wrappers and runtime
+    /// functions; the index disambiguates names that still collide.
+    fn function_symbols(wasm_module: &WasmModule) -> HashMap<u32, String> {
+        let mut taken: HashSet<String> = wasm_module
+            .exports
+            .iter()
+            .map(|export| export.name.clone())
+            .collect();
+        let mut symbols = HashMap::new();
+        for function in &wasm_module.functions {
+            let name = wasm_module.names.functions.get(&function.idx).or_else(|| {
+                wasm_module
+                    .exports
+                    .iter()
+                    .find(|e| e.kind == ExternalKind::Func && e.index == function.idx)
+                    .map(|e| &e.name)
+            });
+            let Some(name) = name else {
+                continue;
+            };
+            let mut symbol = format!("${}", mangle_name(name));
+            if !taken.insert(symbol.clone()) {
+                symbol = format!("{symbol}.{}", function.idx);
+                taken.insert(symbol.clone());
             }
+            symbols.insert(function.idx, symbol);
         }
+        symbols
+    }
 
-        let exit_code = self.context.i32_type().const_int(0, false);
-        self.builder.build_return(Some(&exit_code)).unwrap();
+    /// LLVM value name for a Wasm local or global, unless stable names were
+    /// requested.
+    fn value_name(&self, name: Option<&String>) -> Option<String> {
+        if self.options.stable_names {
+            return None;
+        }
+        name.map(|name| mangle_name(name))
+    }
 
-        Ok(())
+    /// Declares `function` ahead of compiling its body so that calls can refer
+    /// to functions defined later in the module. Wasm functions are internal;
+    /// only the export wrappers and instance functions are visible to the
+    /// linker.
+    fn declare_function(&self, function: &Function) -> Result<FunctionValue<'ctx>> {
+        if function.func_type.results().len() > 1 {
+            return Err(anyhow!("Multiple return values not supported"));
+        }
+        let fn_type = self.create_llvm_function_type(&function.func_type);
+        let llvm_func = self.module.add_function(
+            &self.function_symbol(function),
+            fn_type,
+            Some(self.local_linkage()),
+        );
+        llvm_func.get_first_param().unwrap().set_name("vmctx");
+        Ok(llvm_func)
     }
 
-    fn get_intrinsic_function(
+    /// Adds a function whose first parameter is the vmctx and positions the
+    /// builder at its entry block.
+    fn add_vmctx_function(
         &self,
         name: &str,
-        param_types: &[BasicMetadataTypeEnum<'ctx>],
-        return_type: BasicTypeEnum<'ctx>,
-    ) -> Result<FunctionValue<'ctx>> {
-        let fn_type = return_type.fn_type(param_types, false);
-        let intrinsic_fn = self.module.add_function(name, fn_type, None);
-        Ok(intrinsic_fn)
+        fn_type: inkwell::types::FunctionType<'ctx>,
+        linkage: Option<Linkage>,
+    ) -> FunctionValue<'ctx> {
+        let function = self.module.add_function(name, fn_type, linkage);
+        function.get_first_param().unwrap().set_name("vmctx");
+        let entry_block = self.context.append_basic_block(function, "entry");
+        self.builder.position_at_end(entry_block);
+        function
     }
 
-    fn get_void_intrinsic_function(
+    /// Returns the declaration of a C library function or LLVM intrinsic
+    /// used by the generated code, declaring it on first use.
+    fn get_runtime_function(
         &self,
         name: &str,
-        param_types: &[BasicMetadataTypeEnum<'ctx>],
-    ) -> Result<FunctionValue<'ctx>> {
-        let fn_type = self.context.void_type().fn_type(param_types, false);
-        let intrinsic_fn = self.module.add_function(name, fn_type, None);
-        Ok(intrinsic_fn)
+        fn_type: inkwell::types::FunctionType<'ctx>,
+    ) -> FunctionValue<'ctx> {
+        self.module
+            .get_function(name)
+            .unwrap_or_else(|| self.module.add_function(name, fn_type, None))
     }
 
-    fn build_memory_copy(
-        &self,
-        dest: IntValue<'ctx>,
-        src: IntValue<'ctx>,
-        size: IntValue<'ctx>,
-    ) -> Result<()> {
-        let memory = self.memory.ok_or(anyhow!("No memory allocated"))?;
-        let memory_ptr = memory.as_pointer_value();
+    pub(crate) fn layout(&self) -> VmctxLayout {
+        let passive_segments = self
+            .element_segments
+            .iter()
+            .filter(|segment| segment.kind == ElementSegmentKind::Passive)
+            .count();
+        VmctxLayout::new(
+            self.tables.len(),
+            self.globals.len(),
+            passive_segments,
+            self.traps_return().then_some(self.host_imports),
+        )
+        .with_limits(
+            self.options.consume_fuel,
+            self.options.interruptible,
+            self.options.check_stack,
+        )
+    }
 
-        let current_block = self.builder.get_insert_block().unwrap();
-        let function = current_block.get_parent().unwrap();
+    /// Whether the module is compiled with fuel metering, interruption or
+    /// stack checks.
+    fn has_limits(&self) -> bool {
+        self.options.consume_fuel || self.options.interruptible || self.options.check_stack
+    }
 
-        let bounds_check_block = self.context.append_basic_block(function, "bounds_check");
-        let trap_block = self.context.append_basic_block(function, "trap");
-        let continue_block = self.context.append_basic_block(function, "continue");
+    /// Whether traps return to the host through the vmctx trap slot rather
+    /// than being `unreachable`.
+    fn traps_return(&self) -> bool {
+        self.embedded || self.has_limited_main()
+    }
 
-        let false_val = self.context.bool_type().const_zero();
-        self.builder
-            .build_conditional_branch(false_val, continue_block, bounds_check_block)
-            .unwrap();
+    /// Whether `main` is run through `wasm_limited_main`, which reports
+    /// traps. Lazily compiled modules need that for bodies that fail to
+    /// compile.
+    fn has_limited_main(&self) -> bool {
+        self.has_limits() || self.options.lazy
+    }
+
+    /// Position of element segment `elem_index` among the passive segments,
+    /// which are the only ones with state in the vmctx. Active and declared
+    /// segments behave as if dropped once the instance exists.
+    fn passive_segment_index(&self, elem_index: u32) -> Result<Option<u32>> {
+        let segment = self
+            .element_segments
+            .get(elem_index as usize)
+            .ok_or(anyhow!("Invalid element segment index: {}", elem_index))?;
+        if segment.kind != ElementSegmentKind::Passive {
+            return Ok(None);
+        }
+        let passive_index = self.element_segments[..elem_index as usize]
+            .iter()
+            .filter(|segment| segment.kind == ElementSegmentKind::Passive)
+            .count();
+        Ok(Some(passive_index as u32))
+    }
 
-        self.builder.position_at_end(bounds_check_block);
+    /// The vmctx parameter of the function the builder is positioned in.
+    fn current_function(&self) -> FunctionValue<'ctx> {
         self.builder
-            .build_conditional_branch(false_val, trap_block, continue_block)
-            .unwrap();
+            .get_insert_block()
+            .and_then(|block| block.get_parent())
+            .expect("builder is not positioned in a function")
+    }
 
-        self.builder.position_at_end(trap_block);
-        self.builder.build_unreachable().unwrap();
+    fn current_vmctx(&self) -> PointerValue<'ctx> {
+        self.current_function()
+            .get_first_param()
+            .expect("builder is not positioned in a function taking a vmctx")
+            .into_pointer_value()
+    }
 
-        self.builder.position_at_end(continue_block);
+    fn vmctx_field_ptr(
+        &self,
+        vmctx: PointerValue<'ctx>,
+        offset: u32,
+        name: &str,
+    ) -> PointerValue<'ctx> {
+        if offset == 0 {
+            return vmctx;
+        }
+        unsafe {
+            self.builder
+                .build_in_bounds_gep(
+                    self.context.i8_type(),
+                    vmctx,
+                    &[self.context.i64_type().const_int(offset as u64, false)],
+                    &format!("{name}_ptr"),
+                )
+                .unwrap()
+        }
+    }
 
-        let bounds_check1_block = self.context.append_basic_block(function, "bounds_check1");
-        let trap2_block = self.context.append_basic_block(function, "trap2");
-        let continue3_block = self.context.append_basic_block(function, "continue3");
+    fn load_vmctx_field(
+        &self,
+        vmctx: PointerValue<'ctx>,
+        offset: u32,
+        field_type: BasicTypeEnum<'ctx>,
+        name: &str,
+    ) -> BasicValueEnum<'ctx> {
+        let ptr = self.vmctx_field_ptr(vmctx, offset, name);
+        self.builder.build_load(field_type, ptr, name).unwrap()
+    }
 
-        self.builder
-            .build_conditional_branch(false_val, continue3_block, bounds_check1_block)
+    fn store_vmctx_field(
+        &self,
+        vmctx: PointerValue<'ctx>,
+        offset: u32,
+        value: BasicValueEnum<'ctx>,
+        name: &str,
+    ) {
+        let ptr = self.vmctx_field_ptr(vmctx, offset, name);
+        self.builder.build_store(ptr, value).unwrap();
+    }
+
+    fn get_global_ptr(
+        &self,
+        vmctx: PointerValue<'ctx>,
+        global_index: u32,
+    ) -> Result<(PointerValue<'ctx>, GlobalType)> {
+        let global_type = *self
+            .globals
+            .get(global_index as usize)
+            .ok_or(anyhow!("Invalid global index: {}", global_index))?;
+        let ptr = self.vmctx_field_ptr(vmctx, self.layout().global(global_index), "global");
+        Ok((ptr, global_type))
+    }
+
+    /// Calls the host function bound to function import `import_index`
+    /// through its vmctx slot.
+    fn build_host_import_call(
+        &self,
+        value_stack: &mut Vec<BasicValueEnum<'ctx>>,
+        import_index: u32,
+        wasm_module: &WasmModule,
+    ) -> Result<()> {
+        let import = wasm_module
+            .imported_functions
+            .get(import_index as usize)
+            .ok_or(anyhow!("Invalid function index: {}", import_index))?;
+        if import.func_type.results().len() > 1 {
+            return Err(anyhow!("Multiple return values not supported"));
+        }
+        let ptr_type = self.context.ptr_type(inkwell::AddressSpace::default());
+        let param_types = import
+            .func_type
+            .params()
+            .iter()
+            .map(|param| self.val_type_to_llvm_type(*param).into())
+            .collect::<Vec<BasicMetadataTypeEnum>>();
+        let fn_type = match import.func_type.results().first() {
+            Some(result) => self
+                .val_type_to_llvm_type(*result)
+                .fn_type(&param_types, false),
+            None => self.context.void_type().fn_type(&param_types, false),
+        };
+
+        let mut args = Vec::new();
+        for _ in import.func_type.params() {
+            args.push(Self::pop_single_value(value_stack)?.into());
+        }
+        args.reverse();
+        let host_fn = self
+            .load_vmctx_field(
+                self.current_vmctx(),
+                self.layout().host_import(import_index),
+                ptr_type.into(),
+                "host_fn",
+            )
+            .into_pointer_value();
+        let call_result = self
+            .builder
+            .build_indirect_call(fn_type, host_fn, &args, "host_call")
             .unwrap();
+        if let Some(result) = call_result.try_as_basic_value().left() {
+            value_stack.push(result);
+        }
+        Ok(())
+    }
 
-        self.builder.position_at_end(bounds_check1_block);
+    /// Exports the start function as `wasm_instance_start`, which the
+    /// embedding API calls once the host functions are bound.
+    fn create_start_export(&self, wasm_module: &WasmModule, start_func_idx: u32) -> Result<()> {
+        let ptr_type = self.context.ptr_type(inkwell::AddressSpace::default());
+        let start_func = wasm_module
+            .functions
+            .iter()
+            .find(|f| f.idx == start_func_idx)
+            .and_then(|f| self.module.get_function(&self.function_symbol(f)))
+            .ok_or(anyhow!("Invalid start function: {}", start_func_idx))?;
+
+        let wrapper = self.add_vmctx_function(
+            header::INSTANCE_START_SYMBOL,
+            self.context.void_type().fn_type(&[ptr_type.into()], false),
+            None,
+        );
+        let vmctx = wrapper.get_first_param().unwrap();
         self.builder
-            .build_conditional_branch(false_val, trap2_block, continue3_block)
+            .build_call(start_func, &[vmctx.into()], "")
             .unwrap();
+        self.builder.build_return(None).unwrap();
+        Ok(())
+    }
 
-        self.builder.position_at_end(trap2_block);
-        self.builder.build_unreachable().unwrap();
-
-        self.builder.position_at_end(continue3_block);
+    /// Emits a symbol named after each function export that forwards to the
+    /// compiled function, so AOT outputs can be called by their Wasm names.
+    fn create_export_wrappers(&self, wasm_module: &WasmModule) -> Result<()> {
+        for export in &wasm_module.exports {
+            if export.kind != ExternalKind::Func {
+                continue;
+            }
+            if self.module.get_function(&export.name).is_some() {
+                return Err(anyhow!(
+                    "Export \"{}\" collides with symbol {}",
+                    export.name.escape_default(),
+                    export.name
+                ));
+            }
+            let Some(function) = wasm_module.functions.iter().find(|f| f.idx == export.index)
+            else {
+                continue;
+            };
+
+            let target_name = self.function_symbol(function);
+            let target = self
+                .module
+                .get_function(&target_name)
+                .ok_or(anyhow!("Unknown function: {}", target_name))?;
+
+            let wrapper = self.add_vmctx_function(&export.name, target.get_type(), None);
+            let args: Vec<BasicMetadataValueEnum> =
+                wrapper.get_param_iter().map(Into::into).collect();
+            let call_result = self.builder.build_call(target, &args, "call").unwrap();
+            match call_result.try_as_basic_value().left() {
+                Some(value) => self.builder.build_return(Some(&value)).unwrap(),
+                None => self.builder.build_return(None).unwrap(),
+            };
+        }
+        Ok(())
+    }
+
+    /// Emits a trampoline per function export that takes the parameters from
+    /// an array of 8-byte slots and stores the result in the first one, so
+    /// that the embedding API can call exports whose signature it only knows
+    /// at run time.
+    fn create_array_call_trampolines(&self, wasm_module: &WasmModule) -> Result<()> {
+        let ptr_type = self.context.ptr_type(inkwell::AddressSpace::default());
+        let i64_type = self.context.i64_type();
+        let trampoline_type = self
+            .context
+            .void_type()
+            .fn_type(&[ptr_type.into(), ptr_type.into()], false);
+
+        for export in &wasm_module.exports {
+            if export.kind != ExternalKind::Func {
+                continue;
+            }
+            let Some(function) = wasm_module.functions.iter().find(|f| f.idx == export.index)
+            else {
+                continue;
+            };
+            let target = self
+                .module
+                .get_function(&export.name)
+                .ok_or(anyhow!("Unknown function: {}", export.name))?;
+
+            let trampoline = self.add_vmctx_function(
+                &header::array_call_symbol(&export.name),
+                trampoline_type,
+                None,
+            );
+            let vmctx = trampoline.get_first_param().unwrap();
+            let values = trampoline.get_nth_param(1).unwrap().into_pointer_value();
+            values.set_name("values");
+            let slot = |index: usize| unsafe {
+                self.builder
+                    .build_gep(
+                        i64_type,
+                        values,
+                        &[i64_type.const_int(index as u64, false)],
+                        "slot",
+                    )
+                    .unwrap()
+            };
+
+            let mut args: Vec<BasicMetadataValueEnum> = vec![vmctx.into()];
+            for (index, param) in function.func_type.params().iter().enumerate() {
+                let arg = self
+                    .builder
+                    .build_load(self.val_type_to_llvm_type(*param), slot(index), "arg")
+                    .unwrap();
+                args.push(arg.into());
+            }
+            let call_result = self.builder.build_call(target, &args, "call").unwrap();
+            if let Some(result) = call_result.try_as_basic_value().left() {
+                self.builder.build_store(slot(0), result).unwrap();
+            }
+            self.builder.build_return(None).unwrap();
+        }
+        Ok(())
+    }
+
+    /// Emits the accessor functions declared by the generated C header for
+    /// exported memories and globals.
+    fn create_export_accessors(&self, wasm_module: &WasmModule) -> Result<()> {
+        let ptr_type = self.context.ptr_type(inkwell::AddressSpace::default());
+        let i64_type = self.context.i64_type();
+
+        for export in &wasm_module.exports {
+            if !header::is_c_identifier(&export.name) {
+                continue;
+            }
+            match export.kind {
+                ExternalKind::Memory => {
+                    self.memory.ok_or(anyhow!("No memory allocated"))?;
+
+                    let base_fn = self.add_vmctx_function(
+                        &header::memory_base_symbol(&export.name),
+                        ptr_type.fn_type(&[ptr_type.into()], false),
+                        None,
+                    );
+                    let vmctx = base_fn.get_first_param().unwrap().into_pointer_value();
+                    let base = self.load_vmctx_field(
+                        vmctx,
+                        VmctxLayout::MEMORY_BASE,
+                        ptr_type.into(),
+                        "mem_base",
+                    );
+                    self.builder.build_return(Some(&base)).unwrap();
+
+                    let size_fn = self.add_vmctx_function(
+                        &header::memory_size_symbol(&export.name),
+                        i64_type.fn_type(&[ptr_type.into()], false),
+                        None,
+                    );
+                    let vmctx = size_fn.get_first_param().unwrap().into_pointer_value();
+                    let size = self.load_memory_size(vmctx, "mem_size");
+                    self.builder.build_return(Some(&size)).unwrap();
+                }
+                ExternalKind::Global => {
+                    let global_type = *self
+                        .globals
+                        .get(export.index as usize)
+                        .ok_or(anyhow!("Invalid global index: {}", export.index))?;
+                    let llvm_type = self.val_type_to_llvm_type(global_type.content_type);
+
+                    let getter = self.add_vmctx_function(
+                        &header::global_getter_symbol(&export.name),
+                        llvm_type.fn_type(&[ptr_type.into()], false),
+                        None,
+                    );
+                    let vmctx = getter.get_first_param().unwrap().into_pointer_value();
+                    let (global_ptr, _) = self.get_global_ptr(vmctx, export.index)?;
+                    let value = self
+                        .builder
+                        .build_load(llvm_type, global_ptr, "global_load")
+                        .unwrap();
+                    self.builder.build_return(Some(&value)).unwrap();
+
+                    if global_type.mutable {
+                        let setter = self.add_vmctx_function(
+                            &header::global_setter_symbol(&export.name),
+                            self.context
+                                .void_type()
+                                .fn_type(&[ptr_type.into(), llvm_type.into()], false),
+                            None,
+                        );
+                        let vmctx = setter.get_first_param().unwrap().into_pointer_value();
+                        let value = setter.get_nth_param(1).unwrap();
+                        let (global_ptr, _) = self.get_global_ptr(vmctx, export.index)?;
+                        self.builder.build_store(global_ptr, value).unwrap();
+                        self.builder.build_return(None).unwrap();
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Emits `wasm_instance_new` and `wasm_instance_free`. An instance is a
+    /// heap-allocated vmctx that owns its linear memory and tables, so a
+    /// compiled module can be instantiated any number of times.
+    fn create_instance_functions(&self, wasm_module: &WasmModule) -> Result<()> {
+        let ptr_type = self.context.ptr_type(inkwell::AddressSpace::default());
+        let i32_type = self.context.i32_type();
+        let i64_type = self.context.i64_type();
+        let layout = self.layout();
+
+        let calloc_fn = self.get_runtime_function(
+            "calloc",
+            ptr_type.fn_type(&[i64_type.into(), i64_type.into()], false),
+        );
+        let free_fn = self.get_runtime_function(
+            "free",
+            self.context.void_type().fn_type(&[ptr_type.into()], false),
+        );
+
+        let new_fn = self.module.add_function(
+            header::INSTANCE_NEW_SYMBOL,
+            ptr_type.fn_type(&[], false),
+            None,
+        );
+        let entry_block = self.context.append_basic_block(new_fn, "entry");
+        let out_of_memory = self.context.append_basic_block(new_fn, "out_of_memory");
+        self.builder.position_at_end(entry_block);
+
+        // Instantiation fails with a null instance rather than a trap, since
+        // the trap slot lives in the vmctx. A zero-byte `calloc` may return
+        // null, so only allocations of at least one byte are checked.
+        let calloc = |count: u64, size: u64, name: &str, on_failure: BasicBlock<'ctx>| {
+            let ptr = self
+                .builder
+                .build_call(
+                    calloc_fn,
+                    &[
+                        i64_type.const_int(count, false).into(),
+                        i64_type.const_int(size, false).into(),
+                    ],
+                    name,
+                )
+                .unwrap()
+                .try_as_basic_value()
+                .left()
+                .unwrap()
+                .into_pointer_value();
+            if count * size > 0 {
+                let failed = self
+                    .builder
+                    .build_is_null(ptr, &format!("{name}_failed"))
+                    .unwrap();
+                let allocated = self
+                    .context
+                    .append_basic_block(new_fn, &format!("{name}_allocated"));
+                self.builder
+                    .build_conditional_branch(failed, on_failure, allocated)
+                    .unwrap();
+                self.builder.position_at_end(allocated);
+            }
+            ptr
+        };
+
+        let vmctx = calloc(1, layout.size() as u64, "vmctx", out_of_memory);
+        // Frees what was allocated before a later allocation failed.
+        let alloc_failed = self.context.append_basic_block(new_fn, "alloc_failed");
+        if self.options.consume_fuel {
+            self.store_vmctx_field(
+                vmctx,
+                layout.fuel(),
+                i64_type.const_int(i64::MAX as u64, false).into(),
+                "fuel",
+            );
+        }
+        if self.options.interruptible {
+            let no_interrupt = self.module.add_global(i32_type, None, NO_INTERRUPT_SYMBOL);
+            no_interrupt.set_initializer(&i32_type.const_zero());
+            no_interrupt.set_constant(true);
+            no_interrupt.set_linkage(Linkage::Private);
+            self.store_vmctx_field(
+                vmctx,
+                layout.interrupt(),
+                no_interrupt.as_pointer_value().into(),
+                "interrupt_flag",
+            );
+        }
+
+        if let Some(memory_type) = self.memory {
+            let size = memory_type.initial * PAGE_SIZE;
+            let capacity = match memory_type.maximum {
+                Some(maximum) if memory_type.shared => maximum * PAGE_SIZE,
+                _ => size,
+            };
+            let memory = calloc(capacity, 1, "memory", alloc_failed);
+            self.store_vmctx_field(vmctx, VmctxLayout::MEMORY_BASE, memory.into(), "mem_base");
+            self.store_vmctx_field(
+                vmctx,
+                VmctxLayout::MEMORY_SIZE,
+                i64_type.const_int(size, false).into(),
+                "mem_size",
+            );
+        }
+
+        let mut tables = Vec::new();
+        for (table_index, table_type) in self.tables.iter().enumerate() {
+            let table_index = table_index as u32;
+            let table = calloc(table_type.initial, 8, "table", alloc_failed);
+            self.store_vmctx_field(
+                vmctx,
+                layout.table_base(table_index),
+                table.into(),
+                "table_base",
+            );
+            self.store_vmctx_field(
+                vmctx,
+                layout.table_size(table_index),
+                i32_type.const_int(table_type.initial, false).into(),
+                "table_size",
+            );
+            tables.push((table, table_type.initial));
+        }
+
+        for element_segment in &wasm_module.element_segments {
+            let ElementSegmentKind::Active {
+                table_index,
+                offset,
+            } = element_segment.kind
+            else {
+                continue;
+            };
+            let Some(&(table, table_size)) = tables.get(table_index as usize) else {
+                continue;
+            };
+            let end = offset as u64 + element_segment.items.len() as u64;
+            if end > table_size {
+                return Err(anyhow!(
+                    "Element segment out of bounds for table {}",
+                    table_index
+                ));
+            }
+
+            for (i, item) in element_segment.items.iter().enumerate() {
+                let Some(func_ref) = item.and_then(|idx| self.function_reference(wasm_module, idx))
+                else {
+                    continue;
+                };
+                let slot = offset as u64 + i as u64;
+                let elem_ptr = unsafe {
+                    self.builder
+                        .build_gep(
+                            ptr_type,
+                            table,
+                            &[i64_type.const_int(slot, false)],
+                            "elem_ptr",
+                        )
+                        .unwrap()
+                };
+                self.builder.build_store(elem_ptr, func_ref).unwrap();
+            }
+        }
+
+        for (elem_index, element_segment) in wasm_module.element_segments.iter().enumerate() {
+            if let Some(passive_index) = self.passive_segment_index(elem_index as u32)? {
+                self.store_vmctx_field(
+                    vmctx,
+                    layout.element_segment(passive_index),
+                    i32_type
+                        .const_int(element_segment.items.len() as u64, false)
+                        .into(),
+                    "elem_len",
+                );
+            }
+        }
+
+        self.builder.build_return(Some(&vmctx)).unwrap();
+        self.builder.position_at_end(out_of_memory);
+        self.builder
+            .build_return(Some(&ptr_type.const_null()))
+            .unwrap();
+
+        let free_fn_type = self.context.void_type().fn_type(&[ptr_type.into()], false);
+        let instance_free_fn =
+            self.add_vmctx_function(header::INSTANCE_FREE_SYMBOL, free_fn_type, None);
+        let new_vmctx = vmctx;
+        let vmctx = instance_free_fn
+            .get_first_param()
+            .unwrap()
+            .into_pointer_value();
+
+        if self.memory.is_some() {
+            let memory =
+                self.load_vmctx_field(vmctx, VmctxLayout::MEMORY_BASE, ptr_type.into(), "mem_base");
+            self.builder
+                .build_call(free_fn, &[memory.into()], "")
+                .unwrap();
+        }
+        for table_index in 0..self.tables.len() as u32 {
+            let table = self.load_vmctx_field(
+                vmctx,
+                layout.table_base(table_index),
+                ptr_type.into(),
+                "table_base",
+            );
+            self.builder
+                .build_call(free_fn, &[table.into()], "")
+                .unwrap();
+        }
+        self.builder
+            .build_call(free_fn, &[vmctx.into()], "")
+            .unwrap();
+        self.builder.build_return(None).unwrap();
+
+        if alloc_failed.get_first_use().is_some() {
+            self.builder.position_at_end(alloc_failed);
+            self.builder
+                .build_call(instance_free_fn, &[new_vmctx.into()], "")
+                .unwrap();
+            self.builder
+                .build_return(Some(&ptr_type.const_null()))
+                .unwrap();
+        } else {
+            unsafe { alloc_failed.delete() }.unwrap();
+        }
+
+        Ok(())
+    }
+
+    /// Layout of the descriptor a funcref points to: the function's address
+    /// and the id of its signature, which `call_indirect` checks before
+    /// calling through it.
+    fn func_ref_type(&self) -> StructType<'ctx> {
+        let ptr_type = self.context.ptr_type(inkwell::AddressSpace::default());
+        self.context
+            .struct_type(&[ptr_type.into(), self.context.i32_type().into()], false)
+    }
+
+    /// Signature id of `func_type`: the index of the first structurally equal
+    /// type in the type section, so that equivalent types share an id.
+    fn signature_id(
+        function_types: &[wasmparser::FuncType],
+        func_type: &wasmparser::FuncType,
+    ) -> u32 {
+        function_types
+            .iter()
+            .position(|t| t == func_type)
+            .map_or(u32::MAX, |index| index as u32)
+    }
+
+    /// The funcref of the function with index `func_idx`, a pointer to its
+    /// descriptor, or `None` if the function is not defined in the module.
+    fn function_reference(
+        &self,
+        wasm_module: &WasmModule,
+        func_idx: u32,
+    ) -> Option<PointerValue<'ctx>> {
+        let function = wasm_module.functions.iter().find(|f| f.idx == func_idx)?;
+        let symbol = self.function_symbol(function);
+        let name = format!("{symbol}.funcref");
+        if let Some(descriptor) = self.module.get_global(&name) {
+            return Some(descriptor.as_pointer_value());
+        }
+
+        let llvm_func = self.module.get_function(&symbol)?;
+        let signature = Self::signature_id(&wasm_module.function_types, &function.func_type);
+        let value = self.func_ref_type().const_named_struct(&[
+            llvm_func.as_global_value().as_pointer_value().into(),
+            self.context
+                .i32_type()
+                .const_int(signature as u64, false)
+                .into(),
+        ]);
+        let descriptor = self.module.add_global(self.func_ref_type(), None, &name);
+        descriptor.set_initializer(&value);
+        descriptor.set_constant(true);
+        descriptor.set_linkage(self.constant_linkage());
+        Some(descriptor.as_pointer_value())
+    }
+
+    fn create_main(&self, wasm_module: &WasmModule, start_func_idx: u32) -> Result<()> {
+        let i32_type = self.context.i32_type();
+        let main_fn_type = i32_type.fn_type(&[], false);
+        let main_func = self.module.add_function("main", main_fn_type, None);
+
+        let entry_block = self.context.append_basic_block(main_func, "entry");
+        self.builder.position_at_end(entry_block);
+
+        let instance_new = self
+            .module
+            .get_function(header::INSTANCE_NEW_SYMBOL)
+            .ok_or(anyhow!("Instance functions have not been created"))?;
+        let instance_free = self
+            .module
+            .get_function(header::INSTANCE_FREE_SYMBOL)
+            .ok_or(anyhow!("Instance functions have not been created"))?;
+
+        let vmctx = self
+            .builder
+            .build_call(instance_new, &[], "vmctx")
+            .unwrap()
+            .try_as_basic_value()
+            .left()
+            .unwrap();
+
+        let start_func = wasm_module
+            .functions
+            .iter()
+            .find(|f| f.idx == start_func_idx)
+            .and_then(|f| self.module.get_function(&self.function_symbol(f)));
+        if let Some(start_func) = start_func {
+            self.builder
+                .build_call(start_func, &[vmctx.into()], "")
+                .unwrap();
+        }
+
+        self.builder
+            .build_call(instance_free, &[vmctx.into()], "")
+            .unwrap();
+
+        let exit_code = self.context.i32_type().const_int(0, false);
+        self.builder.build_return(Some(&exit_code)).unwrap();
+
+        Ok(())
+    }
+
+    /// Emits `wasm_limited_main(fuel, interrupt_flag, stack_limit)`, which
+    /// runs the start function like `main` with `fuel`, the interrupt flag
+    /// at `interrupt_flag` and `stack_limit`, and returns the code of the
+    /// trap that ended it, or 0.
+    fn create_limited_main(&self, wasm_module: &WasmModule, start_func_idx: u32) -> Result<()> {
+        let i32_type = self.context.i32_type();
+        let i64_type = self.context.i64_type();
+        let ptr_type = self.context.ptr_type(inkwell::AddressSpace::default());
+        let layout = self.layout();
+        let function = self.module.add_function(
+            LIMITED_MAIN_SYMBOL,
+            i32_type.fn_type(&[i64_type.into(), ptr_type.into(), i64_type.into()], false),
+            None,
+        );
+        let fuel = function.get_nth_param(0).unwrap();
+        fuel.set_name("fuel");
+        let interrupt_flag = function.get_nth_param(1).unwrap();
+        interrupt_flag.set_name("interrupt_flag");
+        let stack_limit = function.get_nth_param(2).unwrap();
+        stack_limit.set_name("stack_limit");
+        let entry_block = self.context.append_basic_block(function, "entry");
+        self.builder.position_at_end(entry_block);
+
+        let instance_new = self
+            .module
+            .get_function(header::INSTANCE_NEW_SYMBOL)
+            .ok_or(anyhow!("Instance functions have not been created"))?;
+        let instance_free = self
+            .module
+            .get_function(header::INSTANCE_FREE_SYMBOL)
+            .ok_or(anyhow!("Instance functions have not been created"))?;
+        let start_func = wasm_module
+            .functions
+            .iter()
+            .find(|f| f.idx == start_func_idx)
+            .and_then(|f| self.module.get_function(&self.function_symbol(f)))
+            .ok_or(anyhow!("Invalid start function: {}", start_func_idx))?;
+
+        let vmctx = self
+            .builder
+            .build_call(instance_new, &[], "vmctx")
+            .unwrap()
+            .try_as_basic_value()
+            .left()
+            .unwrap()
+            .into_pointer_value();
+        if self.options.consume_fuel {
+            self.store_vmctx_field(vmctx, layout.fuel(), fuel, "fuel");
+        }
+        if self.options.interruptible {
+            self.store_vmctx_field(vmctx, layout.interrupt(), interrupt_flag, "interrupt_flag");
+        }
+        if self.options.check_stack {
+            self.store_vmctx_field(vmctx, layout.stack_limit(), stack_limit, "stack_limit");
+        }
+        self.builder
+            .build_call(start_func, &[vmctx.into()], "")
+            .unwrap();
+        let trap = self.load_vmctx_field(vmctx, layout.trap(), i32_type.into(), "trap");
+        self.builder
+            .build_call(instance_free, &[vmctx.into()], "")
+            .unwrap();
+        self.builder.build_return(Some(&trap)).unwrap();
+        Ok(())
+    }
+
+    fn get_intrinsic_function(
+        &self,
+        name: &str,
+        param_types: &[BasicMetadataTypeEnum<'ctx>],
+        return_type: BasicTypeEnum<'ctx>,
+    ) -> Result<FunctionValue<'ctx>> {
+        if let Some(intrinsic_fn) = self.module.get_function(name) {
+            return Ok(intrinsic_fn);
+        }
+        let fn_type = return_type.fn_type(param_types, false);
+        let intrinsic_fn = self.module.add_function(name, fn_type, None);
+        Ok(intrinsic_fn)
+    }
+
+    fn get_void_intrinsic_function(
+        &self,
+        name: &str,
+        param_types: &[BasicMetadataTypeEnum<'ctx>],
+    ) -> Result<FunctionValue<'ctx>> {
+        if let Some(intrinsic_fn) = self.module.get_function(name) {
+            return Ok(intrinsic_fn);
+        }
+        let fn_type = self.context.void_type().fn_type(param_types, false);
+        let intrinsic_fn = self.module.add_function(name, fn_type, None);
+        Ok(intrinsic_fn)
+    }
+
+    fn build_memory_copy(
+        &self,
+        dest: IntValue<'ctx>,
+        src: IntValue<'ctx>,
+        size: IntValue<'ctx>,
+    ) -> Result<()> {
+        let memory_ptr = self.get_memory_base()?;
+        let i64_type = self.context.i64_type();
+        let dest = self
+            .builder
+            .build_int_z_extend(dest, i64_type, "dest")
+            .unwrap();
+        let src = self
+            .builder
+            .build_int_z_extend(src, i64_type, "src")
+            .unwrap();
+        let length = self
+            .builder
+            .build_int_z_extend(size, i64_type, "length")
+            .unwrap();
+        self.build_memory_bounds_check(dest, length);
+        self.build_memory_bounds_check(src, length);
+        let false_val = self.context.bool_type().const_zero();
 
         let dest_ptr = unsafe {
             self.builder
@@ -2085,30 +4520,18 @@ impl<'ctx> Compiler<'ctx> {
         value: IntValue<'ctx>,
         size: IntValue<'ctx>,
     ) -> Result<()> {
-        let memory = self.memory.ok_or(anyhow!("No memory allocated"))?;
-        let memory_ptr = memory.as_pointer_value();
-
-        let current_block = self.builder.get_insert_block().unwrap();
-        let function = current_block.get_parent().unwrap();
-
-        let bounds_check4_block = self.context.append_basic_block(function, "bounds_check4");
-        let trap5_block = self.context.append_basic_block(function, "trap5");
-        let continue6_block = self.context.append_basic_block(function, "continue6");
-
-        let false_val = self.context.bool_type().const_zero();
-        self.builder
-            .build_conditional_branch(false_val, continue6_block, bounds_check4_block)
+        let memory_ptr = self.get_memory_base()?;
+        let i64_type = self.context.i64_type();
+        let dest = self
+            .builder
+            .build_int_z_extend(dest, i64_type, "dest")
             .unwrap();
-
-        self.builder.position_at_end(bounds_check4_block);
-        self.builder
-            .build_conditional_branch(false_val, trap5_block, continue6_block)
+        let length = self
+            .builder
+            .build_int_z_extend(size, i64_type, "length")
             .unwrap();
-
-        self.builder.position_at_end(trap5_block);
-        self.builder.build_unreachable().unwrap();
-
-        self.builder.position_at_end(continue6_block);
+        self.build_memory_bounds_check(dest, length);
+        let false_val = self.context.bool_type().const_zero();
 
         let dest_ptr = unsafe {
             self.builder
@@ -2149,457 +4572,2714 @@ impl<'ctx> Compiler<'ctx> {
         Ok(())
     }
 
-    fn val_type_to_llvm_type(&self, val_type: ValType) -> BasicTypeEnum<'ctx> {
-        match val_type {
-            ValType::I32 => self.context.i32_type().into(),
-            ValType::I64 => self.context.i64_type().into(),
-            ValType::F32 => self.context.f32_type().into(),
-            ValType::F64 => self.context.f64_type().into(),
-            _ => panic!("Unsupported value type: {val_type:?}"),
+    /// Loads the element array and element count of table `table_index`.
+    fn load_table(&self, table_index: u32) -> Result<(PointerValue<'ctx>, IntValue<'ctx>)> {
+        if table_index as usize >= self.tables.len() {
+            return Err(anyhow!("Invalid table index: {}", table_index));
         }
+        let vmctx = self.current_vmctx();
+        let layout = self.layout();
+        let ptr_type = self.context.ptr_type(inkwell::AddressSpace::default());
+        let base = self
+            .load_vmctx_field(
+                vmctx,
+                layout.table_base(table_index),
+                ptr_type.into(),
+                "table_base",
+            )
+            .into_pointer_value();
+        let size = self
+            .load_vmctx_field(
+                vmctx,
+                layout.table_size(table_index),
+                self.context.i32_type().into(),
+                "table_size",
+            )
+            .into_int_value();
+        Ok((base, size))
     }
 
-    fn create_llvm_function_type(
+    fn table_element_ptr(
         &self,
-        func_type: &wasmparser::FuncType,
-    ) -> inkwell::types::FunctionType<'ctx> {
-        let param_types: Vec<BasicMetadataTypeEnum> = func_type
-            .params()
-            .iter()
-            .map(|vt| self.val_type_to_llvm_type(*vt).into())
-            .collect();
-
-        if func_type.results().is_empty() {
-            self.context.void_type().fn_type(&param_types, false)
-        } else if func_type.results().len() == 1 {
-            let return_type = self.val_type_to_llvm_type(func_type.results()[0]);
-            return_type.fn_type(&param_types, false)
-        } else {
-            panic!("Multiple return values not supported");
+        base: PointerValue<'ctx>,
+        index: IntValue<'ctx>,
+    ) -> PointerValue<'ctx> {
+        let ptr_type = self.context.ptr_type(inkwell::AddressSpace::default());
+        unsafe {
+            self.builder
+                .build_gep(ptr_type, base, &[index], "elem_ptr")
+                .unwrap()
         }
     }
 
-    fn create_globals(&mut self, globals: &[crate::wasm_parser::WasmGlobal]) -> Result<()> {
-        for (idx, global) in globals.iter().enumerate() {
-            let global_type = global.global_type;
-            let llvm_type = self.val_type_to_llvm_type(global_type.content_type);
+    /// Ends the current block with `trap`. Code compiled for the embedding
+    /// API or with execution limits records the trap in the vmctx and
+    /// returns, leaving the callers to return in turn; elsewhere a trap
+    /// calls `llvm.trap`, which the optimizer must keep, and the process
+    /// dies with `SIGILL`.
+    fn build_trap(&self, trap: Trap) {
+        if !self.traps_return() {
+            let trap_fn = self.get_void_intrinsic_function("llvm.trap", &[]).unwrap();
+            self.builder.build_call(trap_fn, &[], "").unwrap();
+            self.builder.build_unreachable().unwrap();
+            return;
+        }
+        let i32_type = self.context.i32_type();
+        self.store_vmctx_field(
+            self.current_vmctx(),
+            self.layout().trap(),
+            i32_type.const_int(trap.code() as u64, false).into(),
+            "trap",
+        );
+        self.build_trap_return();
+    }
 
-            let global_name = format!("global_{idx}");
-            let global_var = self.module.add_global(llvm_type, None, &global_name);
+    /// Returns from the current function while a trap is pending. The
+    /// returned value is never looked at.
+    fn build_trap_return(&self) {
+        match self.current_function().get_type().get_return_type() {
+            Some(return_type) => self
+                .builder
+                .build_return(Some(&return_type.const_zero()))
+                .unwrap(),
+            None => self.builder.build_return(None).unwrap(),
+        };
+    }
 
-            let initializer = match global_type.content_type {
-                ValType::I32 => self.context.i32_type().const_zero().as_basic_value_enum(),
-                ValType::I64 => self.context.i64_type().const_zero().as_basic_value_enum(),
-                ValType::F32 => self.context.f32_type().const_zero().as_basic_value_enum(),
-                ValType::F64 => self.context.f64_type().const_zero().as_basic_value_enum(),
-                _ => {
-                    return Err(anyhow!(
-                        "Unsupported global type: {:?}",
-                        global_type.content_type
-                    ));
-                }
-            };
-            global_var.set_initializer(&initializer);
-
-            if !global_type.mutable {
-                global_var.set_constant(true);
-            }
-
-            self.globals.push((global_var, global_type.content_type));
+    /// After a call to a Wasm function in code whose traps return, returns
+    /// if the callee trapped.
+    fn build_trap_propagation(&self) {
+        if !self.traps_return() {
+            return;
         }
-        Ok(())
+        let i32_type = self.context.i32_type();
+        let trap = self
+            .load_vmctx_field(
+                self.current_vmctx(),
+                self.layout().trap(),
+                i32_type.into(),
+                "pending_trap",
+            )
+            .into_int_value();
+        let trapped = self
+            .builder
+            .build_int_compare(IntPredicate::NE, trap, i32_type.const_zero(), "trapped")
+            .unwrap();
+        let function = self.current_function();
+        let trapped_block = self.context.append_basic_block(function, "propagate_trap");
+        let continue_block = self.context.append_basic_block(function, "no_trap");
+        self.builder
+            .build_conditional_branch(trapped, trapped_block, continue_block)
+            .unwrap();
+        self.builder.position_at_end(trapped_block);
+        self.build_trap_return();
+        self.builder.position_at_end(continue_block);
     }
 
-    fn create_memory(&mut self, memory_type: &wasmparser::MemoryType) -> Result<()> {
-        let i8_type = self.context.i8_type();
-        let _i32_type = self.context.i32_type();
-        let page_size = 65536;
-        let initial_size = memory_type.initial * page_size;
-
-        let array_type = i8_type.array_type(initial_size as u32);
-        let memory_global = self.module.add_global(array_type, None, "memory");
-        let zero_initializer = array_type.const_zero();
-        memory_global.set_initializer(&zero_initializer);
-
-        self.memory = Some(memory_global);
-        Ok(())
+    /// Traps with `trap` when `condition` holds and continues in a fresh
+    /// block otherwise.
+    fn build_trap_if(&self, condition: IntValue<'ctx>, trap: Trap) {
+        let function = self.current_function();
+        let trap_block = self.context.append_basic_block(function, "trap");
+        let continue_block = self.context.append_basic_block(function, "no_trap");
+        self.builder
+            .build_conditional_branch(condition, trap_block, continue_block)
+            .unwrap();
+        self.builder.position_at_end(trap_block);
+        self.build_trap(trap);
+        self.builder.position_at_end(continue_block);
     }
 
-    fn initialize_function_tables(
-        &mut self,
-        element_segments: &[crate::wasm_parser::ElementSegment],
-        functions: &[crate::wasm_parser::Function],
-    ) -> Result<()> {
-        for element_segment in element_segments {
-            if (element_segment.table_index as usize) < self.function_tables.len() {
-                let table = self.function_tables[element_segment.table_index as usize];
-                let ptr_type = self.context.ptr_type(inkwell::AddressSpace::default());
-
-                let mut function_ptrs = Vec::new();
-                for &func_idx in &element_segment.function_indices {
-                    if let Some(function) = functions.iter().find(|f| f.idx == func_idx) {
-                        let function_name = format!("func_{}", function.idx);
-                        if let Some(func_value) = self.module.get_function(&function_name) {
-                            let func_ptr = func_value.as_global_value().as_pointer_value();
-                            function_ptrs.push(func_ptr);
-                        } else {
-                            function_ptrs.push(ptr_type.const_null());
-                        }
-                    } else {
-                        function_ptrs.push(ptr_type.const_null());
-                    }
-                }
-
-                if !function_ptrs.is_empty() {
-                    let array_value = ptr_type.const_array(&function_ptrs);
-                    table.set_initializer(&array_value);
-                }
-            }
+    /// With `CompilerOptions::profile`, declares the counter array of
+    /// `function`, which the host maps in `add_host_mappings`. The Wasm
+    /// offsets of its loops are recorded next to it as little-endian `u64`s,
+    /// in a byte string since inkwell can read those back; loops never start
+    /// at offset 0, so the string is never folded into a `zeroinitializer`.
+    fn declare_profile_counters(&self, function: &Function) -> Option<GlobalValue<'ctx>> {
+        if !self.options.profile {
+            return None;
         }
-
-        Ok(())
+        let loop_offsets: Vec<u64> = function
+            .body
+            .operators
+            .iter()
+            .zip(&function.body.offsets)
+            .filter(|(operator, _)| matches!(operator, Operator::Loop { .. }))
+            .map(|(_, &offset)| offset as u64)
+            .collect();
+        let symbol = self.function_symbol(function);
+        if !loop_offsets.is_empty() {
+            let bytes: Vec<u8> = loop_offsets.iter().flat_map(|o| o.to_le_bytes()).collect();
+            let offsets = self.context.const_string(&bytes, false);
+            let offsets_global = self.module.add_global(
+                offsets.get_type(),
+                None,
+                &format!("{symbol}{}", profile::LOOP_OFFSETS_SUFFIX),
+            );
+            offsets_global.set_initializer(&offsets);
+            offsets_global.set_constant(true);
+        }
+        let i64_type = self.context.i64_type();
+        let counters_type = i64_type.array_type(loop_offsets.len() as u32 + 1);
+        Some(self.module.add_global(
+            counters_type,
+            None,
+            &format!("{symbol}{}", profile::COUNTERS_SUFFIX),
+        ))
     }
 
-    fn get_memory_ptr(
-        &self,
-        offset: inkwell::values::IntValue<'ctx>,
-        static_offset: u64,
-    ) -> Result<PointerValue<'ctx>> {
-        let memory = self.memory.ok_or(anyhow!("No memory allocated"))?;
-        let i32_type = self.context.i32_type();
-
-        let base_ptr = memory.as_pointer_value();
-        let _zero = i32_type.const_zero();
-        let offset_with_static = if static_offset > 0 {
-            let static_offset_val = i32_type.const_int(static_offset, false);
-            self.builder
-                .build_int_add(offset, static_offset_val, "offset_sum")
-                .unwrap()
-        } else {
-            offset
+    /// Increments element `index` of the profile counters, if any.
+    fn build_profile_count(&self, counters: Option<GlobalValue<'ctx>>, index: u64) {
+        let Some(counters) = counters else {
+            return;
         };
+        let i64_type = self.context.i64_type();
+        let counter = unsafe {
+            self.builder.build_in_bounds_gep(
+                counters.get_value_type().into_array_type(),
+                counters.as_pointer_value(),
+                &[i64_type.const_zero(), i64_type.const_int(index, false)],
+                "profile_counter",
+            )
+        }
+        .unwrap();
+        let count = self
+            .builder
+            .build_load(i64_type, counter, "profile_count")
+            .unwrap()
+            .into_int_value();
+        let count = self
+            .builder
+            .build_int_add(count, i64_type.const_int(1, false), "profile_count_next")
+            .unwrap();
+        self.builder.build_store(counter, count).unwrap();
+    }
 
-        let i8_ptr = self
+    /// With stack checks, traps in a function prologue if its frame is below
+    /// the limit in the vmctx. The limit is 0 until the host sets
+    /// it on entering Wasm code.
+    fn build_stack_check(&self) {
+        if !self.options.check_stack {
+            return;
+        }
+        let i64_type = self.context.i64_type();
+        let ptr_type = self.context.ptr_type(inkwell::AddressSpace::default());
+        let frame_address = self.get_runtime_function(
+            "llvm.frameaddress.p0",
+            ptr_type.fn_type(&[self.context.i32_type().into()], false),
+        );
+        let stack_pointer = self
             .builder
-            .build_bit_cast(
-                base_ptr,
-                self.context.ptr_type(inkwell::AddressSpace::default()),
-                "mem_base",
+            .build_call(
+                frame_address,
+                &[self.context.i32_type().const_zero().into()],
+                "frame_address",
             )
             .unwrap()
+            .try_as_basic_value()
+            .left()
+            .unwrap()
             .into_pointer_value();
+        let stack_pointer = self
+            .builder
+            .build_ptr_to_int(stack_pointer, i64_type, "stack_address")
+            .unwrap();
+        let limit = self
+            .load_vmctx_field(
+                self.current_vmctx(),
+                self.layout().stack_limit(),
+                i64_type.into(),
+                "stack_limit",
+            )
+            .into_int_value();
+        let exhausted = self
+            .builder
+            .build_int_compare(IntPredicate::ULT, stack_pointer, limit, "stack_exhausted")
+            .unwrap();
+        self.build_trap_if(exhausted, Trap::StackOverflow);
+    }
 
-        let ptr = unsafe {
-            self.builder
-                .build_gep(
-                    self.context.i8_type(),
-                    i8_ptr,
-                    &[offset_with_static],
-                    "mem_ptr",
+    /// Spends one unit of fuel and polls the interrupt flag, as configured,
+    /// at a function entry or loop header.
+    fn build_limit_checks(&self) {
+        let vmctx = self.current_vmctx();
+        let layout = self.layout();
+        if self.options.consume_fuel {
+            let i64_type = self.context.i64_type();
+            let fuel = self
+                .load_vmctx_field(vmctx, layout.fuel(), i64_type.into(), "fuel")
+                .into_int_value();
+            let fuel = self
+                .builder
+                .build_int_sub(fuel, i64_type.const_int(1, false), "fuel_left")
+                .unwrap();
+            self.store_vmctx_field(vmctx, layout.fuel(), fuel.into(), "fuel");
+            let out_of_fuel = self
+                .builder
+                .build_int_compare(
+                    IntPredicate::SLT,
+                    fuel,
+                    i64_type.const_zero(),
+                    "out_of_fuel",
+                )
+                .unwrap();
+            self.build_trap_if(out_of_fuel, Trap::OutOfFuel);
+        }
+        if self.options.interruptible {
+            let i32_type = self.context.i32_type();
+            let ptr_type = self.context.ptr_type(inkwell::AddressSpace::default());
+            let flag_ptr = self
+                .load_vmctx_field(vmctx, layout.interrupt(), ptr_type.into(), "interrupt_flag")
+                .into_pointer_value();
+            let flag = self
+                .builder
+                .build_load(i32_type, flag_ptr, "interrupt")
+                .unwrap();
+            let load = flag.as_instruction_value().unwrap();
+            load.set_atomic_ordering(AtomicOrdering::Monotonic).unwrap();
+            load.set_alignment(4).unwrap();
+            let interrupted = self
+                .builder
+                .build_int_compare(
+                    IntPredicate::NE,
+                    flag.into_int_value(),
+                    i32_type.const_zero(),
+                    "interrupted",
                 )
+                .unwrap();
+            self.build_trap_if(interrupted, Trap::Interrupted);
+        }
+    }
+
+    /// Traps unless `index` is below `size`.
+    fn build_index_check(&self, index: IntValue<'ctx>, size: IntValue<'ctx>) {
+        let out_of_bounds = self
+            .builder
+            .build_int_compare(IntPredicate::UGE, index, size, "out_of_bounds")
+            .unwrap();
+        self.build_trap_if(out_of_bounds, Trap::TableOutOfBounds);
+    }
+
+    /// Traps unless `start + count <= size`. The sum is formed in 64 bits so
+    /// that it cannot wrap around.
+    fn build_range_check(
+        &self,
+        start: IntValue<'ctx>,
+        count: IntValue<'ctx>,
+        size: IntValue<'ctx>,
+    ) {
+        let i64_type = self.context.i64_type();
+        let extend = |value: IntValue<'ctx>, name: &str| {
+            self.builder
+                .build_int_z_extend(value, i64_type, name)
                 .unwrap()
         };
-        Ok(ptr)
+        let end = self
+            .builder
+            .build_int_add(extend(start, "start64"), extend(count, "count64"), "end64")
+            .unwrap();
+        let out_of_bounds = self
+            .builder
+            .build_int_compare(
+                IntPredicate::UGT,
+                end,
+                extend(size, "size64"),
+                "out_of_bounds",
+            )
+            .unwrap();
+        self.build_trap_if(out_of_bounds, Trap::TableOutOfBounds);
     }
 
-    fn get_memory_size(&self) -> Result<inkwell::values::IntValue<'ctx>> {
-        let memory = self.memory.ok_or(anyhow!("No memory allocated"))?;
-        let memory_type = memory.get_value_type().into_array_type();
-        let size_in_bytes = memory_type.len();
-        let pages = size_in_bytes / 65536;
-        Ok(self.context.i32_type().const_int(pages as u64, false))
+    /// Stores `value` into the elements `start..end` of the table at `base`.
+    fn build_table_fill(
+        &self,
+        base: PointerValue<'ctx>,
+        start: IntValue<'ctx>,
+        end: IntValue<'ctx>,
+        value: PointerValue<'ctx>,
+    ) {
+        let function = self.current_function();
+        let preheader = self.builder.get_insert_block().unwrap();
+        let header_block = self.context.append_basic_block(function, "fill_header");
+        let body_block = self.context.append_basic_block(function, "fill_body");
+        let exit_block = self.context.append_basic_block(function, "fill_exit");
+        self.builder
+            .build_unconditional_branch(header_block)
+            .unwrap();
+
+        self.builder.position_at_end(header_block);
+        let index = self
+            .builder
+            .build_phi(self.context.i32_type(), "fill_index")
+            .unwrap();
+        let index_value = index.as_basic_value().into_int_value();
+        let more = self
+            .builder
+            .build_int_compare(IntPredicate::ULT, index_value, end, "fill_more")
+            .unwrap();
+        self.builder
+            .build_conditional_branch(more, body_block, exit_block)
+            .unwrap();
+
+        self.builder.position_at_end(body_block);
+        let elem_ptr = self.table_element_ptr(base, index_value);
+        self.builder.build_store(elem_ptr, value).unwrap();
+        let next = self
+            .builder
+            .build_int_add(
+                index_value,
+                self.context.i32_type().const_int(1, false),
+                "fill_next",
+            )
+            .unwrap();
+        self.builder
+            .build_unconditional_branch(header_block)
+            .unwrap();
+        index.add_incoming(&[(&start, preheader), (&next, body_block)]);
+
+        self.builder.position_at_end(exit_block);
     }
 
-    fn grow_memory(
+    /// Copies `count` table elements; the ranges may overlap.
+    fn build_table_move(
         &self,
-        _delta: inkwell::values::IntValue<'ctx>,
-    ) -> Result<inkwell::values::IntValue<'ctx>> {
-        let neg_one = self.context.i32_type().const_int((-1i64) as u64, true);
-        Ok(neg_one)
+        dest: PointerValue<'ctx>,
+        src: PointerValue<'ctx>,
+        count: IntValue<'ctx>,
+    ) {
+        let ptr_type = self.context.ptr_type(inkwell::AddressSpace::default());
+        let i64_type = self.context.i64_type();
+        let bool_type = self.context.bool_type();
+        let memmove_fn = self.get_runtime_function(
+            "llvm.memmove.p0.p0.i64",
+            self.context.void_type().fn_type(
+                &[
+                    ptr_type.into(),
+                    ptr_type.into(),
+                    i64_type.into(),
+                    bool_type.into(),
+                ],
+                false,
+            ),
+        );
+        let count = self
+            .builder
+            .build_int_z_extend(count, i64_type, "count64")
+            .unwrap();
+        let bytes = self
+            .builder
+            .build_int_mul(count, i64_type.const_int(8, false), "bytes")
+            .unwrap();
+        self.builder
+            .build_call(
+                memmove_fn,
+                &[
+                    dest.into(),
+                    src.into(),
+                    bytes.into(),
+                    bool_type.const_zero().into(),
+                ],
+                "",
+            )
+            .unwrap();
     }
 
-    fn declare_assert_functions(&self) {
+    /// Grows table `table_index` by `delta` elements set to `init` and
+    /// returns its previous size, or -1 if the table cannot grow.
+    fn build_table_grow(
+        &self,
+        table_index: u32,
+        init: PointerValue<'ctx>,
+        delta: IntValue<'ctx>,
+    ) -> Result<IntValue<'ctx>> {
+        let ptr_type = self.context.ptr_type(inkwell::AddressSpace::default());
         let i32_type = self.context.i32_type();
         let i64_type = self.context.i64_type();
-        let void_type = self.context.void_type();
+        let (base, old_size) = self.load_table(table_index)?;
+        let maximum = self.tables[table_index as usize]
+            .maximum
+            .unwrap_or(u32::MAX as u64)
+            .min(u32::MAX as u64);
+
+        let function = self.current_function();
+        let entry_block = self.builder.get_insert_block().unwrap();
+        let realloc_block = self.context.append_basic_block(function, "table_realloc");
+        let commit_block = self.context.append_basic_block(function, "table_commit");
+        let done_block = self.context.append_basic_block(function, "table_grow_done");
+
+        let new_size = self
+            .builder
+            .build_int_add(
+                self.builder
+                    .build_int_z_extend(old_size, i64_type, "old_size64")
+                    .unwrap(),
+                self.builder
+                    .build_int_z_extend(delta, i64_type, "delta64")
+                    .unwrap(),
+                "new_size64",
+            )
+            .unwrap();
+        let too_large = self
+            .builder
+            .build_int_compare(
+                IntPredicate::UGT,
+                new_size,
+                i64_type.const_int(maximum, false),
+                "too_large",
+            )
+            .unwrap();
+        // Growing by zero must not reallocate: realloc may free a block
+        // resized to zero bytes.
+        let no_change = self
+            .builder
+            .build_int_compare(IntPredicate::EQ, delta, i32_type.const_zero(), "no_change")
+            .unwrap();
+        let skip = self
+            .builder
+            .build_or(too_large, no_change, "skip_grow")
+            .unwrap();
+        let skip_result = self
+            .builder
+            .build_select(
+                too_large,
+                i32_type.const_all_ones(),
+                old_size,
+                "skip_result",
+            )
+            .unwrap()
+            .into_int_value();
+        self.builder
+            .build_conditional_branch(skip, done_block, realloc_block)
+            .unwrap();
 
-        let assert_eq32_fn_type = void_type.fn_type(&[i32_type.into(), i32_type.into()], false);
-        self.module
-            .add_function("assert_eq32", assert_eq32_fn_type, None);
+        self.builder.position_at_end(realloc_block);
+        let realloc_fn = self.get_runtime_function(
+            "realloc",
+            ptr_type.fn_type(&[ptr_type.into(), i64_type.into()], false),
+        );
+        let bytes = self
+            .builder
+            .build_int_mul(new_size, i64_type.const_int(8, false), "bytes")
+            .unwrap();
+        let new_base = self
+            .builder
+            .build_call(realloc_fn, &[base.into(), bytes.into()], "new_table")
+            .unwrap()
+            .try_as_basic_value()
+            .left()
+            .unwrap()
+            .into_pointer_value();
+        let failed = self
+            .builder
+            .build_is_null(new_base, "realloc_failed")
+            .unwrap();
+        self.builder
+            .build_conditional_branch(failed, done_block, commit_block)
+            .unwrap();
 
-        let assert_eq64_fn_type = void_type.fn_type(&[i64_type.into(), i64_type.into()], false);
-        self.module
-            .add_function("assert_eq64", assert_eq64_fn_type, None);
+        self.builder.position_at_end(commit_block);
+        let vmctx = self.current_vmctx();
+        let layout = self.layout();
+        let new_size = self
+            .builder
+            .build_int_truncate(new_size, i32_type, "new_size")
+            .unwrap();
+        self.store_vmctx_field(
+            vmctx,
+            layout.table_base(table_index),
+            new_base.into(),
+            "table_base",
+        );
+        self.store_vmctx_field(
+            vmctx,
+            layout.table_size(table_index),
+            new_size.into(),
+            "table_size",
+        );
+        self.build_table_fill(new_base, old_size, new_size, init);
+        let fill_exit = self.builder.get_insert_block().unwrap();
+        self.builder.build_unconditional_branch(done_block).unwrap();
+
+        self.builder.position_at_end(done_block);
+        let result = self.builder.build_phi(i32_type, "table_grow").unwrap();
+        result.add_incoming(&[
+            (&skip_result, entry_block),
+            (&i32_type.const_all_ones(), realloc_block),
+            (&old_size, fill_exit),
+        ]);
+        Ok(result.as_basic_value().into_int_value())
     }
 
-    pub fn run_main(&self) -> Result<i32> {
-        type MainFunc = unsafe extern "C" fn() -> i32;
-
-        unsafe {
-            if let Some(assert_eq32_func) = self.module.get_function("assert_eq32") {
-                self.execution_engine.add_global_mapping(
-                    &assert_eq32_func,
-                    assert_eq32_wrapper as *const () as usize,
-                );
-            }
+    /// Constant array with the items of element segment `elem_index`, which
+    /// `table.init` copies from.
+    fn element_segment_items(
+        &self,
+        wasm_module: &WasmModule,
+        elem_index: u32,
+    ) -> Result<PointerValue<'ctx>> {
+        let name = format!("elem_{elem_index}");
+        if let Some(global) = self.module.get_global(&name) {
+            return Ok(global.as_pointer_value());
+        }
+        let segment = self
+            .element_segments
+            .get(elem_index as usize)
+            .ok_or(anyhow!("Invalid element segment index: {}", elem_index))?;
+        let ptr_type = self.context.ptr_type(inkwell::AddressSpace::default());
+        let items: Vec<PointerValue> = segment
+            .items
+            .iter()
+            .map(|item| {
+                item.and_then(|idx| self.function_reference(wasm_module, idx))
+                    .unwrap_or(ptr_type.const_null())
+            })
+            .collect();
+        let array = ptr_type.const_array(&items);
+        let global = self.module.add_global(array.get_type(), None, &name);
+        global.set_initializer(&array);
+        global.set_constant(true);
+        global.set_linkage(self.constant_linkage());
+        Ok(global.as_pointer_value())
+    }
 
-            if let Some(assert_eq64_func) = self.module.get_function("assert_eq64") {
-                self.execution_engine.add_global_mapping(
-                    &assert_eq64_func,
-                    assert_eq64_wrapper as *const () as usize,
-                );
-            }
+    fn val_type_to_llvm_type(&self, val_type: ValType) -> BasicTypeEnum<'ctx> {
+        match val_type {
+            ValType::I32 => self.context.i32_type().into(),
+            ValType::I64 => self.context.i64_type().into(),
+            ValType::F32 => self.context.f32_type().into(),
+            ValType::F64 => self.context.f64_type().into(),
+            ValType::Ref(_) => self
+                .context
+                .ptr_type(inkwell::AddressSpace::default())
+                .into(),
+            _ => panic!("Unsupported value type: {val_type:?}"),
+        }
+    }
 
-            let main_func: inkwell::execution_engine::JitFunction<MainFunc> = self
-                .execution_engine
-                .get_function("main")
-                .map_err(|_| anyhow!("Failed to find main function"))?;
+    fn create_llvm_function_type(
+        &self,
+        func_type: &wasmparser::FuncType,
+    ) -> inkwell::types::FunctionType<'ctx> {
+        let vmctx_type = self.context.ptr_type(inkwell::AddressSpace::default());
+        let param_types: Vec<BasicMetadataTypeEnum> = std::iter::once(vmctx_type.into())
+            .chain(
+                func_type
+                    .params()
+                    .iter()
+                    .map(|vt| self.val_type_to_llvm_type(*vt).into()),
+            )
+            .collect();
 
-            Ok(main_func.call())
+        if func_type.results().is_empty() {
+            self.context.void_type().fn_type(&param_types, false)
+        } else if func_type.results().len() == 1 {
+            let return_type = self.val_type_to_llvm_type(func_type.results()[0]);
+            return_type.fn_type(&param_types, false)
+        } else {
+            panic!("Multiple return values not supported");
         }
     }
 
-    pub fn print_ir_to_stdout(&self) {
-        self.module.print_to_stderr();
+    fn create_globals(&mut self, globals: &[crate::wasm_parser::WasmGlobal]) -> Result<()> {
+        for global in globals {
+            let global_type = global.global_type;
+            match global_type.content_type {
+                ValType::I32 | ValType::I64 | ValType::F32 | ValType::F64 | ValType::Ref(_) => {}
+                _ => {
+                    return Err(anyhow!(
+                        "Unsupported global type: {:?}",
+                        global_type.content_type
+                    ));
+                }
+            }
+            self.globals.push(global_type);
+        }
+        Ok(())
     }
 
-    pub fn write_ir_to_file(&self, output_path: &str) -> Result<()> {
-        self.module
-            .print_to_file(output_path)
-            .map_err(|e| anyhow!("Failed to write IR to file: {}", e))?;
+    fn create_memory(&mut self, memory_type: &MemoryType) -> Result<()> {
+        if memory_type.shared && memory_type.maximum.is_none() {
+            return Err(anyhow!("Shared memory must declare a maximum size"));
+        }
+        self.memory = Some(*memory_type);
+        let maximum_pages = memory_type.maximum.unwrap_or(MAX_PAGES).min(MAX_PAGES);
+        if matches!(self.role, ModuleRole::Partition | ModuleRole::LazyBody) {
+            let ptr_type = self.context.ptr_type(inkwell::AddressSpace::default());
+            let i32_type = self.context.i32_type();
+            self.module.add_function(
+                MEMORY_GROW_SYMBOL,
+                i32_type.fn_type(&[ptr_type.into(), i32_type.into()], false),
+                None,
+            );
+        } else if memory_type.shared {
+            self.create_shared_memory_grow_function(maximum_pages);
+        } else {
+            self.create_memory_grow_function(maximum_pages);
+        }
         Ok(())
     }
 
-    pub fn write_object_file(&self, output_path: &str) -> Result<()> {
-        use inkwell::targets::{CodeModel, FileType, RelocMode, TargetMachine};
+    /// Shared memory is reserved at its maximum size when the instance is
+    /// created, since other threads may hold pointers into it. Growing only
+    /// publishes more of the reservation, with a compare-and-swap on the size
+    /// so that concurrent grows each get a distinct previous size.
+    fn create_shared_memory_grow_function(&self, maximum_pages: u64) {
+        let ptr_type = self.context.ptr_type(inkwell::AddressSpace::default());
+        let i32_type = self.context.i32_type();
+        let i64_type = self.context.i64_type();
 
-        let target_triple = TargetMachine::get_default_triple();
-        let target = Target::from_triple(&target_triple)
-            .map_err(|e| anyhow!("Failed to get target: {}", e))?;
+        let grow_fn = self.add_vmctx_function(
+            MEMORY_GROW_SYMBOL,
+            i32_type.fn_type(&[ptr_type.into(), i32_type.into()], false),
+            Some(self.local_linkage()),
+        );
+        let vmctx = grow_fn.get_first_param().unwrap().into_pointer_value();
+        let delta = grow_fn.get_nth_param(1).unwrap().into_int_value();
+        delta.set_name("delta");
+
+        let entry_block = self.builder.get_insert_block().unwrap();
+        let retry_block = self.context.append_basic_block(grow_fn, "retry");
+        let exchange_block = self.context.append_basic_block(grow_fn, "exchange");
+        let commit_block = self.context.append_basic_block(grow_fn, "commit");
+        let fail_block = self.context.append_basic_block(grow_fn, "fail");
+
+        let delta = self
+            .builder
+            .build_int_z_extend(delta, i64_type, "delta64")
+            .unwrap();
+        let delta_size = self
+            .builder
+            .build_left_shift(delta, i64_type.const_int(16, false), "delta_size")
+            .unwrap();
+        let size_ptr = self.vmctx_field_ptr(vmctx, VmctxLayout::MEMORY_SIZE, "mem_size");
+        let initial_size = self.load_memory_size(vmctx, "initial_size");
+        self.builder
+            .build_unconditional_branch(retry_block)
+            .unwrap();
 
-        let target_machine = target
-            .create_target_machine(
-                &target_triple,
-                "generic",
-                "",
-                inkwell::OptimizationLevel::None,
-                RelocMode::Default,
-                CodeModel::Default,
+        self.builder.position_at_end(retry_block);
+        let old_size = self.builder.build_phi(i64_type, "old_size").unwrap();
+        let old_size_value = old_size.as_basic_value().into_int_value();
+        let new_size = self
+            .builder
+            .build_int_add(old_size_value, delta_size, "new_size")
+            .unwrap();
+        let too_large = self
+            .builder
+            .build_int_compare(
+                IntPredicate::UGT,
+                new_size,
+                i64_type.const_int(maximum_pages * PAGE_SIZE, false),
+                "too_large",
             )
-            .ok_or_else(|| anyhow!("Failed to create target machine"))?;
-
+            .unwrap();
+        self.builder
+            .build_conditional_branch(too_large, fail_block, exchange_block)
+            .unwrap();
+
+        self.builder.position_at_end(exchange_block);
+        let exchange = self
+            .builder
+            .build_cmpxchg(
+                size_ptr,
+                old_size_value,
+                new_size,
+                AtomicOrdering::SequentiallyConsistent,
+                AtomicOrdering::SequentiallyConsistent,
+            )
+            .unwrap();
+        let current_size = self
+            .builder
+            .build_extract_value(exchange, 0, "current_size")
+            .unwrap();
+        let exchanged = self
+            .builder
+            .build_extract_value(exchange, 1, "exchanged")
+            .unwrap()
+            .into_int_value();
+        self.builder
+            .build_conditional_branch(exchanged, commit_block, retry_block)
+            .unwrap();
+        old_size.add_incoming(&[
+            (&initial_size, entry_block),
+            (&current_size, exchange_block),
+        ]);
+
+        self.builder.position_at_end(commit_block);
+        let old_pages = self
+            .builder
+            .build_right_shift(
+                old_size_value,
+                i64_type.const_int(16, false),
+                false,
+                "old_pages",
+            )
+            .unwrap();
+        let old_pages = self
+            .builder
+            .build_int_truncate(old_pages, i32_type, "old_pages32")
+            .unwrap();
+        self.builder.build_return(Some(&old_pages)).unwrap();
+
+        self.builder.position_at_end(fail_block);
+        self.builder
+            .build_return(Some(&i32_type.const_all_ones()))
+            .unwrap();
+    }
+
+    /// Emits `wasm_memory_grow(vmctx, delta)`, which reallocates the instance's
+    /// linear memory, zeroes the new pages and returns the previous size in
+    /// pages, or -1 if the memory cannot grow.
+    fn create_memory_grow_function(&self, maximum_pages: u64) {
+        let ptr_type = self.context.ptr_type(inkwell::AddressSpace::default());
+        let i32_type = self.context.i32_type();
+        let i64_type = self.context.i64_type();
+
+        let realloc_fn = self.get_runtime_function(
+            "realloc",
+            ptr_type.fn_type(&[ptr_type.into(), i64_type.into()], false),
+        );
+
+        let grow_fn = self.add_vmctx_function(
+            MEMORY_GROW_SYMBOL,
+            i32_type.fn_type(&[ptr_type.into(), i32_type.into()], false),
+            Some(self.local_linkage()),
+        );
+        let vmctx = grow_fn.get_first_param().unwrap().into_pointer_value();
+        let delta = grow_fn.get_nth_param(1).unwrap().into_int_value();
+        delta.set_name("delta");
+
+        let realloc_block = self.context.append_basic_block(grow_fn, "realloc");
+        let commit_block = self.context.append_basic_block(grow_fn, "commit");
+        let fail_block = self.context.append_basic_block(grow_fn, "fail");
+
+        let old_size = self
+            .load_vmctx_field(vmctx, VmctxLayout::MEMORY_SIZE, i64_type.into(), "old_size")
+            .into_int_value();
+        let old_pages = self
+            .builder
+            .build_right_shift(old_size, i64_type.const_int(16, false), false, "old_pages")
+            .unwrap();
+        let delta = self
+            .builder
+            .build_int_z_extend(delta, i64_type, "delta64")
+            .unwrap();
+        let new_pages = self
+            .builder
+            .build_int_add(old_pages, delta, "new_pages")
+            .unwrap();
+        let too_large = self
+            .builder
+            .build_int_compare(
+                IntPredicate::UGT,
+                new_pages,
+                i64_type.const_int(maximum_pages, false),
+                "too_large",
+            )
+            .unwrap();
+        self.builder
+            .build_conditional_branch(too_large, fail_block, realloc_block)
+            .unwrap();
+
+        self.builder.position_at_end(realloc_block);
+        let new_size = self
+            .builder
+            .build_left_shift(new_pages, i64_type.const_int(16, false), "new_size")
+            .unwrap();
+        let old_base =
+            self.load_vmctx_field(vmctx, VmctxLayout::MEMORY_BASE, ptr_type.into(), "old_base");
+        let new_base = self
+            .builder
+            .build_call(realloc_fn, &[old_base.into(), new_size.into()], "new_base")
+            .unwrap()
+            .try_as_basic_value()
+            .left()
+            .unwrap()
+            .into_pointer_value();
+        let is_null = self.builder.build_is_null(new_base, "is_null").unwrap();
+        let non_empty = self
+            .builder
+            .build_int_compare(
+                IntPredicate::NE,
+                new_size,
+                i64_type.const_zero(),
+                "non_empty",
+            )
+            .unwrap();
+        let failed = self
+            .builder
+            .build_and(is_null, non_empty, "failed")
+            .unwrap();
+        self.builder
+            .build_conditional_branch(failed, fail_block, commit_block)
+            .unwrap();
+
+        self.builder.position_at_end(commit_block);
+        let tail = unsafe {
+            self.builder
+                .build_gep(self.context.i8_type(), new_base, &[old_size], "tail")
+                .unwrap()
+        };
+        let tail_size = self
+            .builder
+            .build_int_sub(new_size, old_size, "tail_size")
+            .unwrap();
+        self.builder
+            .build_memset(tail, 1, self.context.i8_type().const_zero(), tail_size)
+            .unwrap();
+        self.store_vmctx_field(vmctx, VmctxLayout::MEMORY_BASE, new_base.into(), "mem_base");
+        self.store_vmctx_field(vmctx, VmctxLayout::MEMORY_SIZE, new_size.into(), "mem_size");
+        let old_pages = self
+            .builder
+            .build_int_truncate(old_pages, i32_type, "old_pages32")
+            .unwrap();
+        self.builder.build_return(Some(&old_pages)).unwrap();
+
+        self.builder.position_at_end(fail_block);
+        self.builder
+            .build_return(Some(&i32_type.const_all_ones()))
+            .unwrap();
+    }
+
+    fn get_memory_base(&self) -> Result<PointerValue<'ctx>> {
+        self.memory.ok_or(anyhow!("No memory allocated"))?;
+        let ptr_type = self.context.ptr_type(inkwell::AddressSpace::default());
+        Ok(self
+            .load_vmctx_field(
+                self.current_vmctx(),
+                VmctxLayout::MEMORY_BASE,
+                ptr_type.into(),
+                "mem_base",
+            )
+            .into_pointer_value())
+    }
+
+    fn get_effective_address(
+        &self,
+        offset: inkwell::values::IntValue<'ctx>,
+        static_offset: u64,
+    ) -> inkwell::values::IntValue<'ctx> {
+        let i64_type = self.context.i64_type();
+
+        let address = self
+            .builder
+            .build_int_z_extend(offset, i64_type, "address")
+            .unwrap();
+        if static_offset > 0 {
+            let static_offset_val = i64_type.const_int(static_offset, false);
+            self.builder
+                .build_int_add(address, static_offset_val, "offset_sum")
+                .unwrap()
+        } else {
+            address
+        }
+    }
+
+    /// Traps unless the `length` bytes from `address`, both `i64`, lie within
+    /// linear memory.
+    fn build_memory_bounds_check(&self, address: IntValue<'ctx>, length: IntValue<'ctx>) {
+        let end = self
+            .builder
+            .build_int_add(address, length, "access_end")
+            .unwrap();
+        let size = self.load_memory_size(self.current_vmctx(), "mem_size");
+        let out_of_bounds = self
+            .builder
+            .build_int_compare(IntPredicate::UGT, end, size, "out_of_bounds")
+            .unwrap();
+        self.build_trap_if(out_of_bounds, Trap::MemoryOutOfBounds);
+    }
+
+    /// The size in bytes of the access `memarg` describes, which is its
+    /// natural alignment for every supported memory instruction.
+    fn access_size(&self, memarg: &wasmparser::MemArg) -> IntValue<'ctx> {
+        self.context
+            .i64_type()
+            .const_int(1 << memarg.max_align, false)
+    }
+
+    /// Pointer to the memory accessed at `offset` with `memarg`, trapping if
+    /// any byte of the access is out of bounds.
+    fn get_memory_ptr(
+        &self,
+        offset: inkwell::values::IntValue<'ctx>,
+        memarg: &wasmparser::MemArg,
+    ) -> Result<PointerValue<'ctx>> {
+        let base_ptr = self.get_memory_base()?;
+        let address = self.get_effective_address(offset, memarg.offset);
+        self.build_memory_bounds_check(address, self.access_size(memarg));
+
+        let ptr = unsafe {
+            self.builder
+                .build_gep(self.context.i8_type(), base_ptr, &[address], "mem_ptr")
+                .unwrap()
+        };
+        Ok(ptr)
+    }
+
+    /// Like `get_memory_ptr`, but also traps unless the effective address is
+    /// naturally aligned for the access, as atomic accesses require.
+    fn get_atomic_ptr(
+        &self,
+        offset: inkwell::values::IntValue<'ctx>,
+        memarg: &wasmparser::MemArg,
+    ) -> Result<PointerValue<'ctx>> {
+        let base_ptr = self.get_memory_base()?;
+        let address = self.get_effective_address(offset, memarg.offset);
+        self.build_memory_bounds_check(address, self.access_size(memarg));
+
+        if memarg.max_align > 0 {
+            let i64_type = self.context.i64_type();
+            let function = self.current_function();
+            let misaligned_bits = self
+                .builder
+                .build_and(
+                    address,
+                    i64_type.const_int((1 << memarg.max_align) - 1, false),
+                    "misaligned_bits",
+                )
+                .unwrap();
+            let misaligned = self
+                .builder
+                .build_int_compare(
+                    IntPredicate::NE,
+                    misaligned_bits,
+                    i64_type.const_zero(),
+                    "misaligned",
+                )
+                .unwrap();
+            let trap_block = self.context.append_basic_block(function, "unaligned_trap");
+            let aligned_block = self.context.append_basic_block(function, "aligned");
+            self.builder
+                .build_conditional_branch(misaligned, trap_block, aligned_block)
+                .unwrap();
+
+            self.builder.position_at_end(trap_block);
+            self.build_trap(Trap::UnalignedAtomic);
+            self.builder.position_at_end(aligned_block);
+        }
+
+        let ptr = unsafe {
+            self.builder
+                .build_gep(self.context.i8_type(), base_ptr, &[address], "atomic_ptr")
+                .unwrap()
+        };
+        Ok(ptr)
+    }
+
+    fn has_shared_memory(&self) -> bool {
+        self.memory.is_some_and(|memory| memory.shared)
+    }
+
+    /// Loads the memory size in bytes. Other threads may grow a shared
+    /// memory concurrently, so the load is atomic there.
+    fn load_memory_size(&self, vmctx: PointerValue<'ctx>, name: &str) -> IntValue<'ctx> {
+        let size = self
+            .load_vmctx_field(
+                vmctx,
+                VmctxLayout::MEMORY_SIZE,
+                self.context.i64_type().into(),
+                name,
+            )
+            .into_int_value();
+        if self.has_shared_memory() {
+            let load = size.as_instruction().unwrap();
+            load.set_atomic_ordering(AtomicOrdering::SequentiallyConsistent)
+                .unwrap();
+            load.set_alignment(8).unwrap();
+        }
+        size
+    }
+
+    fn get_memory_size(&self) -> Result<inkwell::values::IntValue<'ctx>> {
+        self.memory.ok_or(anyhow!("No memory allocated"))?;
+        let i64_type = self.context.i64_type();
+        let size = self.load_memory_size(self.current_vmctx(), "mem_size");
+        let pages = self
+            .builder
+            .build_right_shift(size, i64_type.const_int(16, false), false, "pages")
+            .unwrap();
+        Ok(self
+            .builder
+            .build_int_truncate(pages, self.context.i32_type(), "memory_size")
+            .unwrap())
+    }
+
+    fn grow_memory(
+        &self,
+        delta: inkwell::values::IntValue<'ctx>,
+    ) -> Result<inkwell::values::IntValue<'ctx>> {
+        self.memory.ok_or(anyhow!("No memory allocated"))?;
+        let grow_fn = self
+            .module
+            .get_function(MEMORY_GROW_SYMBOL)
+            .ok_or(anyhow!("No memory allocated"))?;
+        let result = self
+            .builder
+            .build_call(
+                grow_fn,
+                &[self.current_vmctx().into(), delta.into()],
+                "memory_grow",
+            )
+            .unwrap();
+        Ok(result.try_as_basic_value().left().unwrap().into_int_value())
+    }
+
+    fn declare_assert_functions(&self) {
+        let i32_type = self.context.i32_type();
+        let i64_type = self.context.i64_type();
+        let void_type = self.context.void_type();
+
+        let assert_eq32_fn_type = void_type.fn_type(&[i32_type.into(), i32_type.into()], false);
+        self.module
+            .add_function("assert_eq32", assert_eq32_fn_type, None);
+
+        let assert_eq64_fn_type = void_type.fn_type(&[i64_type.into(), i64_type.into()], false);
+        self.module
+            .add_function("assert_eq64", assert_eq64_fn_type, None);
+    }
+
+    /// Points the host functions the module calls at their in-process
+    /// implementations before anything is JIT-compiled.
+    fn add_host_mappings(&self, execution_engine: &ExecutionEngine<'ctx>) {
+        let host_functions = [
+            ("assert_eq32", assert_eq32_wrapper as *const () as usize),
+            ("assert_eq64", assert_eq64_wrapper as *const () as usize),
+            (
+                runtime::WAIT32_SYMBOL,
+                runtime::memory_atomic_wait32 as *const () as usize,
+            ),
+            (
+                runtime::WAIT64_SYMBOL,
+                runtime::memory_atomic_wait64 as *const () as usize,
+            ),
+            (
+                runtime::NOTIFY_SYMBOL,
+                runtime::memory_atomic_notify as *const () as usize,
+            ),
+            (LAZY_COMPILE_SYMBOL, lazy_compile as *const () as usize),
+        ];
+        for (name, address) in host_functions {
+            if let Some(function) = self.module.get_function(name) {
+                execution_engine.add_global_mapping(&function, address);
+            }
+        }
+        let counters = self
+            .profile_counters
+            .get_or_init(|| self.profile_counters());
+        for function_counters in counters {
+            let symbol = format!("{}{}", function_counters.function, profile::COUNTERS_SUFFIX);
+            if let Some(global) = self.module.get_global(&symbol) {
+                execution_engine.add_global_mapping(&global, function_counters.address());
+            }
+        }
+    }
+
+    /// Allocates the counters declared by `declare_profile_counters`.
+    fn profile_counters(&self) -> Vec<FunctionCounters> {
+        self.module
+            .get_globals()
+            .filter(|global| global.is_declaration())
+            .filter_map(|global| {
+                let name = global.get_name().to_str().ok()?;
+                let function = name.strip_suffix(profile::COUNTERS_SUFFIX)?;
+                let offsets_symbol = format!("{function}{}", profile::LOOP_OFFSETS_SUFFIX);
+                let loop_offsets = self
+                    .module
+                    .get_global(&offsets_symbol)
+                    .and_then(|offsets| offsets.get_initializer())
+                    .and_then(|offsets| {
+                        let offsets = offsets.into_array_value();
+                        let bytes = offsets.as_const_string()?;
+                        Some(
+                            bytes
+                                .chunks_exact(8)
+                                .map(|chunk| u64::from_le_bytes(chunk.try_into().unwrap()))
+                                .collect(),
+                        )
+                    })
+                    .unwrap_or_default();
+                Some(FunctionCounters::new(function.to_string(), loop_offsets))
+            })
+            .collect()
+    }
+
+    /// Calls of every function and iterations of every loop since the module
+    /// started running, highest first. Empty unless compiled with
+    /// `CompilerOptions::profile`.
+    pub fn profile(&self) -> Vec<ProfileEntry> {
+        self.profile_counters
+            .get()
+            .map(|counters| profile::collect_entries(counters))
+            .unwrap_or_default()
+    }
+
+    /// Appends the address range of every JIT-compiled function to
+    /// `/tmp/perf-<pid>.map`, returning its path. Needs the module to be
+    /// compiled with `CompilerOptions::perf_map`. Functions inlined into all
+    /// of their callers have no code and are left out.
+    pub fn write_perf_map(&self) -> Result<PathBuf> {
+        if !self.options.perf_map {
+            return Err(anyhow!("Perf maps need a module compiled with perf_map"));
+        }
+        let defined: HashSet<String> = self
+            .module
+            .get_functions()
+            .filter(|function| function.count_basic_blocks() > 0)
+            .map(|function| function.get_name().to_string_lossy().into_owned())
+            .collect();
+        // Instance functions are external and never inlined away, so they
+        // identify the object holding this module's code.
+        let instance_new = self.function_address(header::INSTANCE_NEW_SYMBOL)?;
+        let mut functions: Vec<JitFunction> = profile::jit_functions(instance_new)?
+            .into_iter()
+            .filter(|function| defined.contains(&function.name))
+            .collect();
+        functions.sort_by_key(|function| function.address);
+        profile::write_perf_map(&functions)
+    }
+
+    pub fn run_main(&self) -> Result<i32> {
+        self.run_main_with_limits(&ExecutionLimits::default())
+    }
+
+    /// Runs `main` within `limits`, which need the module to be compiled
+    /// with `CompilerOptions::consume_fuel` for fuel,
+    /// `CompilerOptions::interruptible` for a timeout and
+    /// `CompilerOptions::check_stack` for a stack budget. Running out of any
+    /// of them fails with the corresponding `Trap`.
+    pub fn run_main_with_limits(&self, limits: &ExecutionLimits) -> Result<i32> {
+        let execution_engine = self
+            .execution_engine
+            .as_ref()
+            .ok_or(anyhow!("Compiler has no execution engine"))?;
+        if self.role == ModuleRole::LazyMain {
+            return Err(anyhow!("Lazily compiled modules run with run_main_lazy"));
+        }
+        self.add_host_mappings(execution_engine);
+        self.call_main(execution_engine, limits)
+    }
+
+    /// Runs `main` of a module compiled with `CompilerOptions::lazy`, within
+    /// `limits` as for `run_main_with_limits`. `wasm_module` must be the
+    /// module passed to `compile_module`; function bodies are compiled from
+    /// it as they are first called, on the calling thread.
+    pub fn run_main_lazy(&self, wasm_module: &WasmModule, limits: &ExecutionLimits) -> Result<i32> {
+        let execution_engine = self
+            .execution_engine
+            .as_ref()
+            .ok_or(anyhow!("Compiler has no execution engine"))?;
+        if self.role != ModuleRole::LazyMain {
+            return Err(anyhow!("Module was not compiled lazily"));
+        }
+        self.add_host_mappings(execution_engine);
+        let jit = LazyJit {
+            compiler: self,
+            wasm_module,
+            error: RefCell::new(None),
+        };
+        let previous = LAZY_JIT.replace(&jit as *const LazyJit as *const ());
+        let result = self.call_main(execution_engine, limits);
+        LAZY_JIT.set(previous);
+        match jit.error.into_inner() {
+            Some(error) => Err(error),
+            None => result,
+        }
+    }
+
+    /// Calls `main`, or `wasm_limited_main` in modules compiled with
+    /// execution limits or lazily.
+    fn call_main(
+        &self,
+        execution_engine: &ExecutionEngine<'ctx>,
+        limits: &ExecutionLimits,
+    ) -> Result<i32> {
+        type MainFunc = unsafe extern "C" fn() -> i32;
+        type LimitedMainFunc = unsafe extern "C" fn(i64, usize, usize) -> u32;
+
+        if limits.fuel.is_some() && !self.options.consume_fuel {
+            return Err(anyhow!(
+                "Fuel limits need a module compiled to consume fuel"
+            ));
+        }
+        if limits.timeout.is_some() && !self.options.interruptible {
+            return Err(anyhow!(
+                "Timeouts need a module compiled to be interruptible"
+            ));
+        }
+        if limits.max_stack.is_some() && !self.options.check_stack {
+            return Err(anyhow!(
+                "Stack budgets need a module compiled with stack checks"
+            ));
+        }
+        if !self.has_limited_main() {
+            return unsafe {
+                execution_engine
+                    .get_function::<MainFunc>("main")
+                    .map(|main_func| main_func.call())
+                    .map_err(|_| anyhow!("Failed to find main function"))
+            };
+        }
+
+        let limited_main =
+            unsafe { execution_engine.get_function::<LimitedMainFunc>(LIMITED_MAIN_SYMBOL) }
+                .map_err(|_| anyhow!("Failed to find main function"))?;
+        let fuel = limits
+            .fuel
+            .map_or(i64::MAX, |fuel| fuel.min(i64::MAX as u64) as i64);
+        let interrupt = InterruptHandle::default();
+        let max_stack = limits.max_stack.unwrap_or(DEFAULT_MAX_STACK);
+        let trap = interrupt.run_with_timeout(limits.timeout, || unsafe {
+            limited_main.call(fuel, interrupt.flag_address(), stack_limit(max_stack))
+        });
+        Trap::check_code(trap)?;
+        Ok(0)
+    }
+
+    /// Address of the JIT-compiled function `name`. Host functions are mapped
+    /// first, since the module is finalized on the first lookup.
+    pub(crate) fn function_address(&self, name: &str) -> Result<usize> {
+        let execution_engine = self
+            .execution_engine
+            .as_ref()
+            .ok_or(anyhow!("Compiler has no execution engine"))?;
+        self.add_host_mappings(execution_engine);
+        execution_engine
+            .get_function_address(name)
+            .map_err(|e| anyhow!("Failed to find {}: {}", name, e))
+    }
+
+    /// The compiled module as bitcode, for `from_bitcode`.
+    pub fn to_bitcode(&self) -> Vec<u8> {
+        self.module.write_bitcode_to_memory().as_slice().to_vec()
+    }
+
+    pub fn print_ir_to_stdout(&self) {
+        self.module.print_to_stderr();
+    }
+
+    pub fn write_ir_to_file(&self, output_path: &str) -> Result<()> {
+        self.module
+            .print_to_file(output_path)
+            .map_err(|e| anyhow!("Failed to write IR to file: {}", e))?;
+        Ok(())
+    }
+
+    pub fn write_object_file(&self, output_path: &str) -> Result<()> {
+        self.write_machine_code(output_path.as_ref(), FileType::Object, self.options.reloc)
+    }
+
+    pub fn write_output(&self, output_path: &str, kind: EmitKind) -> Result<()> {
+        let output_path = Path::new(output_path);
+        match kind {
+            EmitKind::Obj => {
+                self.write_machine_code(output_path, FileType::Object, self.options.reloc)
+            }
+            EmitKind::Asm => {
+                self.write_machine_code(output_path, FileType::Assembly, self.options.reloc)
+            }
+            EmitKind::Bc => {
+                if self.module.write_bitcode_to_path(output_path) {
+                    Ok(())
+                } else {
+                    Err(anyhow!(
+                        "Failed to write bitcode to {}",
+                        output_path.display()
+                    ))
+                }
+            }
+            EmitKind::Ll => self
+                .module
+                .print_to_file(output_path)
+                .map_err(|e| anyhow!("Failed to write IR to file: {}", e)),
+            EmitKind::So => {
+                self.check_linker()?;
+                let object = linker::temp_path("o");
+                self.write_machine_code(&object, FileType::Object, RelocModel::Pic)?;
+                let result = linker::link_shared_library(&object, output_path);
+                std::fs::remove_file(&object).ok();
+                result
+            }
+            EmitKind::Exe => {
+                if self.module.get_function("main").is_none() {
+                    return Err(anyhow!("Executable output requires a start function"));
+                }
+                self.check_linker()?;
+                let reloc = match self.options.reloc {
+                    RelocModel::Static => RelocModel::Static,
+                    _ => RelocModel::Pic,
+                };
+                let object = linker::temp_path("o");
+                self.write_machine_code(&object, FileType::Object, reloc)?;
+                let result =
+                    linker::link_executable(&object, output_path, reloc == RelocModel::Pic);
+                std::fs::remove_file(&object).ok();
+                result
+            }
+        }
+    }
+
+    /// Fails if linking needs a cross linker that is not configured: the
+    /// host `cc` only links for the host, so a foreign `--target` needs `CC`.
+    fn check_linker(&self) -> Result<()> {
+        let Some(target) = &self.options.target else {
+            return Ok(());
+        };
+        let target = TargetMachine::normalize_triple(&TargetTriple::create(target));
+        let host = TargetMachine::normalize_triple(&TargetMachine::get_default_triple());
+        if target == host || linker::cc_overridden() {
+            return Ok(());
+        }
+        Err(anyhow!(
+            "Linking for {} needs a cross compiler: set CC, or emit an object file",
+            target.as_str().to_string_lossy()
+        ))
+    }
+
+    fn write_machine_code(
+        &self,
+        output_path: &Path,
+        file_type: FileType,
+        reloc: RelocModel,
+    ) -> Result<()> {
+        let target_machine = self.create_target_machine_with_reloc(reloc)?;
+
         target_machine
-            .write_to_file(&self.module, FileType::Object, output_path.as_ref())
-            .map_err(|e| anyhow!("Failed to write object file: {}", e))?;
+            .write_to_file(&self.module, file_type, output_path)
+            .map_err(|e| anyhow!("Failed to write {}: {}", output_path.display(), e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wasm_parser::{Function, FunctionBody, Names, WasmModule};
+    use inkwell::context::Context;
+    use wasmparser::{FuncType, ValType};
+
+    fn create_simple_function(idx: u32, operators: Vec<Operator<'static>>) -> Function {
+        Function {
+            idx,
+            name: None,
+            func_type: FuncType::new([], []),
+            body: FunctionBody {
+                locals: vec![],
+                operators,
+                offsets: vec![],
+            },
+        }
+    }
+
+    #[test]
+    fn test_compiler_creation() {
+        let context = Context::create();
+        let result = Compiler::new(&context, "test_module");
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_val_type_conversion() {
+        let context = Context::create();
+        let compiler = Compiler::new(&context, "test").unwrap();
+
+        let i32_type = compiler.val_type_to_llvm_type(ValType::I32);
+        assert!(i32_type.is_int_type());
+
+        let i64_type = compiler.val_type_to_llvm_type(ValType::I64);
+        assert!(i64_type.is_int_type());
+
+        let f32_type = compiler.val_type_to_llvm_type(ValType::F32);
+        assert!(f32_type.is_float_type());
+
+        let f64_type = compiler.val_type_to_llvm_type(ValType::F64);
+        assert!(f64_type.is_float_type());
+    }
+
+    #[test]
+    fn test_compile_empty_function() {
+        let context = Context::create();
+        let compiler = Compiler::new(&context, "test").unwrap();
+
+        let function = create_simple_function(0, vec![Operator::End]);
+        let module = WasmModule {
+            ..Default::default()
+        };
+        let result = compiler.compile_function(&function, &[], &module);
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_compile_function_with_i32_const() {
+        let context = Context::create();
+        let compiler = Compiler::new(&context, "test").unwrap();
+
+        let operators = vec![
+            Operator::I32Const { value: 42 },
+            Operator::Drop,
+            Operator::End,
+        ];
+
+        let function = create_simple_function(0, operators);
+        let module = WasmModule {
+            ..Default::default()
+        };
+        let result = compiler.compile_function(&function, &[], &module);
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_compile_function_with_arithmetic() {
+        let context = Context::create();
+        let compiler = Compiler::new(&context, "test").unwrap();
+
+        let operators = vec![
+            Operator::I32Const { value: 10 },
+            Operator::I32Const { value: 5 },
+            Operator::I32Add,
+            Operator::Drop,
+            Operator::End,
+        ];
+
+        let function = create_simple_function(0, operators);
+        let module = WasmModule {
+            ..Default::default()
+        };
+        let result = compiler.compile_function(&function, &[], &module);
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_compile_function_with_all_arithmetic_ops() {
+        let context = Context::create();
+        let compiler = Compiler::new(&context, "test").unwrap();
+
+        let operators = vec![
+            Operator::I32Const { value: 20 },
+            Operator::I32Const { value: 4 },
+            Operator::I32Add,
+            Operator::I32Const { value: 2 },
+            Operator::I32Sub,
+            Operator::I32Const { value: 3 },
+            Operator::I32Mul,
+            Operator::I32Const { value: 6 },
+            Operator::I32DivS,
+            Operator::Drop,
+            Operator::End,
+        ];
+
+        let function = create_simple_function(0, operators);
+        let module = WasmModule {
+            ..Default::default()
+        };
+        let result = compiler.compile_function(&function, &[], &module);
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_compile_empty_module() {
+        let context = Context::create();
+        let mut compiler = Compiler::new(&context, "test").unwrap();
+
+        let module = WasmModule {
+            ..Default::default()
+        };
+
+        let result = compiler.compile_module(&module);
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_compile_module_with_optimization() {
+        let context = Context::create();
+        let options = CompilerOptions {
+            opt_level: OptLevel::O2,
+            ..Default::default()
+        };
+        let mut compiler = Compiler::with_options(&context, "test", options).unwrap();
+
+        let function = Function {
+            idx: 0,
+            name: None,
+            func_type: FuncType::new([], []),
+            body: FunctionBody {
+                locals: vec![ValType::I32],
+                operators: vec![
+                    Operator::I32Const { value: 42 },
+                    Operator::LocalSet { local_index: 0 },
+                    Operator::LocalGet { local_index: 0 },
+                    Operator::Drop,
+                    Operator::End,
+                ],
+                offsets: vec![],
+            },
+        };
+        let module = WasmModule {
+            functions: vec![function],
+            start_func_idx: Some(0),
+            ..Default::default()
+        };
+
+        compiler.compile_module(&module).unwrap();
+        let ir = compiler.module.print_to_string().to_string();
+        assert!(!ir.contains("alloca"), "mem2reg should have run:\n{ir}");
+    }
+
+    #[test]
+    fn test_bitcode_round_trip() {
+        use crate::wasm_parser::Export;
+
+        let mut function =
+            create_simple_function(0, vec![Operator::I32Const { value: 42 }, Operator::End]);
+        function.func_type = FuncType::new([], [ValType::I32]);
+        let start = create_simple_function(1, vec![Operator::End]);
+        let module = WasmModule {
+            functions: vec![function, start],
+            start_func_idx: Some(1),
+            exports: vec![Export {
+                name: "answer".to_string(),
+                kind: ExternalKind::Func,
+                index: 0,
+            }],
+            ..Default::default()
+        };
+
+        let options = CompilerOptions {
+            opt_level: OptLevel::O2,
+            ..Default::default()
+        };
+        let bitcode = {
+            let context = Context::create();
+            let mut compiler = Compiler::with_options(&context, "test", options.clone()).unwrap();
+            compiler.compile_module(&module).unwrap();
+            compiler.to_bitcode()
+        };
+
+        let context = Context::create();
+        let compiler = Compiler::from_bitcode(&context, "test", &bitcode, options).unwrap();
+        assert_eq!(compiler.run_main().unwrap(), 0);
+
+        type NewFn = unsafe extern "C" fn() -> *mut u8;
+        type FreeFn = unsafe extern "C" fn(*mut u8);
+        type AnswerFn = unsafe extern "C" fn(*mut u8) -> i32;
+
+        unsafe {
+            let engine = compiler.execution_engine.as_ref().unwrap();
+            let new = engine.get_function::<NewFn>("wasm_instance_new").unwrap();
+            let free = engine.get_function::<FreeFn>("wasm_instance_free").unwrap();
+            let answer = engine.get_function::<AnswerFn>("answer").unwrap();
+
+            let instance = new.call();
+            assert_eq!(answer.call(instance), 42);
+            free.call(instance);
+        }
+
+        let context = Context::create();
+        assert!(
+            Compiler::from_bitcode(&context, "test", b"not bitcode", CompilerOptions::default())
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_parallel_compilation_is_deterministic() {
+        use crate::wasm_parser::{ElementSegment, ElementSegmentKind, Export};
+        use wasmparser::{RefType, TableType};
+
+        // A chain of functions each calling the next, spanning several
+        // partitions, plus a dispatcher calling into them through a table.
+        let chain_length = 2 * PARTITION_SIZE + 88;
+        let last = chain_length as u32 - 1;
+        let mut functions: Vec<Function> = (0..last)
+            .map(|idx| {
+                let mut function = create_simple_function(
+                    idx,
+                    vec![
+                        Operator::Call {
+                            function_index: idx + 1,
+                        },
+                        Operator::I32Const { value: 1 },
+                        Operator::I32Add,
+                        Operator::End,
+                    ],
+                );
+                function.func_type = FuncType::new([], [ValType::I32]);
+                function
+            })
+            .collect();
+        let mut tail = create_simple_function(
+            last,
+            vec![
+                Operator::I32Const { value: 0 },
+                Operator::MemoryGrow { mem: 0 },
+                Operator::End,
+            ],
+        );
+        tail.func_type = FuncType::new([], [ValType::I32]);
+        functions.push(tail);
+        let mut dispatch = create_simple_function(
+            last + 1,
+            vec![
+                Operator::LocalGet { local_index: 0 },
+                Operator::CallIndirect {
+                    type_index: 0,
+                    table_index: 0,
+                },
+                Operator::End,
+            ],
+        );
+        dispatch.func_type = FuncType::new([ValType::I32], [ValType::I32]);
+        functions.push(dispatch);
+
+        let module = WasmModule {
+            functions,
+            memories: vec![MemoryType {
+                memory64: false,
+                shared: false,
+                initial: 1,
+                maximum: None,
+                page_size_log2: None,
+            }],
+            tables: vec![TableType {
+                element_type: RefType::FUNCREF,
+                table64: false,
+                initial: 2,
+                maximum: None,
+                shared: false,
+            }],
+            function_types: vec![
+                FuncType::new([], [ValType::I32]),
+                FuncType::new([ValType::I32], [ValType::I32]),
+            ],
+            element_segments: vec![ElementSegment {
+                kind: ElementSegmentKind::Active {
+                    table_index: 0,
+                    offset: 0,
+                },
+                items: vec![Some(last), Some(300)],
+            }],
+            exports: vec![
+                Export {
+                    name: "chain".to_string(),
+                    kind: ExternalKind::Func,
+                    index: 0,
+                },
+                Export {
+                    name: "dispatch".to_string(),
+                    kind: ExternalKind::Func,
+                    index: last + 1,
+                },
+            ],
+            ..Default::default()
+        };
+
+        let compile = |jobs, opt_level| {
+            let context = Context::create();
+            let options = CompilerOptions {
+                jobs: Some(jobs),
+                opt_level,
+                ..Default::default()
+            };
+            let mut compiler = Compiler::with_options(&context, "test", options).unwrap();
+            compiler.compile_module(&module).unwrap();
+            assert!(compiler.module.verify().is_ok());
+            compiler.module.print_to_string().to_string()
+        };
+        // Partitions are optimized on their own, so the output does not
+        // depend on the thread count at any level.
+        assert_eq!(compile(1, OptLevel::O2), compile(3, OptLevel::O2));
+        let ir = compile(1, OptLevel::O0);
+        assert_eq!(ir, compile(3, OptLevel::O0));
+        assert!(
+            ir.contains("define internal i32 @func_300(ptr %vmctx)"),
+            "{ir}"
+        );
+        assert!(
+            ir.contains("define internal i32 @wasm_memory_grow("),
+            "{ir}"
+        );
+        assert_eq!(ir.matches(".funcref = private constant").count(), 2, "{ir}");
+
+        type NewFn = unsafe extern "C" fn() -> *mut u8;
+        type FreeFn = unsafe extern "C" fn(*mut u8);
+        type ChainFn = unsafe extern "C" fn(*mut u8) -> i32;
+        type DispatchFn = unsafe extern "C" fn(*mut u8, i32) -> i32;
+
+        for opt_level in [OptLevel::O0, OptLevel::O2] {
+            let context = Context::create();
+            let options = CompilerOptions {
+                opt_level,
+                ..Default::default()
+            };
+            let mut compiler = Compiler::with_options(&context, "test", options).unwrap();
+            compiler.compile_module(&module).unwrap();
+
+            unsafe {
+                let engine = compiler.execution_engine.as_ref().unwrap();
+                let new = engine.get_function::<NewFn>("wasm_instance_new").unwrap();
+                let free = engine.get_function::<FreeFn>("wasm_instance_free").unwrap();
+                let chain = engine.get_function::<ChainFn>("chain").unwrap();
+                let dispatch = engine.get_function::<DispatchFn>("dispatch").unwrap();
+
+                let instance = new.call();
+                assert_eq!(chain.call(instance), chain_length as i32);
+                assert_eq!(dispatch.call(instance, 0), 1);
+                assert_eq!(dispatch.call(instance, 1), last as i32 - 300 + 1);
+                free.call(instance);
+            }
+        }
+    }
+
+    #[test]
+    fn test_lazy_compilation_tiers_up_hot_functions() {
+        // Function 1 doubles and increments its argument. The start function
+        // calls it four times and checks the result through the
+        // `assert_eq32` import, while function 3 would fail to compile.
+        let mut twice_plus_one = create_simple_function(
+            1,
+            vec![
+                Operator::LocalGet { local_index: 0 },
+                Operator::I32Const { value: 2 },
+                Operator::I32Mul,
+                Operator::I32Const { value: 1 },
+                Operator::I32Add,
+                Operator::End,
+            ],
+        );
+        twice_plus_one.func_type = FuncType::new([ValType::I32], [ValType::I32]);
+        let mut operators = vec![Operator::I32Const { value: 1 }];
+        operators.extend((0..4).map(|_| Operator::Call { function_index: 1 }));
+        operators.extend([
+            Operator::I32Const { value: 31 },
+            Operator::Call { function_index: 0 },
+            Operator::End,
+        ]);
+        let start = create_simple_function(2, operators);
+        let uncalled = create_simple_function(
+            3,
+            vec![
+                Operator::RefFunc { function_index: 0 },
+                Operator::Drop,
+                Operator::End,
+            ],
+        );
+        let module = WasmModule {
+            functions: vec![twice_plus_one, start, uncalled],
+            start_func_idx: Some(2),
+            has_assert_eq32_import: true,
+            import_count: 1,
+            ..Default::default()
+        };
+
+        let context = Context::create();
+        let options = CompilerOptions {
+            lazy: true,
+            tier_up_calls: Some(3),
+            ..Default::default()
+        };
+        let mut compiler = Compiler::with_options(&context, "test", options).unwrap();
+        compiler.compile_module(&module).unwrap();
+        assert!(compiler.module.verify().is_ok());
+        let ir = compiler.module.print_to_string().to_string();
+        assert!(
+            ir.contains("@func_1.slot = internal global ptr null"),
+            "{ir}"
+        );
+        assert!(ir.contains("@func_1.calls = internal global i32 0"), "{ir}");
+        assert!(!ir.contains("mul i32"), "{ir}");
+        assert!(compiler.run_main().is_err());
+
+        assert_eq!(
+            compiler
+                .run_main_lazy(&module, &ExecutionLimits::default())
+                .unwrap(),
+            0
+        );
+        let engine = compiler.execution_engine.as_ref().unwrap();
+        assert!(engine.get_function_address("func_1.tier1").is_ok());
+        assert!(engine.get_function_address("func_1.tier2").is_ok());
+        assert!(engine.get_function_address("func_3.tier1").is_err());
+    }
+
+    #[test]
+    fn test_lazy_compile_failure_is_reported() {
+        // The start function calls function 2, whose body refers to an
+        // imported function and fails to compile on its first call.
+        let start =
+            create_simple_function(1, vec![Operator::Call { function_index: 2 }, Operator::End]);
+        let broken = create_simple_function(
+            2,
+            vec![
+                Operator::RefFunc { function_index: 0 },
+                Operator::Drop,
+                Operator::End,
+            ],
+        );
+        let module = WasmModule {
+            functions: vec![start, broken],
+            start_func_idx: Some(1),
+            has_assert_eq32_import: true,
+            import_count: 1,
+            ..Default::default()
+        };
+
+        let context = Context::create();
+        let options = CompilerOptions {
+            lazy: true,
+            ..Default::default()
+        };
+        let mut compiler = Compiler::with_options(&context, "test", options).unwrap();
+        compiler.compile_module(&module).unwrap();
+        assert!(compiler.module.verify().is_ok());
+
+        let error = compiler
+            .run_main_lazy(&module, &ExecutionLimits::default())
+            .unwrap_err();
+        assert!(
+            error.to_string().contains("Failed to compile func 2"),
+            "{error:#}"
+        );
+    }
+
+    #[test]
+    fn test_locals_are_zero_initialized() {
+        use crate::wasm_parser::Export;
+        use wasmparser::{Ieee32, Ieee64, RefType};
+
+        let context = Context::create();
+        let options = CompilerOptions {
+            opt_level: OptLevel::O2,
+            ..Default::default()
+        };
+        let mut compiler = Compiler::with_options(&context, "test", options).unwrap();
+
+        let function = Function {
+            idx: 0,
+            name: None,
+            func_type: FuncType::new([], [ValType::I32]),
+            body: FunctionBody {
+                locals: vec![
+                    ValType::I32,
+                    ValType::I64,
+                    ValType::F32,
+                    ValType::F64,
+                    ValType::Ref(RefType::EXTERNREF),
+                ],
+                operators: vec![
+                    Operator::LocalGet { local_index: 0 },
+                    Operator::I32Eqz,
+                    Operator::LocalGet { local_index: 1 },
+                    Operator::I64Eqz,
+                    Operator::I32And,
+                    Operator::LocalGet { local_index: 2 },
+                    Operator::F32Const {
+                        value: Ieee32::from(0.0),
+                    },
+                    Operator::F32Eq,
+                    Operator::I32And,
+                    Operator::LocalGet { local_index: 3 },
+                    Operator::F64Const {
+                        value: Ieee64::from(0.0),
+                    },
+                    Operator::F64Eq,
+                    Operator::I32And,
+                    Operator::LocalGet { local_index: 4 },
+                    Operator::RefIsNull,
+                    Operator::I32And,
+                    Operator::End,
+                ],
+                offsets: vec![],
+            },
+        };
+        let module = WasmModule {
+            functions: vec![function],
+            exports: vec![Export {
+                name: "all_zero".to_string(),
+                kind: ExternalKind::Func,
+                index: 0,
+            }],
+            ..Default::default()
+        };
+
+        compiler.compile_module(&module).unwrap();
+        assert!(compiler.module.verify().is_ok());
+
+        type NewFn = unsafe extern "C" fn() -> *mut u8;
+        type FreeFn = unsafe extern "C" fn(*mut u8);
+        type AllZeroFn = unsafe extern "C" fn(*mut u8) -> i32;
+
+        unsafe {
+            let engine = compiler.execution_engine.as_ref().unwrap();
+            let new = engine.get_function::<NewFn>("wasm_instance_new").unwrap();
+            let free = engine.get_function::<FreeFn>("wasm_instance_free").unwrap();
+            let all_zero = engine.get_function::<AllZeroFn>("all_zero").unwrap();
+
+            let instance = new.call();
+            assert_eq!(all_zero.call(instance), 1);
+            free.call(instance);
+        }
+    }
+
+    #[test]
+    fn test_table_instructions() {
+        use crate::wasm_parser::{ElementSegment, ElementSegmentKind, Export};
+        use wasmparser::{AbstractHeapType, HeapType, RefType, TableType};
+
+        let context = Context::create();
+        let mut compiler = Compiler::new(&context, "test").unwrap();
+
+        let ref_null = Operator::RefNull {
+            hty: HeapType::Abstract {
+                shared: false,
+                ty: AbstractHeapType::Func,
+            },
+        };
+        let table = 0;
+        // Returns grow + size * 10 + is_null(t[3]) * 100 + is_null(t[4]) * 1000
+        // + call_indirect(t[0]) * 10000.
+        let operators = vec![
+            ref_null.clone(),
+            Operator::I32Const { value: 3 },
+            Operator::TableGrow { table },
+            Operator::TableSize { table },
+            Operator::I32Const { value: 10 },
+            Operator::I32Mul,
+            Operator::I32Add,
+            Operator::I32Const { value: 3 },
+            Operator::I32Const { value: 0 },
+            Operator::I32Const { value: 2 },
+            Operator::TableInit {
+                elem_index: 0,
+                table,
+            },
+            Operator::ElemDrop { elem_index: 0 },
+            Operator::I32Const { value: 3 },
+            Operator::TableGet { table },
+            Operator::RefIsNull,
+            Operator::I32Const { value: 100 },
+            Operator::I32Mul,
+            Operator::I32Add,
+            Operator::I32Const { value: 4 },
+            Operator::TableGet { table },
+            Operator::RefIsNull,
+            Operator::I32Const { value: 1000 },
+            Operator::I32Mul,
+            Operator::I32Add,
+            Operator::I32Const { value: 0 },
+            Operator::I32Const { value: 3 },
+            Operator::I32Const { value: 1 },
+            Operator::TableCopy {
+                dst_table: table,
+                src_table: table,
+            },
+            Operator::I32Const { value: 1 },
+            ref_null,
+            Operator::I32Const { value: 2 },
+            Operator::TableFill { table },
+            Operator::I32Const { value: 0 },
+            Operator::CallIndirect {
+                type_index: 0,
+                table_index: table,
+            },
+            Operator::I32Const { value: 10000 },
+            Operator::I32Mul,
+            Operator::I32Add,
+            Operator::End,
+        ];
+        let mut function = create_simple_function(0, operators);
+        function.func_type = FuncType::new([], [ValType::I32]);
+        let mut callee =
+            create_simple_function(1, vec![Operator::I32Const { value: 7 }, Operator::End]);
+        callee.func_type = FuncType::new([], [ValType::I32]);
+
+        let module = WasmModule {
+            functions: vec![function, callee],
+            tables: vec![TableType {
+                element_type: RefType::FUNCREF,
+                table64: false,
+                initial: 2,
+                maximum: Some(10),
+                shared: false,
+            }],
+            function_types: vec![FuncType::new([], [ValType::I32])],
+            element_segments: vec![ElementSegment {
+                kind: ElementSegmentKind::Passive,
+                items: vec![Some(1), None],
+            }],
+            exports: vec![Export {
+                name: "tables".to_string(),
+                kind: ExternalKind::Func,
+                index: 0,
+            }],
+            ..Default::default()
+        };
+
+        compiler.compile_module(&module).unwrap();
+        assert!(compiler.module.verify().is_ok());
+
+        type NewFn = unsafe extern "C" fn() -> *mut u8;
+        type FreeFn = unsafe extern "C" fn(*mut u8);
+        type TablesFn = unsafe extern "C" fn(*mut u8) -> i32;
+
+        unsafe {
+            let engine = compiler.execution_engine.as_ref().unwrap();
+            let new = engine.get_function::<NewFn>("wasm_instance_new").unwrap();
+            let free = engine.get_function::<FreeFn>("wasm_instance_free").unwrap();
+            let tables = engine.get_function::<TablesFn>("tables").unwrap();
+
+            let instance = new.call();
+            assert_eq!(tables.call(instance), 71052);
+            free.call(instance);
+        }
+    }
+
+    #[test]
+    fn test_funcref_descriptors() {
+        use crate::wasm_parser::{ElementSegment, ElementSegmentKind, Export, WasmGlobal};
+        use wasmparser::{AbstractHeapType, GlobalType, HeapType, RefType, TableType};
+
+        let context = Context::create();
+        let mut compiler = Compiler::new(&context, "test").unwrap();
+
+        let funcref = ValType::Ref(RefType::FUNCREF);
+        // Stores `select (result funcref)` of two references in a global,
+        // moves it into the table and calls it through call_indirect.
+        let operators = vec![
+            Operator::RefFunc { function_index: 1 },
+            Operator::RefFunc { function_index: 2 },
+            Operator::LocalGet { local_index: 0 },
+            Operator::TypedSelect { ty: funcref },
+            Operator::GlobalSet { global_index: 0 },
+            Operator::I32Const { value: 0 },
+            Operator::GlobalGet { global_index: 0 },
+            Operator::TableSet { table: 0 },
+            Operator::RefNull {
+                hty: HeapType::Abstract {
+                    shared: false,
+                    ty: AbstractHeapType::Func,
+                },
+            },
+            Operator::RefIsNull,
+            Operator::Drop,
+            Operator::I32Const { value: 0 },
+            Operator::CallIndirect {
+                type_index: 0,
+                table_index: 0,
+            },
+            Operator::End,
+        ];
+        let mut function = create_simple_function(0, operators);
+        function.func_type = FuncType::new([ValType::I32], [ValType::I32]);
+        let mut first =
+            create_simple_function(1, vec![Operator::I32Const { value: 7 }, Operator::End]);
+        first.func_type = FuncType::new([], [ValType::I32]);
+        let mut second =
+            create_simple_function(2, vec![Operator::I32Const { value: 9 }, Operator::End]);
+        second.func_type = FuncType::new([], [ValType::I32]);
+
+        let module = WasmModule {
+            functions: vec![function, first, second],
+            globals: vec![WasmGlobal {
+                global_type: GlobalType {
+                    content_type: funcref,
+                    mutable: true,
+                    shared: false,
+                },
+            }],
+            tables: vec![TableType {
+                element_type: RefType::FUNCREF,
+                table64: false,
+                initial: 1,
+                maximum: None,
+                shared: false,
+            }],
+            function_types: vec![
+                FuncType::new([], [ValType::I32]),
+                FuncType::new([ValType::I32], [ValType::I32]),
+            ],
+            element_segments: vec![ElementSegment {
+                kind: ElementSegmentKind::Declared,
+                items: vec![Some(0)],
+            }],
+            exports: vec![Export {
+                name: "pick".to_string(),
+                kind: ExternalKind::Func,
+                index: 0,
+            }],
+            ..Default::default()
+        };
+
+        compiler.compile_module(&module).unwrap();
+        assert!(compiler.module.verify().is_ok());
+
+        let ir = compiler.module.print_to_string().to_string();
+        assert!(
+            ir.contains("@func_1.funcref = private constant { ptr, i32 } { ptr @func_1, i32 0 }"),
+            "{ir}"
+        );
+        assert!(
+            ir.contains("%signature_check = icmp eq i32 %signature, 0"),
+            "{ir}"
+        );
+        assert!(!ir.contains(" = alloca "), "{ir}");
+
+        type NewFn = unsafe extern "C" fn() -> *mut u8;
+        type FreeFn = unsafe extern "C" fn(*mut u8);
+        type PickFn = unsafe extern "C" fn(*mut u8, i32) -> i32;
+
+        unsafe {
+            let engine = compiler.execution_engine.as_ref().unwrap();
+            let new = engine.get_function::<NewFn>("wasm_instance_new").unwrap();
+            let free = engine.get_function::<FreeFn>("wasm_instance_free").unwrap();
+            let pick = engine.get_function::<PickFn>("pick").unwrap();
+
+            let instance = new.call();
+            assert_eq!(pick.call(instance, 1), 7);
+            assert_eq!(pick.call(instance, 0), 9);
+            free.call(instance);
+        }
+    }
+
+    #[test]
+    fn test_ref_func_of_import_is_rejected() {
+        let context = Context::create();
+        let compiler = Compiler::new(&context, "test").unwrap();
 
-        Ok(())
+        let operators = vec![
+            Operator::RefFunc { function_index: 0 },
+            Operator::Drop,
+            Operator::End,
+        ];
+        let function = create_simple_function(1, operators);
+        let module = WasmModule {
+            import_count: 1,
+            ..Default::default()
+        };
+        let result = compiler.compile_function(&function, &[], &module);
+        assert!(result.is_err());
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::wasm_parser::{Function, FunctionBody, WasmModule};
-    use inkwell::context::Context;
-    use wasmparser::{FuncType, ValType};
+    #[test]
+    fn test_export_wrappers() {
+        use crate::wasm_parser::Export;
 
-    fn create_simple_function(idx: u32, operators: Vec<Operator<'static>>) -> Function {
-        Function {
-            idx,
+        let context = Context::create();
+        let mut compiler = Compiler::new(&context, "test").unwrap();
+
+        let function = Function {
+            idx: 0,
             name: None,
-            func_type: FuncType::new([], []),
+            func_type: FuncType::new([ValType::I32, ValType::I32], [ValType::I32]),
             body: FunctionBody {
                 locals: vec![],
-                operators,
+                operators: vec![
+                    Operator::LocalGet { local_index: 0 },
+                    Operator::LocalGet { local_index: 1 },
+                    Operator::I32Add,
+                    Operator::End,
+                ],
+                offsets: vec![],
             },
-        }
+        };
+        let module = WasmModule {
+            functions: vec![function],
+            exports: vec![Export {
+                name: "add".to_string(),
+                kind: ExternalKind::Func,
+                index: 0,
+            }],
+            ..Default::default()
+        };
+
+        compiler.compile_module(&module).unwrap();
+        let wrapper = compiler.module.get_function("add").unwrap();
+        assert_eq!(wrapper.count_params(), 3);
+        assert_eq!(
+            compiler.module.get_function("$add").unwrap().get_linkage(),
+            Linkage::Internal
+        );
+        assert!(compiler.module.verify().is_ok());
     }
 
     #[test]
-    fn test_compiler_creation() {
+    fn test_export_shadowing_runtime_symbol() {
+        use crate::wasm_parser::Export;
+
         let context = Context::create();
-        let result = Compiler::new(&context, "test_module");
-        assert!(result.is_ok());
+        let mut compiler = Compiler::new(&context, "test").unwrap();
+        let module = WasmModule {
+            functions: vec![create_simple_function(0, vec![Operator::End])],
+            exports: vec![Export {
+                name: "calloc".to_string(),
+                kind: ExternalKind::Func,
+                index: 0,
+            }],
+            ..Default::default()
+        };
+        let error = compiler.compile_module(&module).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Export \"calloc\" collides with runtime symbol calloc"
+        );
     }
 
     #[test]
-    fn test_val_type_conversion() {
+    fn test_global_imports_are_rejected() {
         let context = Context::create();
-        let compiler = Compiler::new(&context, "test").unwrap();
+        let mut compiler = Compiler::new(&context, "test").unwrap();
+        let module = WasmModule {
+            imported_global_count: 1,
+            ..Default::default()
+        };
+        let error = compiler.compile_module(&module).unwrap_err();
+        assert_eq!(error.to_string(), "Global imports are not supported");
+    }
 
-        let i32_type = compiler.val_type_to_llvm_type(ValType::I32);
-        assert!(i32_type.is_int_type());
+    #[test]
+    fn test_instances_are_isolated() {
+        use crate::wasm_parser::{Export, WasmGlobal};
+        use wasmparser::GlobalType;
 
-        let i64_type = compiler.val_type_to_llvm_type(ValType::I64);
-        assert!(i64_type.is_int_type());
+        let context = Context::create();
+        let mut compiler = Compiler::new(&context, "test").unwrap();
 
-        let f32_type = compiler.val_type_to_llvm_type(ValType::F32);
-        assert!(f32_type.is_float_type());
+        let grow = Function {
+            idx: 0,
+            name: None,
+            func_type: FuncType::new([ValType::I32], [ValType::I32]),
+            body: FunctionBody {
+                locals: vec![],
+                operators: vec![
+                    Operator::LocalGet { local_index: 0 },
+                    Operator::MemoryGrow { mem: 0 },
+                    Operator::End,
+                ],
+                offsets: vec![],
+            },
+        };
+        let export = |name: &str, kind| Export {
+            name: name.to_string(),
+            kind,
+            index: 0,
+        };
+        let module = WasmModule {
+            functions: vec![grow],
+            memories: vec![wasmparser::MemoryType {
+                memory64: false,
+                shared: false,
+                initial: 1,
+                maximum: Some(3),
+                page_size_log2: None,
+            }],
+            globals: vec![WasmGlobal {
+                global_type: GlobalType {
+                    content_type: ValType::I32,
+                    mutable: true,
+                    shared: false,
+                },
+            }],
+            exports: vec![
+                export("grow", ExternalKind::Func),
+                export("memory", ExternalKind::Memory),
+                export("counter", ExternalKind::Global),
+            ],
+            ..Default::default()
+        };
 
-        let f64_type = compiler.val_type_to_llvm_type(ValType::F64);
-        assert!(f64_type.is_float_type());
+        compiler.compile_module(&module).unwrap();
+        assert!(compiler.module.verify().is_ok());
+
+        type NewFn = unsafe extern "C" fn() -> *mut u8;
+        type FreeFn = unsafe extern "C" fn(*mut u8);
+        type GrowFn = unsafe extern "C" fn(*mut u8, i32) -> i32;
+        type SizeFn = unsafe extern "C" fn(*mut u8) -> u64;
+        type GetFn = unsafe extern "C" fn(*mut u8) -> i32;
+        type SetFn = unsafe extern "C" fn(*mut u8, i32);
+
+        unsafe {
+            let engine = compiler.execution_engine.as_ref().unwrap();
+            let new = engine.get_function::<NewFn>("wasm_instance_new").unwrap();
+            let free = engine.get_function::<FreeFn>("wasm_instance_free").unwrap();
+            let grow = engine.get_function::<GrowFn>("grow").unwrap();
+            let memory_size = engine.get_function::<SizeFn>("memory_size").unwrap();
+            let counter_get = engine.get_function::<GetFn>("counter_get").unwrap();
+            let counter_set = engine.get_function::<SetFn>("counter_set").unwrap();
+
+            let first = new.call();
+            let second = new.call();
+            assert!(!first.is_null() && !second.is_null());
+
+            counter_set.call(first, 7);
+            assert_eq!(counter_get.call(first), 7);
+            assert_eq!(counter_get.call(second), 0);
+
+            assert_eq!(grow.call(first, 1), 1);
+            assert_eq!(memory_size.call(first), 2 * 65536);
+            assert_eq!(memory_size.call(second), 65536);
+            assert_eq!(grow.call(first, 5), -1);
+            assert_eq!(memory_size.call(first), 2 * 65536);
+
+            free.call(first);
+            free.call(second);
+        }
     }
 
     #[test]
-    fn test_compile_empty_function() {
+    fn test_shared_memory_atomics() {
+        use crate::wasm_parser::Export;
+
         let context = Context::create();
-        let compiler = Compiler::new(&context, "test").unwrap();
+        let mut compiler = Compiler::new(&context, "test").unwrap();
 
-        let function = create_simple_function(0, vec![Operator::End]);
+        let memarg = wasmparser::MemArg {
+            align: 2,
+            max_align: 2,
+            offset: 0,
+            memory: 0,
+        };
+        let function = |idx: u32, params: &[ValType], operators: Vec<Operator<'static>>| Function {
+            idx,
+            name: None,
+            func_type: FuncType::new(params.iter().copied(), [ValType::I32]),
+            body: FunctionBody {
+                locals: vec![],
+                operators,
+                offsets: vec![],
+            },
+        };
+        let functions = vec![
+            function(
+                0,
+                &[ValType::I32, ValType::I32],
+                vec![
+                    Operator::LocalGet { local_index: 0 },
+                    Operator::LocalGet { local_index: 1 },
+                    Operator::I32AtomicRmwAdd { memarg },
+                    Operator::End,
+                ],
+            ),
+            function(
+                1,
+                &[ValType::I32, ValType::I32, ValType::I32],
+                vec![
+                    Operator::LocalGet { local_index: 0 },
+                    Operator::LocalGet { local_index: 1 },
+                    Operator::LocalGet { local_index: 2 },
+                    Operator::I32AtomicRmwCmpxchg { memarg },
+                    Operator::End,
+                ],
+            ),
+            function(
+                2,
+                &[ValType::I32, ValType::I32],
+                vec![
+                    Operator::LocalGet { local_index: 0 },
+                    Operator::LocalGet { local_index: 1 },
+                    Operator::I64Const { value: 0 },
+                    Operator::MemoryAtomicWait32 { memarg },
+                    Operator::End,
+                ],
+            ),
+            function(
+                3,
+                &[ValType::I32],
+                vec![
+                    Operator::LocalGet { local_index: 0 },
+                    Operator::MemoryGrow { mem: 0 },
+                    Operator::End,
+                ],
+            ),
+        ];
+        let export = |name: &str, index| Export {
+            name: name.to_string(),
+            kind: ExternalKind::Func,
+            index,
+        };
         let module = WasmModule {
-            functions: vec![],
-            start_func_idx: None,
-            memories: vec![],
-            has_assert_eq32_import: false,
-            has_assert_eq64_import: false,
-            import_count: 0,
-            globals: vec![],
-            tables: vec![],
-            function_types: vec![],
-            element_segments: vec![],
+            functions,
+            memories: vec![wasmparser::MemoryType {
+                memory64: false,
+                shared: true,
+                initial: 1,
+                maximum: Some(2),
+                page_size_log2: None,
+            }],
+            exports: vec![
+                export("add", 0),
+                export("cas", 1),
+                export("wait", 2),
+                export("grow", 3),
+            ],
+            ..Default::default()
         };
-        let result = compiler.compile_function(&function, &[], &module);
-        assert!(result.is_ok());
+
+        compiler.compile_module(&module).unwrap();
+        assert!(compiler.module.verify().is_ok());
+        let ir = compiler.module.print_to_string().to_string();
+        assert!(ir.contains("atomicrmw add ptr %atomic_ptr"));
+        assert!(ir.contains("seq_cst seq_cst"));
+        assert!(ir.contains("unaligned_trap"));
+
+        type NewFn = unsafe extern "C" fn() -> *mut u8;
+        type FreeFn = unsafe extern "C" fn(*mut u8);
+        type UnaryFn = unsafe extern "C" fn(*mut u8, i32) -> i32;
+        type BinaryFn = unsafe extern "C" fn(*mut u8, i32, i32) -> i32;
+        type TernaryFn = unsafe extern "C" fn(*mut u8, i32, i32, i32) -> i32;
+
+        let engine = compiler.execution_engine.as_ref().unwrap();
+        compiler.add_host_mappings(engine);
+        unsafe {
+            let new = engine.get_function::<NewFn>("wasm_instance_new").unwrap();
+            let free = engine.get_function::<FreeFn>("wasm_instance_free").unwrap();
+            let add = engine.get_function::<BinaryFn>("add").unwrap();
+            let cas = engine.get_function::<TernaryFn>("cas").unwrap();
+            let wait = engine.get_function::<BinaryFn>("wait").unwrap();
+            let grow = engine.get_function::<UnaryFn>("grow").unwrap();
+
+            let instance = new.call();
+            assert_eq!(add.call(instance, 0, 5), 0);
+            assert_eq!(add.call(instance, 0, 3), 5);
+            assert_eq!(cas.call(instance, 0, 8, 1), 8);
+            assert_eq!(cas.call(instance, 0, 8, 2), 1);
+            assert_eq!(add.call(instance, 0, 0), 1);
+
+            // not-equal, then timed-out after a zero timeout
+            assert_eq!(wait.call(instance, 0, 7), 1);
+            assert_eq!(wait.call(instance, 0, 1), 2);
+
+            assert_eq!(grow.call(instance, 1), 1);
+            assert_eq!(grow.call(instance, 1), -1);
+            assert_eq!(add.call(instance, 65536, 9), 0);
+            free.call(instance);
+        }
     }
 
     #[test]
-    fn test_compile_function_with_i32_const() {
+    fn test_shared_memory_requires_maximum() {
         let context = Context::create();
-        let compiler = Compiler::new(&context, "test").unwrap();
-
-        let operators = vec![
-            Operator::I32Const { value: 42 },
-            Operator::Drop,
-            Operator::End,
-        ];
+        let mut compiler = Compiler::new(&context, "test").unwrap();
 
-        let function = create_simple_function(0, operators);
-        let module = WasmModule {
-            functions: vec![],
-            start_func_idx: None,
-            memories: vec![],
-            has_assert_eq32_import: false,
-            has_assert_eq64_import: false,
-            import_count: 0,
-            globals: vec![],
-            tables: vec![],
-            function_types: vec![],
-            element_segments: vec![],
+        let memory_type = wasmparser::MemoryType {
+            memory64: false,
+            shared: true,
+            initial: 1,
+            maximum: None,
+            page_size_log2: None,
         };
-        let result = compiler.compile_function(&function, &[], &module);
-        assert!(result.is_ok());
+        assert!(compiler.create_memory(&memory_type).is_err());
     }
 
     #[test]
-    fn test_compile_function_with_arithmetic() {
+    fn test_stack_underflow() {
         let context = Context::create();
         let compiler = Compiler::new(&context, "test").unwrap();
 
-        let operators = vec![
-            Operator::I32Const { value: 10 },
-            Operator::I32Const { value: 5 },
-            Operator::I32Add,
-            Operator::Drop,
-            Operator::End,
-        ];
+        let operators = vec![Operator::I32Add, Operator::End];
 
         let function = create_simple_function(0, operators);
         let module = WasmModule {
-            functions: vec![],
-            start_func_idx: None,
-            memories: vec![],
-            has_assert_eq32_import: false,
-            has_assert_eq64_import: false,
-            import_count: 0,
-            globals: vec![],
-            tables: vec![],
-            function_types: vec![],
-            element_segments: vec![],
+            ..Default::default()
         };
         let result = compiler.compile_function(&function, &[], &module);
-        assert!(result.is_ok());
+        assert!(result.is_err());
     }
 
     #[test]
-    fn test_compile_function_with_all_arithmetic_ops() {
+    fn test_operand_type_mismatch() {
         let context = Context::create();
         let compiler = Compiler::new(&context, "test").unwrap();
 
         let operators = vec![
-            Operator::I32Const { value: 20 },
-            Operator::I32Const { value: 4 },
+            Operator::I32Const { value: 1 },
+            Operator::F32Const {
+                value: wasmparser::Ieee32::from(1.0),
+            },
             Operator::I32Add,
-            Operator::I32Const { value: 2 },
-            Operator::I32Sub,
-            Operator::I32Const { value: 3 },
-            Operator::I32Mul,
-            Operator::I32Const { value: 6 },
-            Operator::I32DivS,
             Operator::Drop,
             Operator::End,
         ];
 
         let function = create_simple_function(0, operators);
         let module = WasmModule {
-            functions: vec![],
-            start_func_idx: None,
-            memories: vec![],
-            has_assert_eq32_import: false,
-            has_assert_eq64_import: false,
-            import_count: 0,
-            globals: vec![],
-            tables: vec![],
-            function_types: vec![],
-            element_segments: vec![],
+            ..Default::default()
         };
-        let result = compiler.compile_function(&function, &[], &module);
-        assert!(result.is_ok());
+        let error = compiler
+            .compile_function(&function, &[], &module)
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "func 0: Type mismatch: expected integer operand, found float"
+        );
+    }
+
+    fn named_module(operators: Vec<Operator<'static>>, offsets: Vec<usize>) -> WasmModule {
+        let function = Function {
+            idx: 0,
+            name: None,
+            func_type: FuncType::new([], []),
+            body: FunctionBody {
+                locals: vec![],
+                operators,
+                offsets,
+            },
+        };
+        WasmModule {
+            functions: vec![function],
+            names: Names {
+                functions: [(0, "my_kernel".to_string())].into(),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
     }
 
     #[test]
-    fn test_compile_empty_module() {
+    fn test_error_source_position() {
         let context = Context::create();
         let mut compiler = Compiler::new(&context, "test").unwrap();
 
-        let module = WasmModule {
-            functions: vec![],
-            start_func_idx: None,
-            memories: vec![],
-            has_assert_eq32_import: false,
-            has_assert_eq64_import: false,
-            import_count: 0,
-            globals: vec![],
-            tables: vec![],
-            function_types: vec![],
-            element_segments: vec![],
+        let module = named_module(
+            vec![
+                Operator::I32Const { value: 1 },
+                Operator::I32Add,
+                Operator::End,
+            ],
+            vec![0x10, 0x12, 0x13],
+        );
+        let error = compiler.compile_module(&module).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "func 0 (my_kernel) at offset 0x12: Stack underflow"
+        );
+    }
+
+    #[test]
+    fn test_debug_locations() {
+        let context = Context::create();
+        let options = CompilerOptions {
+            debug_info: true,
+            ..Default::default()
         };
+        let mut compiler = Compiler::with_options(&context, "test", options).unwrap();
 
-        let result = compiler.compile_module(&module);
-        assert!(result.is_ok());
+        let module = named_module(
+            vec![
+                Operator::I32Const { value: 1 },
+                Operator::I32Const { value: 2 },
+                Operator::I32Add,
+                Operator::Drop,
+                Operator::End,
+            ],
+            vec![0x10, 0x12, 0x14, 0x15, 0x16],
+        );
+        compiler.compile_module(&module).unwrap();
+        assert!(compiler.module.verify().is_ok());
+
+        let ir = compiler.module.print_to_string().to_string();
+        assert!(ir.contains("!DISubprogram(name: \"my_kernel\""), "{ir}");
+        assert!(ir.contains("!DILocation(line: 22,"), "{ir}");
     }
 
     #[test]
-    fn test_stack_underflow() {
+    fn test_source_debug_locations() {
         let context = Context::create();
-        let compiler = Compiler::new(&context, "test").unwrap();
+        let options = CompilerOptions {
+            debug_info: true,
+            ..Default::default()
+        };
+        let mut compiler = Compiler::with_options(&context, "test", options).unwrap();
+
+        let mut module = named_module(
+            vec![
+                Operator::LocalGet { local_index: 0 },
+                Operator::LocalGet { local_index: 0 },
+                Operator::I32Mul,
+                Operator::LocalGet { local_index: 0 },
+                Operator::I32Add,
+                Operator::End,
+            ],
+            vec![0x30, 0x31, 0x32, 0x33, 0x34, 0x35],
+        );
+        module.functions[0].func_type = FuncType::new([ValType::I32], [ValType::I32]);
+        module.dwarf = crate::dwarf::tests::dwarf_sections();
+        compiler.compile_module(&module).unwrap();
+        assert!(compiler.module.verify().is_ok());
+
+        let ir = compiler.module.print_to_string().to_string();
+        assert!(
+            ir.contains("!DIFile(filename: \"kernel.c\", directory: \"src\")"),
+            "{ir}"
+        );
+        assert!(ir.contains("!DILocation(line: 7, column: 3,"), "{ir}");
+        assert!(ir.contains("!DILexicalBlock("), "{ir}");
+        assert!(ir.contains("!DILocation(line: 9,"), "{ir}");
+    }
 
-        let operators = vec![Operator::I32Add, Operator::End];
+    fn module_with_named_locals() -> WasmModule {
+        let mut module = named_module(
+            vec![
+                Operator::LocalGet { local_index: 0 },
+                Operator::LocalSet { local_index: 1 },
+                Operator::LocalGet { local_index: 1 },
+                Operator::Drop,
+                Operator::End,
+            ],
+            vec![],
+        );
+        module.functions[0].func_type = FuncType::new([ValType::I32], []);
+        module.functions[0].body.locals = vec![ValType::I32];
+        module.names.locals = [(0, [(0, "n".to_string()), (1, "sum".to_string())].into())].into();
+        module
+    }
 
-        let function = create_simple_function(0, operators);
-        let module = WasmModule {
-            functions: vec![],
-            start_func_idx: None,
-            memories: vec![],
-            has_assert_eq32_import: false,
-            has_assert_eq64_import: false,
-            import_count: 0,
-            globals: vec![],
-            tables: vec![],
-            function_types: vec![],
-            element_segments: vec![],
+    #[test]
+    fn test_name_section_names() {
+        let context = Context::create();
+        let mut compiler = Compiler::new(&context, "test").unwrap();
+
+        compiler
+            .compile_module(&module_with_named_locals())
+            .unwrap();
+        assert!(compiler.module.verify().is_ok());
+
+        let ir = compiler.module.print_to_string().to_string();
+        assert!(ir.contains("@\"$my_kernel\"(ptr %vmctx, i32 %n)"), "{ir}");
+        assert!(ir.contains("%sum = alloca i32"), "{ir}");
+        assert!(ir.contains("%sum_load = load i32, ptr %sum"), "{ir}");
+    }
+
+    #[test]
+    fn test_stable_names() {
+        let context = Context::create();
+        let options = CompilerOptions {
+            stable_names: true,
+            ..Default::default()
         };
-        let result = compiler.compile_function(&function, &[], &module);
-        assert!(result.is_err());
+        let mut compiler = Compiler::with_options(&context, "test", options).unwrap();
+
+        compiler
+            .compile_module(&module_with_named_locals())
+            .unwrap();
+
+        let ir = compiler.module.print_to_string().to_string();
+        assert!(ir.contains("@func_0(ptr %vmctx, i32 %0)"), "{ir}");
+        assert!(ir.contains("%local_load = load i32, ptr %local"), "{ir}");
+        assert!(!ir.contains("my_kernel"), "{ir}");
+    }
+
+    #[test]
+    fn test_colliding_names_are_disambiguated() {
+        use crate::wasm_parser::Export;
+
+        let mut module = named_module(vec![Operator::End], vec![]);
+        module.exports.push(Export {
+            name: "$my_kernel".to_string(),
+            kind: ExternalKind::Func,
+            index: 0,
+        });
+        module.names.functions.insert(0, "my kernel".to_string());
+
+        let symbols = Compiler::function_symbols(&module);
+        assert_eq!(symbols[&0], "$my_kernel.0");
     }
 
     #[test]
@@ -2616,16 +7296,8 @@ mod tests {
         };
 
         let module = WasmModule {
-            functions: vec![],
-            start_func_idx: None,
             memories: vec![memory_type],
-            has_assert_eq32_import: false,
-            has_assert_eq64_import: false,
-            import_count: 0,
-            globals: vec![],
-            tables: vec![],
-            function_types: vec![],
-            element_segments: vec![],
+            ..Default::default()
         };
 
         let result = compiler.compile_module(&module);
@@ -2667,19 +7339,15 @@ mod tests {
 
         let function = create_simple_function(0, operators);
         let module = WasmModule {
-            functions: vec![],
-            start_func_idx: None,
-            memories: vec![],
-            has_assert_eq32_import: false,
-            has_assert_eq64_import: false,
-            import_count: 0,
-            globals: vec![],
-            tables: vec![],
-            function_types: vec![],
-            element_segments: vec![],
+            ..Default::default()
         };
         let result = compiler.compile_function(&function, &[], &module);
         assert!(result.is_ok());
+
+        // Both accesses check their last byte against the memory size; the
+        // end of an access at a constant address is folded.
+        let ir = compiler.module.print_to_string().to_string();
+        assert_eq!(ir.matches("icmp ugt i64 4, %mem_size").count(), 2, "{ir}");
     }
 
     #[test]
@@ -2705,16 +7373,7 @@ mod tests {
 
         let function = create_simple_function(0, operators);
         let module = WasmModule {
-            functions: vec![],
-            start_func_idx: None,
-            memories: vec![],
-            has_assert_eq32_import: false,
-            has_assert_eq64_import: false,
-            import_count: 0,
-            globals: vec![],
-            tables: vec![],
-            function_types: vec![],
-            element_segments: vec![],
+            ..Default::default()
         };
         let result = compiler.compile_function(&function, &[], &module);
         assert!(result.is_ok());
@@ -2734,21 +7393,152 @@ mod tests {
 
         let function = create_simple_function(0, operators);
         let module = WasmModule {
-            functions: vec![],
-            start_func_idx: None,
-            memories: vec![],
-            has_assert_eq32_import: false,
-            has_assert_eq64_import: false,
-            import_count: 0,
-            globals: vec![],
-            tables: vec![],
-            function_types: vec![],
-            element_segments: vec![],
+            ..Default::default()
         };
         let result = compiler.compile_function(&function, &[], &module);
         assert!(result.is_ok());
     }
 
+    #[test]
+    fn test_select_operand_order() {
+        let context = Context::create();
+        let compiler = Compiler::new(&context, "test").unwrap();
+
+        // `select` yields its first operand when the condition is non-zero.
+        let operators = vec![
+            Operator::I32Const { value: 10 },
+            Operator::I32Const { value: 20 },
+            Operator::LocalGet { local_index: 0 },
+            Operator::Select,
+            Operator::End,
+        ];
+
+        let mut function = create_simple_function(0, operators);
+        function.func_type = FuncType::new([ValType::I32], [ValType::I32]);
+        let module = WasmModule {
+            ..Default::default()
+        };
+        compiler.compile_function(&function, &[], &module).unwrap();
+
+        let ir = compiler.module.print_to_string().to_string();
+        assert!(
+            ir.contains("select i1 %select_cond, i32 10, i32 20"),
+            "{ir}"
+        );
+    }
+
+    #[test]
+    fn test_if_without_else() {
+        use crate::wasm_parser::Export;
+
+        let context = Context::create();
+        let mut compiler = Compiler::new(&context, "test").unwrap();
+
+        // Returns 7 for a non-zero argument and 0 otherwise.
+        let mut function = create_simple_function(
+            0,
+            vec![
+                Operator::LocalGet { local_index: 0 },
+                Operator::If {
+                    blockty: wasmparser::BlockType::Empty,
+                },
+                Operator::I32Const { value: 7 },
+                Operator::LocalSet { local_index: 1 },
+                Operator::End,
+                Operator::LocalGet { local_index: 1 },
+                Operator::End,
+            ],
+        );
+        function.func_type = FuncType::new([ValType::I32], [ValType::I32]);
+        function.body.locals = vec![ValType::I32];
+        let module = WasmModule {
+            functions: vec![function],
+            exports: vec![Export {
+                name: "pick".to_string(),
+                kind: ExternalKind::Func,
+                index: 0,
+            }],
+            ..Default::default()
+        };
+
+        compiler.compile_module(&module).unwrap();
+        assert!(compiler.module.verify().is_ok());
+
+        type NewFn = unsafe extern "C" fn() -> *mut u8;
+        type FreeFn = unsafe extern "C" fn(*mut u8);
+        type PickFn = unsafe extern "C" fn(*mut u8, i32) -> i32;
+
+        unsafe {
+            let engine = compiler.execution_engine.as_ref().unwrap();
+            let new = engine.get_function::<NewFn>("wasm_instance_new").unwrap();
+            let free = engine.get_function::<FreeFn>("wasm_instance_free").unwrap();
+            let pick = engine.get_function::<PickFn>("pick").unwrap();
+
+            let instance = new.call();
+            assert_eq!(pick.call(instance, 1), 7);
+            assert_eq!(pick.call(instance, 0), 0);
+            free.call(instance);
+        }
+    }
+
+    #[test]
+    fn test_early_return() {
+        use crate::wasm_parser::Export;
+
+        let context = Context::create();
+        let mut compiler = Compiler::new(&context, "test").unwrap();
+
+        // Returns 100 for 0 and its argument plus one otherwise; the code
+        // after `return` must still be compiled.
+        let mut function = create_simple_function(
+            0,
+            vec![
+                Operator::LocalGet { local_index: 0 },
+                Operator::I32Eqz,
+                Operator::If {
+                    blockty: wasmparser::BlockType::Empty,
+                },
+                Operator::I32Const { value: 100 },
+                Operator::Return,
+                Operator::End,
+                Operator::LocalGet { local_index: 0 },
+                Operator::I32Const { value: 1 },
+                Operator::I32Add,
+                Operator::Return,
+                Operator::End,
+            ],
+        );
+        function.func_type = FuncType::new([ValType::I32], [ValType::I32]);
+        let module = WasmModule {
+            functions: vec![function],
+            exports: vec![Export {
+                name: "bump".to_string(),
+                kind: ExternalKind::Func,
+                index: 0,
+            }],
+            ..Default::default()
+        };
+
+        compiler.compile_module(&module).unwrap();
+        assert!(compiler.module.verify().is_ok());
+
+        type NewFn = unsafe extern "C" fn() -> *mut u8;
+        type FreeFn = unsafe extern "C" fn(*mut u8);
+        type BumpFn = unsafe extern "C" fn(*mut u8, i32) -> i32;
+
+        unsafe {
+            let engine = compiler.execution_engine.as_ref().unwrap();
+            let new = engine.get_function::<NewFn>("wasm_instance_new").unwrap();
+            let free = engine.get_function::<FreeFn>("wasm_instance_free").unwrap();
+            let bump = engine.get_function::<BumpFn>("bump").unwrap();
+
+            let instance = new.call();
+            assert_eq!(bump.call(instance, 0), 100);
+            assert_eq!(bump.call(instance, 5), 6);
+            free.call(instance);
+        }
+    }
+
     #[test]
     fn test_i32_unsigned_div_rem() {
         let context = Context::create();
@@ -2768,16 +7558,7 @@ mod tests {
 
         let function = create_simple_function(0, operators);
         let module = WasmModule {
-            functions: vec![],
-            start_func_idx: None,
-            memories: vec![],
-            has_assert_eq32_import: false,
-            has_assert_eq64_import: false,
-            import_count: 0,
-            globals: vec![],
-            tables: vec![],
-            function_types: vec![],
-            element_segments: vec![],
+            ..Default::default()
         };
         let result = compiler.compile_function(&function, &[], &module);
         assert!(result.is_ok());
@@ -2814,16 +7595,7 @@ mod tests {
 
         let function = create_simple_function(0, operators);
         let module = WasmModule {
-            functions: vec![],
-            start_func_idx: None,
-            memories: vec![],
-            has_assert_eq32_import: false,
-            has_assert_eq64_import: false,
-            import_count: 0,
-            globals: vec![],
-            tables: vec![],
-            function_types: vec![],
-            element_segments: vec![],
+            ..Default::default()
         };
         let result = compiler.compile_function(&function, &[], &module);
         assert!(result.is_ok());
@@ -2848,16 +7620,7 @@ mod tests {
 
         let function = create_simple_function(0, operators);
         let module = WasmModule {
-            functions: vec![],
-            start_func_idx: None,
-            memories: vec![],
-            has_assert_eq32_import: false,
-            has_assert_eq64_import: false,
-            import_count: 0,
-            globals: vec![],
-            tables: vec![],
-            function_types: vec![],
-            element_segments: vec![],
+            ..Default::default()
         };
         let result = compiler.compile_function(&function, &[], &module);
         assert!(result.is_ok());
@@ -2890,16 +7653,7 @@ mod tests {
 
         let function = create_simple_function(0, operators);
         let module = WasmModule {
-            functions: vec![],
-            start_func_idx: None,
-            memories: vec![],
-            has_assert_eq32_import: false,
-            has_assert_eq64_import: false,
-            import_count: 0,
-            globals: vec![],
-            tables: vec![],
-            function_types: vec![],
-            element_segments: vec![],
+            ..Default::default()
         };
         let result = compiler.compile_function(&function, &[], &module);
         assert!(result.is_ok());
@@ -2937,16 +7691,7 @@ mod tests {
 
         let function = create_simple_function(0, operators);
         let module = WasmModule {
-            functions: vec![],
-            start_func_idx: None,
-            memories: vec![],
-            has_assert_eq32_import: false,
-            has_assert_eq64_import: false,
-            import_count: 0,
-            globals: vec![],
-            tables: vec![],
-            function_types: vec![],
-            element_segments: vec![],
+            ..Default::default()
         };
         let result = compiler.compile_function(&function, &[], &module);
         assert!(result.is_ok());
@@ -2989,16 +7734,7 @@ mod tests {
 
         let function = create_simple_function(0, operators);
         let module = WasmModule {
-            functions: vec![],
-            start_func_idx: None,
-            memories: vec![],
-            has_assert_eq32_import: false,
-            has_assert_eq64_import: false,
-            import_count: 0,
-            globals: vec![],
-            tables: vec![],
-            function_types: vec![],
-            element_segments: vec![],
+            ..Default::default()
         };
         let result = compiler.compile_function(&function, &[], &module);
         assert!(result.is_ok());
@@ -3031,16 +7767,7 @@ mod tests {
 
         let function = create_simple_function(0, operators);
         let module = WasmModule {
-            functions: vec![],
-            start_func_idx: None,
-            memories: vec![],
-            has_assert_eq32_import: false,
-            has_assert_eq64_import: false,
-            import_count: 0,
-            globals: vec![],
-            tables: vec![],
-            function_types: vec![],
-            element_segments: vec![],
+            ..Default::default()
         };
         let result = compiler.compile_function(&function, &[], &module);
         assert!(result.is_ok());
@@ -3077,16 +7804,7 @@ mod tests {
 
         let function = create_simple_function(0, operators);
         let module = WasmModule {
-            functions: vec![],
-            start_func_idx: None,
-            memories: vec![],
-            has_assert_eq32_import: false,
-            has_assert_eq64_import: false,
-            import_count: 0,
-            globals: vec![],
-            tables: vec![],
-            function_types: vec![],
-            element_segments: vec![],
+            ..Default::default()
         };
         let result = compiler.compile_function(&function, &[], &module);
         assert!(result.is_ok());
@@ -3112,16 +7830,7 @@ mod tests {
 
         let function = create_simple_function(0, operators);
         let module = WasmModule {
-            functions: vec![],
-            start_func_idx: None,
-            memories: vec![],
-            has_assert_eq32_import: false,
-            has_assert_eq64_import: false,
-            import_count: 0,
-            globals: vec![],
-            tables: vec![],
-            function_types: vec![],
-            element_segments: vec![],
+            ..Default::default()
         };
         let result = compiler.compile_function(&function, &[], &module);
         assert!(result.is_ok());
@@ -3149,16 +7858,7 @@ mod tests {
 
         let function = create_simple_function(0, operators);
         let module = WasmModule {
-            functions: vec![],
-            start_func_idx: None,
-            memories: vec![],
-            has_assert_eq32_import: false,
-            has_assert_eq64_import: false,
-            import_count: 0,
-            globals: vec![],
-            tables: vec![],
-            function_types: vec![],
-            element_segments: vec![],
+            ..Default::default()
         };
         let result = compiler.compile_function(&function, &[], &module);
         assert!(result.is_ok());
@@ -3199,16 +7899,7 @@ mod tests {
 
         let function = create_simple_function(0, operators);
         let module = WasmModule {
-            functions: vec![],
-            start_func_idx: None,
-            memories: vec![],
-            has_assert_eq32_import: false,
-            has_assert_eq64_import: false,
-            import_count: 0,
-            globals: vec![],
-            tables: vec![],
-            function_types: vec![],
-            element_segments: vec![],
+            ..Default::default()
         };
         let result = compiler.compile_function(&function, &[], &module);
         assert!(result.is_ok());
@@ -3265,16 +7956,7 @@ mod tests {
 
         let function = create_simple_function(0, operators);
         let module = WasmModule {
-            functions: vec![],
-            start_func_idx: None,
-            memories: vec![],
-            has_assert_eq32_import: false,
-            has_assert_eq64_import: false,
-            import_count: 0,
-            globals: vec![],
-            tables: vec![],
-            function_types: vec![],
-            element_segments: vec![],
+            ..Default::default()
         };
         let result = compiler.compile_function(&function, &[], &module);
         assert!(result.is_ok());
@@ -3329,16 +8011,7 @@ mod tests {
 
         let function = create_simple_function(0, operators);
         let module = WasmModule {
-            functions: vec![],
-            start_func_idx: None,
-            memories: vec![],
-            has_assert_eq32_import: false,
-            has_assert_eq64_import: false,
-            import_count: 0,
-            globals: vec![],
-            tables: vec![],
-            function_types: vec![],
-            element_segments: vec![],
+            ..Default::default()
         };
         let result = compiler.compile_function(&function, &[], &module);
         assert!(result.is_ok());
@@ -3403,16 +8076,7 @@ mod tests {
 
         let function = create_simple_function(0, operators);
         let module = WasmModule {
-            functions: vec![],
-            start_func_idx: None,
-            memories: vec![],
-            has_assert_eq32_import: false,
-            has_assert_eq64_import: false,
-            import_count: 0,
-            globals: vec![],
-            tables: vec![],
-            function_types: vec![],
-            element_segments: vec![],
+            ..Default::default()
         };
         let result = compiler.compile_function(&function, &[], &module);
         assert!(result.is_ok());
@@ -3445,16 +8109,7 @@ mod tests {
 
         let function = create_simple_function(0, operators);
         let module = WasmModule {
-            functions: vec![],
-            start_func_idx: None,
-            memories: vec![],
-            has_assert_eq32_import: false,
-            has_assert_eq64_import: false,
-            import_count: 0,
-            globals: vec![],
-            tables: vec![],
-            function_types: vec![],
-            element_segments: vec![],
+            ..Default::default()
         };
         let result = compiler.compile_function(&function, &[], &module);
         assert!(result.is_ok());
@@ -3491,16 +8146,7 @@ mod tests {
 
         let function = create_simple_function(0, operators);
         let module = WasmModule {
-            functions: vec![],
-            start_func_idx: None,
-            memories: vec![],
-            has_assert_eq32_import: false,
-            has_assert_eq64_import: false,
-            import_count: 0,
-            globals: vec![],
-            tables: vec![],
-            function_types: vec![],
-            element_segments: vec![],
+            ..Default::default()
         };
         let result = compiler.compile_function(&function, &[], &module);
         assert!(result.is_ok());
@@ -3533,16 +8179,7 @@ mod tests {
 
         let function = create_simple_function(0, operators);
         let module = WasmModule {
-            functions: vec![],
-            start_func_idx: None,
-            memories: vec![],
-            has_assert_eq32_import: false,
-            has_assert_eq64_import: false,
-            import_count: 0,
-            globals: vec![],
-            tables: vec![],
-            function_types: vec![],
-            element_segments: vec![],
+            ..Default::default()
         };
         let result = compiler.compile_function(&function, &[], &module);
         assert!(result.is_ok());
@@ -3570,16 +8207,7 @@ mod tests {
 
         let function = create_simple_function(0, operators);
         let module = WasmModule {
-            functions: vec![],
-            start_func_idx: None,
-            memories: vec![],
-            has_assert_eq32_import: false,
-            has_assert_eq64_import: false,
-            import_count: 0,
-            globals: vec![],
-            tables: vec![],
-            function_types: vec![],
-            element_segments: vec![],
+            ..Default::default()
         };
         let result = compiler.compile_function(&function, &[], &module);
         assert!(result.is_ok());
@@ -3631,16 +8259,7 @@ mod tests {
 
         let function = create_simple_function(0, operators);
         let module = WasmModule {
-            functions: vec![],
-            start_func_idx: None,
-            memories: vec![],
-            has_assert_eq32_import: false,
-            has_assert_eq64_import: false,
-            import_count: 0,
-            globals: vec![],
-            tables: vec![],
-            function_types: vec![],
-            element_segments: vec![],
+            ..Default::default()
         };
         let result = compiler.compile_function(&function, &[], &module);
         assert!(result.is_ok());
@@ -3692,16 +8311,7 @@ mod tests {
 
         let function = create_simple_function(0, operators);
         let module = WasmModule {
-            functions: vec![],
-            start_func_idx: None,
-            memories: vec![],
-            has_assert_eq32_import: false,
-            has_assert_eq64_import: false,
-            import_count: 0,
-            globals: vec![],
-            tables: vec![],
-            function_types: vec![],
-            element_segments: vec![],
+            ..Default::default()
         };
         let result = compiler.compile_function(&function, &[], &module);
         assert!(result.is_ok());
@@ -3769,16 +8379,7 @@ mod tests {
 
         let function = create_simple_function(0, operators);
         let module = WasmModule {
-            functions: vec![],
-            start_func_idx: None,
-            memories: vec![],
-            has_assert_eq32_import: false,
-            has_assert_eq64_import: false,
-            import_count: 0,
-            globals: vec![],
-            tables: vec![],
-            function_types: vec![],
-            element_segments: vec![],
+            ..Default::default()
         };
         let result = compiler.compile_function(&function, &[], &module);
         assert!(result.is_ok());
@@ -3846,16 +8447,7 @@ mod tests {
 
         let function = create_simple_function(0, operators);
         let module = WasmModule {
-            functions: vec![],
-            start_func_idx: None,
-            memories: vec![],
-            has_assert_eq32_import: false,
-            has_assert_eq64_import: false,
-            import_count: 0,
-            globals: vec![],
-            tables: vec![],
-            function_types: vec![],
-            element_segments: vec![],
+            ..Default::default()
         };
         let result = compiler.compile_function(&function, &[], &module);
         assert!(result.is_ok());
@@ -3877,16 +8469,7 @@ mod tests {
 
         let function = create_simple_function(0, operators);
         let module = WasmModule {
-            functions: vec![],
-            start_func_idx: None,
-            memories: vec![],
-            has_assert_eq32_import: false,
-            has_assert_eq64_import: false,
-            import_count: 0,
-            globals: vec![],
-            tables: vec![],
-            function_types: vec![],
-            element_segments: vec![],
+            ..Default::default()
         };
         let result = compiler.compile_function(&function, &[], &module);
         assert!(result.is_ok());
@@ -3909,16 +8492,7 @@ mod tests {
 
         let function = create_simple_function(0, operators);
         let module = WasmModule {
-            functions: vec![],
-            start_func_idx: None,
-            memories: vec![],
-            has_assert_eq32_import: false,
-            has_assert_eq64_import: false,
-            import_count: 0,
-            globals: vec![],
-            tables: vec![],
-            function_types: vec![],
-            element_segments: vec![],
+            ..Default::default()
         };
         let result = compiler.compile_function(&function, &[], &module);
         assert!(result.is_ok());
@@ -3947,16 +8521,7 @@ mod tests {
 
         let function = create_simple_function(0, operators);
         let module = WasmModule {
-            functions: vec![],
-            start_func_idx: None,
-            memories: vec![],
-            has_assert_eq32_import: false,
-            has_assert_eq64_import: false,
-            import_count: 0,
-            globals: vec![],
-            tables: vec![],
-            function_types: vec![],
-            element_segments: vec![],
+            ..Default::default()
         };
         let result = compiler.compile_function(&function, &[], &module);
         assert!(result.is_ok());
@@ -3993,16 +8558,7 @@ mod tests {
 
         let function = create_simple_function(0, operators);
         let module = WasmModule {
-            functions: vec![],
-            start_func_idx: None,
-            memories: vec![],
-            has_assert_eq32_import: false,
-            has_assert_eq64_import: false,
-            import_count: 0,
-            globals: vec![],
-            tables: vec![],
-            function_types: vec![],
-            element_segments: vec![],
+            ..Default::default()
         };
         let result = compiler.compile_function(&function, &[], &module);
         assert!(result.is_ok());
@@ -4029,16 +8585,7 @@ mod tests {
 
         let function = create_simple_function(0, operators);
         let module = WasmModule {
-            functions: vec![],
-            start_func_idx: None,
-            memories: vec![],
-            has_assert_eq32_import: false,
-            has_assert_eq64_import: false,
-            import_count: 0,
-            globals: vec![],
-            tables: vec![],
-            function_types: vec![],
-            element_segments: vec![],
+            ..Default::default()
         };
         let result = compiler.compile_function(&function, &[], &module);
         assert!(result.is_ok());
@@ -4064,16 +8611,7 @@ mod tests {
 
         let function = create_simple_function(0, operators);
         let module = WasmModule {
-            functions: vec![],
-            start_func_idx: None,
-            memories: vec![],
-            has_assert_eq32_import: false,
-            has_assert_eq64_import: false,
-            import_count: 0,
-            globals: vec![],
-            tables: vec![],
-            function_types: vec![],
-            element_segments: vec![],
+            ..Default::default()
         };
         let result = compiler.compile_function(&function, &[], &module);
         assert!(result.is_ok());
@@ -4097,16 +8635,7 @@ mod tests {
 
         let function = create_simple_function(0, operators);
         let module = WasmModule {
-            functions: vec![],
-            start_func_idx: None,
-            memories: vec![],
-            has_assert_eq32_import: false,
-            has_assert_eq64_import: false,
-            import_count: 0,
-            globals: vec![],
-            tables: vec![],
-            function_types: vec![],
-            element_segments: vec![],
+            ..Default::default()
         };
         let result = compiler.compile_function(&function, &[], &module);
         assert!(result.is_ok());
@@ -4151,16 +8680,7 @@ mod tests {
 
             let function = create_simple_function(0, operators);
             let module = WasmModule {
-                functions: vec![],
-                start_func_idx: None,
-                memories: vec![],
-                has_assert_eq32_import: false,
-                has_assert_eq64_import: false,
-                import_count: 0,
-                globals: vec![],
-                tables: vec![],
-                function_types: vec![],
-                element_segments: vec![],
+                ..Default::default()
             };
             let result = compiler.compile_function(&function, &[], &module);
             assert!(result.is_ok());
@@ -4198,16 +8718,7 @@ mod tests {
 
         let function = create_simple_function(0, operators);
         let module = WasmModule {
-            functions: vec![],
-            start_func_idx: None,
-            memories: vec![],
-            has_assert_eq32_import: false,
-            has_assert_eq64_import: false,
-            import_count: 0,
-            globals: vec![],
-            tables: vec![],
-            function_types: vec![],
-            element_segments: vec![],
+            ..Default::default()
         };
         let result = compiler.compile_function(&function, &[], &module);
         assert!(result.is_ok());
@@ -4254,16 +8765,7 @@ mod tests {
 
             let function = create_simple_function(0, operators);
             let module = WasmModule {
-                functions: vec![],
-                start_func_idx: None,
-                memories: vec![],
-                has_assert_eq32_import: false,
-                has_assert_eq64_import: false,
-                import_count: 0,
-                globals: vec![],
-                tables: vec![],
-                function_types: vec![],
-                element_segments: vec![],
+                ..Default::default()
             };
             let result = compiler.compile_function(&function, &[], &module);
             assert!(result.is_ok());
@@ -4310,16 +8812,7 @@ mod tests {
 
             let function = create_simple_function(0, operators);
             let module = WasmModule {
-                functions: vec![],
-                start_func_idx: None,
-                memories: vec![],
-                has_assert_eq32_import: false,
-                has_assert_eq64_import: false,
-                import_count: 0,
-                globals: vec![],
-                tables: vec![],
-                function_types: vec![],
-                element_segments: vec![],
+                ..Default::default()
             };
             let result = compiler.compile_function(&function, &[], &module);
             assert!(result.is_ok());
@@ -4357,16 +8850,7 @@ mod tests {
 
         let function_f32 = create_simple_function(0, operators_f32);
         let module = WasmModule {
-            functions: vec![],
-            start_func_idx: None,
-            memories: vec![],
-            has_assert_eq32_import: false,
-            has_assert_eq64_import: false,
-            import_count: 0,
-            globals: vec![],
-            tables: vec![],
-            function_types: vec![],
-            element_segments: vec![],
+            ..Default::default()
         };
         let result = compiler.compile_function(&function_f32, &[], &module);
         assert!(result.is_ok());
@@ -4380,16 +8864,7 @@ mod tests {
 
         let function_f64 = create_simple_function(1, operators_f64);
         let module = WasmModule {
-            functions: vec![],
-            start_func_idx: None,
-            memories: vec![],
-            has_assert_eq32_import: false,
-            has_assert_eq64_import: false,
-            import_count: 0,
-            globals: vec![],
-            tables: vec![],
-            function_types: vec![],
-            element_segments: vec![],
+            ..Default::default()
         };
         let result = compiler.compile_function(&function_f64, &[], &module);
         assert!(result.is_ok());
@@ -4432,16 +8907,7 @@ mod tests {
 
         let function = create_simple_function(0, operators);
         let module = WasmModule {
-            functions: vec![],
-            start_func_idx: None,
-            memories: vec![],
-            has_assert_eq32_import: false,
-            has_assert_eq64_import: false,
-            import_count: 0,
-            globals: vec![],
-            tables: vec![],
-            function_types: vec![],
-            element_segments: vec![],
+            ..Default::default()
         };
         let result = compiler.compile_function(&function, &[], &module);
         assert!(result.is_ok());
@@ -4468,16 +8934,7 @@ mod tests {
         function.body.locals = vec![ValType::I32, ValType::I32];
 
         let module = WasmModule {
-            functions: vec![],
-            start_func_idx: None,
-            memories: vec![],
-            has_assert_eq32_import: false,
-            has_assert_eq64_import: false,
-            import_count: 0,
-            globals: vec![],
-            tables: vec![],
-            function_types: vec![],
-            element_segments: vec![],
+            ..Default::default()
         };
         let result = compiler.compile_function(&function, &[], &module);
         assert!(result.is_ok());
@@ -4524,16 +8981,7 @@ mod tests {
 
         let function = create_simple_function(0, operators);
         let module = WasmModule {
-            functions: vec![],
-            start_func_idx: None,
-            memories: vec![],
-            has_assert_eq32_import: false,
-            has_assert_eq64_import: false,
-            import_count: 0,
-            globals: vec![],
-            tables: vec![],
-            function_types: vec![],
-            element_segments: vec![],
+            ..Default::default()
         };
         let result = compiler.compile_function(&function, &[], &module);
         assert!(result.is_ok());
@@ -4558,16 +9006,7 @@ mod tests {
 
         let function = create_simple_function(0, operators);
         let module = WasmModule {
-            functions: vec![],
-            start_func_idx: None,
-            memories: vec![],
-            has_assert_eq32_import: false,
-            has_assert_eq64_import: false,
-            import_count: 0,
-            globals: vec![],
-            tables: vec![],
-            function_types: vec![],
-            element_segments: vec![],
+            ..Default::default()
         };
         let result = compiler.compile_function(&function, &[], &module);
         assert!(result.is_ok());
@@ -0,0 +1,258 @@
+//! Debug info for `-g`. Modules that carry a DWARF line table
+//! (`.debug_line`) from their original source get locations pointing at its
+//! files and lines, so debuggers can step through the Rust or C code the
+//! module was built from. Only the line table is translated: variables,
+//! types and inlining trees in `.debug_info` are not, so debuggers show
+//! source lines but cannot print source-level variables.
+//!
+//! Functions without source DWARF fall back to synthetic debug info: every
+//! Wasm function gets a subprogram and every instruction a location whose
+//! line is the byte offset of the Wasm operator it came from, so debuggers
+//! and symbolized backtraces point back into the module binary.
+
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+
+use inkwell::context::Context;
+use inkwell::debug_info::{
+    AsDIScope, DICompileUnit, DIFile, DIFlags, DIFlagsConstants, DILexicalBlock, DILocation,
+    DIScope, DISubprogram, DWARFEmissionKind, DWARFSourceLanguage, DebugInfoBuilder,
+    debug_metadata_version,
+};
+use inkwell::module::{FlagBehavior, Module};
+use inkwell::values::FunctionValue;
+
+use crate::dwarf::LineTable;
+use crate::wasm_parser::DwarfSections;
+
+pub(crate) struct DebugInfo<'ctx> {
+    builder: DebugInfoBuilder<'ctx>,
+    compile_unit: DICompileUnit<'ctx>,
+    is_optimized: bool,
+    lines: Option<LineTable>,
+    /// Parallel to the files of `lines`.
+    files: Vec<DIFile<'ctx>>,
+    /// Source file of the function being compiled, if it has source lines.
+    function_file: Cell<Option<usize>>,
+    /// Scopes for code of the current function that the line table places
+    /// in another file, typically inlined functions from headers.
+    file_scopes: RefCell<HashMap<usize, DILexicalBlock<'ctx>>>,
+}
+
+impl<'ctx> DebugInfo<'ctx> {
+    pub fn new(
+        context: &'ctx Context,
+        module: &Module<'ctx>,
+        source_name: &str,
+        is_optimized: bool,
+    ) -> Self {
+        module.add_basic_value_flag(
+            "Debug Info Version",
+            FlagBehavior::Warning,
+            context
+                .i32_type()
+                .const_int(debug_metadata_version() as u64, false),
+        );
+        let (builder, compile_unit) = module.create_debug_info_builder(
+            true,
+            // DWARF has no language code for Wasm.
+            DWARFSourceLanguage::C,
+            source_name,
+            ".",
+            env!("CARGO_PKG_NAME"),
+            is_optimized,
+            "",
+            0,
+            "",
+            DWARFEmissionKind::Full,
+            0,
+            false,
+            false,
+            "",
+            "",
+        );
+        Self {
+            builder,
+            compile_unit,
+            is_optimized,
+            lines: None,
+            files: Vec::new(),
+            function_file: Cell::new(None),
+            file_scopes: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// Switches to the source lines in the module's own DWARF, if it has a
+    /// line table.
+    pub fn load_source_lines(&mut self, dwarf: &DwarfSections) {
+        let lines = match LineTable::parse(dwarf) {
+            Ok(Some(lines)) => lines,
+            Ok(None) => return,
+            Err(e) => {
+                eprintln!("Ignoring malformed DWARF line table: {e}");
+                return;
+            }
+        };
+        self.files = lines
+            .files
+            .iter()
+            .map(|file| self.builder.create_file(&file.name, &file.directory))
+            .collect();
+        self.lines = Some(lines);
+    }
+
+    /// Attaches a subprogram to `function`, starting at byte `offset`.
+    pub fn create_function(
+        &self,
+        function: FunctionValue<'ctx>,
+        name: &str,
+        offset: u32,
+    ) -> DISubprogram<'ctx> {
+        let row = self.lines.as_ref().and_then(|l| l.lookup(offset as usize));
+        self.function_file.set(row.map(|row| row.file));
+        self.file_scopes.borrow_mut().clear();
+        let (file, line) = match row {
+            Some(row) => (self.files[row.file], row.line),
+            None => (self.compile_unit.get_file(), offset),
+        };
+
+        let subroutine_type = self
+            .builder
+            .create_subroutine_type(file, None, &[], DIFlags::ZERO);
+        let linkage_name = function.get_name().to_string_lossy();
+        let subprogram = self.builder.create_function(
+            self.compile_unit.as_debug_info_scope(),
+            name,
+            Some(&linkage_name),
+            file,
+            line,
+            subroutine_type,
+            true,
+            true,
+            line,
+            DIFlags::ZERO,
+            self.is_optimized,
+        );
+        function.set_subprogram(subprogram);
+        subprogram
+    }
+
+    /// Location of the code generated for the operator at byte `offset`.
+    /// Operators the line table does not cover get line 0, which debuggers
+    /// treat as compiler-generated code.
+    pub fn location(
+        &self,
+        context: &'ctx Context,
+        subprogram: DISubprogram<'ctx>,
+        offset: u32,
+    ) -> DILocation<'ctx> {
+        let scope = subprogram.as_debug_info_scope();
+        let (line, column, scope) = match (self.function_file.get(), &self.lines) {
+            (Some(function_file), Some(lines)) => match lines.lookup(offset as usize) {
+                Some(row) if row.file == function_file => (row.line, row.column, scope),
+                Some(row) => (row.line, row.column, self.file_scope(scope, row.file)),
+                None => (0, 0, scope),
+            },
+            _ => (offset, 0, scope),
+        };
+        self.builder
+            .create_debug_location(context, line, column, scope, None)
+    }
+
+    fn file_scope(&self, subprogram: DIScope<'ctx>, file: usize) -> DIScope<'ctx> {
+        let mut scopes = self.file_scopes.borrow_mut();
+        let block = scopes.entry(file).or_insert_with(|| {
+            self.builder
+                .create_lexical_block(subprogram, self.files[file], 0, 0)
+        });
+        block.as_debug_info_scope()
+    }
+
+    /// Resolves the debug info; must run before the module is verified or
+    /// emitted.
+    pub fn finalize(&self) {
+        self.builder.finalize();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dwarf::tests::{debug_line_unit, dwarf_sections};
+
+    /// Compiles nothing but a subprogram for a function starting at `offset`
+    /// and returns the location of the operator at each of `operators`.
+    fn locations(dwarf: Option<DwarfSections>, offset: u32, operators: &[u32]) -> Vec<(u32, u32)> {
+        let context = Context::create();
+        let module = context.create_module("test");
+        let mut debug_info = DebugInfo::new(&context, &module, "test.wasm", false);
+        if let Some(dwarf) = dwarf {
+            debug_info.load_source_lines(&dwarf);
+        }
+        let function = module.add_function("f", context.void_type().fn_type(&[], false), None);
+        let subprogram = debug_info.create_function(function, "f", offset);
+        operators
+            .iter()
+            .map(|&operator| {
+                let location = debug_info.location(&context, subprogram, operator);
+                (location.get_line(), location.get_column())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_source_line_locations() {
+        // Code section addresses 0x10..0x16 are module offsets 0x30..0x36.
+        assert_eq!(
+            locations(Some(dwarf_sections()), 0x30, &[0x30, 0x33, 0x34]),
+            [(7, 3), (7, 3), (9, 3)]
+        );
+
+        let context = Context::create();
+        let module = context.create_module("test");
+        let mut debug_info = DebugInfo::new(&context, &module, "test.wasm", false);
+        debug_info.load_source_lines(&dwarf_sections());
+        let function = module.add_function("f", context.void_type().fn_type(&[], false), None);
+        let subprogram = debug_info.create_function(function, "f", 0x30);
+        let scope = subprogram.as_debug_info_scope();
+        // util.h code is placed in a lexical block of that file.
+        assert_eq!(
+            debug_info.location(&context, subprogram, 0x33).get_scope(),
+            scope
+        );
+        assert_ne!(
+            debug_info.location(&context, subprogram, 0x34).get_scope(),
+            scope
+        );
+    }
+
+    #[test]
+    fn test_operators_without_line_rows() {
+        // Past the end of the sequence and before the code the table covers.
+        assert_eq!(
+            locations(Some(dwarf_sections()), 0x30, &[0x36, 0x2f]),
+            [(0, 0), (0, 0)]
+        );
+        // Without a line table, lines are Wasm byte offsets.
+        assert_eq!(locations(None, 0x30, &[0x33, 0x36]), [(0x33, 0), (0x36, 0)]);
+    }
+
+    #[test]
+    fn test_out_of_order_sequences() {
+        let mut program = Vec::new();
+        // set_address 0x18, advance_line 19, copy, advance_pc 4, end_sequence
+        program.extend([0, 5, 2, 0x18, 0, 0, 0, 3, 19, 1, 2, 4, 0, 1, 1]);
+        // set_address 0x10, advance_line 4, copy, advance_pc 8, end_sequence
+        program.extend([0, 5, 2, 0x10, 0, 0, 0, 3, 4, 1, 2, 8, 0, 1, 1]);
+        let dwarf = DwarfSections {
+            code_section_offset: 0x20,
+            sections: [(".debug_line".to_string(), debug_line_unit(&program))].into(),
+        };
+
+        // The second sequence ends at 0x18, where the first one starts.
+        assert_eq!(
+            locations(Some(dwarf), 0x30, &[0x30, 0x37, 0x38, 0x3b, 0x3c]),
+            [(5, 0), (5, 0), (20, 0), (20, 0), (0, 0)]
+        );
+    }
+}
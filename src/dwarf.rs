@@ -0,0 +1,574 @@
+//! Reads the DWARF line table that toolchains such as clang and rustc embed
+//! in Wasm custom sections, so code can be mapped back to source lines.
+//!
+//! Addresses in Wasm DWARF are byte offsets from the start of the code
+//! section contents.
+
+use anyhow::{Result, anyhow};
+
+use crate::wasm_parser::DwarfSections;
+
+const DW_LNS_COPY: u8 = 1;
+const DW_LNS_ADVANCE_PC: u8 = 2;
+const DW_LNS_ADVANCE_LINE: u8 = 3;
+const DW_LNS_SET_FILE: u8 = 4;
+const DW_LNS_SET_COLUMN: u8 = 5;
+const DW_LNS_CONST_ADD_PC: u8 = 8;
+const DW_LNS_FIXED_ADVANCE_PC: u8 = 9;
+
+const DW_LNE_END_SEQUENCE: u8 = 1;
+const DW_LNE_SET_ADDRESS: u8 = 2;
+const DW_LNE_DEFINE_FILE: u8 = 3;
+
+const DW_LNCT_PATH: u64 = 1;
+const DW_LNCT_DIRECTORY_INDEX: u64 = 2;
+
+const DW_FORM_BLOCK: u64 = 0x09;
+const DW_FORM_DATA1: u64 = 0x0b;
+const DW_FORM_DATA2: u64 = 0x05;
+const DW_FORM_DATA4: u64 = 0x06;
+const DW_FORM_DATA8: u64 = 0x07;
+const DW_FORM_DATA16: u64 = 0x1e;
+const DW_FORM_STRING: u64 = 0x08;
+const DW_FORM_STRP: u64 = 0x0e;
+const DW_FORM_UDATA: u64 = 0x0f;
+const DW_FORM_LINE_STRP: u64 = 0x1f;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct SourceFile {
+    pub directory: String,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct LineRow {
+    pub address: u64,
+    /// Index into [`LineTable::files`].
+    pub file: usize,
+    pub line: u32,
+    pub column: u32,
+    end_sequence: bool,
+}
+
+/// The rows of every line program in `.debug_line`, sorted by address.
+#[derive(Debug, Default)]
+pub(crate) struct LineTable {
+    pub files: Vec<SourceFile>,
+    rows: Vec<LineRow>,
+    code_section_offset: usize,
+}
+
+impl LineTable {
+    /// Returns `None` when the module carries no `.debug_line` section.
+    pub fn parse(dwarf: &DwarfSections) -> Result<Option<Self>> {
+        let Some(debug_line) = dwarf.sections.get(".debug_line") else {
+            return Ok(None);
+        };
+        let strings = Strings {
+            debug_str: dwarf.sections.get(".debug_str").map_or(&[], |s| s),
+            debug_line_str: dwarf.sections.get(".debug_line_str").map_or(&[], |s| s),
+        };
+
+        let mut table = LineTable {
+            code_section_offset: dwarf.code_section_offset,
+            ..Default::default()
+        };
+        let mut reader = Reader::new(debug_line);
+        while !reader.is_empty() {
+            table.parse_unit(&mut reader, &strings)?;
+        }
+        // A sequence may start where another one listed after it ends, so
+        // end rows sort first to keep the start row authoritative.
+        table
+            .rows
+            .sort_by_key(|row| (row.address, !row.end_sequence));
+        Ok(Some(table))
+    }
+
+    /// Finds the row covering the operator at `offset` in the module binary.
+    pub fn lookup(&self, offset: usize) -> Option<&LineRow> {
+        let address = offset.checked_sub(self.code_section_offset)? as u64;
+        let end = self.rows.partition_point(|row| row.address <= address);
+        let row = self.rows[..end].last()?;
+        (!row.end_sequence).then_some(row)
+    }
+
+    fn parse_unit(&mut self, reader: &mut Reader, strings: &Strings) -> Result<()> {
+        let (unit_length, offset_size) = match reader.u32()? {
+            0xffff_ffff => (reader.u64()?, 8),
+            length => (length as u64, 4),
+        };
+        let mut unit = Reader::new(reader.bytes(unit_length as usize)?);
+
+        let version = unit.u16()?;
+        if !(2..=5).contains(&version) {
+            return Err(anyhow!("Unsupported .debug_line version: {}", version));
+        }
+        if version >= 5 {
+            let _address_size = unit.u8()?;
+            let _segment_selector_size = unit.u8()?;
+        }
+        let header_length = unit.offset(offset_size)?;
+        let mut program = unit.clone();
+        program.skip(header_length as usize)?;
+
+        let min_instruction_length = unit.u8()?;
+        if version >= 4 {
+            // Wasm has no VLIW bundles, so operation indices are ignored.
+            let _max_ops_per_instruction = unit.u8()?;
+        }
+        let _default_is_stmt = unit.u8()?;
+        let header = LineProgramHeader {
+            min_instruction_length,
+            line_base: unit.u8()? as i8,
+            line_range: unit.u8()?,
+            opcode_base: unit.u8()?,
+        };
+        if header.line_range == 0 {
+            return Err(anyhow!("Invalid .debug_line header: line_range is 0"));
+        }
+        let mut standard_opcode_lengths = Vec::new();
+        for _ in 1..header.opcode_base {
+            standard_opcode_lengths.push(unit.u8()?);
+        }
+
+        // Maps the unit's file numbers to indices into `self.files`.
+        let mut files = Vec::new();
+        if version >= 5 {
+            let directories: Vec<String> = self
+                .read_entries(&mut unit, strings, offset_size)?
+                .into_iter()
+                .map(|(path, _)| path)
+                .collect();
+            for (name, directory) in self.read_entries(&mut unit, strings, offset_size)? {
+                let directory = directories.get(directory).cloned().unwrap_or_default();
+                files.push(self.add_file(directory, name));
+            }
+        } else {
+            // DWARF 4 numbers files and directories from 1, with 0 meaning
+            // the compilation directory.
+            let mut directories = vec![String::new()];
+            loop {
+                let directory = unit.string()?;
+                if directory.is_empty() {
+                    break;
+                }
+                directories.push(directory);
+            }
+            files.push(self.add_file(String::new(), String::new()));
+            loop {
+                let name = unit.string()?;
+                if name.is_empty() {
+                    break;
+                }
+                let directory = unit.uleb128()? as usize;
+                let _mtime = unit.uleb128()?;
+                let _length = unit.uleb128()?;
+                let directory = directories.get(directory).cloned().unwrap_or_default();
+                files.push(self.add_file(directory, name));
+            }
+        }
+
+        self.run_program(
+            &mut program,
+            &header,
+            &standard_opcode_lengths,
+            &mut files,
+            if version >= 5 { 0 } else { 1 },
+        )
+    }
+
+    fn run_program(
+        &mut self,
+        program: &mut Reader,
+        header: &LineProgramHeader,
+        standard_opcode_lengths: &[u8],
+        files: &mut Vec<usize>,
+        initial_file: u64,
+    ) -> Result<()> {
+        let initial = LineState {
+            address: 0,
+            file: initial_file,
+            line: 1,
+            column: 0,
+        };
+        let mut state = initial;
+        let mut sequence: Vec<LineRow> = Vec::new();
+        let min_instruction_length = header.min_instruction_length as u64;
+
+        while !program.is_empty() {
+            let opcode = program.u8()?;
+            if opcode >= header.opcode_base {
+                let adjusted = opcode - header.opcode_base;
+                state.address += (adjusted / header.line_range) as u64 * min_instruction_length;
+                state.line = state.line.wrapping_add_signed(
+                    header.line_base as i64 + (adjusted % header.line_range) as i64,
+                );
+                sequence.push(state.row(files, false));
+                continue;
+            }
+            match opcode {
+                0 => {
+                    let length = program.uleb128()? as usize;
+                    let mut instruction = Reader::new(program.bytes(length)?);
+                    match instruction.u8()? {
+                        DW_LNE_END_SEQUENCE => {
+                            sequence.push(state.row(files, true));
+                            self.add_sequence(&mut sequence);
+                            state = initial;
+                        }
+                        DW_LNE_SET_ADDRESS => {
+                            state.address = instruction.address(length - 1)?;
+                        }
+                        DW_LNE_DEFINE_FILE => {
+                            let name = instruction.string()?;
+                            files.push(self.add_file(String::new(), name));
+                        }
+                        _ => {}
+                    }
+                }
+                DW_LNS_COPY => sequence.push(state.row(files, false)),
+                DW_LNS_ADVANCE_PC => {
+                    state.address += program.uleb128()? * min_instruction_length;
+                }
+                DW_LNS_ADVANCE_LINE => {
+                    state.line = state.line.wrapping_add_signed(program.sleb128()?);
+                }
+                DW_LNS_SET_FILE => state.file = program.uleb128()?,
+                DW_LNS_SET_COLUMN => state.column = program.uleb128()?,
+                DW_LNS_CONST_ADD_PC => {
+                    let adjusted = 255 - header.opcode_base;
+                    state.address += (adjusted / header.line_range) as u64 * min_instruction_length;
+                }
+                DW_LNS_FIXED_ADVANCE_PC => state.address += program.u16()? as u64,
+                _ => {
+                    let operands = standard_opcode_lengths
+                        .get(opcode as usize - 1)
+                        .copied()
+                        .unwrap_or(0);
+                    for _ in 0..operands {
+                        program.uleb128()?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Keeps a finished sequence unless the linker discarded the code it
+    /// describes, which it marks by relocating the sequence to address 0 or
+    /// to a tombstone near `u32::MAX`.
+    fn add_sequence(&mut self, sequence: &mut Vec<LineRow>) {
+        let dead = sequence
+            .first()
+            .is_none_or(|row| row.address == 0 || row.address >= u32::MAX as u64 - 1);
+        if dead {
+            sequence.clear();
+        } else {
+            self.rows.append(sequence);
+        }
+    }
+
+    fn add_file(&mut self, directory: String, name: String) -> usize {
+        let file = SourceFile { directory, name };
+        match self.files.iter().position(|f| *f == file) {
+            Some(index) => index,
+            None => {
+                self.files.push(file);
+                self.files.len() - 1
+            }
+        }
+    }
+
+    /// Reads a DWARF 5 directory or file name table as (path, directory
+    /// index) pairs.
+    fn read_entries(
+        &self,
+        unit: &mut Reader,
+        strings: &Strings,
+        offset_size: usize,
+    ) -> Result<Vec<(String, usize)>> {
+        let format_count = unit.u8()?;
+        let mut format = Vec::new();
+        for _ in 0..format_count {
+            format.push((unit.uleb128()?, unit.uleb128()?));
+        }
+        let count = unit.uleb128()?;
+        let mut entries = Vec::new();
+        for _ in 0..count {
+            let mut path = String::new();
+            let mut directory = 0;
+            for &(content_type, form) in &format {
+                match (content_type, form) {
+                    (DW_LNCT_PATH, DW_FORM_STRING) => path = unit.string()?,
+                    (DW_LNCT_PATH, DW_FORM_LINE_STRP) => {
+                        path = strings.get(strings.debug_line_str, unit.offset(offset_size)?)?;
+                    }
+                    (DW_LNCT_PATH, DW_FORM_STRP) => {
+                        path = strings.get(strings.debug_str, unit.offset(offset_size)?)?;
+                    }
+                    (DW_LNCT_DIRECTORY_INDEX, DW_FORM_DATA1) => directory = unit.u8()? as usize,
+                    (DW_LNCT_DIRECTORY_INDEX, DW_FORM_DATA2) => {
+                        directory = unit.u16()? as usize;
+                    }
+                    (DW_LNCT_DIRECTORY_INDEX, DW_FORM_UDATA) => {
+                        directory = unit.uleb128()? as usize;
+                    }
+                    (_, form) => unit.skip_form(form, offset_size)?,
+                }
+            }
+            entries.push((path, directory));
+        }
+        Ok(entries)
+    }
+}
+
+struct LineProgramHeader {
+    min_instruction_length: u8,
+    line_base: i8,
+    line_range: u8,
+    opcode_base: u8,
+}
+
+#[derive(Clone, Copy)]
+struct LineState {
+    address: u64,
+    file: u64,
+    line: u64,
+    column: u64,
+}
+
+impl LineState {
+    fn row(&self, files: &[usize], end_sequence: bool) -> LineRow {
+        LineRow {
+            address: self.address,
+            file: files.get(self.file as usize).copied().unwrap_or(0),
+            line: self.line as u32,
+            column: self.column as u32,
+            end_sequence,
+        }
+    }
+}
+
+struct Strings<'a> {
+    debug_str: &'a [u8],
+    debug_line_str: &'a [u8],
+}
+
+impl Strings<'_> {
+    fn get(&self, section: &[u8], offset: u64) -> Result<String> {
+        let mut reader = Reader::new(section);
+        reader.skip(offset as usize)?;
+        reader.string()
+    }
+}
+
+#[derive(Clone)]
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        if len > self.data.len() {
+            return Err(anyhow!("Unexpected end of DWARF section"));
+        }
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    fn skip(&mut self, len: usize) -> Result<()> {
+        self.bytes(len).map(|_| ())
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_le_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+
+    /// A section offset, 4 bytes in 32-bit DWARF and 8 in 64-bit DWARF.
+    fn offset(&mut self, offset_size: usize) -> Result<u64> {
+        self.address(offset_size)
+    }
+
+    fn address(&mut self, size: usize) -> Result<u64> {
+        let bytes = self.bytes(size)?;
+        if size > 8 {
+            return Err(anyhow!("Unsupported DWARF address size: {}", size));
+        }
+        let mut buf = [0; 8];
+        buf[..size].copy_from_slice(bytes);
+        Ok(u64::from_le_bytes(buf))
+    }
+
+    fn uleb128(&mut self) -> Result<u64> {
+        let mut result = 0;
+        let mut shift = 0;
+        loop {
+            let byte = self.u8()?;
+            if shift < 64 {
+                result |= ((byte & 0x7f) as u64) << shift;
+            }
+            shift += 7;
+            if byte & 0x80 == 0 {
+                return Ok(result);
+            }
+        }
+    }
+
+    fn sleb128(&mut self) -> Result<i64> {
+        let mut result = 0i64;
+        let mut shift = 0;
+        loop {
+            let byte = self.u8()?;
+            if shift < 64 {
+                result |= ((byte & 0x7f) as i64) << shift;
+            }
+            shift += 7;
+            if byte & 0x80 == 0 {
+                if shift < 64 && byte & 0x40 != 0 {
+                    result |= -1 << shift;
+                }
+                return Ok(result);
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String> {
+        let len = self
+            .data
+            .iter()
+            .position(|&b| b == 0)
+            .ok_or(anyhow!("Unterminated string in DWARF section"))?;
+        let string = String::from_utf8_lossy(&self.data[..len]).into_owned();
+        self.skip(len + 1)?;
+        Ok(string)
+    }
+
+    fn skip_form(&mut self, form: u64, offset_size: usize) -> Result<()> {
+        match form {
+            DW_FORM_DATA1 => self.skip(1),
+            DW_FORM_DATA2 => self.skip(2),
+            DW_FORM_DATA4 => self.skip(4),
+            DW_FORM_DATA8 => self.skip(8),
+            DW_FORM_DATA16 => self.skip(16),
+            DW_FORM_UDATA => self.uleb128().map(|_| ()),
+            DW_FORM_STRING => self.string().map(|_| ()),
+            DW_FORM_STRP | DW_FORM_LINE_STRP => self.skip(offset_size),
+            DW_FORM_BLOCK => {
+                let len = self.uleb128()? as usize;
+                self.skip(len)
+            }
+            _ => Err(anyhow!(
+                "Unsupported DWARF form in line table: 0x{:x}",
+                form
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// A DWARF 4 line table placing code section addresses 0x10..0x14 at
+    /// src/kernel.c:7:3 and 0x14..0x16 at src/util.h:9, plus a sequence the
+    /// linker discarded.
+    pub(crate) fn debug_line_section() -> Vec<u8> {
+        let mut program = Vec::new();
+        // set_address 0x10, advance_line 6, set_column 3, copy
+        program.extend([0, 5, 2, 0x10, 0, 0, 0, 3, 6, 5, 3, 1]);
+        // advance_pc 4, set_file 2, advance_line 2, copy
+        program.extend([2, 4, 4, 2, 3, 2, 1]);
+        // advance_pc 2, end_sequence
+        program.extend([2, 2, 0, 1, 1]);
+        // set_address 0, copy, advance_pc 2, end_sequence
+        program.extend([0, 5, 2, 0, 0, 0, 0, 1, 2, 2, 0, 1, 1]);
+        debug_line_unit(&program)
+    }
+
+    /// A DWARF 4 `.debug_line` section with one unit that runs `program`
+    /// over the files src/kernel.c (1) and src/util.h (2).
+    pub(crate) fn debug_line_unit(program: &[u8]) -> Vec<u8> {
+        let mut header = vec![1, 1, 1, (-5i8) as u8, 14, 13];
+        header.extend([0, 1, 1, 1, 1, 0, 0, 0, 1, 0, 0, 1]);
+        header.extend(b"src\0\0");
+        header.extend(b"kernel.c\0\x01\0\0");
+        header.extend(b"util.h\0\x01\0\0\0");
+
+        let mut unit = 4u16.to_le_bytes().to_vec();
+        unit.extend((header.len() as u32).to_le_bytes());
+        unit.extend(header);
+        unit.extend(program);
+
+        let mut section = (unit.len() as u32).to_le_bytes().to_vec();
+        section.extend(unit);
+        section
+    }
+
+    pub(crate) fn dwarf_sections() -> DwarfSections {
+        DwarfSections {
+            code_section_offset: 0x20,
+            sections: [(".debug_line".to_string(), debug_line_section())].into(),
+        }
+    }
+
+    #[test]
+    fn test_line_table_lookup() {
+        let table = LineTable::parse(&dwarf_sections()).unwrap().unwrap();
+
+        let row = table.lookup(0x33).unwrap();
+        assert_eq!((row.line, row.column), (7, 3));
+        assert_eq!(
+            table.files[row.file],
+            SourceFile {
+                directory: "src".to_string(),
+                name: "kernel.c".to_string(),
+            }
+        );
+
+        let row = table.lookup(0x34).unwrap();
+        assert_eq!(row.line, 9);
+        assert_eq!(table.files[row.file].name, "util.h");
+
+        assert!(table.lookup(0x36).is_none());
+        assert!(table.lookup(0x2f).is_none());
+        assert!(table.lookup(0x10).is_none());
+    }
+
+    #[test]
+    fn test_missing_line_table() {
+        assert!(
+            LineTable::parse(&DwarfSections::default())
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_truncated_line_table() {
+        let mut dwarf = dwarf_sections();
+        dwarf.sections.get_mut(".debug_line").unwrap().truncate(20);
+        assert!(LineTable::parse(&dwarf).is_err());
+    }
+}
@@ -0,0 +1,844 @@
+//! Embedding API for running Wasm modules in-process.
+//!
+//! An [`Engine`] holds the compiler configuration, a [`Module`] is a Wasm
+//! module JIT-compiled by an engine, and an [`Instance`] is a module
+//! instantiated with host functions for its imports:
+//!
+//! ```no_run
+//! use auto_parallel_wasm::{Engine, Imports, Instance, Module};
+//!
+//! extern "C" fn log(value: i32) {
+//!     println!("{value}");
+//! }
+//!
+//! # fn main() -> anyhow::Result<()> {
+//! let engine = Engine::default();
+//! let module = Module::from_bytes(&engine, &std::fs::read("add.wasm")?)?;
+//! let mut imports = Imports::new();
+//! imports.func("env", "log", log as extern "C" fn(i32));
+//! let instance = Instance::new(&module, &imports)?;
+//! let add = instance.get_typed_func::<(i32, i32), i32>("add")?;
+//! assert_eq!(add.call((2, 3))?, 5);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Host functions are plain `extern "C"` functions taking and returning Wasm
+//! value types. Engines and modules are `Send` and `Sync`, so one module can
+//! be instantiated on any number of threads at once, while each instance
+//! stays on the thread that created it.
+//! A trap makes the call that raised it return a [`Trap`] error, after which
+//! the instance can still be used. Engines configured with `consume_fuel` or
+//! `interruptible` bound untrusted code through [`Instance::set_fuel`] and
+//! [`Instance::interrupt_handle`], and with `check_stack` deep recursion
+//! traps instead of overflowing the stack.
+
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::mem;
+use std::sync::{Arc, mpsc};
+use std::thread::{self, JoinHandle};
+
+use anyhow::{Result, anyhow};
+use inkwell::context::Context;
+use wasmparser::{ExternalKind, FuncType, GlobalType, ValType};
+
+use crate::compiler::Compiler;
+use crate::header;
+use crate::limits::{DEFAULT_MAX_STACK, InterruptHandle, stack_limit};
+use crate::options::CompilerOptions;
+use crate::trap::Trap;
+use crate::vmctx::VmctxLayout;
+use crate::wasm_parser::{Export, ImportedFunction, WasmModule};
+
+/// Compiler configuration shared by the modules it compiles.
+#[derive(Debug, Clone, Default)]
+pub struct Engine {
+    config: CompilerOptions,
+}
+
+impl Engine {
+    /// An engine compiling with `config`. Lazy compilation is not supported
+    /// and makes `Module::from_bytes` fail.
+    pub fn new(config: CompilerOptions) -> Self {
+        Self { config }
+    }
+
+    pub fn config(&self) -> &CompilerOptions {
+        &self.config
+    }
+}
+
+/// A compiled module, cheap to clone and instantiate any number of times,
+/// from any thread.
+#[derive(Clone)]
+pub struct Module {
+    inner: Arc<ModuleInner>,
+}
+
+struct ModuleInner {
+    options: CompilerOptions,
+    wasm_module: WasmModule,
+    layout: VmctxLayout,
+    /// Addresses of the instance functions and of every defined function
+    /// export with its array-call wrapper.
+    symbols: HashMap<String, usize>,
+    _code: JitCode,
+}
+
+/// Keeps the machine code of a module alive. The LLVM context and execution
+/// engine owning the code never leave the thread that compiled them, which
+/// waits until the code is dropped and then frees both in order.
+struct JitCode {
+    release: Option<mpsc::Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Drop for JitCode {
+    fn drop(&mut self) {
+        self.release.take();
+        if let Some(thread) = self.thread.take() {
+            thread.join().ok();
+        }
+    }
+}
+
+/// What the compiling thread hands back to `Module::from_bytes`.
+struct Compiled {
+    wasm_module: WasmModule,
+    layout: VmctxLayout,
+    symbols: HashMap<String, usize>,
+}
+
+impl Compiled {
+    fn new(compiler: &mut Compiler, wasm_module: WasmModule) -> Result<Self> {
+        compiler.compile_module(&wasm_module)?;
+        let mut names = vec![
+            header::INSTANCE_NEW_SYMBOL.to_string(),
+            header::INSTANCE_FREE_SYMBOL.to_string(),
+        ];
+        if wasm_module.start_func_idx.is_some() {
+            names.push(header::INSTANCE_START_SYMBOL.to_string());
+        }
+        for export in &wasm_module.exports {
+            let defined = wasm_module.functions.iter().any(|f| f.idx == export.index);
+            if export.kind == ExternalKind::Func && defined {
+                names.push(export.name.clone());
+                names.push(header::array_call_symbol(&export.name));
+            }
+        }
+        let symbols = names
+            .into_iter()
+            .map(|name| Ok((name.clone(), compiler.function_address(&name)?)))
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            layout: compiler.layout(),
+            wasm_module,
+            symbols,
+        })
+    }
+}
+
+impl Module {
+    pub fn from_bytes(engine: &Engine, wasm_bytes: &[u8]) -> Result<Self> {
+        let wasm_module = WasmModule::parse(wasm_bytes)?;
+        let options = engine.config.clone();
+        let (result_sender, result_receiver) = mpsc::channel();
+        let (release, released) = mpsc::channel::<()>();
+        let thread = {
+            let options = options.clone();
+            thread::Builder::new()
+                .name("wasm-jit".to_string())
+                .spawn(move || {
+                    let context = Context::create();
+                    let compiled =
+                        Compiler::for_embedding(&context, options).and_then(|mut compiler| {
+                            let compiled = Compiled::new(&mut compiler, wasm_module)?;
+                            Ok((compiler, compiled))
+                        });
+                    match compiled {
+                        // The compiler, and with it the code, lives until the
+                        // module releases it, and goes before the context.
+                        Ok((_compiler, compiled)) => {
+                            if result_sender.send(Ok(compiled)).is_ok() {
+                                released.recv().ok();
+                            }
+                        }
+                        Err(e) => {
+                            result_sender.send(Err(e)).ok();
+                        }
+                    }
+                })?
+        };
+        let code = JitCode {
+            release: Some(release),
+            thread: Some(thread),
+        };
+        let compiled = result_receiver
+            .recv()
+            .map_err(|_| anyhow!("Compilation thread panicked"))??;
+        Ok(Self {
+            inner: Arc::new(ModuleInner {
+                options,
+                wasm_module: compiled.wasm_module,
+                layout: compiled.layout,
+                symbols: compiled.symbols,
+                _code: code,
+            }),
+        })
+    }
+
+    pub fn imports(&self) -> &[ImportedFunction] {
+        &self.inner.wasm_module.imported_functions
+    }
+
+    pub fn exports(&self) -> &[Export] {
+        &self.inner.wasm_module.exports
+    }
+
+    fn export(&self, name: &str, kind: ExternalKind) -> Option<&Export> {
+        self.exports()
+            .iter()
+            .find(|export| export.name == name && export.kind == kind)
+    }
+
+    fn address(&self, name: &str) -> Result<usize> {
+        self.inner
+            .symbols
+            .get(name)
+            .copied()
+            .ok_or(anyhow!("Failed to find {}", name))
+    }
+}
+
+/// Host functions for the function imports of a module, keyed by import
+/// module and name.
+#[derive(Default)]
+pub struct Imports {
+    funcs: HashMap<(String, String), (FuncType, usize)>,
+}
+
+impl Imports {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Binds import `module`.`name` to `func`, which must be cast to its
+    /// function pointer type, as in `log as extern "C" fn(i32)`.
+    pub fn func<F: HostFunc>(&mut self, module: &str, name: &str, func: F) -> &mut Self {
+        self.funcs.insert(
+            (module.to_string(), name.to_string()),
+            (F::func_type(), func.address()),
+        );
+        self
+    }
+
+    /// Binds import `module`.`name` to the host function at `address`, which
+    /// must have type `func_type`.
+    pub(crate) fn func_raw(
+        &mut self,
+        module: &str,
+        name: &str,
+        func_type: FuncType,
+        address: usize,
+    ) {
+        self.funcs
+            .insert((module.to_string(), name.to_string()), (func_type, address));
+    }
+}
+
+/// An instance of a module, with its own memory, tables and globals.
+pub struct Instance {
+    module: Module,
+    vmctx: *mut u8,
+    free: unsafe extern "C" fn(*mut u8),
+    interrupt: InterruptHandle,
+    max_stack: Cell<usize>,
+}
+
+impl Instance {
+    /// Instantiates `module`, binding every function import to its host
+    /// function in `imports`, and runs the start function.
+    pub fn new(module: &Module, imports: &Imports) -> Result<Self> {
+        let mut host_functions = Vec::new();
+        for import in module.imports() {
+            let key = (import.module.clone(), import.name.clone());
+            let (func_type, address) = imports.funcs.get(&key).ok_or(anyhow!(
+                "Missing host function for import {}::{}",
+                import.module,
+                import.name
+            ))?;
+            if *func_type != import.func_type {
+                return Err(anyhow!(
+                    "Host function for import {}::{} has type {}, expected {}",
+                    import.module,
+                    import.name,
+                    func_type,
+                    import.func_type
+                ));
+            }
+            host_functions.push(*address);
+        }
+
+        type NewFn = unsafe extern "C" fn() -> *mut u8;
+        type FreeFn = unsafe extern "C" fn(*mut u8);
+        type StartFn = unsafe extern "C" fn(*mut u8);
+        let layout = module.inner.layout;
+        let instance = unsafe {
+            let new: NewFn = mem::transmute(module.address(header::INSTANCE_NEW_SYMBOL)?);
+            let free: FreeFn = mem::transmute(module.address(header::INSTANCE_FREE_SYMBOL)?);
+            let vmctx = new();
+            if vmctx.is_null() {
+                return Err(anyhow!("Failed to allocate instance memory"));
+            }
+            let instance = Self {
+                module: module.clone(),
+                vmctx,
+                free,
+                interrupt: InterruptHandle::default(),
+                max_stack: Cell::new(DEFAULT_MAX_STACK),
+            };
+            for (import_index, address) in host_functions.into_iter().enumerate() {
+                let slot = instance.vmctx_field(layout.host_import(import_index as u32));
+                slot.cast::<usize>().write_unaligned(address);
+            }
+            if module.inner.options.interruptible {
+                let slot = instance.vmctx_field(layout.interrupt());
+                slot.cast::<usize>()
+                    .write_unaligned(instance.interrupt.flag_address());
+            }
+            instance
+        };
+
+        if module.inner.wasm_module.start_func_idx.is_some() {
+            let start: StartFn =
+                unsafe { mem::transmute(module.address(header::INSTANCE_START_SYMBOL)?) };
+            instance.enter();
+            unsafe { start(instance.vmctx) };
+            instance.take_trap()?;
+        }
+        Ok(instance)
+    }
+
+    /// Prepares a call from the host: with stack checks, Wasm code may use
+    /// up to `max_stack` bytes below the caller's frame.
+    fn enter(&self) {
+        if self.module.inner.options.check_stack {
+            let slot = self.vmctx_field(self.module.inner.layout.stack_limit());
+            let limit = stack_limit(self.max_stack.get());
+            unsafe { slot.cast::<usize>().write_unaligned(limit) };
+        }
+    }
+
+    /// Fails with the trap raised by the last call into the instance, if
+    /// any, and clears it.
+    fn take_trap(&self) -> Result<()> {
+        let slot = self
+            .vmctx_field(self.module.inner.layout.trap())
+            .cast::<u32>();
+        let code = unsafe { slot.read_unaligned() };
+        if code == 0 {
+            return Ok(());
+        }
+        unsafe { slot.write_unaligned(0) };
+        if code == Trap::Interrupted.code() {
+            self.interrupt.clear();
+        }
+        Trap::check_code(code)
+    }
+
+    pub fn module(&self) -> &Module {
+        &self.module
+    }
+
+    /// Sets the fuel left for calls into the instance, which starts out
+    /// unlimited. Fails unless the engine has `consume_fuel` enabled.
+    pub fn set_fuel(&self, fuel: u64) -> Result<()> {
+        if !self.module.inner.options.consume_fuel {
+            return Err(anyhow!("Fuel metering is not enabled"));
+        }
+        let slot = self.vmctx_field(self.module.inner.layout.fuel());
+        unsafe {
+            slot.cast::<i64>()
+                .write_unaligned(fuel.min(i64::MAX as u64) as i64)
+        };
+        Ok(())
+    }
+
+    /// The fuel left, if the engine has `consume_fuel` enabled.
+    pub fn fuel(&self) -> Option<u64> {
+        if !self.module.inner.options.consume_fuel {
+            return None;
+        }
+        let slot = self.vmctx_field(self.module.inner.layout.fuel());
+        Some(unsafe { slot.cast::<i64>().read_unaligned() }.max(0) as u64)
+    }
+
+    /// Sets the bytes of native stack calls into the instance may use,
+    /// `DEFAULT_MAX_STACK` to begin with. Fails unless the engine has
+    /// `check_stack` enabled.
+    pub fn set_max_stack(&self, bytes: usize) -> Result<()> {
+        if !self.module.inner.options.check_stack {
+            return Err(anyhow!("Stack checks are not enabled"));
+        }
+        self.max_stack.set(bytes);
+        Ok(())
+    }
+
+    /// A handle for interrupting the instance from another thread, if the
+    /// engine has `interruptible` enabled.
+    pub fn interrupt_handle(&self) -> Option<InterruptHandle> {
+        self.module
+            .inner
+            .options
+            .interruptible
+            .then(|| self.interrupt.clone())
+    }
+
+    fn vmctx_field(&self, offset: u32) -> *mut u8 {
+        unsafe { self.vmctx.add(offset as usize) }
+    }
+
+    /// The exported function `name`, unless it re-exports an import.
+    pub fn get_func(&self, name: &str) -> Option<Func<'_>> {
+        let export = self.module.export(name, ExternalKind::Func)?;
+        let function = self
+            .module
+            .inner
+            .wasm_module
+            .functions
+            .iter()
+            .find(|f| f.idx == export.index)?;
+        Some(Func {
+            instance: self,
+            address: self.module.address(&export.name).ok()?,
+            array_call: self
+                .module
+                .address(&header::array_call_symbol(&export.name))
+                .ok()?,
+            ty: function.func_type.clone(),
+        })
+    }
+
+    /// The exported function `name` as a function taking `P` and returning
+    /// `R`, if its type matches.
+    pub fn get_typed_func<P: WasmParams, R: WasmResults>(
+        &self,
+        name: &str,
+    ) -> Result<TypedFunc<'_, P, R>> {
+        self.get_func(name)
+            .ok_or(anyhow!("Unknown function export: {}", name))?
+            .typed()
+    }
+
+    pub fn get_memory(&self, name: &str) -> Option<Memory<'_>> {
+        self.module.export(name, ExternalKind::Memory)?;
+        Some(Memory { instance: self })
+    }
+
+    /// The exported global `name`, if it holds a number.
+    pub fn get_global(&self, name: &str) -> Option<Global<'_>> {
+        let export = self.module.export(name, ExternalKind::Global)?;
+        let wasm_module = &self.module.inner.wasm_module;
+        let global = wasm_module.defined_global(export.index)?;
+        if !matches!(
+            global.global_type.content_type,
+            ValType::I32 | ValType::I64 | ValType::F32 | ValType::F64
+        ) {
+            return None;
+        }
+        Some(Global {
+            instance: self,
+            offset: self
+                .module
+                .inner
+                .layout
+                .global(export.index - wasm_module.imported_global_count),
+            ty: global.global_type,
+        })
+    }
+}
+
+impl Drop for Instance {
+    fn drop(&mut self) {
+        unsafe { (self.free)(self.vmctx) };
+    }
+}
+
+/// An exported function.
+pub struct Func<'a> {
+    instance: &'a Instance,
+    address: usize,
+    array_call: usize,
+    ty: FuncType,
+}
+
+impl<'a> Func<'a> {
+    pub fn ty(&self) -> &FuncType {
+        &self.ty
+    }
+
+    /// Calls the function with `params`, which must match its parameter
+    /// types, and returns its result.
+    pub fn call(&self, params: &[Val]) -> Result<Option<Val>> {
+        let param_types: Vec<ValType> = params.iter().map(Val::ty).collect();
+        if param_types != self.ty.params() {
+            return Err(anyhow!(
+                "Function has type {}, called with {:?}",
+                self.ty,
+                param_types
+            ));
+        }
+
+        type ArrayCallFn = unsafe extern "C" fn(*mut u8, *mut u64);
+        let mut values = vec![0u64; params.len().max(1)];
+        for (slot, param) in values.iter_mut().zip(params) {
+            let slot = (slot as *mut u64).cast::<u8>();
+            unsafe { param.write_to(slot) };
+        }
+        self.instance.enter();
+        unsafe {
+            let array_call: ArrayCallFn = mem::transmute(self.array_call);
+            array_call(self.instance.vmctx, values.as_mut_ptr());
+        }
+        self.instance.take_trap()?;
+
+        let result = self.ty.results().first().map(|result| {
+            let slot = values.as_ptr().cast::<u8>();
+            unsafe { Val::read_from(*result, slot) }
+        });
+        Ok(result)
+    }
+
+    /// This function as one taking `P` and returning `R`, if its type
+    /// matches.
+    pub fn typed<P: WasmParams, R: WasmResults>(&self) -> Result<TypedFunc<'a, P, R>> {
+        let expected = FuncType::new(P::val_types(), R::val_types());
+        if expected != self.ty {
+            return Err(anyhow!(
+                "Function has type {}, expected {}",
+                self.ty,
+                expected
+            ));
+        }
+        Ok(TypedFunc {
+            instance: self.instance,
+            address: self.address,
+            _signature: PhantomData,
+        })
+    }
+}
+
+/// An exported function with a statically checked signature.
+pub struct TypedFunc<'a, P, R> {
+    instance: &'a Instance,
+    address: usize,
+    _signature: PhantomData<fn(P) -> R>,
+}
+
+impl<P: WasmParams, R: WasmResults> TypedFunc<'_, P, R> {
+    pub fn call(&self, params: P) -> Result<R> {
+        self.instance.enter();
+        let result = unsafe { params.call(self.address, self.instance.vmctx) };
+        self.instance.take_trap()?;
+        Ok(result)
+    }
+}
+
+/// The exported linear memory of an instance.
+pub struct Memory<'a> {
+    instance: &'a Instance,
+}
+
+impl Memory<'_> {
+    /// Base address of the memory, which moves when the memory grows.
+    pub fn data_ptr(&self) -> *mut u8 {
+        unsafe {
+            self.instance
+                .vmctx_field(VmctxLayout::MEMORY_BASE)
+                .cast::<*mut u8>()
+                .read_unaligned()
+        }
+    }
+
+    /// Current size of the memory in bytes.
+    pub fn data_size(&self) -> usize {
+        unsafe {
+            self.instance
+                .vmctx_field(VmctxLayout::MEMORY_SIZE)
+                .cast::<u64>()
+                .read_unaligned() as usize
+        }
+    }
+
+    fn check_range(&self, offset: usize, len: usize) -> Result<()> {
+        match offset.checked_add(len) {
+            Some(end) if end <= self.data_size() => Ok(()),
+            _ => Err(anyhow!(
+                "Memory access out of bounds: {} bytes at offset {}",
+                len,
+                offset
+            )),
+        }
+    }
+
+    pub fn read(&self, offset: usize, buffer: &mut [u8]) -> Result<()> {
+        self.check_range(offset, buffer.len())?;
+        unsafe {
+            self.data_ptr()
+                .add(offset)
+                .copy_to_nonoverlapping(buffer.as_mut_ptr(), buffer.len());
+        }
+        Ok(())
+    }
+
+    pub fn write(&self, offset: usize, data: &[u8]) -> Result<()> {
+        self.check_range(offset, data.len())?;
+        unsafe {
+            self.data_ptr()
+                .add(offset)
+                .copy_from_nonoverlapping(data.as_ptr(), data.len());
+        }
+        Ok(())
+    }
+}
+
+/// A Wasm number.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Val {
+    I32(i32),
+    I64(i64),
+    F32(f32),
+    F64(f64),
+}
+
+impl Val {
+    pub fn ty(&self) -> ValType {
+        match self {
+            Val::I32(_) => ValType::I32,
+            Val::I64(_) => ValType::I64,
+            Val::F32(_) => ValType::F32,
+            Val::F64(_) => ValType::F64,
+        }
+    }
+
+    /// Reads a value of numeric type `ty` from `slot`.
+    ///
+    /// # Safety
+    ///
+    /// `slot` must be valid for reading the value.
+    unsafe fn read_from(ty: ValType, slot: *const u8) -> Val {
+        unsafe {
+            match ty {
+                ValType::I32 => Val::I32(slot.cast::<i32>().read_unaligned()),
+                ValType::I64 => Val::I64(slot.cast::<i64>().read_unaligned()),
+                ValType::F32 => Val::F32(slot.cast::<f32>().read_unaligned()),
+                ValType::F64 => Val::F64(slot.cast::<f64>().read_unaligned()),
+                _ => unreachable!("only numeric values are read"),
+            }
+        }
+    }
+
+    /// Writes the value to `slot`.
+    ///
+    /// # Safety
+    ///
+    /// `slot` must be valid for writing the value.
+    unsafe fn write_to(self, slot: *mut u8) {
+        unsafe {
+            match self {
+                Val::I32(value) => slot.cast::<i32>().write_unaligned(value),
+                Val::I64(value) => slot.cast::<i64>().write_unaligned(value),
+                Val::F32(value) => slot.cast::<f32>().write_unaligned(value),
+                Val::F64(value) => slot.cast::<f64>().write_unaligned(value),
+            }
+        }
+    }
+}
+
+/// An exported global holding a number.
+pub struct Global<'a> {
+    instance: &'a Instance,
+    offset: u32,
+    ty: GlobalType,
+}
+
+impl Global<'_> {
+    pub fn ty(&self) -> GlobalType {
+        self.ty
+    }
+
+    pub fn get(&self) -> Val {
+        let slot = self.instance.vmctx_field(self.offset);
+        unsafe { Val::read_from(self.ty.content_type, slot) }
+    }
+
+    pub fn set(&self, value: Val) -> Result<()> {
+        if !self.ty.mutable {
+            return Err(anyhow!("Global is immutable"));
+        }
+        if value.ty() != self.ty.content_type {
+            return Err(anyhow!(
+                "Global has type {}, got a value of type {}",
+                self.ty.content_type,
+                value.ty()
+            ));
+        }
+        unsafe { value.write_to(self.instance.vmctx_field(self.offset)) };
+        Ok(())
+    }
+}
+
+mod sealed {
+    pub trait Sealed {}
+}
+
+/// A Rust type passed to and from Wasm as a Wasm value.
+pub trait WasmTy: Copy + sealed::Sealed {
+    const VAL_TYPE: ValType;
+}
+
+macro_rules! impl_wasm_ty {
+    ($($ty:ty => $val_type:ident),*) => {
+        $(
+            impl sealed::Sealed for $ty {}
+
+            impl WasmTy for $ty {
+                const VAL_TYPE: ValType = ValType::$val_type;
+            }
+        )*
+    };
+}
+
+impl_wasm_ty!(i32 => I32, i64 => I64, f32 => F32, f64 => F64);
+
+/// Parameters of a typed function: a `WasmTy` or a tuple of them.
+pub trait WasmParams {
+    fn val_types() -> Vec<ValType>;
+
+    /// Calls the function at `address` with `vmctx` and these parameters.
+    ///
+    /// # Safety
+    ///
+    /// `address` must be a function of this signature taking the vmctx
+    /// first.
+    #[doc(hidden)]
+    unsafe fn call<R: WasmResults>(self, address: usize, vmctx: *mut u8) -> R;
+}
+
+/// Results of a typed function: `()` or a `WasmTy`.
+pub trait WasmResults: sealed::Sealed {
+    fn val_types() -> Vec<ValType>;
+}
+
+impl sealed::Sealed for () {}
+
+impl WasmResults for () {
+    fn val_types() -> Vec<ValType> {
+        Vec::new()
+    }
+}
+
+impl<T: WasmTy> WasmResults for T {
+    fn val_types() -> Vec<ValType> {
+        vec![T::VAL_TYPE]
+    }
+}
+
+impl<T: WasmTy> WasmParams for T {
+    fn val_types() -> Vec<ValType> {
+        vec![T::VAL_TYPE]
+    }
+
+    unsafe fn call<R: WasmResults>(self, address: usize, vmctx: *mut u8) -> R {
+        unsafe { (self,).call(address, vmctx) }
+    }
+}
+
+/// An `extern "C"` function pointer usable as a host function.
+pub trait HostFunc {
+    fn func_type() -> FuncType;
+
+    fn address(self) -> usize;
+}
+
+macro_rules! impl_signatures {
+    ($($param:ident),*) => {
+        impl<$($param: WasmTy),*> WasmParams for ($($param,)*) {
+            fn val_types() -> Vec<ValType> {
+                vec![$($param::VAL_TYPE),*]
+            }
+
+            #[allow(non_snake_case)]
+            unsafe fn call<R: WasmResults>(self, address: usize, vmctx: *mut u8) -> R {
+                let ($($param,)*) = self;
+                unsafe {
+                    let func: unsafe extern "C" fn(*mut u8, $($param),*) -> R =
+                        mem::transmute(address);
+                    func(vmctx, $($param),*)
+                }
+            }
+        }
+
+        impl<$($param: WasmTy,)* R: WasmResults> HostFunc for extern "C" fn($($param),*) -> R {
+            fn func_type() -> FuncType {
+                FuncType::new([$($param::VAL_TYPE),*], R::val_types())
+            }
+
+            fn address(self) -> usize {
+                self as usize
+            }
+        }
+    };
+}
+
+impl_signatures!();
+impl_signatures!(A);
+impl_signatures!(A, B);
+impl_signatures!(A, B, C);
+impl_signatures!(A, B, C, D);
+impl_signatures!(A, B, C, D, E);
+impl_signatures!(A, B, C, D, E, F);
+impl_signatures!(A, B, C, D, E, F, G);
+impl_signatures!(A, B, C, D, E, F, G, H);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    extern "C" fn scale(x: f64, factor: i32) -> f64 {
+        x * factor as f64
+    }
+
+    extern "C" fn log(_: i64) {}
+
+    #[test]
+    fn test_signature_types() {
+        assert_eq!(<(i32, f64)>::val_types(), [ValType::I32, ValType::F64]);
+        assert_eq!(<i64 as WasmParams>::val_types(), [ValType::I64]);
+        assert!(<() as WasmParams>::val_types().is_empty());
+        assert!(<() as WasmResults>::val_types().is_empty());
+        assert_eq!(<f32 as WasmResults>::val_types(), [ValType::F32]);
+    }
+
+    #[test]
+    fn test_host_function_types() {
+        assert_eq!(
+            <extern "C" fn(f64, i32) -> f64>::func_type(),
+            FuncType::new([ValType::F64, ValType::I32], [ValType::F64])
+        );
+        assert_eq!(
+            <extern "C" fn(i64)>::func_type(),
+            FuncType::new([ValType::I64], [])
+        );
+
+        let mut imports = Imports::new();
+        imports
+            .func("env", "scale", scale as extern "C" fn(f64, i32) -> f64)
+            .func("env", "log", log as extern "C" fn(i64));
+        let (func_type, address) = &imports.funcs[&("env".to_string(), "scale".to_string())];
+        assert_eq!(func_type.params(), [ValType::F64, ValType::I32]);
+        assert_eq!(*address, scale as *const () as usize);
+    }
+}
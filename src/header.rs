@@ -0,0 +1,409 @@
+use std::collections::HashSet;
+use std::fmt::Write;
+
+use anyhow::{Result, anyhow};
+use wasmparser::{ExternalKind, FuncType, ValType};
+
+use crate::wasm_parser::{Export, WasmModule};
+
+pub(crate) const INSTANCE_NEW_SYMBOL: &str = "wasm_instance_new";
+pub(crate) const INSTANCE_FREE_SYMBOL: &str = "wasm_instance_free";
+/// Runs the start function; only emitted for the embedding API.
+pub(crate) const INSTANCE_START_SYMBOL: &str = "wasm_instance_start";
+
+/// Calls export `export_name` with its parameters and result in an array of
+/// 8-byte slots; only emitted for the embedding API.
+pub(crate) fn array_call_symbol(export_name: &str) -> String {
+    format!("{export_name}.array_call")
+}
+
+pub(crate) fn memory_base_symbol(export_name: &str) -> String {
+    format!("{export_name}_base")
+}
+
+pub(crate) fn memory_size_symbol(export_name: &str) -> String {
+    format!("{export_name}_size")
+}
+
+pub(crate) fn global_getter_symbol(export_name: &str) -> String {
+    format!("{export_name}_get")
+}
+
+pub(crate) fn global_setter_symbol(export_name: &str) -> String {
+    format!("{export_name}_set")
+}
+
+/// Whether `name` is a symbol the compiler defines or calls besides the
+/// export symbols, which an export must not shadow.
+fn is_reserved_symbol(name: &str) -> bool {
+    matches!(
+        name,
+        "main" | "calloc" | "realloc" | "free" | "assert_eq32" | "assert_eq64"
+    ) || name.starts_with("wasm_")
+        || name.starts_with('$')
+        || name.starts_with("llvm.")
+}
+
+/// The symbols defined for `export`: the export itself for functions and
+/// its accessors for memories and globals with C-compatible names.
+fn export_symbols(wasm_module: &WasmModule, export: &Export) -> Vec<String> {
+    match export.kind {
+        ExternalKind::Func => vec![export.name.clone()],
+        _ if !is_c_identifier(&export.name) => vec![],
+        ExternalKind::Memory => vec![
+            memory_base_symbol(&export.name),
+            memory_size_symbol(&export.name),
+        ],
+        ExternalKind::Global => {
+            let Some(global) = wasm_module.defined_global(export.index) else {
+                return vec![];
+            };
+            let mut symbols = vec![global_getter_symbol(&export.name)];
+            if global.global_type.mutable {
+                symbols.push(global_setter_symbol(&export.name));
+            }
+            symbols
+        }
+        _ => vec![],
+    }
+}
+
+/// Fails if an export would define a symbol twice or shadow a runtime
+/// symbol, which would leave C callers linking against the wrong function.
+pub(crate) fn check_export_symbols(wasm_module: &WasmModule) -> Result<()> {
+    let mut defined = HashSet::new();
+    for export in &wasm_module.exports {
+        for symbol in export_symbols(wasm_module, export) {
+            if is_reserved_symbol(&symbol) {
+                return Err(anyhow!(
+                    "Export \"{}\" collides with runtime symbol {}",
+                    export.name.escape_default(),
+                    symbol
+                ));
+            }
+            if !defined.insert(symbol.clone()) {
+                return Err(anyhow!(
+                    "Export \"{}\" defines symbol {} twice",
+                    export.name.escape_default(),
+                    symbol
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn is_c_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c == '_' || c.is_ascii_alphabetic())
+        && chars.all(|c| c == '_' || c.is_ascii_alphanumeric())
+}
+
+const INSTANCE_PARAM: &str = "wasm_instance_t *instance";
+
+fn c_type(val_type: ValType) -> Result<&'static str> {
+    match val_type {
+        ValType::I32 => Ok("int32_t"),
+        ValType::I64 => Ok("int64_t"),
+        ValType::F32 => Ok("float"),
+        ValType::F64 => Ok("double"),
+        _ => Err(anyhow!(
+            "Unsupported value type in C header: {:?}",
+            val_type
+        )),
+    }
+}
+
+fn function_prototype(name: &str, func_type: &FuncType) -> Result<String> {
+    let return_type = match func_type.results() {
+        [] => "void",
+        [result] => c_type(*result)?,
+        _ => return Err(anyhow!("Multiple return values not supported: {}", name)),
+    };
+
+    let params = func_type
+        .params()
+        .iter()
+        .enumerate()
+        .map(|(i, param)| Ok(format!(", {} p{i}", c_type(*param)?)))
+        .collect::<Result<String>>()?;
+
+    Ok(format!("{return_type} {name}({INSTANCE_PARAM}{params});"))
+}
+
+fn include_guard(header_name: &str) -> String {
+    let guard: String = header_name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if guard.starts_with(|c: char| c.is_ascii_digit()) {
+        format!("_{guard}")
+    } else {
+        guard
+    }
+}
+
+/// Renders a C header declaring the exports of an AOT-compiled module.
+///
+/// Every export takes the instance returned by `wasm_instance_new` as its
+/// first argument.
+///
+/// `header_name` is the file name of the header and only determines the
+/// include guard.
+pub fn generate_header(wasm_module: &WasmModule, header_name: &str) -> Result<String> {
+    check_export_symbols(wasm_module)?;
+    let guard = include_guard(header_name);
+    let mut functions = String::new();
+    let mut memories = String::new();
+    let mut globals = String::new();
+
+    for export in &wasm_module.exports {
+        if !is_c_identifier(&export.name) {
+            writeln!(
+                functions,
+                "/* export \"{}\" has no C-compatible name */",
+                export.name.escape_default()
+            )?;
+            continue;
+        }
+
+        match export.kind {
+            ExternalKind::Func => {
+                let Some(function) = wasm_module.functions.iter().find(|f| f.idx == export.index)
+                else {
+                    writeln!(
+                        functions,
+                        "/* export \"{}\" re-exports an import */",
+                        export.name
+                    )?;
+                    continue;
+                };
+                writeln!(
+                    functions,
+                    "{}",
+                    function_prototype(&export.name, &function.func_type)?
+                )?;
+            }
+            ExternalKind::Memory => {
+                writeln!(
+                    memories,
+                    "uint8_t *{}({INSTANCE_PARAM});",
+                    memory_base_symbol(&export.name)
+                )?;
+                writeln!(
+                    memories,
+                    "uint64_t {}({INSTANCE_PARAM});",
+                    memory_size_symbol(&export.name)
+                )?;
+            }
+            ExternalKind::Global => {
+                if export.index < wasm_module.imported_global_count {
+                    writeln!(
+                        globals,
+                        "/* export \"{}\" re-exports an import */",
+                        export.name
+                    )?;
+                    continue;
+                }
+                let global = wasm_module
+                    .defined_global(export.index)
+                    .ok_or(anyhow!("Invalid global index: {}", export.index))?;
+                let ty = c_type(global.global_type.content_type)?;
+                writeln!(
+                    globals,
+                    "{ty} {}({INSTANCE_PARAM});",
+                    global_getter_symbol(&export.name)
+                )?;
+                if global.global_type.mutable {
+                    writeln!(
+                        globals,
+                        "void {}({INSTANCE_PARAM}, {ty} value);",
+                        global_setter_symbol(&export.name)
+                    )?;
+                }
+            }
+            ExternalKind::Table => {
+                writeln!(
+                    functions,
+                    "/* export \"{}\" is a table, which has no C accessors */",
+                    export.name
+                )?;
+            }
+            _ => {}
+        }
+    }
+
+    let mut header = String::new();
+    writeln!(
+        header,
+        "/* Generated by auto-parallel-wasm. Do not edit. */"
+    )?;
+    writeln!(header, "#ifndef {guard}")?;
+    writeln!(header, "#define {guard}")?;
+    writeln!(header)?;
+    writeln!(header, "#include <stdint.h>")?;
+    writeln!(header)?;
+    writeln!(header, "#ifdef __cplusplus")?;
+    writeln!(header, "extern \"C\" {{")?;
+    writeln!(header, "#endif")?;
+    writeln!(header)?;
+    writeln!(header, "typedef struct wasm_instance wasm_instance_t;")?;
+    writeln!(header)?;
+    writeln!(header, "wasm_instance_t *{INSTANCE_NEW_SYMBOL}(void);")?;
+    writeln!(header, "void {INSTANCE_FREE_SYMBOL}({INSTANCE_PARAM});")?;
+    for section in [functions, memories, globals] {
+        if !section.is_empty() {
+            writeln!(header)?;
+            header.push_str(&section);
+        }
+    }
+    writeln!(header)?;
+    writeln!(header, "#ifdef __cplusplus")?;
+    writeln!(header, "}}")?;
+    writeln!(header, "#endif")?;
+    writeln!(header)?;
+    writeln!(header, "#endif /* {guard} */")?;
+
+    Ok(header)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wasm_parser::{Function, FunctionBody, WasmGlobal};
+    use wasmparser::{GlobalType, MemoryType};
+
+    fn module_with_exports(exports: Vec<Export>) -> WasmModule {
+        WasmModule {
+            functions: vec![Function {
+                idx: 0,
+                name: None,
+                func_type: FuncType::new([ValType::I32, ValType::F64], [ValType::I64]),
+                body: FunctionBody {
+                    locals: vec![],
+                    operators: vec![],
+                    offsets: vec![],
+                },
+            }],
+            memories: vec![MemoryType {
+                memory64: false,
+                shared: false,
+                initial: 1,
+                maximum: None,
+                page_size_log2: None,
+            }],
+            globals: vec![WasmGlobal {
+                global_type: GlobalType {
+                    content_type: ValType::I32,
+                    mutable: true,
+                    shared: false,
+                },
+            }],
+            exports,
+            ..Default::default()
+        }
+    }
+
+    fn export(name: &str, kind: ExternalKind) -> Export {
+        Export {
+            name: name.to_string(),
+            kind,
+            index: 0,
+        }
+    }
+
+    #[test]
+    fn test_function_prototypes() {
+        let module = module_with_exports(vec![export("compute", ExternalKind::Func)]);
+        let header = generate_header(&module, "kernel.h").unwrap();
+
+        assert!(header.contains("#ifndef KERNEL_H"));
+        assert!(
+            header.contains("int64_t compute(wasm_instance_t *instance, int32_t p0, double p1);")
+        );
+    }
+
+    #[test]
+    fn test_memory_and_global_accessors() {
+        let module = module_with_exports(vec![
+            export("memory", ExternalKind::Memory),
+            export("counter", ExternalKind::Global),
+        ]);
+        let header = generate_header(&module, "kernel.h").unwrap();
+
+        assert!(header.contains("uint8_t *memory_base(wasm_instance_t *instance);"));
+        assert!(header.contains("uint64_t memory_size(wasm_instance_t *instance);"));
+        assert!(header.contains("int32_t counter_get(wasm_instance_t *instance);"));
+        assert!(header.contains("void counter_set(wasm_instance_t *instance, int32_t value);"));
+    }
+
+    #[test]
+    fn test_instance_lifecycle_declarations() {
+        let module = module_with_exports(vec![]);
+        let header = generate_header(&module, "kernel.h").unwrap();
+
+        assert!(header.contains("typedef struct wasm_instance wasm_instance_t;"));
+        assert!(header.contains("wasm_instance_t *wasm_instance_new(void);"));
+        assert!(header.contains("void wasm_instance_free(wasm_instance_t *instance);"));
+    }
+
+    #[test]
+    fn test_export_symbol_collisions() {
+        for name in ["main", "free", "wasm_instance_new", "$add"] {
+            let module = module_with_exports(vec![export(name, ExternalKind::Func)]);
+            let error = generate_header(&module, "kernel.h").unwrap_err();
+            assert!(error.to_string().contains("collides"), "{error}");
+        }
+
+        let module = module_with_exports(vec![
+            export("memory", ExternalKind::Memory),
+            export("memory_size", ExternalKind::Func),
+        ]);
+        let error = generate_header(&module, "kernel.h").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Export \"memory_size\" defines symbol memory_size twice"
+        );
+    }
+
+    #[test]
+    fn test_non_c_export_names_are_skipped() {
+        let module = module_with_exports(vec![export("do-work", ExternalKind::Func)]);
+        let header = generate_header(&module, "kernel.h").unwrap();
+
+        assert!(header.contains("/* export \"do-work\" has no C-compatible name */"));
+        assert!(!header.contains("do-work("));
+    }
+
+    #[test]
+    fn test_imported_globals_offset_export_indices() {
+        let mut module = module_with_exports(vec![
+            export("host_counter", ExternalKind::Global),
+            Export {
+                index: 1,
+                ..export("counter", ExternalKind::Global)
+            },
+        ]);
+        module.imported_global_count = 1;
+        let header = generate_header(&module, "kernel.h").unwrap();
+
+        assert!(header.contains("/* export \"host_counter\" re-exports an import */"));
+        assert!(!header.contains("host_counter_get"));
+        assert!(header.contains("int32_t counter_get(wasm_instance_t *instance);"));
+        assert!(header.contains("void counter_set(wasm_instance_t *instance, int32_t value);"));
+    }
+
+    #[test]
+    fn test_table_exports_are_noted() {
+        let module = module_with_exports(vec![export("table", ExternalKind::Table)]);
+        let header = generate_header(&module, "kernel.h").unwrap();
+
+        assert!(header.contains("/* export \"table\" is a table, which has no C accessors */"));
+    }
+}
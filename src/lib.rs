@@ -1,5 +1,24 @@
+pub mod cache;
+mod capi;
 pub mod compiler;
+mod debug_info;
+mod dwarf;
+pub mod embed;
+pub mod header;
+pub mod limits;
+mod linker;
+pub mod loop_ir;
+pub mod options;
+pub mod profile;
+mod runtime;
+pub mod trap;
+mod vmctx;
 pub mod wasm_parser;
 
 pub use compiler::Compiler;
+pub use embed::{Engine, Func, Global, Imports, Instance, Memory, Module, TypedFunc, Val};
+pub use limits::{ExecutionLimits, InterruptHandle};
+pub use options::{CompilerOptions, EmitKind, OptLevel, RelocModel};
+pub use profile::ProfileEntry;
+pub use trap::Trap;
 pub use wasm_parser::WasmModule;
@@ -0,0 +1,161 @@
+//! Execution limits for untrusted modules.
+//!
+//! Modules compiled with `CompilerOptions::consume_fuel` count down a fuel
+//! budget at every function entry and loop iteration and trap with
+//! `Trap::OutOfFuel` once it is spent. Modules compiled with
+//! `CompilerOptions::interruptible` poll an interrupt flag at the same points
+//! and trap with `Trap::Interrupted` once another thread has raised it
+//! through an [`InterruptHandle`]. Modules compiled with
+//! `CompilerOptions::check_stack` compare the stack pointer with a limit in
+//! every function prologue and trap with `Trap::StackOverflow` rather than
+//! overflowing the native stack.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{Result, anyhow};
+
+/// Limits applied by `Compiler::run_main_with_limits`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ExecutionLimits {
+    /// Function entries and loop iterations allowed before trapping;
+    /// unlimited when unset.
+    pub fuel: Option<u64>,
+    /// Wall-clock time after which a watchdog thread interrupts the module.
+    pub timeout: Option<Duration>,
+    /// Bytes of native stack the module may use; `DEFAULT_MAX_STACK` when
+    /// unset.
+    pub max_stack: Option<usize>,
+}
+
+/// Native stack budget of Wasm code when no other is configured. It leaves
+/// room for the host on threads with the 2 MiB stacks Rust gives spawned
+/// threads by default.
+pub const DEFAULT_MAX_STACK: usize = 1 << 20;
+
+/// The lowest stack address that code called from here may use without
+/// going over `max_stack` bytes. Stacks grow downwards on every supported
+/// target.
+pub(crate) fn stack_limit(max_stack: usize) -> usize {
+    let marker = 0u8;
+    (std::hint::black_box(&marker) as *const u8 as usize).saturating_sub(max_stack)
+}
+
+/// Interrupts an instance from any thread. The handle stays valid after the
+/// instance is gone, when interrupting it does nothing.
+#[derive(Debug, Clone, Default)]
+pub struct InterruptHandle {
+    flag: Arc<AtomicU32>,
+}
+
+impl InterruptHandle {
+    /// Makes the instance trap at its next function entry or loop
+    /// iteration, or at the start of its next call if it is not running.
+    pub fn interrupt(&self) {
+        self.flag.store(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn clear(&self) {
+        self.flag.store(0, Ordering::Relaxed);
+    }
+
+    /// Address of the flag polled by generated code, stored in the vmctx.
+    pub(crate) fn flag_address(&self) -> usize {
+        Arc::as_ptr(&self.flag) as usize
+    }
+
+    /// Runs `f`, interrupting it through this handle if it has not returned
+    /// after `timeout`.
+    pub(crate) fn run_with_timeout<T>(
+        &self,
+        timeout: Option<Duration>,
+        f: impl FnOnce() -> T,
+    ) -> T {
+        let Some(timeout) = timeout else {
+            return f();
+        };
+        let (done, watchdog) = mpsc::channel::<()>();
+        thread::scope(|scope| {
+            scope.spawn(move || {
+                if let Err(RecvTimeoutError::Timeout) = watchdog.recv_timeout(timeout) {
+                    self.interrupt();
+                }
+            });
+            let result = f();
+            drop(done);
+            result
+        })
+    }
+}
+
+/// Parses a duration such as `5s`, `250ms`, `1.5m` or `10` (seconds), as
+/// taken by `exec --timeout`.
+pub fn parse_duration(s: &str) -> Result<Duration> {
+    let (number, seconds_per_unit) = if let Some(number) = s.strip_suffix("ms") {
+        (number, 0.001)
+    } else if let Some(number) = s.strip_suffix('s') {
+        (number, 1.0)
+    } else if let Some(number) = s.strip_suffix('m') {
+        (number, 60.0)
+    } else {
+        (s, 1.0)
+    };
+    number
+        .parse::<f64>()
+        .ok()
+        .and_then(|n| Duration::try_from_secs_f64(n * seconds_per_unit).ok())
+        .filter(|duration| !duration.is_zero())
+        .ok_or(anyhow!("Invalid duration: {}", s))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("5s").unwrap(), Duration::from_secs(5));
+        assert_eq!(parse_duration("250ms").unwrap(), Duration::from_millis(250));
+        assert_eq!(parse_duration("1.5m").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_duration("10").unwrap(), Duration::from_secs(10));
+        assert!(parse_duration("0s").is_err());
+        assert!(parse_duration("-1s").is_err());
+        assert!(parse_duration("s").is_err());
+    }
+
+    #[test]
+    fn test_stack_limit_is_below_the_current_frame() {
+        let marker = 0u8;
+        let here = &marker as *const u8 as usize;
+        let limit = stack_limit(DEFAULT_MAX_STACK);
+        assert!(limit < here);
+        assert!(here - limit <= DEFAULT_MAX_STACK + 4096);
+        assert_eq!(stack_limit(usize::MAX), 0);
+    }
+
+    #[test]
+    fn test_watchdog_interrupts_after_timeout() {
+        let handle = InterruptHandle::default();
+        let flag = handle.flag.clone();
+        let start = Instant::now();
+        let polls = handle.run_with_timeout(Some(Duration::from_millis(20)), || {
+            let mut polls = 0u64;
+            while flag.load(Ordering::Relaxed) == 0 {
+                polls += 1;
+                thread::yield_now();
+            }
+            polls
+        });
+        assert!(polls > 0);
+        assert!(start.elapsed() >= Duration::from_millis(20));
+
+        handle.clear();
+        let value = handle.run_with_timeout(Some(Duration::from_secs(60)), || 7);
+        assert_eq!(value, 7);
+        assert_eq!(flag.load(Ordering::Relaxed), 0);
+    }
+}
@@ -0,0 +1,76 @@
+use std::env;
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use anyhow::{Result, anyhow};
+
+const AOT_RUNTIME_SOURCE: &str = include_str!("aot_runtime.c");
+
+/// Returns a fresh path in the system temp directory for intermediate files.
+pub fn temp_path(extension: &str) -> PathBuf {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+    env::temp_dir().join(format!(
+        "auto-parallel-wasm-{}-{n}.{extension}",
+        process::id()
+    ))
+}
+
+pub fn link_shared_library(object: &Path, output: &Path) -> Result<()> {
+    with_runtime_source(|runtime| {
+        run_cc(&[
+            OsStr::new("-shared"),
+            OsStr::new("-fPIC"),
+            OsStr::new("-o"),
+            output.as_os_str(),
+            object.as_os_str(),
+            runtime.as_os_str(),
+        ])
+    })
+}
+
+pub fn link_executable(object: &Path, output: &Path, pie: bool) -> Result<()> {
+    with_runtime_source(|runtime| {
+        let mut args = vec![
+            OsStr::new("-o"),
+            output.as_os_str(),
+            object.as_os_str(),
+            runtime.as_os_str(),
+        ];
+        if !pie {
+            args.push(OsStr::new("-no-pie"));
+        }
+        run_cc(&args)
+    })
+}
+
+/// Writes the AOT runtime to a temporary C file for the duration of `link`.
+fn with_runtime_source(link: impl FnOnce(&Path) -> Result<()>) -> Result<()> {
+    let runtime = temp_path("c");
+    fs::write(&runtime, AOT_RUNTIME_SOURCE)?;
+    let result = link(&runtime);
+    fs::remove_file(&runtime).ok();
+    result
+}
+
+/// Whether `CC` names the compiler to link with, rather than the host `cc`.
+pub fn cc_overridden() -> bool {
+    env::var_os("CC").is_some()
+}
+
+fn run_cc(args: &[&OsStr]) -> Result<()> {
+    let cc = env::var("CC").unwrap_or_else(|_| "cc".to_string());
+    let status = Command::new(&cc)
+        .args(args)
+        .status()
+        .map_err(|e| anyhow!("Failed to run linker {}: {}", cc, e))?;
+
+    if status.success() {
+        Ok(())
+    } else {
+        Err(anyhow!("Linker {} failed with {}", cc, status))
+    }
+}
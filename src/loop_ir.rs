@@ -0,0 +1,1644 @@
+//! Structured form of function bodies for loop-level analysis, built from a
+//! `WasmModule`. `Compiler` still lowers the Wasm operators directly; the
+//! loop IR only feeds the analyses, printed by the `loop-ir` command.
+//!
+//! Stack code is rebuilt into statements over expression trees, keeping
+//! blocks, loops and ifs as nested nodes. A value that would otherwise be
+//! read after a side effect is spilled into a temporary first, so running the
+//! statements in order is equivalent to running the original operators.
+//! Loops carry the induction variables and trip counts recovered from their
+//! exit tests.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use anyhow::{Result, anyhow};
+use wasmparser::{
+    BlockType, ContType, FrameKind, FuncType, ModuleArity, Operator, RefType, SubType, ValType,
+};
+
+use crate::wasm_parser::{Function, WasmModule};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Label(pub u32);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Temp(pub u32);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Constant {
+    I32(i32),
+    I64(i64),
+    F32(f32),
+    F64(f64),
+}
+
+/// A load or store of linear memory.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MemoryAccess {
+    /// Type of the value on the operand stack.
+    pub ty: ValType,
+    /// Bytes read or written, which is less than the size of `ty` for the
+    /// narrow forms.
+    pub bytes: u8,
+    /// Whether a narrow load sign-extends.
+    pub signed: bool,
+    pub atomic: bool,
+    pub address: Box<Expr>,
+    pub offset: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Callee {
+    Direct(u32),
+    Indirect {
+        type_index: u32,
+        table_index: u32,
+        index: Box<Expr>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Const(Constant),
+    Local(u32),
+    Global(u32),
+    Temp(Temp),
+    Load(MemoryAccess),
+    /// Any other operator applied to its operands in stack order.
+    Op {
+        op: Operator<'static>,
+        args: Vec<Expr>,
+    },
+    Call {
+        callee: Callee,
+        args: Vec<Expr>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Stmt {
+    LocalSet {
+        local: u32,
+        value: Expr,
+    },
+    GlobalSet {
+        global: u32,
+        value: Expr,
+    },
+    Let {
+        temp: Temp,
+        value: Expr,
+    },
+    Store {
+        access: MemoryAccess,
+        value: Expr,
+    },
+    /// Evaluates an expression only for its side effects.
+    Eval(Expr),
+    Block {
+        label: Label,
+        body: Vec<Stmt>,
+    },
+    Loop(Loop),
+    If {
+        label: Label,
+        cond: Expr,
+        then_body: Vec<Stmt>,
+        else_body: Vec<Stmt>,
+    },
+    Br {
+        target: Label,
+    },
+    BrIf {
+        target: Label,
+        cond: Expr,
+    },
+    Return(Option<Expr>),
+    Unreachable,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Loop {
+    pub label: Label,
+    pub body: Vec<Stmt>,
+    pub induction_variables: Vec<InductionVariable>,
+    pub trip_count: Option<TripCount>,
+}
+
+/// A local updated by a constant step exactly once per iteration.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InductionVariable {
+    pub local: u32,
+    /// Value on loop entry, when it is known.
+    pub start: Option<Expr>,
+    pub step: i64,
+}
+
+/// Comparison under which a counted loop keeps iterating, with the induction
+/// variable on the left.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Predicate {
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Ne,
+}
+
+/// Number of times a counted loop body runs: the induction variable goes
+/// from `start` by `step` while `predicate(iv, bound)` holds.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TripCount {
+    pub local: u32,
+    pub start: Expr,
+    pub bound: Expr,
+    pub step: i64,
+    pub predicate: Predicate,
+    pub signed: bool,
+    /// The exit test follows the body, so the body runs at least once.
+    pub bottom_tested: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct FunctionIr {
+    pub index: u32,
+    pub name: Option<String>,
+    pub func_type: FuncType,
+    pub locals: Vec<ValType>,
+    pub body: Vec<Stmt>,
+}
+
+/// Lowers every defined function of `wasm_module`.
+pub fn lower_module(wasm_module: &WasmModule) -> Result<Vec<FunctionIr>> {
+    wasm_module
+        .functions
+        .iter()
+        .map(|function| lower_function(wasm_module, function))
+        .collect()
+}
+
+pub fn lower_function(wasm_module: &WasmModule, function: &Function) -> Result<FunctionIr> {
+    let mut lowering = Lowering {
+        module: wasm_module,
+        function,
+        stack: Vec::new(),
+        frames: vec![Frame::new(FrameType::Function, Label(0), None, 0)],
+        next_label: 1,
+        next_temp: 0,
+    };
+    let mut body = lowering.lower()?;
+    analyze_stmts(&mut body, &mut HashMap::new(), None);
+
+    Ok(FunctionIr {
+        index: function.idx,
+        name: function.name.clone(),
+        func_type: function.func_type.clone(),
+        locals: function.body.locals.clone(),
+        body,
+    })
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum FrameType {
+    Function,
+    Block,
+    Loop,
+    If,
+}
+
+struct Frame {
+    frame_type: FrameType,
+    label: Label,
+    /// Receives the value a block produces.
+    result: Option<Temp>,
+    stack_height: usize,
+    stmts: Vec<Stmt>,
+    cond: Option<Expr>,
+    then_body: Option<Vec<Stmt>>,
+    unreachable: bool,
+    /// Blocks opened in unreachable code, which are skipped.
+    dead_depth: u32,
+}
+
+impl Frame {
+    fn new(frame_type: FrameType, label: Label, result: Option<Temp>, stack_height: usize) -> Self {
+        Self {
+            frame_type,
+            label,
+            result,
+            stack_height,
+            stmts: Vec::new(),
+            cond: None,
+            then_body: None,
+            unreachable: false,
+            dead_depth: 0,
+        }
+    }
+}
+
+/// Operator arities that do not depend on the module, as needed by
+/// `Operator::operator_arity`. Control flow and calls are handled directly.
+struct FixedArity;
+
+impl ModuleArity for FixedArity {
+    fn sub_type_at(&self, _type_idx: u32) -> Option<&SubType> {
+        None
+    }
+
+    fn tag_type_arity(&self, _at: u32) -> Option<(u32, u32)> {
+        None
+    }
+
+    fn type_index_of_function(&self, _function_idx: u32) -> Option<u32> {
+        None
+    }
+
+    fn func_type_of_cont_type(&self, _c: &ContType) -> Option<&FuncType> {
+        None
+    }
+
+    fn sub_type_of_ref_type(&self, _rt: &RefType) -> Option<&SubType> {
+        None
+    }
+
+    fn control_stack_height(&self) -> u32 {
+        0
+    }
+
+    fn label_block(&self, _depth: u32) -> Option<(BlockType, FrameKind)> {
+        None
+    }
+}
+
+struct Lowering<'a> {
+    module: &'a WasmModule,
+    function: &'a Function,
+    stack: Vec<Expr>,
+    frames: Vec<Frame>,
+    next_label: u32,
+    next_temp: u32,
+}
+
+impl Lowering<'_> {
+    fn lower(&mut self) -> Result<Vec<Stmt>> {
+        let function = self.function;
+        for operator in &function.body.operators {
+            let frame = self.frames.last_mut().unwrap();
+            if frame.unreachable {
+                match operator {
+                    Operator::Block { .. } | Operator::Loop { .. } | Operator::If { .. } => {
+                        frame.dead_depth += 1;
+                        continue;
+                    }
+                    Operator::End | Operator::Else if frame.dead_depth > 0 => {
+                        if matches!(operator, Operator::End) {
+                            frame.dead_depth -= 1;
+                        }
+                        continue;
+                    }
+                    Operator::End | Operator::Else => {}
+                    _ => continue,
+                }
+            }
+
+            if let Some(body) = self.lower_operator(operator)? {
+                return Ok(body);
+            }
+        }
+        Err(anyhow!(
+            "Function {} ends without closing its body",
+            self.function.idx
+        ))
+    }
+
+    /// Lowers one operator, returning the function body once its final
+    /// `end` has been reached.
+    fn lower_operator(&mut self, operator: &Operator<'static>) -> Result<Option<Vec<Stmt>>> {
+        match operator {
+            Operator::I32Const { value } => self.push(Expr::Const(Constant::I32(*value))),
+            Operator::I64Const { value } => self.push(Expr::Const(Constant::I64(*value))),
+            Operator::F32Const { value } => {
+                self.push(Expr::Const(Constant::F32(f32::from_bits(value.bits()))))
+            }
+            Operator::F64Const { value } => {
+                self.push(Expr::Const(Constant::F64(f64::from_bits(value.bits()))))
+            }
+            Operator::LocalGet { local_index } => self.push(Expr::Local(*local_index)),
+            Operator::LocalSet { local_index } | Operator::LocalTee { local_index } => {
+                let value = self.pop()?;
+                self.spill_if(|expr| expr.reads_local(*local_index));
+                self.emit(Stmt::LocalSet {
+                    local: *local_index,
+                    value,
+                });
+                if matches!(operator, Operator::LocalTee { .. }) {
+                    self.push(Expr::Local(*local_index));
+                }
+            }
+            Operator::GlobalGet { global_index } => self.push(Expr::Global(*global_index)),
+            Operator::GlobalSet { global_index } => {
+                let value = self.pop()?;
+                self.spill_unstable();
+                self.emit(Stmt::GlobalSet {
+                    global: *global_index,
+                    value,
+                });
+            }
+            Operator::Nop => {}
+            Operator::Drop => {
+                let value = self.pop()?;
+                if !matches!(value, Expr::Const(_) | Expr::Temp(_) | Expr::Local(_)) {
+                    self.emit(Stmt::Eval(value));
+                }
+            }
+            Operator::Block { blockty } | Operator::Loop { blockty } => {
+                let frame_type = if matches!(operator, Operator::Loop { .. }) {
+                    FrameType::Loop
+                } else {
+                    FrameType::Block
+                };
+                self.open_frame(frame_type, *blockty)?;
+            }
+            Operator::If { blockty } => {
+                let cond = self.pop()?;
+                self.open_frame(FrameType::If, *blockty)?;
+                self.frames.last_mut().unwrap().cond = Some(cond);
+            }
+            Operator::Else => {
+                self.close_arm()?;
+                let frame = self.frames.last_mut().unwrap();
+                frame.then_body = Some(std::mem::take(&mut frame.stmts));
+                frame.unreachable = false;
+            }
+            Operator::End if self.frames.len() == 1 => {
+                if !self.frames[0].unreachable && !self.function.func_type.results().is_empty() {
+                    let value = self.pop()?;
+                    self.emit(Stmt::Return(Some(value)));
+                }
+                return Ok(Some(self.frames.pop().unwrap().stmts));
+            }
+            Operator::End => {
+                self.close_arm()?;
+                let frame = self.frames.pop().unwrap();
+                let result = frame.result;
+                let stmt = match frame.frame_type {
+                    FrameType::Function => unreachable!("the function frame is closed above"),
+                    FrameType::Block => Stmt::Block {
+                        label: frame.label,
+                        body: frame.stmts,
+                    },
+                    FrameType::Loop => Stmt::Loop(Loop {
+                        label: frame.label,
+                        body: frame.stmts,
+                        induction_variables: Vec::new(),
+                        trip_count: None,
+                    }),
+                    FrameType::If => {
+                        let (then_body, else_body) = match frame.then_body {
+                            Some(then_body) => (then_body, frame.stmts),
+                            None => (frame.stmts, Vec::new()),
+                        };
+                        Stmt::If {
+                            label: frame.label,
+                            cond: frame.cond.unwrap(),
+                            then_body,
+                            else_body,
+                        }
+                    }
+                };
+                self.emit(stmt);
+                if let Some(temp) = result {
+                    self.push(Expr::Temp(temp));
+                }
+            }
+            Operator::Br { relative_depth } => {
+                let target = self.branch_target(*relative_depth)?;
+                let stmt = if target == 0 {
+                    Stmt::Return(self.pop_return_value()?)
+                } else {
+                    if let Some(temp) = self.branch_result(target) {
+                        let value = self.pop()?;
+                        self.emit(Stmt::Let { temp, value });
+                    }
+                    Stmt::Br {
+                        target: self.frames[target].label,
+                    }
+                };
+                self.emit(stmt);
+                self.mark_unreachable();
+            }
+            Operator::BrIf { relative_depth } => {
+                let cond = self.pop()?;
+                let target = self.branch_target(*relative_depth)?;
+                let carries_value = if target == 0 {
+                    !self.function.func_type.results().is_empty()
+                } else {
+                    self.branch_result(target).is_some()
+                };
+                // A passed value stays on the stack when the branch is not
+                // taken, so it is evaluated once up front.
+                let value = if carries_value {
+                    let value = self.pop()?;
+                    let value = self.materialize(value);
+                    self.push(value.clone());
+                    Some(value)
+                } else {
+                    None
+                };
+                let stmt = if target == 0 {
+                    Stmt::If {
+                        label: self.new_label(),
+                        cond,
+                        then_body: vec![Stmt::Return(value)],
+                        else_body: Vec::new(),
+                    }
+                } else {
+                    if let (Some(temp), Some(value)) = (self.branch_result(target), value) {
+                        self.emit(Stmt::Let { temp, value });
+                    }
+                    Stmt::BrIf {
+                        target: self.frames[target].label,
+                        cond,
+                    }
+                };
+                self.emit(stmt);
+            }
+            Operator::Return => {
+                let value = self.pop_return_value()?;
+                self.emit(Stmt::Return(value));
+                self.mark_unreachable();
+            }
+            Operator::Unreachable => {
+                self.emit(Stmt::Unreachable);
+                self.mark_unreachable();
+            }
+            Operator::Call { function_index } => {
+                let func_type = self.callee_type(*function_index)?;
+                let args = self.pop_n(func_type.params().len())?;
+                self.emit_effect(
+                    Expr::Call {
+                        callee: Callee::Direct(*function_index),
+                        args,
+                    },
+                    !func_type.results().is_empty(),
+                );
+            }
+            Operator::CallIndirect {
+                type_index,
+                table_index,
+            } => {
+                let func_type = self
+                    .module
+                    .function_types
+                    .get(*type_index as usize)
+                    .ok_or(anyhow!("Invalid type index: {}", type_index))?
+                    .clone();
+                let index = self.pop()?;
+                let args = self.pop_n(func_type.params().len())?;
+                self.emit_effect(
+                    Expr::Call {
+                        callee: Callee::Indirect {
+                            type_index: *type_index,
+                            table_index: *table_index,
+                            index: Box::new(index),
+                        },
+                        args,
+                    },
+                    !func_type.results().is_empty(),
+                );
+            }
+            _ => {
+                if let Some(access) = load_access(operator) {
+                    let address = self.pop()?;
+                    self.push(Expr::Load(access.with_address(address)));
+                } else if let Some(access) = store_access(operator) {
+                    let value = self.pop()?;
+                    let address = self.pop()?;
+                    self.spill_unstable();
+                    self.emit(Stmt::Store {
+                        access: access.with_address(address),
+                        value,
+                    });
+                } else {
+                    let (params, results) = operator
+                        .operator_arity(&FixedArity)
+                        .ok_or(anyhow!("Unsupported operator: {:?}", operator))?;
+                    let args = self.pop_n(params as usize)?;
+                    let expr = Expr::Op {
+                        op: operator.clone(),
+                        args,
+                    };
+                    if results == 0 || has_side_effects(operator) {
+                        self.emit_effect(expr, results > 0);
+                    } else {
+                        self.push(expr);
+                    }
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    fn push(&mut self, expr: Expr) {
+        self.stack.push(expr);
+    }
+
+    fn pop(&mut self) -> Result<Expr> {
+        let frame = self.frames.last().unwrap();
+        if self.stack.len() <= frame.stack_height {
+            return Err(anyhow!("Stack underflow"));
+        }
+        Ok(self.stack.pop().unwrap())
+    }
+
+    fn pop_n(&mut self, count: usize) -> Result<Vec<Expr>> {
+        let mut values = (0..count).map(|_| self.pop()).collect::<Result<Vec<_>>>()?;
+        values.reverse();
+        Ok(values)
+    }
+
+    fn emit(&mut self, stmt: Stmt) {
+        self.frames.last_mut().unwrap().stmts.push(stmt);
+    }
+
+    fn new_label(&mut self) -> Label {
+        self.next_label += 1;
+        Label(self.next_label - 1)
+    }
+
+    fn new_temp(&mut self) -> Temp {
+        self.next_temp += 1;
+        Temp(self.next_temp - 1)
+    }
+
+    /// Evaluates `value` now, returning an expression that reads the result.
+    fn materialize(&mut self, value: Expr) -> Expr {
+        if matches!(value, Expr::Const(_) | Expr::Temp(_)) {
+            return value;
+        }
+        let temp = self.new_temp();
+        self.emit(Stmt::Let { temp, value });
+        Expr::Temp(temp)
+    }
+
+    fn spill_if(&mut self, should_spill: impl Fn(&Expr) -> bool) {
+        for i in 0..self.stack.len() {
+            if should_spill(&self.stack[i]) {
+                let value = self.stack[i].clone();
+                self.stack[i] = self.materialize(value);
+            }
+        }
+    }
+
+    /// Spills every pending value whose result a side effect could change or
+    /// whose evaluation could trap.
+    fn spill_unstable(&mut self) {
+        self.spill_if(|expr| !expr.is_stable());
+    }
+
+    fn emit_effect(&mut self, expr: Expr, has_result: bool) {
+        self.spill_unstable();
+        if has_result {
+            let temp = self.new_temp();
+            self.emit(Stmt::Let { temp, value: expr });
+            self.push(Expr::Temp(temp));
+        } else {
+            self.emit(Stmt::Eval(expr));
+        }
+    }
+
+    fn open_frame(&mut self, frame_type: FrameType, blockty: BlockType) -> Result<()> {
+        let has_result = match blockty {
+            BlockType::Empty => false,
+            BlockType::Type(_) => true,
+            BlockType::FuncType(type_index) => {
+                let func_type = self
+                    .module
+                    .function_types
+                    .get(type_index as usize)
+                    .ok_or(anyhow!("Invalid type index: {}", type_index))?;
+                if !func_type.params().is_empty() || func_type.results().len() > 1 {
+                    return Err(anyhow!("Multi-value blocks are not supported"));
+                }
+                !func_type.results().is_empty()
+            }
+        };
+        // The block body may have side effects, and values below the block
+        // are only read after it.
+        self.spill_if(|_| true);
+        let label = self.new_label();
+        let result = has_result.then(|| self.new_temp());
+        self.frames
+            .push(Frame::new(frame_type, label, result, self.stack.len()));
+        Ok(())
+    }
+
+    /// Finishes the current arm of a block, moving its value into the block
+    /// result.
+    fn close_arm(&mut self) -> Result<()> {
+        let frame = self.frames.last().unwrap();
+        if !frame.unreachable
+            && let Some(temp) = frame.result
+        {
+            let value = self.pop()?;
+            self.emit(Stmt::Let { temp, value });
+        }
+        let frame = self.frames.last().unwrap();
+        self.stack.truncate(frame.stack_height);
+        Ok(())
+    }
+
+    fn mark_unreachable(&mut self) {
+        let frame = self.frames.last_mut().unwrap();
+        self.stack.truncate(frame.stack_height);
+        frame.unreachable = true;
+    }
+
+    fn branch_target(&self, relative_depth: u32) -> Result<usize> {
+        let depth = relative_depth as usize;
+        if depth >= self.frames.len() {
+            return Err(anyhow!("Invalid branch depth: {}", relative_depth));
+        }
+        Ok(self.frames.len() - 1 - depth)
+    }
+
+    /// The temporary receiving a value passed by a branch to `target`.
+    /// Branches to a loop go back to its start and carry no value.
+    fn branch_result(&self, target: usize) -> Option<Temp> {
+        let frame = &self.frames[target];
+        match frame.frame_type {
+            FrameType::Loop => None,
+            _ => frame.result,
+        }
+    }
+
+    fn pop_return_value(&mut self) -> Result<Option<Expr>> {
+        match self.function.func_type.results() {
+            [] => Ok(None),
+            _ => Ok(Some(self.pop()?)),
+        }
+    }
+
+    fn callee_type(&self, function_index: u32) -> Result<FuncType> {
+        if function_index < self.module.import_count {
+            let mut imports = Vec::new();
+            if self.module.has_assert_eq32_import {
+                imports.push(FuncType::new([ValType::I32, ValType::I32], []));
+            }
+            if self.module.has_assert_eq64_import {
+                imports.push(FuncType::new([ValType::I64, ValType::I64], []));
+            }
+            return imports
+                .get(function_index as usize)
+                .cloned()
+                .ok_or(anyhow!("Unknown imported function: {}", function_index));
+        }
+        self.module
+            .functions
+            .iter()
+            .find(|f| f.idx == function_index)
+            .map(|f| f.func_type.clone())
+            .ok_or(anyhow!("Invalid function index: {}", function_index))
+    }
+}
+
+/// Shape of a load or store before its address is known.
+struct AccessShape {
+    ty: ValType,
+    bytes: u8,
+    signed: bool,
+    atomic: bool,
+    offset: u64,
+}
+
+impl AccessShape {
+    fn with_address(self, address: Expr) -> MemoryAccess {
+        MemoryAccess {
+            ty: self.ty,
+            bytes: self.bytes,
+            signed: self.signed,
+            atomic: self.atomic,
+            address: Box::new(address),
+            offset: self.offset,
+        }
+    }
+}
+
+fn load_access(operator: &Operator) -> Option<AccessShape> {
+    let (memarg, ty, bytes, signed, atomic) = match operator {
+        Operator::I32Load { memarg } => (memarg, ValType::I32, 4, false, false),
+        Operator::I64Load { memarg } => (memarg, ValType::I64, 8, false, false),
+        Operator::F32Load { memarg } => (memarg, ValType::F32, 4, false, false),
+        Operator::F64Load { memarg } => (memarg, ValType::F64, 8, false, false),
+        Operator::I32Load8S { memarg } => (memarg, ValType::I32, 1, true, false),
+        Operator::I32Load8U { memarg } => (memarg, ValType::I32, 1, false, false),
+        Operator::I32Load16S { memarg } => (memarg, ValType::I32, 2, true, false),
+        Operator::I32Load16U { memarg } => (memarg, ValType::I32, 2, false, false),
+        Operator::I64Load8S { memarg } => (memarg, ValType::I64, 1, true, false),
+        Operator::I64Load8U { memarg } => (memarg, ValType::I64, 1, false, false),
+        Operator::I64Load16S { memarg } => (memarg, ValType::I64, 2, true, false),
+        Operator::I64Load16U { memarg } => (memarg, ValType::I64, 2, false, false),
+        Operator::I64Load32S { memarg } => (memarg, ValType::I64, 4, true, false),
+        Operator::I64Load32U { memarg } => (memarg, ValType::I64, 4, false, false),
+        Operator::I32AtomicLoad { memarg } => (memarg, ValType::I32, 4, false, true),
+        Operator::I64AtomicLoad { memarg } => (memarg, ValType::I64, 8, false, true),
+        Operator::I32AtomicLoad8U { memarg } => (memarg, ValType::I32, 1, false, true),
+        Operator::I32AtomicLoad16U { memarg } => (memarg, ValType::I32, 2, false, true),
+        Operator::I64AtomicLoad8U { memarg } => (memarg, ValType::I64, 1, false, true),
+        Operator::I64AtomicLoad16U { memarg } => (memarg, ValType::I64, 2, false, true),
+        Operator::I64AtomicLoad32U { memarg } => (memarg, ValType::I64, 4, false, true),
+        _ => return None,
+    };
+    Some(AccessShape {
+        ty,
+        bytes,
+        signed,
+        atomic,
+        offset: memarg.offset,
+    })
+}
+
+fn store_access(operator: &Operator) -> Option<AccessShape> {
+    let (memarg, ty, bytes, atomic) = match operator {
+        Operator::I32Store { memarg } => (memarg, ValType::I32, 4, false),
+        Operator::I64Store { memarg } => (memarg, ValType::I64, 8, false),
+        Operator::F32Store { memarg } => (memarg, ValType::F32, 4, false),
+        Operator::F64Store { memarg } => (memarg, ValType::F64, 8, false),
+        Operator::I32Store8 { memarg } => (memarg, ValType::I32, 1, false),
+        Operator::I32Store16 { memarg } => (memarg, ValType::I32, 2, false),
+        Operator::I64Store8 { memarg } => (memarg, ValType::I64, 1, false),
+        Operator::I64Store16 { memarg } => (memarg, ValType::I64, 2, false),
+        Operator::I64Store32 { memarg } => (memarg, ValType::I64, 4, false),
+        Operator::I32AtomicStore { memarg } => (memarg, ValType::I32, 4, true),
+        Operator::I64AtomicStore { memarg } => (memarg, ValType::I64, 8, true),
+        Operator::I32AtomicStore8 { memarg } => (memarg, ValType::I32, 1, true),
+        Operator::I32AtomicStore16 { memarg } => (memarg, ValType::I32, 2, true),
+        Operator::I64AtomicStore8 { memarg } => (memarg, ValType::I64, 1, true),
+        Operator::I64AtomicStore16 { memarg } => (memarg, ValType::I64, 2, true),
+        Operator::I64AtomicStore32 { memarg } => (memarg, ValType::I64, 4, true),
+        _ => return None,
+    };
+    Some(AccessShape {
+        ty,
+        bytes,
+        signed: false,
+        atomic,
+        offset: memarg.offset,
+    })
+}
+
+/// Value-producing operators that must stay in place relative to other
+/// side effects.
+fn has_side_effects(operator: &Operator) -> bool {
+    matches!(
+        operator,
+        Operator::MemoryGrow { .. } | Operator::TableGrow { .. }
+    ) || mnemonic(operator).contains("atomic")
+}
+
+/// Pure-looking operators that can trap or whose result depends on state
+/// other than their operands.
+fn may_trap_or_read_state(operator: &Operator) -> bool {
+    matches!(
+        operator,
+        Operator::I32DivS
+            | Operator::I32DivU
+            | Operator::I32RemS
+            | Operator::I32RemU
+            | Operator::I64DivS
+            | Operator::I64DivU
+            | Operator::I64RemS
+            | Operator::I64RemU
+            | Operator::I32TruncF32S
+            | Operator::I32TruncF32U
+            | Operator::I32TruncF64S
+            | Operator::I32TruncF64U
+            | Operator::I64TruncF32S
+            | Operator::I64TruncF32U
+            | Operator::I64TruncF64S
+            | Operator::I64TruncF64U
+            | Operator::MemorySize { .. }
+            | Operator::TableGet { .. }
+            | Operator::TableSize { .. }
+    )
+}
+
+/// Text-format name of an operator, derived from its `Debug` name, so that
+/// `I32TruncF64S` becomes `i32.trunc_f64_s`.
+fn mnemonic(operator: &Operator) -> String {
+    let debug = format!("{operator:?}");
+    let name = debug.split([' ', '{', '(']).next().unwrap_or_default();
+
+    let mut words: Vec<String> = Vec::new();
+    for c in name.chars() {
+        match words.last_mut() {
+            Some(word) if !c.is_ascii_uppercase() => word.push(c),
+            _ => words.push(c.to_ascii_lowercase().to_string()),
+        }
+    }
+
+    let mut text = String::new();
+    for (i, word) in words.iter().enumerate() {
+        if i > 0 {
+            let previous = words[i - 1].as_str();
+            let namespace = i == 1
+                && matches!(
+                    previous,
+                    "i32" | "i64" | "f32" | "f64" | "memory" | "local" | "global" | "ref"
+                );
+            let dotted = namespace || previous == "atomic" || previous.starts_with("rmw");
+            text.push(if dotted { '.' } else { '_' });
+        }
+        text.push_str(word);
+    }
+    text
+}
+
+impl Expr {
+    fn any(&self, predicate: &impl Fn(&Expr) -> bool) -> bool {
+        predicate(self)
+            || match self {
+                Expr::Load(access) => access.address.any(predicate),
+                Expr::Op { args, .. } => args.iter().any(|arg| arg.any(predicate)),
+                Expr::Call { callee, args } => {
+                    matches!(callee, Callee::Indirect { index, .. } if index.any(predicate))
+                        || args.iter().any(|arg| arg.any(predicate))
+                }
+                Expr::Const(_) | Expr::Local(_) | Expr::Global(_) | Expr::Temp(_) => false,
+            }
+    }
+
+    pub fn reads_local(&self, local: u32) -> bool {
+        self.any(&|expr| matches!(expr, Expr::Local(l) if *l == local))
+    }
+
+    pub fn reads_temp(&self, temp: Temp) -> bool {
+        self.any(&|expr| matches!(expr, Expr::Temp(t) if *t == temp))
+    }
+
+    /// Whether the expression evaluates to the same value, without trapping,
+    /// anywhere up to the next write of a local it reads.
+    pub fn is_stable(&self) -> bool {
+        match self {
+            Expr::Const(_) | Expr::Local(_) | Expr::Temp(_) => true,
+            Expr::Op { op, args } => {
+                !may_trap_or_read_state(op) && args.iter().all(Expr::is_stable)
+            }
+            Expr::Global(_) | Expr::Load(_) | Expr::Call { .. } => false,
+        }
+    }
+
+    fn int_constant(&self, signed: bool) -> Option<i128> {
+        match self {
+            Expr::Const(Constant::I32(value)) if signed => Some(*value as i128),
+            Expr::Const(Constant::I32(value)) => Some(*value as u32 as i128),
+            Expr::Const(Constant::I64(value)) if signed => Some(*value as i128),
+            Expr::Const(Constant::I64(value)) => Some(*value as u64 as i128),
+            _ => None,
+        }
+    }
+}
+
+/// Visits every statement in `stmts`, including nested ones, in order.
+fn walk_stmts<'a>(stmts: &'a [Stmt], visit: &mut impl FnMut(&'a Stmt)) {
+    for stmt in stmts {
+        visit(stmt);
+        match stmt {
+            Stmt::Block { body, .. } | Stmt::Loop(Loop { body, .. }) => walk_stmts(body, visit),
+            Stmt::If {
+                then_body,
+                else_body,
+                ..
+            } => {
+                walk_stmts(then_body, visit);
+                walk_stmts(else_body, visit);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// State written by a list of statements.
+#[derive(Default)]
+struct Effects {
+    /// Number of assignments to each local.
+    locals: HashMap<u32, usize>,
+    temps: HashSet<Temp>,
+    globals: HashSet<u32>,
+    calls: bool,
+}
+
+impl Effects {
+    fn of(stmts: &[Stmt]) -> Self {
+        let mut effects = Effects::default();
+        walk_stmts(stmts, &mut |stmt| match stmt {
+            Stmt::LocalSet { local, .. } => *effects.locals.entry(*local).or_default() += 1,
+            Stmt::Let { temp, value } => {
+                effects.temps.insert(*temp);
+                effects.calls |= matches!(value, Expr::Call { .. });
+            }
+            Stmt::GlobalSet { global, .. } => {
+                effects.globals.insert(*global);
+            }
+            Stmt::Eval(Expr::Call { .. }) => effects.calls = true,
+            _ => {}
+        });
+        effects
+    }
+
+    fn forget(&self, known: &mut HashMap<u32, Expr>) {
+        for local in self.locals.keys() {
+            forget_local(known, *local);
+        }
+        known.retain(|_, value| !self.temps.iter().any(|temp| value.reads_temp(*temp)));
+    }
+
+    fn is_invariant(&self, expr: &Expr) -> bool {
+        match expr {
+            Expr::Const(_) => true,
+            Expr::Local(local) => !self.locals.contains_key(local),
+            Expr::Temp(temp) => !self.temps.contains(temp),
+            Expr::Global(global) => !self.globals.contains(global) && !self.calls,
+            Expr::Op { op, args } => {
+                !may_trap_or_read_state(op) && args.iter().all(|arg| self.is_invariant(arg))
+            }
+            Expr::Load(_) | Expr::Call { .. } => false,
+        }
+    }
+}
+
+fn forget_local(known: &mut HashMap<u32, Expr>, local: u32) {
+    known.remove(&local);
+    known.retain(|_, value| !value.reads_local(local));
+}
+
+/// Annotates every loop in `stmts`. `known` maps locals to their current
+/// value where straight-line code makes it known, and `exit_label` is the
+/// block whose end immediately follows the last statement.
+fn analyze_stmts(stmts: &mut [Stmt], known: &mut HashMap<u32, Expr>, exit_label: Option<Label>) {
+    let last = stmts.len().saturating_sub(1);
+    for (i, stmt) in stmts.iter_mut().enumerate() {
+        match stmt {
+            Stmt::LocalSet { local, value } => {
+                forget_local(known, *local);
+                if value.is_stable() && !value.reads_local(*local) {
+                    known.insert(*local, value.clone());
+                }
+            }
+            Stmt::Let { temp, .. } => {
+                let temp = *temp;
+                known.retain(|_, value| !value.reads_temp(temp));
+            }
+            Stmt::Loop(loop_) => {
+                analyze_loop(loop_, known, if i == last { exit_label } else { None });
+                Effects::of(&loop_.body).forget(known);
+                analyze_stmts(&mut loop_.body, &mut known.clone(), None);
+            }
+            Stmt::Block { label, body } => {
+                analyze_stmts(body, &mut known.clone(), Some(*label));
+                Effects::of(body).forget(known);
+            }
+            Stmt::If {
+                then_body,
+                else_body,
+                ..
+            } => {
+                analyze_stmts(then_body, &mut known.clone(), None);
+                analyze_stmts(else_body, &mut known.clone(), None);
+                Effects::of(then_body).forget(known);
+                Effects::of(else_body).forget(known);
+            }
+            _ => {}
+        }
+    }
+}
+
+fn analyze_loop(loop_: &mut Loop, known: &HashMap<u32, Expr>, exit_label: Option<Label>) {
+    let label = loop_.label;
+    let mut back_edges = 0;
+    let mut outside_targets = Vec::new();
+    let mut defined_labels = HashSet::new();
+    let mut leaves_function = false;
+    walk_stmts(&loop_.body, &mut |stmt| match stmt {
+        Stmt::Br { target } | Stmt::BrIf { target, .. } => {
+            if *target == label {
+                back_edges += 1;
+            } else {
+                outside_targets.push(*target);
+            }
+        }
+        Stmt::Block { label, .. } | Stmt::If { label, .. } | Stmt::Loop(Loop { label, .. }) => {
+            defined_labels.insert(*label);
+        }
+        Stmt::Return(_) | Stmt::Unreachable => leaves_function = true,
+        _ => {}
+    });
+    outside_targets.retain(|target| !defined_labels.contains(target));
+
+    // With a single back edge at the end of the body, every top-level
+    // statement runs exactly once per iteration.
+    let ends_with_back_edge = matches!(
+        loop_.body.last(),
+        Some(Stmt::Br { target } | Stmt::BrIf { target, .. }) if *target == label
+    );
+    if back_edges != 1 || !ends_with_back_edge {
+        return;
+    }
+
+    let effects = Effects::of(&loop_.body);
+    loop_.induction_variables = loop_
+        .body
+        .iter()
+        .filter_map(|stmt| match stmt {
+            Stmt::LocalSet { local, value } if effects.locals[local] == 1 => {
+                Some(InductionVariable {
+                    local: *local,
+                    start: known.get(local).cloned(),
+                    step: constant_step(*local, value)?,
+                })
+            }
+            _ => None,
+        })
+        .collect();
+
+    // Bottom-tested loops continue while the test holds; top-tested ones
+    // leave the enclosing block when it holds.
+    let (cond, bottom_tested, exit) = match (loop_.body.first(), loop_.body.last()) {
+        (_, Some(Stmt::BrIf { cond, .. })) => (cond, true, None),
+        (Some(Stmt::BrIf { target, cond }), Some(Stmt::Br { .. }))
+            if Some(*target) == exit_label =>
+        {
+            (cond, false, exit_label)
+        }
+        _ => return,
+    };
+    if leaves_function || outside_targets.iter().any(|target| Some(*target) != exit) {
+        return;
+    }
+    if outside_targets.len() > usize::from(exit.is_some()) {
+        return;
+    }
+
+    let Some((induction_variable, predicate, signed, bound)) =
+        comparison(cond, !bottom_tested, &loop_.induction_variables)
+    else {
+        return;
+    };
+    let Some(start) = induction_variable.start.clone() else {
+        return;
+    };
+    let step = induction_variable.step;
+    let moves_toward_bound = match predicate {
+        Predicate::Lt | Predicate::Le => step > 0,
+        Predicate::Gt | Predicate::Ge => step < 0,
+        Predicate::Ne => step != 0,
+    };
+    if !moves_toward_bound || !effects.is_invariant(&bound) {
+        return;
+    }
+
+    loop_.trip_count = Some(TripCount {
+        local: induction_variable.local,
+        start,
+        bound,
+        step,
+        predicate,
+        signed,
+        bottom_tested,
+    });
+}
+
+/// Step of `local = local + c` or `local = local - c`.
+fn constant_step(local: u32, value: &Expr) -> Option<i64> {
+    let Expr::Op { op, args } = value else {
+        return None;
+    };
+    let constant = |expr: &Expr| expr.int_constant(true).map(|c| c as i64);
+    match (op, args.as_slice()) {
+        (Operator::I32Add | Operator::I64Add, [Expr::Local(l), c])
+        | (Operator::I32Add | Operator::I64Add, [c, Expr::Local(l)])
+            if *l == local =>
+        {
+            constant(c)
+        }
+        (Operator::I32Sub | Operator::I64Sub, [Expr::Local(l), c]) if *l == local => {
+            constant(c).map(|c| -c)
+        }
+        _ => None,
+    }
+}
+
+/// Splits a loop test into an induction variable, the predicate under which
+/// the loop continues, its signedness and the bound. `negate` is set when the
+/// loop exits while `cond` holds.
+fn comparison<'a>(
+    cond: &Expr,
+    negate: bool,
+    induction_variables: &'a [InductionVariable],
+) -> Option<(&'a InductionVariable, Predicate, bool, Expr)> {
+    let Expr::Op { op, args } = cond else {
+        return None;
+    };
+    let [lhs, rhs] = args.as_slice() else {
+        return None;
+    };
+    let (when_true, when_false, signed) = match op {
+        Operator::I32LtS | Operator::I64LtS => (Some(Predicate::Lt), Some(Predicate::Ge), true),
+        Operator::I32LtU | Operator::I64LtU => (Some(Predicate::Lt), Some(Predicate::Ge), false),
+        Operator::I32LeS | Operator::I64LeS => (Some(Predicate::Le), Some(Predicate::Gt), true),
+        Operator::I32LeU | Operator::I64LeU => (Some(Predicate::Le), Some(Predicate::Gt), false),
+        Operator::I32GtS | Operator::I64GtS => (Some(Predicate::Gt), Some(Predicate::Le), true),
+        Operator::I32GtU | Operator::I64GtU => (Some(Predicate::Gt), Some(Predicate::Le), false),
+        Operator::I32GeS | Operator::I64GeS => (Some(Predicate::Ge), Some(Predicate::Lt), true),
+        Operator::I32GeU | Operator::I64GeU => (Some(Predicate::Ge), Some(Predicate::Lt), false),
+        Operator::I32Ne | Operator::I64Ne => (Some(Predicate::Ne), None, true),
+        Operator::I32Eq | Operator::I64Eq => (None, Some(Predicate::Ne), true),
+        _ => return None,
+    };
+    let predicate = if negate { when_false } else { when_true }?;
+
+    let find = |expr: &Expr| match expr {
+        Expr::Local(local) => induction_variables.iter().find(|iv| iv.local == *local),
+        _ => None,
+    };
+    if let Some(induction_variable) = find(lhs) {
+        Some((induction_variable, predicate, signed, rhs.clone()))
+    } else {
+        find(rhs).map(|induction_variable| {
+            (induction_variable, predicate.swapped(), signed, lhs.clone())
+        })
+    }
+}
+
+impl Predicate {
+    fn swapped(self) -> Self {
+        match self {
+            Predicate::Lt => Predicate::Gt,
+            Predicate::Le => Predicate::Ge,
+            Predicate::Gt => Predicate::Lt,
+            Predicate::Ge => Predicate::Le,
+            Predicate::Ne => Predicate::Ne,
+        }
+    }
+}
+
+impl TripCount {
+    /// The trip count when the start and bound are constants, or `None` if
+    /// they are not or the loop never reaches its bound.
+    pub fn constant(&self) -> Option<u64> {
+        let start = self.start.int_constant(self.signed)?;
+        let bound = self.bound.int_constant(self.signed)?;
+        let step = self.step as i128;
+        let count = match self.predicate {
+            Predicate::Lt if bound > start => (bound - start + step - 1) / step,
+            Predicate::Le if bound >= start => (bound - start) / step + 1,
+            Predicate::Gt if start > bound => (start - bound - step - 1) / -step,
+            Predicate::Ge if start >= bound => (start - bound) / -step + 1,
+            Predicate::Ne => {
+                let distance = bound - start;
+                if distance % step != 0 || distance / step < i128::from(self.bottom_tested) {
+                    return None;
+                }
+                distance / step
+            }
+            _ => 0,
+        };
+        let count = if self.bottom_tested {
+            count.max(1)
+        } else {
+            count
+        };
+        u64::try_from(count).ok()
+    }
+}
+
+impl fmt::Display for Label {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "L{}", self.0)
+    }
+}
+
+impl fmt::Display for Temp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "t{}", self.0)
+    }
+}
+
+impl fmt::Display for Constant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Constant::I32(value) => write!(f, "{value}"),
+            Constant::I64(value) => write!(f, "{value}i64"),
+            Constant::F32(value) => write!(f, "{value}f32"),
+            Constant::F64(value) => write!(f, "{value}f64"),
+        }
+    }
+}
+
+fn write_list(f: &mut fmt::Formatter<'_>, exprs: &[Expr]) -> fmt::Result {
+    for (i, expr) in exprs.iter().enumerate() {
+        if i > 0 {
+            write!(f, ", ")?;
+        }
+        write!(f, "{expr}")?;
+    }
+    Ok(())
+}
+
+impl MemoryAccess {
+    fn write(&self, f: &mut fmt::Formatter<'_>, kind: &str) -> fmt::Result {
+        write!(f, "{}.", self.ty)?;
+        if self.atomic {
+            write!(f, "atomic.")?;
+        }
+        write!(f, "{kind}")?;
+        let bits = u32::from(self.bytes) * 8;
+        let type_bits = match self.ty {
+            ValType::I64 | ValType::F64 => 64,
+            _ => 32,
+        };
+        if bits != type_bits {
+            write!(f, "{bits}")?;
+            if kind == "load" {
+                write!(f, "{}", if self.signed { "_s" } else { "_u" })?;
+            }
+        }
+        write!(f, "[{}", self.address)?;
+        if self.offset > 0 {
+            write!(f, " + {}", self.offset)?;
+        }
+        write!(f, "]")
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expr::Const(constant) => write!(f, "{constant}"),
+            Expr::Local(local) => write!(f, "l{local}"),
+            Expr::Global(global) => write!(f, "g{global}"),
+            Expr::Temp(temp) => write!(f, "{temp}"),
+            Expr::Load(access) => access.write(f, "load"),
+            Expr::Op { op, args } => {
+                write!(f, "{}(", mnemonic(op))?;
+                write_list(f, args)?;
+                write!(f, ")")
+            }
+            Expr::Call { callee, args } => {
+                match callee {
+                    Callee::Direct(index) => write!(f, "call f{index}(")?,
+                    Callee::Indirect {
+                        type_index,
+                        table_index,
+                        index,
+                    } => write!(
+                        f,
+                        "call_indirect type {type_index} table {table_index} [{index}]("
+                    )?,
+                }
+                write_list(f, args)?;
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+impl fmt::Display for TripCount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(count) = self.constant() {
+            return write!(f, "{count}");
+        }
+        let (start, bound, step) = (&self.start, &self.bound, self.step);
+        let formula = match self.predicate {
+            Predicate::Lt => format!("ceil(({bound} - {start}) / {step})"),
+            Predicate::Le => format!("({bound} - {start}) / {step} + 1"),
+            Predicate::Gt => format!("ceil(({start} - {bound}) / {})", -step),
+            Predicate::Ge => format!("({start} - {bound}) / {} + 1", -step),
+            Predicate::Ne => return write!(f, "({bound} - {start}) / {step}"),
+        };
+        write!(f, "max({}, {formula})", u8::from(self.bottom_tested))
+    }
+}
+
+fn write_stmts(f: &mut fmt::Formatter<'_>, stmts: &[Stmt], depth: usize) -> fmt::Result {
+    let indent = "  ".repeat(depth);
+    for stmt in stmts {
+        write!(f, "{indent}")?;
+        match stmt {
+            Stmt::LocalSet { local, value } => writeln!(f, "l{local} = {value}")?,
+            Stmt::GlobalSet { global, value } => writeln!(f, "g{global} = {value}")?,
+            Stmt::Let { temp, value } => writeln!(f, "{temp} = {value}")?,
+            Stmt::Store { access, value } => {
+                access.write(f, "store")?;
+                writeln!(f, " = {value}")?;
+            }
+            Stmt::Eval(expr) => writeln!(f, "{expr}")?,
+            Stmt::Block { label, body } => {
+                writeln!(f, "block {label}")?;
+                write_stmts(f, body, depth + 1)?;
+                writeln!(f, "{indent}end")?;
+            }
+            Stmt::Loop(loop_) => {
+                writeln!(f, "loop {}", loop_.label)?;
+                for iv in &loop_.induction_variables {
+                    write!(f, "{indent}  ;; induction l{}", iv.local)?;
+                    if let Some(start) = &iv.start {
+                        write!(f, " from {start}")?;
+                    }
+                    writeln!(f, " step {}", iv.step)?;
+                }
+                if let Some(trip_count) = &loop_.trip_count {
+                    writeln!(f, "{indent}  ;; trip count {trip_count}")?;
+                }
+                write_stmts(f, &loop_.body, depth + 1)?;
+                writeln!(f, "{indent}end")?;
+            }
+            Stmt::If {
+                label,
+                cond,
+                then_body,
+                else_body,
+            } => {
+                writeln!(f, "if {label} {cond}")?;
+                write_stmts(f, then_body, depth + 1)?;
+                if !else_body.is_empty() {
+                    writeln!(f, "{indent}else")?;
+                    write_stmts(f, else_body, depth + 1)?;
+                }
+                writeln!(f, "{indent}end")?;
+            }
+            Stmt::Br { target } => writeln!(f, "br {target}")?,
+            Stmt::BrIf { target, cond } => writeln!(f, "br_if {target} {cond}")?,
+            Stmt::Return(None) => writeln!(f, "return")?,
+            Stmt::Return(Some(value)) => writeln!(f, "return {value}")?,
+            Stmt::Unreachable => writeln!(f, "unreachable")?,
+        }
+    }
+    Ok(())
+}
+
+impl fmt::Display for FunctionIr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "func f{}", self.index)?;
+        if let Some(name) = &self.name {
+            write!(f, " ${name}")?;
+        }
+        let types = |types: &[ValType]| {
+            types
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        if !self.func_type.params().is_empty() {
+            write!(f, " (param {})", types(self.func_type.params()))?;
+        }
+        if !self.func_type.results().is_empty() {
+            write!(f, " (result {})", types(self.func_type.results()))?;
+        }
+        writeln!(f)?;
+        if !self.locals.is_empty() {
+            writeln!(f, "  (local {})", types(&self.locals))?;
+        }
+        write_stmts(f, &self.body, 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wasm_parser::FunctionBody;
+    use wasmparser::MemArg;
+
+    fn lower(
+        params: Vec<ValType>,
+        results: Vec<ValType>,
+        locals: Vec<ValType>,
+        operators: Vec<Operator<'static>>,
+    ) -> FunctionIr {
+        let function = Function {
+            idx: 0,
+            name: None,
+            func_type: FuncType::new(params, results),
+            body: FunctionBody {
+                locals,
+                operators,
+                offsets: vec![],
+            },
+        };
+        let module = WasmModule {
+            ..Default::default()
+        };
+        lower_function(&module, &function).unwrap()
+    }
+
+    fn only_loop(body: &[Stmt]) -> &Loop {
+        let mut loops = Vec::new();
+        walk_stmts(body, &mut |stmt| {
+            if let Stmt::Loop(loop_) = stmt {
+                loops.push(loop_);
+            }
+        });
+        assert_eq!(loops.len(), 1);
+        loops[0]
+    }
+
+    #[test]
+    fn test_bottom_tested_loop() {
+        // l0 = 0; do { l1 += l0; l0 += 1 } while (l0 < 3)
+        let ir = lower(
+            vec![],
+            vec![],
+            vec![ValType::I32, ValType::I32],
+            vec![
+                Operator::I32Const { value: 0 },
+                Operator::LocalSet { local_index: 0 },
+                Operator::Loop {
+                    blockty: BlockType::Empty,
+                },
+                Operator::LocalGet { local_index: 1 },
+                Operator::LocalGet { local_index: 0 },
+                Operator::I32Add,
+                Operator::LocalSet { local_index: 1 },
+                Operator::LocalGet { local_index: 0 },
+                Operator::I32Const { value: 1 },
+                Operator::I32Add,
+                Operator::LocalSet { local_index: 0 },
+                Operator::LocalGet { local_index: 0 },
+                Operator::I32Const { value: 3 },
+                Operator::I32LtS,
+                Operator::BrIf { relative_depth: 0 },
+                Operator::End,
+                Operator::End,
+            ],
+        );
+
+        let loop_ = only_loop(&ir.body);
+        assert_eq!(
+            loop_.induction_variables,
+            vec![InductionVariable {
+                local: 0,
+                start: Some(Expr::Const(Constant::I32(0))),
+                step: 1,
+            }]
+        );
+        let trip_count = loop_.trip_count.as_ref().unwrap();
+        assert!(trip_count.bottom_tested);
+        assert_eq!(trip_count.predicate, Predicate::Lt);
+        assert_eq!(trip_count.constant(), Some(3));
+
+        let text = ir.to_string();
+        assert!(text.contains(";; induction l0 from 0 step 1"));
+        assert!(text.contains(";; trip count 3"));
+        assert!(text.contains("l1 = i32.add(l1, l0)"));
+        assert!(text.contains("br_if L1 i32.lt_s(l0, 3)"));
+    }
+
+    #[test]
+    fn test_top_tested_loop_with_memory_access() {
+        // for (l1 = 0; l1 < l0; l1++) mem[l1 * 4 + 4] = l1
+        let ir = lower(
+            vec![ValType::I32],
+            vec![],
+            vec![ValType::I32],
+            vec![
+                Operator::I32Const { value: 0 },
+                Operator::LocalSet { local_index: 1 },
+                Operator::Block {
+                    blockty: BlockType::Empty,
+                },
+                Operator::Loop {
+                    blockty: BlockType::Empty,
+                },
+                Operator::LocalGet { local_index: 1 },
+                Operator::LocalGet { local_index: 0 },
+                Operator::I32GeS,
+                Operator::BrIf { relative_depth: 1 },
+                Operator::LocalGet { local_index: 1 },
+                Operator::I32Const { value: 2 },
+                Operator::I32Shl,
+                Operator::LocalGet { local_index: 1 },
+                Operator::I32Store {
+                    memarg: MemArg {
+                        align: 2,
+                        max_align: 2,
+                        offset: 4,
+                        memory: 0,
+                    },
+                },
+                Operator::LocalGet { local_index: 1 },
+                Operator::I32Const { value: 1 },
+                Operator::I32Add,
+                Operator::LocalSet { local_index: 1 },
+                Operator::Br { relative_depth: 0 },
+                Operator::End,
+                Operator::End,
+                Operator::End,
+            ],
+        );
+
+        let loop_ = only_loop(&ir.body);
+        assert!(matches!(
+            loop_.body[1],
+            Stmt::Store {
+                access: MemoryAccess {
+                    ty: ValType::I32,
+                    bytes: 4,
+                    offset: 4,
+                    ..
+                },
+                value: Expr::Local(1),
+            }
+        ));
+        let trip_count = loop_.trip_count.as_ref().unwrap();
+        assert!(!trip_count.bottom_tested);
+        assert_eq!(trip_count.predicate, Predicate::Lt);
+        assert_eq!(trip_count.bound, Expr::Local(0));
+        assert_eq!(trip_count.constant(), None);
+
+        let text = ir.to_string();
+        assert!(text.contains(";; trip count max(0, ceil((l0 - 0) / 1))"));
+        assert!(text.contains("i32.store[i32.shl(l1, 2) + 4] = l1"));
+    }
+
+    #[test]
+    fn test_loop_with_early_exit_has_no_trip_count() {
+        let ir = lower(
+            vec![ValType::I32],
+            vec![],
+            vec![ValType::I32],
+            vec![
+                Operator::Loop {
+                    blockty: BlockType::Empty,
+                },
+                Operator::LocalGet { local_index: 0 },
+                Operator::If {
+                    blockty: BlockType::Empty,
+                },
+                Operator::Return,
+                Operator::End,
+                Operator::LocalGet { local_index: 1 },
+                Operator::I32Const { value: 1 },
+                Operator::I32Add,
+                Operator::LocalTee { local_index: 1 },
+                Operator::I32Const { value: 10 },
+                Operator::I32Ne,
+                Operator::BrIf { relative_depth: 0 },
+                Operator::End,
+                Operator::End,
+            ],
+        );
+
+        let loop_ = only_loop(&ir.body);
+        assert_eq!(loop_.induction_variables.len(), 1);
+        assert_eq!(loop_.trip_count, None);
+    }
+
+    #[test]
+    fn test_spill_before_local_set() {
+        let ir = lower(
+            vec![ValType::I32],
+            vec![ValType::I32],
+            vec![],
+            vec![
+                Operator::LocalGet { local_index: 0 },
+                Operator::I32Const { value: 5 },
+                Operator::LocalSet { local_index: 0 },
+                Operator::End,
+            ],
+        );
+
+        assert_eq!(
+            ir.body,
+            vec![
+                Stmt::Let {
+                    temp: Temp(0),
+                    value: Expr::Local(0),
+                },
+                Stmt::LocalSet {
+                    local: 0,
+                    value: Expr::Const(Constant::I32(5)),
+                },
+                Stmt::Return(Some(Expr::Temp(Temp(0)))),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_mnemonic() {
+        assert_eq!(mnemonic(&Operator::I32TruncF64S), "i32.trunc_f64_s");
+        assert_eq!(
+            mnemonic(&Operator::I64AtomicRmw32AddU {
+                memarg: MemArg {
+                    align: 2,
+                    max_align: 2,
+                    offset: 0,
+                    memory: 0,
+                },
+            }),
+            "i64.atomic.rmw32.add_u"
+        );
+        assert_eq!(mnemonic(&Operator::MemoryGrow { mem: 0 }), "memory.grow");
+    }
+}
@@ -1,9 +1,26 @@
-use anyhow::Result;
-use auto_parallel_wasm::{Compiler, WasmModule};
+use anyhow::{Result, anyhow};
+use auto_parallel_wasm::cache::{self, Cache, CacheKey};
+use auto_parallel_wasm::header::generate_header;
+use auto_parallel_wasm::limits::{ExecutionLimits, parse_duration};
+use auto_parallel_wasm::loop_ir::lower_module;
+use auto_parallel_wasm::profile;
+use auto_parallel_wasm::{Compiler, CompilerOptions, EmitKind, RelocModel, WasmModule};
 use inkwell::context::Context;
 use std::env;
 use std::fs;
+use std::io;
+use std::path::Path;
 use std::process;
+use std::time::SystemTime;
+
+struct CommandLine<'a> {
+    positional: Vec<&'a str>,
+    options: CompilerOptions,
+    emit: Option<EmitKind>,
+    emit_header: Option<String>,
+    no_cache: bool,
+    limits: ExecutionLimits,
+}
 
 fn main() -> Result<()> {
     let args: Vec<String> = env::args().collect();
@@ -13,32 +30,99 @@ fn main() -> Result<()> {
     }
 
     let command = &args[1];
+    let CommandLine {
+        positional,
+        options,
+        emit,
+        emit_header,
+        no_cache,
+        limits,
+    } = parse_command_line(&args[2..])?;
+    if (options.perf_map || options.profile) && command != "exec" {
+        return Err(anyhow!(
+            "--perf-map and --profile are only supported by exec"
+        ));
+    }
+    if options.lazy && (options.perf_map || options.profile) {
+        return Err(anyhow!(
+            "--perf-map and --profile are not supported with --lazy"
+        ));
+    }
+    if (emit.is_some() || emit_header.is_some()) && command != "compile" {
+        return Err(anyhow!(
+            "--emit and --emit-header are only supported by compile"
+        ));
+    }
+    if options.lazy && command != "exec" {
+        return Err(anyhow!("--lazy and --tier-up are only supported by exec"));
+    }
+    if (options.consume_fuel || options.interruptible || options.check_stack) && command != "exec" {
+        return Err(anyhow!(
+            "--fuel, --timeout and --max-stack are only supported by exec"
+        ));
+    }
     match command.as_str() {
         "exec" => {
-            if args.len() != 3 {
-                eprintln!("Usage: exec <wasm-file>");
+            if positional.len() != 1 {
+                eprintln!("Usage: exec [options] <wasm-file>");
                 process::exit(1);
             }
-            exec_command(&args[2])
+            // The JIT's target machine is always the generic host one, which
+            // these options cannot reach.
+            if options.target.is_some()
+                || options.cpu.is_some()
+                || options.features.is_some()
+                || options.reloc != RelocModel::Default
+            {
+                return Err(anyhow!(
+                    "--target, --cpu, --features and --reloc are only supported by compile"
+                ));
+            }
+            exec_command(positional[0], options, limits, no_cache)
         }
         "compile" => {
-            if args.len() != 4 {
-                eprintln!("Usage: compile <wasm-file> <output-file>");
+            if positional.len() != 2 {
+                eprintln!("Usage: compile [options] <wasm-file> <output-file>");
                 process::exit(1);
             }
-            compile_command(&args[2], &args[3])
+            let emit = emit.unwrap_or_default();
+            compile_command(
+                positional[0],
+                positional[1],
+                options,
+                emit,
+                emit_header.as_deref(),
+            )
         }
         "ir" => {
-            if args.len() < 3 || args.len() > 4 {
-                eprintln!("Usage: ir <wasm-file> [output-file]");
+            if positional.is_empty() || positional.len() > 2 {
+                eprintln!("Usage: ir [options] <wasm-file> [output-file]");
                 process::exit(1);
             }
-            let output_file = if args.len() == 4 {
-                Some(args[3].as_str())
-            } else {
-                None
-            };
-            ir_command(&args[2], output_file)
+            ir_command(positional[0], positional.get(1).copied(), options)
+        }
+        "header" => {
+            if positional.is_empty() || positional.len() > 2 {
+                eprintln!("Usage: header <wasm-file> [output-file]");
+                process::exit(1);
+            }
+            header_command(positional[0], positional.get(1).copied())
+        }
+        "cache" => match positional.as_slice() {
+            ["list"] => cache_list_command(),
+            ["clear"] => cache_clear_command(),
+            ["prune", max_size] => cache_prune_command(cache::parse_size(max_size)?),
+            _ => {
+                eprintln!("Usage: cache list | cache clear | cache prune <max-size>");
+                process::exit(1);
+            }
+        },
+        "loop-ir" => {
+            if positional.is_empty() || positional.len() > 2 {
+                eprintln!("Usage: loop-ir <wasm-file> [output-file]");
+                process::exit(1);
+            }
+            loop_ir_command(positional[0], positional.get(1).copied())
         }
         _ => {
             print_usage();
@@ -49,44 +133,319 @@ fn main() -> Result<()> {
 
 fn print_usage() {
     eprintln!("Usage:");
-    eprintln!("  exec <wasm-file>");
-    eprintln!("  compile <wasm-file> <output-file>");
-    eprintln!("  ir <wasm-file> [output-file]");
+    eprintln!("  exec [options] <wasm-file>");
+    eprintln!("  compile [options] <wasm-file> <output-file>");
+    eprintln!("  ir [options] <wasm-file> [output-file]");
+    eprintln!("  header <wasm-file> [output-file]");
+    eprintln!("  loop-ir <wasm-file> [output-file]");
+    eprintln!("  cache list | cache clear | cache prune <max-size>");
+    eprintln!();
+    eprintln!("Options:");
+    eprintln!("  -O0, -O1, -O2, -O3, -Os  optimization level (default: -O0)");
+    eprintln!("  -g                       emit line info from the module's DWARF line table,");
+    eprintln!("                           or mapping code to Wasm byte offsets without one");
+    eprintln!("  --target <triple>        target triple (compile only, default: host)");
+    eprintln!("  --cpu <name>             target CPU, or `native` for the host CPU (compile only)");
+    eprintln!("  --features <list>        LLVM target features, e.g. +avx2,-sse4.1 (compile only)");
+    eprintln!("  --reloc pic|static       relocation model (compile only)");
+    eprintln!("  --emit <kind>            obj|asm|bc|ll|so|exe (compile only, default: obj)");
+    eprintln!("  --emit-header <file>     also write a C header for the exports (compile only)");
+    eprintln!("  --stable-names           name functions func_N instead of after the name section");
+    eprintln!("  --jobs <n>               threads compiling functions (default: all cores)");
+    eprintln!("  --no-cache               compile from scratch without the cache (exec only)");
+    eprintln!("  --lazy                   compile functions on their first call (exec only)");
+    eprintln!(
+        "  --tier-up <calls>        with --lazy, recompile functions at -O3 after <calls> calls"
+    );
+    eprintln!("                           (loop iterations do not count towards <calls>)");
+    eprintln!(
+        "  --fuel <n>               trap after <n> function entries and loop iterations (exec only)"
+    );
+    eprintln!("  --timeout <duration>     trap after running for e.g. 5s or 500ms (exec only)");
+    eprintln!(
+        "  --max-stack <size>       trap when recursion uses more stack, e.g. 512K (exec only)"
+    );
+    eprintln!("  --perf-map               write /tmp/perf-<pid>.map for `perf report` (exec only)");
+    eprintln!(
+        "  --profile                count calls and loop iterations and report them (exec only)"
+    );
+    eprintln!();
+    eprintln!(
+        "exec caches compiled modules in ${} (default: ~/.cache/auto-parallel-wasm).",
+        cache::CACHE_DIR_VAR
+    );
+    eprintln!("cache prune takes a size such as 500M and drops the least recently used entries.");
 }
 
-fn exec_command(wasm_file: &str) -> Result<()> {
-    let wasm_bytes = fs::read(wasm_file)?;
-    let wasm_module = WasmModule::parse(&wasm_bytes)?;
+fn parse_command_line(args: &[String]) -> Result<CommandLine<'_>> {
+    let mut cli = CommandLine {
+        positional: Vec::new(),
+        options: CompilerOptions::default(),
+        emit: None,
+        emit_header: None,
+        no_cache: false,
+        limits: ExecutionLimits::default(),
+    };
+    let options = &mut cli.options;
 
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        if let Some(level) = arg.strip_prefix("-O") {
+            options.opt_level = level.parse()?;
+        } else if arg == "-g" {
+            options.debug_info = true;
+        } else if arg.starts_with("--") {
+            let (name, inline_value) = match arg.split_once('=') {
+                Some((name, value)) => (name, Some(value.to_string())),
+                None => (arg.as_str(), None),
+            };
+            let mut value = || {
+                inline_value
+                    .clone()
+                    .or_else(|| iter.next().cloned())
+                    .ok_or_else(|| anyhow!("Missing value for {}", name))
+            };
+            match name {
+                "--target" => options.target = Some(value()?),
+                "--cpu" => options.cpu = Some(value()?),
+                "--features" => options.features = Some(value()?),
+                "--reloc" => options.reloc = value()?.parse()?,
+                "--emit" => cli.emit = Some(value()?.parse()?),
+                "--emit-header" => cli.emit_header = Some(value()?),
+                "--stable-names" => options.stable_names = true,
+                "--no-cache" => cli.no_cache = true,
+                "--jobs" => {
+                    let jobs = value()?;
+                    options.jobs = Some(
+                        jobs.parse()
+                            .ok()
+                            .filter(|&n| n > 0)
+                            .ok_or(anyhow!("Invalid number of jobs: {}", jobs))?,
+                    );
+                }
+                "--lazy" => options.lazy = true,
+                "--tier-up" => {
+                    let calls = value()?;
+                    options.lazy = true;
+                    options.tier_up_calls = Some(
+                        calls
+                            .parse()
+                            .ok()
+                            .filter(|&n| n > 0)
+                            .ok_or(anyhow!("Invalid number of calls: {}", calls))?,
+                    );
+                }
+                "--fuel" => {
+                    let fuel = value()?;
+                    options.consume_fuel = true;
+                    cli.limits.fuel = Some(
+                        fuel.parse()
+                            .map_err(|_| anyhow!("Invalid amount of fuel: {}", fuel))?,
+                    );
+                }
+                "--timeout" => {
+                    options.interruptible = true;
+                    cli.limits.timeout = Some(parse_duration(&value()?)?);
+                }
+                "--max-stack" => {
+                    options.check_stack = true;
+                    cli.limits.max_stack = Some(cache::parse_size(&value()?)? as usize);
+                }
+                "--perf-map" => options.perf_map = true,
+                "--profile" => options.profile = true,
+                _ => return Err(anyhow!("Unknown option: {}", arg)),
+            }
+        } else if arg.starts_with('-') {
+            return Err(anyhow!("Unknown option: {}", arg));
+        } else {
+            cli.positional.push(arg.as_str());
+        }
+    }
+
+    Ok(cli)
+}
+
+fn exec_command(
+    wasm_file: &str,
+    options: CompilerOptions,
+    limits: ExecutionLimits,
+    no_cache: bool,
+) -> Result<()> {
+    let wasm_bytes = fs::read(wasm_file)?;
     let context = Context::create();
-    let mut compiler = Compiler::new(&context, "wasm_aot")?;
 
-    compiler.compile_module(&wasm_module)?;
+    if options.lazy {
+        let wasm_module = WasmModule::parse(&wasm_bytes)?;
+        let mut compiler = Compiler::with_options(&context, "wasm_aot", options)?;
+        compiler.compile_module(&wasm_module)?;
+        let exit_code = compiler.run_main_lazy(&wasm_module, &limits)?;
+        process::exit(exit_code);
+    }
+
+    let cache = if no_cache {
+        None
+    } else {
+        Cache::open_default()
+            .map_err(|e| eprintln!("Compilation cache disabled: {e}"))
+            .ok()
+    };
+    let key = CacheKey::new(&wasm_bytes, &options);
 
-    let exit_code = compiler.run_main()?;
-    process::exit(exit_code);
+    let cached = cache
+        .as_ref()
+        .and_then(|cache| cache.get(key))
+        .and_then(|bitcode| {
+            Compiler::from_bitcode(&context, "wasm_aot", &bitcode, options.clone())
+                .map_err(|e| eprintln!("Ignoring cache entry {key}: {e}"))
+                .ok()
+        });
+    let compiler = match cached {
+        Some(compiler) => compiler,
+        None => {
+            let wasm_module = WasmModule::parse(&wasm_bytes)?;
+            let mut compiler = Compiler::with_options(&context, "wasm_aot", options)?;
+            compiler.compile_module(&wasm_module)?;
+            if let Some(cache) = &cache
+                && let Err(e) = cache.put(key, &compiler.to_bitcode())
+            {
+                eprintln!("{e}");
+            }
+            compiler
+        }
+    };
+
+    if compiler.options().perf_map {
+        let path = compiler.write_perf_map()?;
+        eprintln!("perf map: {}", path.display());
+    }
+    let result = compiler.run_main_with_limits(&limits);
+    if compiler.options().profile {
+        profile::write_report(&mut io::stderr().lock(), &compiler.profile())?;
+    }
+    process::exit(result?);
 }
 
-fn compile_command(wasm_file: &str, output_file: &str) -> Result<()> {
+fn cache_list_command() -> Result<()> {
+    let cache = Cache::open_default()?;
+    let entries = cache.entries()?;
+    let now = SystemTime::now();
+    for entry in &entries {
+        let age = now
+            .duration_since(entry.last_used)
+            .unwrap_or_default()
+            .as_secs();
+        println!(
+            "{}  {:>10} bytes  last used {}s ago",
+            entry.key, entry.size, age
+        );
+    }
+    let total: u64 = entries.iter().map(|entry| entry.size).sum();
+    println!(
+        "{} entries, {} bytes in {}",
+        entries.len(),
+        total,
+        cache.dir().display()
+    );
+    Ok(())
+}
+
+fn cache_clear_command() -> Result<()> {
+    let cache = Cache::open_default()?;
+    let removed = cache.clear()?;
+    println!("Removed {removed} entries from {}", cache.dir().display());
+    Ok(())
+}
+
+fn cache_prune_command(max_size: u64) -> Result<()> {
+    let cache = Cache::open_default()?;
+    let removed = cache.prune(max_size)?;
+    let freed: u64 = removed.iter().map(|entry| entry.size).sum();
+    println!(
+        "Removed {} entries ({} bytes) from {}",
+        removed.len(),
+        freed,
+        cache.dir().display()
+    );
+    Ok(())
+}
+
+fn compile_command(
+    wasm_file: &str,
+    output_file: &str,
+    options: CompilerOptions,
+    emit: EmitKind,
+    header_file: Option<&str>,
+) -> Result<()> {
     let wasm_bytes = fs::read(wasm_file)?;
     let wasm_module = WasmModule::parse(&wasm_bytes)?;
 
     let context = Context::create();
-    let mut compiler = Compiler::new(&context, "wasm_aot")?;
+    let mut compiler = Compiler::with_options(&context, "wasm_aot", options)?;
 
     compiler.compile_module(&wasm_module)?;
-    compiler.write_object_file(output_file)?;
+    compiler.write_output(output_file, emit)?;
 
     println!("Compiled to: {output_file}");
+
+    if let Some(header_file) = header_file {
+        write_header(&wasm_module, header_file)?;
+        println!("C header written to: {header_file}");
+    }
+    Ok(())
+}
+
+fn header_command(wasm_file: &str, output_file: Option<&str>) -> Result<()> {
+    let wasm_bytes = fs::read(wasm_file)?;
+    let wasm_module = WasmModule::parse(&wasm_bytes)?;
+
+    match output_file {
+        Some(file) => {
+            write_header(&wasm_module, file)?;
+            println!("C header written to: {file}");
+        }
+        None => {
+            let stem = Path::new(wasm_file)
+                .file_stem()
+                .map(|s| s.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            print!("{}", generate_header(&wasm_module, &format!("{stem}.h"))?);
+        }
+    }
+    Ok(())
+}
+
+fn loop_ir_command(wasm_file: &str, output_file: Option<&str>) -> Result<()> {
+    let wasm_bytes = fs::read(wasm_file)?;
+    let wasm_module = WasmModule::parse(&wasm_bytes)?;
+
+    let functions = lower_module(&wasm_module)?;
+    let text: String = functions.iter().map(|f| format!("{f}\n")).collect();
+
+    match output_file {
+        Some(file) => {
+            fs::write(file, text)?;
+            println!("Loop IR written to: {file}");
+        }
+        None => print!("{text}"),
+    }
+    Ok(())
+}
+
+fn write_header(wasm_module: &WasmModule, header_file: &str) -> Result<()> {
+    let header_name = Path::new(header_file)
+        .file_name()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    fs::write(header_file, generate_header(wasm_module, &header_name)?)?;
     Ok(())
 }
 
-fn ir_command(wasm_file: &str, output_file: Option<&str>) -> Result<()> {
+fn ir_command(wasm_file: &str, output_file: Option<&str>, options: CompilerOptions) -> Result<()> {
     let wasm_bytes = fs::read(wasm_file)?;
     let wasm_module = WasmModule::parse(&wasm_bytes)?;
 
     let context = Context::create();
-    let mut compiler = Compiler::new(&context, "wasm_aot")?;
+    let mut compiler = Compiler::with_options(&context, "wasm_aot", options)?;
 
     compiler.compile_module(&wasm_module)?;
 
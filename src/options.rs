@@ -0,0 +1,195 @@
+use std::str::FromStr;
+
+use anyhow::{Result, anyhow};
+use inkwell::OptimizationLevel;
+use inkwell::targets::RelocMode;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OptLevel {
+    #[default]
+    O0,
+    O1,
+    O2,
+    O3,
+    Os,
+}
+
+impl OptLevel {
+    pub fn codegen_level(self) -> OptimizationLevel {
+        match self {
+            OptLevel::O0 => OptimizationLevel::None,
+            OptLevel::O1 => OptimizationLevel::Less,
+            OptLevel::O2 | OptLevel::Os => OptimizationLevel::Default,
+            OptLevel::O3 => OptimizationLevel::Aggressive,
+        }
+    }
+
+    /// Pipeline description understood by the LLVM new pass manager.
+    pub fn pass_pipeline(self) -> &'static str {
+        match self {
+            OptLevel::O0 => "default<O0>",
+            OptLevel::O1 => "default<O1>",
+            OptLevel::O2 => "default<O2>",
+            OptLevel::O3 => "default<O3>",
+            OptLevel::Os => "default<Os>",
+        }
+    }
+}
+
+impl FromStr for OptLevel {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "0" => Ok(OptLevel::O0),
+            "1" => Ok(OptLevel::O1),
+            "2" => Ok(OptLevel::O2),
+            "3" => Ok(OptLevel::O3),
+            "s" => Ok(OptLevel::Os),
+            _ => Err(anyhow!("Unknown optimization level: -O{}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RelocModel {
+    #[default]
+    Default,
+    Pic,
+    Static,
+}
+
+impl RelocModel {
+    pub fn reloc_mode(self) -> RelocMode {
+        match self {
+            RelocModel::Default => RelocMode::Default,
+            RelocModel::Pic => RelocMode::PIC,
+            RelocModel::Static => RelocMode::Static,
+        }
+    }
+}
+
+impl FromStr for RelocModel {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "default" => Ok(RelocModel::Default),
+            "pic" => Ok(RelocModel::Pic),
+            "static" => Ok(RelocModel::Static),
+            _ => Err(anyhow!("Unknown relocation model: {}", s)),
+        }
+    }
+}
+
+/// Output produced by `Compiler::write_output`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EmitKind {
+    #[default]
+    Obj,
+    Asm,
+    Bc,
+    Ll,
+    So,
+    Exe,
+}
+
+impl FromStr for EmitKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "obj" => Ok(EmitKind::Obj),
+            "asm" => Ok(EmitKind::Asm),
+            "bc" => Ok(EmitKind::Bc),
+            "ll" => Ok(EmitKind::Ll),
+            "so" => Ok(EmitKind::So),
+            "exe" => Ok(EmitKind::Exe),
+            _ => Err(anyhow!("Unknown emit kind: {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CompilerOptions {
+    pub opt_level: OptLevel,
+    /// Target triple for AOT output; the host triple when unset.
+    pub target: Option<String>,
+    /// CPU name passed to the target machine, or `native` for the host CPU.
+    pub cpu: Option<String>,
+    /// LLVM feature string such as `+avx2,-sse4.1`.
+    pub features: Option<String>,
+    pub reloc: RelocModel,
+    /// Emit debug locations: source lines from the module's DWARF line
+    /// table, or Wasm byte offsets for modules without one.
+    pub debug_info: bool,
+    /// Name functions `func_N` and values generically instead of after the
+    /// name section and exports, so the IR is independent of the producer.
+    pub stable_names: bool,
+    /// Threads compiling function bodies of large modules; all available
+    /// cores when unset. The output does not depend on it.
+    pub jobs: Option<usize>,
+    /// Compile each function on its first call instead of ahead of running.
+    pub lazy: bool,
+    /// With `lazy`, recompile a function at `-O3` once it has been called
+    /// this many times. Only calls count, not loop iterations, so a hot loop
+    /// in a function called once stays at the first tier.
+    pub tier_up_calls: Option<u32>,
+    /// Count down fuel at function entries and loop headers and trap once
+    /// it runs out; see `crate::limits`.
+    pub consume_fuel: bool,
+    /// Poll an interrupt flag at function entries and loop headers so that
+    /// a watchdog thread can stop the module.
+    pub interruptible: bool,
+    /// Check the native stack pointer against a limit in function
+    /// prologues and trap instead of overflowing the stack.
+    pub check_stack: bool,
+    /// Allow `Compiler::write_perf_map` to list JIT-compiled functions for
+    /// `perf`.
+    pub perf_map: bool,
+    /// Count calls of every function and iterations of every loop; see
+    /// `Compiler::profile`.
+    pub profile: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_opt_levels() {
+        assert_eq!("0".parse::<OptLevel>().unwrap(), OptLevel::O0);
+        assert_eq!("1".parse::<OptLevel>().unwrap(), OptLevel::O1);
+        assert_eq!("2".parse::<OptLevel>().unwrap(), OptLevel::O2);
+        assert_eq!("3".parse::<OptLevel>().unwrap(), OptLevel::O3);
+        assert_eq!("s".parse::<OptLevel>().unwrap(), OptLevel::Os);
+        assert!("4".parse::<OptLevel>().is_err());
+        assert!("z".parse::<OptLevel>().is_err());
+    }
+
+    #[test]
+    fn test_parse_reloc_models() {
+        assert_eq!("pic".parse::<RelocModel>().unwrap(), RelocModel::Pic);
+        assert_eq!("static".parse::<RelocModel>().unwrap(), RelocModel::Static);
+        assert_eq!(RelocModel::Pic.reloc_mode(), RelocMode::PIC);
+        assert!("dynamic-no-pic".parse::<RelocModel>().is_err());
+    }
+
+    #[test]
+    fn test_parse_emit_kinds() {
+        assert_eq!("obj".parse::<EmitKind>().unwrap(), EmitKind::Obj);
+        assert_eq!("asm".parse::<EmitKind>().unwrap(), EmitKind::Asm);
+        assert_eq!("bc".parse::<EmitKind>().unwrap(), EmitKind::Bc);
+        assert_eq!("ll".parse::<EmitKind>().unwrap(), EmitKind::Ll);
+        assert_eq!("so".parse::<EmitKind>().unwrap(), EmitKind::So);
+        assert_eq!("exe".parse::<EmitKind>().unwrap(), EmitKind::Exe);
+        assert!("dll".parse::<EmitKind>().is_err());
+    }
+
+    #[test]
+    fn test_default_options_are_unoptimized() {
+        let options = CompilerOptions::default();
+        assert_eq!(options.opt_level, OptLevel::O0);
+        assert_eq!(options.opt_level.codegen_level(), OptimizationLevel::None);
+    }
+}
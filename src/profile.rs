@@ -0,0 +1,222 @@
+//! Profiling support for JIT-compiled code.
+//!
+//! With `CompilerOptions::perf_map`, `Compiler::write_perf_map` writes the
+//! address range of every JIT-compiled function to `/tmp/perf-<pid>.map`,
+//! where `perf report` looks up symbols of anonymous executable memory. The
+//! ranges come from the symbol table of the object the JIT loaded, which it
+//! registers for debuggers with its sections at their load addresses.
+//!
+//! With `CompilerOptions::profile`, every function counts its calls and
+//! the iterations of each of its loops in a counter array owned by the host,
+//! which `Compiler::profile` reads back as [`ProfileEntry`]s.
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::{ptr, slice};
+
+use anyhow::{Result, anyhow};
+use inkwell::memory_buffer::MemoryBuffer;
+
+/// Suffix of the counter array of a function, after its symbol. Element 0
+/// counts calls and element `k` the iterations of the `k`-th loop.
+pub(crate) const COUNTERS_SUFFIX: &str = ".profile";
+/// Suffix of the constant array holding the Wasm byte offset of each loop
+/// of a function, after its symbol.
+pub(crate) const LOOP_OFFSETS_SUFFIX: &str = ".profile_loops";
+
+/// Counters of one function, mapped into the JIT-compiled code.
+pub(crate) struct FunctionCounters {
+    pub function: String,
+    pub loop_offsets: Vec<u64>,
+    pub counts: Box<[AtomicU64]>,
+}
+
+impl FunctionCounters {
+    pub fn new(function: String, loop_offsets: Vec<u64>) -> Self {
+        let counts = (0..=loop_offsets.len())
+            .map(|_| AtomicU64::new(0))
+            .collect();
+        Self {
+            function,
+            loop_offsets,
+            counts,
+        }
+    }
+
+    pub fn address(&self) -> usize {
+        self.counts.as_ptr() as usize
+    }
+
+    fn entries(&self) -> impl Iterator<Item = ProfileEntry> + '_ {
+        let loops = self.loop_offsets.iter().map(|&offset| Some(offset));
+        std::iter::once(None)
+            .chain(loops)
+            .zip(self.counts.iter())
+            .map(|(loop_offset, count)| ProfileEntry {
+                function: self.function.clone(),
+                loop_offset,
+                count: count.load(Ordering::Relaxed),
+            })
+    }
+}
+
+/// How often a function was called, or how often one of its loops iterated.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProfileEntry {
+    pub function: String,
+    /// Wasm byte offset of the loop, or `None` for calls of the function.
+    pub loop_offset: Option<u64>,
+    pub count: u64,
+}
+
+impl fmt::Display for ProfileEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.loop_offset {
+            Some(offset) => write!(f, "{}: loop at {:#x}", self.function, offset),
+            None => write!(f, "{}: calls", self.function),
+        }
+    }
+}
+
+/// The non-zero counts of `counters`, highest first.
+pub(crate) fn collect_entries(counters: &[FunctionCounters]) -> Vec<ProfileEntry> {
+    let mut entries: Vec<ProfileEntry> = counters
+        .iter()
+        .flat_map(FunctionCounters::entries)
+        .filter(|entry| entry.count > 0)
+        .collect();
+    entries.sort_by(|a, b| {
+        b.count
+            .cmp(&a.count)
+            .then_with(|| a.function.cmp(&b.function))
+            .then_with(|| a.loop_offset.cmp(&b.loop_offset))
+    });
+    entries
+}
+
+/// Writes `entries` as a table, as printed by `exec --profile`.
+pub fn write_report(out: &mut impl Write, entries: &[ProfileEntry]) -> io::Result<()> {
+    writeln!(out, "{:>16}  location", "count")?;
+    for entry in entries {
+        writeln!(out, "{:>16}  {}", entry.count, entry)?;
+    }
+    Ok(())
+}
+
+/// An entry of GDB's JIT interface: an object file in memory.
+#[repr(C)]
+struct JitCodeEntry {
+    next: *const JitCodeEntry,
+    prev: *const JitCodeEntry,
+    symfile_addr: *const u8,
+    symfile_size: u64,
+}
+
+/// The list of objects registered through GDB's JIT interface.
+#[repr(C)]
+struct JitDescriptor {
+    version: u32,
+    action_flag: u32,
+    relevant_entry: *const JitCodeEntry,
+    first_entry: *const JitCodeEntry,
+}
+
+unsafe extern "C" {
+    /// Defined by LLVM's GDB registration listener, which MCJIT notifies of
+    /// every object it loads.
+    #[allow(non_upper_case_globals)]
+    static mut __jit_debug_descriptor: JitDescriptor;
+}
+
+/// A symbol of JIT-compiled code.
+pub(crate) struct JitFunction {
+    pub address: usize,
+    pub size: usize,
+    pub name: String,
+}
+
+/// The symbols with a size in the object the JIT loaded the symbol at
+/// `address` from. LLVM unregisters objects when an execution engine is
+/// disposed of, which must not happen on another thread meanwhile.
+pub(crate) fn jit_functions(address: usize) -> Result<Vec<JitFunction>> {
+    let mut entry = unsafe { ptr::read_volatile(&raw const __jit_debug_descriptor.first_entry) };
+    while let Some(current) = unsafe { entry.as_ref() } {
+        let contents =
+            unsafe { slice::from_raw_parts(current.symfile_addr, current.symfile_size as usize) };
+        let object = MemoryBuffer::create_from_memory_range(contents, "jit")
+            .create_object_file()
+            .map_err(|()| anyhow!("Failed to read a JIT-compiled object"))?;
+        let symbols: Vec<JitFunction> = object
+            .get_symbols()
+            .filter(|symbol| symbol.size() > 0)
+            .filter_map(|symbol| {
+                Some(JitFunction {
+                    address: symbol.get_address() as usize,
+                    size: symbol.size() as usize,
+                    name: symbol.get_name()?.to_string_lossy().into_owned(),
+                })
+            })
+            .collect();
+        if symbols.iter().any(|symbol| symbol.address == address) {
+            return Ok(symbols);
+        }
+        entry = current.next;
+    }
+    Err(anyhow!("No JIT-compiled object defines {:#x}", address))
+}
+
+/// Writes a perf map for `functions`.
+pub(crate) fn write_perf_map(functions: &[JitFunction]) -> Result<PathBuf> {
+    let path = PathBuf::from(format!("/tmp/perf-{}.map", process::id()));
+    let lines: String = functions
+        .iter()
+        .map(|function| {
+            format!(
+                "{:x} {:x} {}\n",
+                function.address, function.size, function.name
+            )
+        })
+        .collect();
+    File::options()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .and_then(|mut file| file.write_all(lines.as_bytes()))
+        .map_err(|e| anyhow!("Failed to write {}: {}", path.display(), e))?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_entries_are_sorted_by_count() {
+        let fib = FunctionCounters::new("fib".to_string(), vec![0x2a, 0x40]);
+        fib.counts[0].store(10, Ordering::Relaxed);
+        fib.counts[1].store(500, Ordering::Relaxed);
+        let main = FunctionCounters::new("main".to_string(), Vec::new());
+        main.counts[0].store(10, Ordering::Relaxed);
+
+        let entries = collect_entries(&[main, fib]);
+        let lines: Vec<String> = entries.iter().map(ToString::to_string).collect();
+        assert_eq!(lines, ["fib: loop at 0x2a", "fib: calls", "main: calls"]);
+        assert_eq!(entries[0].count, 500);
+
+        let mut report = Vec::new();
+        write_report(&mut report, &entries).unwrap();
+        let report = String::from_utf8(report).unwrap();
+        assert_eq!(report.lines().count(), 4);
+        assert!(
+            report
+                .lines()
+                .nth(1)
+                .unwrap()
+                .ends_with("500  fib: loop at 0x2a")
+        );
+    }
+}
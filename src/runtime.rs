@@ -0,0 +1,190 @@
+//! Host functions behind `memory.atomic.wait32`, `memory.atomic.wait64` and
+//! `memory.atomic.notify` for JIT execution. AOT outputs get the same
+//! functions from `aot_runtime.c`.
+//!
+//! Waiters block on a futex keyed by the address of the waited-on cell, so a
+//! notify only has to wake that address.
+
+use std::sync::atomic::{AtomicI32, AtomicI64, Ordering};
+
+pub(crate) const WAIT32_SYMBOL: &str = "wasm_memory_atomic_wait32";
+pub(crate) const WAIT64_SYMBOL: &str = "wasm_memory_atomic_wait64";
+pub(crate) const NOTIFY_SYMBOL: &str = "wasm_memory_atomic_notify";
+
+const WAIT_OK: i32 = 0;
+const WAIT_NOT_EQUAL: i32 = 1;
+const WAIT_TIMED_OUT: i32 = 2;
+
+/// Blocks until notified if the i32 at `addr` equals `expected`. A negative
+/// `timeout_ns` waits forever.
+pub(crate) extern "C" fn memory_atomic_wait32(
+    addr: *const AtomicI32,
+    expected: i32,
+    timeout_ns: i64,
+) -> i32 {
+    let cell = unsafe { &*addr };
+    if cell.load(Ordering::SeqCst) != expected {
+        return WAIT_NOT_EQUAL;
+    }
+    futex::wait(addr.cast(), expected as u32, timeout_ns)
+}
+
+/// Blocks until notified if the i64 at `addr` equals `expected`. Futexes only
+/// compare 32 bits, so the kernel checks the word at `addr` after the full
+/// comparison here.
+pub(crate) extern "C" fn memory_atomic_wait64(
+    addr: *const AtomicI64,
+    expected: i64,
+    timeout_ns: i64,
+) -> i32 {
+    let cell = unsafe { &*addr };
+    if cell.load(Ordering::SeqCst) != expected {
+        return WAIT_NOT_EQUAL;
+    }
+    let first_word = if cfg!(target_endian = "little") {
+        expected as u32
+    } else {
+        (expected >> 32) as u32
+    };
+    futex::wait(addr.cast(), first_word, timeout_ns)
+}
+
+/// Wakes up to `count` waiters on `addr` and returns how many were woken.
+pub(crate) extern "C" fn memory_atomic_notify(addr: *const AtomicI32, count: u32) -> u32 {
+    futex::wake(addr.cast(), count)
+}
+
+#[cfg(target_os = "linux")]
+mod futex {
+    use super::{WAIT_NOT_EQUAL, WAIT_OK, WAIT_TIMED_OUT};
+    use std::time::{Duration, Instant};
+
+    pub fn wait(word: *const u32, expected: u32, timeout_ns: i64) -> i32 {
+        let deadline =
+            (timeout_ns >= 0).then(|| Instant::now() + Duration::from_nanos(timeout_ns as u64));
+        loop {
+            // A signal interrupts the wait, so restart it with whatever is
+            // left of the timeout.
+            let timeout = deadline.map(|deadline| {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                libc::timespec {
+                    tv_sec: remaining.as_secs() as _,
+                    tv_nsec: remaining.subsec_nanos() as _,
+                }
+            });
+            let timeout_ptr = timeout
+                .as_ref()
+                .map_or(std::ptr::null(), |t| t as *const libc::timespec);
+            let result = unsafe {
+                libc::syscall(
+                    libc::SYS_futex,
+                    word,
+                    libc::FUTEX_WAIT | libc::FUTEX_PRIVATE_FLAG,
+                    expected,
+                    timeout_ptr,
+                )
+            };
+            if result == 0 {
+                return WAIT_OK;
+            }
+            return match std::io::Error::last_os_error().raw_os_error() {
+                Some(libc::EINTR) => continue,
+                Some(libc::EAGAIN) => WAIT_NOT_EQUAL,
+                Some(libc::ETIMEDOUT) => WAIT_TIMED_OUT,
+                _ => WAIT_OK,
+            };
+        }
+    }
+
+    pub fn wake(word: *const u32, count: u32) -> u32 {
+        let count = count.min(i32::MAX as u32) as i32;
+        let woken = unsafe {
+            libc::syscall(
+                libc::SYS_futex,
+                word,
+                libc::FUTEX_WAKE | libc::FUTEX_PRIVATE_FLAG,
+                count,
+            )
+        };
+        woken.max(0) as u32
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod futex {
+    pub fn wait(_word: *const u32, _expected: u32, _timeout_ns: i64) -> i32 {
+        eprintln!("memory.atomic.wait is only supported on Linux");
+        std::process::abort();
+    }
+
+    pub fn wake(_word: *const u32, _count: u32) -> u32 {
+        0
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_wait_not_equal_and_timeout() {
+        let cell = AtomicI32::new(5);
+        assert_eq!(memory_atomic_wait32(&cell, 4, -1), WAIT_NOT_EQUAL);
+        assert_eq!(memory_atomic_wait32(&cell, 5, 1_000_000), WAIT_TIMED_OUT);
+
+        let wide = AtomicI64::new(1 << 40);
+        assert_eq!(memory_atomic_wait64(&wide, 0, -1), WAIT_NOT_EQUAL);
+        assert_eq!(
+            memory_atomic_wait64(&wide, 1 << 40, 1_000_000),
+            WAIT_TIMED_OUT
+        );
+    }
+
+    #[test]
+    fn test_notify_wakes_waiter() {
+        let cell = Arc::new(AtomicI32::new(0));
+        let waiter = {
+            let cell = Arc::clone(&cell);
+            thread::spawn(move || memory_atomic_wait32(&*cell, 0, -1))
+        };
+
+        let mut woken = 0;
+        while woken == 0 {
+            thread::sleep(Duration::from_millis(1));
+            woken = memory_atomic_notify(&*cell, 1);
+        }
+        assert_eq!(woken, 1);
+        assert_eq!(waiter.join().unwrap(), WAIT_OK);
+    }
+
+    #[test]
+    fn test_signal_does_not_end_wait_early() {
+        extern "C" fn ignore(_: libc::c_int) {}
+        unsafe {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = ignore as extern "C" fn(libc::c_int) as usize;
+            libc::sigaction(libc::SIGUSR1, &action, std::ptr::null_mut());
+        }
+
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let waiter = thread::spawn(move || {
+            sender.send(unsafe { libc::pthread_self() }).unwrap();
+            let cell = AtomicI32::new(0);
+            let start = std::time::Instant::now();
+            let result = memory_atomic_wait32(&cell, 0, 200_000_000);
+            (result, start.elapsed())
+        });
+        let thread_id = receiver.recv().unwrap();
+        for _ in 0..5 {
+            thread::sleep(Duration::from_millis(10));
+            unsafe { libc::pthread_kill(thread_id, libc::SIGUSR1) };
+        }
+
+        let (result, elapsed) = waiter.join().unwrap();
+        assert_eq!(result, WAIT_TIMED_OUT);
+        assert!(elapsed >= Duration::from_millis(200), "{elapsed:?}");
+    }
+}
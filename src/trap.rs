@@ -0,0 +1,107 @@
+//! Traps of Wasm code compiled for the embedding API, lazily or with
+//! execution limits.
+//!
+//! Such code does not abort on a trap: it stores the trap code in the vmctx
+//! and returns, every caller returns in turn, and the host reports the trap
+//! once control is back in Rust.
+
+use std::fmt;
+
+use anyhow::{Result, anyhow};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trap {
+    Unreachable = 1,
+    TableOutOfBounds,
+    BadIndirectCall,
+    MemoryOutOfBounds,
+    UnalignedAtomic,
+    WaitOnUnsharedMemory,
+    OutOfFuel,
+    Interrupted,
+    StackOverflow,
+    CompileFailed,
+    IntegerDivideByZero,
+    IntegerOverflow,
+    InvalidConversionToInteger,
+}
+
+impl Trap {
+    pub(crate) fn code(self) -> u32 {
+        self as u32
+    }
+
+    pub(crate) fn from_code(code: u32) -> Option<Self> {
+        [
+            Trap::Unreachable,
+            Trap::TableOutOfBounds,
+            Trap::BadIndirectCall,
+            Trap::MemoryOutOfBounds,
+            Trap::UnalignedAtomic,
+            Trap::WaitOnUnsharedMemory,
+            Trap::OutOfFuel,
+            Trap::Interrupted,
+            Trap::StackOverflow,
+            Trap::CompileFailed,
+            Trap::IntegerDivideByZero,
+            Trap::IntegerOverflow,
+            Trap::InvalidConversionToInteger,
+        ]
+        .into_iter()
+        .find(|trap| trap.code() == code)
+    }
+
+    /// The outcome of a call that left `code` in the trap slot.
+    pub(crate) fn check_code(code: u32) -> Result<()> {
+        match code {
+            0 => Ok(()),
+            code => Err(Trap::from_code(code)
+                .map_or_else(|| anyhow!("Unknown trap code: {}", code), Into::into)),
+        }
+    }
+}
+
+impl fmt::Display for Trap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let message = match self {
+            Trap::Unreachable => "unreachable executed",
+            Trap::TableOutOfBounds => "table index out of bounds",
+            Trap::BadIndirectCall => "indirect call to a missing or mismatched function",
+            Trap::MemoryOutOfBounds => "memory access out of bounds",
+            Trap::UnalignedAtomic => "unaligned atomic access",
+            Trap::WaitOnUnsharedMemory => "atomic wait on unshared memory",
+            Trap::OutOfFuel => "all fuel consumed",
+            Trap::Interrupted => "execution interrupted",
+            Trap::StackOverflow => "call stack exhausted",
+            Trap::CompileFailed => "function failed to compile",
+            Trap::IntegerDivideByZero => "integer divide by zero",
+            Trap::IntegerOverflow => "integer overflow",
+            Trap::InvalidConversionToInteger => "invalid conversion to integer",
+        };
+        write!(f, "wasm trap: {message}")
+    }
+}
+
+impl std::error::Error for Trap {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_codes_round_trip() {
+        for code in 1..=13 {
+            assert_eq!(Trap::from_code(code).unwrap().code(), code);
+        }
+        assert_eq!(Trap::from_code(0), None);
+        assert_eq!(Trap::from_code(14), None);
+    }
+
+    #[test]
+    fn test_check_code() {
+        assert!(Trap::check_code(0).is_ok());
+        let error = Trap::check_code(Trap::OutOfFuel.code()).unwrap_err();
+        assert_eq!(error.downcast_ref::<Trap>(), Some(&Trap::OutOfFuel));
+        assert!(Trap::check_code(100).is_err());
+    }
+}
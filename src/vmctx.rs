@@ -0,0 +1,200 @@
+/// Byte layout of the per-instance context (`vmctx`) that every generated
+/// function receives as its first argument.
+///
+/// ```text
+/// 0                     memory base pointer
+/// 8                     memory size in bytes (i64)
+/// 16 + 16 * t           table t: element pointer, then element count (i32)
+/// 16 + 16 * T + 8 * g   global g, one 8-byte slot per global
+/// ... + 8 * G + 8 * e    remaining length of passive element segment e (i32)
+/// ... + 8 * E + 8 * i    host function bound to function import i
+/// ... + 8 * I            code of the pending trap, or 0 (i32)
+/// ... + 8                remaining fuel (i64), with fuel metering
+/// ... + 8                pointer to the interrupt flag (i32), if interruptible
+/// ... + 8                lowest stack address Wasm code may use, with stack
+///                        checks
+/// ```
+///
+/// Host function slots only exist in modules compiled for the embedding API;
+/// elsewhere calls to imports other than the assertion helpers are dropped.
+/// The trap slot exists there and in modules compiled with execution limits;
+/// elsewhere traps are `unreachable`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VmctxLayout {
+    num_tables: u32,
+    num_globals: u32,
+    num_passive_segments: u32,
+    num_host_imports: u32,
+    has_trap: bool,
+    has_fuel: bool,
+    has_interrupt: bool,
+    has_stack_limit: bool,
+}
+
+impl VmctxLayout {
+    pub const MEMORY_BASE: u32 = 0;
+    pub const MEMORY_SIZE: u32 = 8;
+    const TABLES_START: u32 = 16;
+    const TABLE_STRIDE: u32 = 16;
+    const GLOBAL_STRIDE: u32 = 8;
+    const SEGMENT_STRIDE: u32 = 8;
+    const HOST_IMPORT_STRIDE: u32 = 8;
+    const TRAP_SIZE: u32 = 8;
+    const FUEL_SIZE: u32 = 8;
+    const INTERRUPT_SIZE: u32 = 8;
+    const STACK_LIMIT_SIZE: u32 = 8;
+
+    /// A layout with `host_imports` host function slots followed by the trap
+    /// slot, or neither if `None`.
+    pub fn new(
+        num_tables: usize,
+        num_globals: usize,
+        num_passive_segments: usize,
+        host_imports: Option<usize>,
+    ) -> Self {
+        Self {
+            num_tables: num_tables as u32,
+            num_globals: num_globals as u32,
+            num_passive_segments: num_passive_segments as u32,
+            num_host_imports: host_imports.unwrap_or(0) as u32,
+            has_trap: host_imports.is_some(),
+            has_fuel: false,
+            has_interrupt: false,
+            has_stack_limit: false,
+        }
+    }
+
+    /// Adds the fuel, interrupt and stack limit slots after the trap slot,
+    /// which must exist.
+    pub fn with_limits(self, fuel: bool, interrupt: bool, stack_limit: bool) -> Self {
+        debug_assert!(self.has_trap || !(fuel || interrupt || stack_limit));
+        Self {
+            has_fuel: fuel,
+            has_interrupt: interrupt,
+            has_stack_limit: stack_limit,
+            ..self
+        }
+    }
+
+    pub fn table_base(&self, table_index: u32) -> u32 {
+        Self::TABLES_START + Self::TABLE_STRIDE * table_index
+    }
+
+    pub fn table_size(&self, table_index: u32) -> u32 {
+        self.table_base(table_index) + 8
+    }
+
+    pub fn global(&self, global_index: u32) -> u32 {
+        self.globals_start() + Self::GLOBAL_STRIDE * global_index
+    }
+
+    /// Slot of the `passive_index`-th passive element segment.
+    pub fn element_segment(&self, passive_index: u32) -> u32 {
+        self.segments_start() + Self::SEGMENT_STRIDE * passive_index
+    }
+
+    /// Slot holding the host function of function import `import_index`.
+    pub fn host_import(&self, import_index: u32) -> u32 {
+        self.host_imports_start() + Self::HOST_IMPORT_STRIDE * import_index
+    }
+
+    /// Slot of the pending trap, in layouts that have one.
+    pub fn trap(&self) -> u32 {
+        self.host_imports_start() + Self::HOST_IMPORT_STRIDE * self.num_host_imports
+    }
+
+    /// Slot of the remaining fuel, with fuel metering.
+    pub fn fuel(&self) -> u32 {
+        self.trap() + Self::TRAP_SIZE
+    }
+
+    /// Slot of the pointer to the interrupt flag, in interruptible layouts.
+    pub fn interrupt(&self) -> u32 {
+        self.fuel() + if self.has_fuel { Self::FUEL_SIZE } else { 0 }
+    }
+
+    /// Slot of the stack limit, with stack checks.
+    pub fn stack_limit(&self) -> u32 {
+        self.interrupt()
+            + if self.has_interrupt {
+                Self::INTERRUPT_SIZE
+            } else {
+                0
+            }
+    }
+
+    fn globals_start(&self) -> u32 {
+        Self::TABLES_START + Self::TABLE_STRIDE * self.num_tables
+    }
+
+    fn segments_start(&self) -> u32 {
+        self.globals_start() + Self::GLOBAL_STRIDE * self.num_globals
+    }
+
+    fn host_imports_start(&self) -> u32 {
+        self.segments_start() + Self::SEGMENT_STRIDE * self.num_passive_segments
+    }
+
+    pub fn size(&self) -> u32 {
+        if !self.has_trap {
+            self.host_imports_start()
+        } else if self.has_stack_limit {
+            self.stack_limit() + Self::STACK_LIMIT_SIZE
+        } else {
+            self.stack_limit()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_layout_without_tables_or_globals() {
+        let layout = VmctxLayout::new(0, 0, 0, None);
+        assert_eq!(layout.size(), 16);
+    }
+
+    #[test]
+    fn test_layout_offsets() {
+        let layout = VmctxLayout::new(2, 3, 2, None);
+        assert_eq!(layout.table_base(0), 16);
+        assert_eq!(layout.table_size(0), 24);
+        assert_eq!(layout.table_base(1), 32);
+        assert_eq!(layout.global(0), 48);
+        assert_eq!(layout.global(2), 64);
+        assert_eq!(layout.element_segment(0), 72);
+        assert_eq!(layout.element_segment(1), 80);
+        assert_eq!(layout.size(), 88);
+    }
+
+    #[test]
+    fn test_embedded_layout() {
+        let layout = VmctxLayout::new(1, 1, 1, Some(2));
+        assert_eq!(layout.element_segment(0), 40);
+        assert_eq!(layout.host_import(0), 48);
+        assert_eq!(layout.host_import(1), 56);
+        assert_eq!(layout.trap(), 64);
+        assert_eq!(layout.size(), 72);
+    }
+
+    #[test]
+    fn test_layout_with_limits() {
+        let layout = VmctxLayout::new(0, 1, 0, Some(0)).with_limits(true, true, true);
+        assert_eq!(layout.trap(), 24);
+        assert_eq!(layout.fuel(), 32);
+        assert_eq!(layout.interrupt(), 40);
+        assert_eq!(layout.stack_limit(), 48);
+        assert_eq!(layout.size(), 56);
+
+        let layout = VmctxLayout::new(0, 1, 0, Some(0)).with_limits(false, true, false);
+        assert_eq!(layout.interrupt(), 32);
+        assert_eq!(layout.size(), 40);
+        let layout = VmctxLayout::new(0, 1, 0, Some(0)).with_limits(true, false, false);
+        assert_eq!(layout.size(), 40);
+        let layout = VmctxLayout::new(0, 1, 0, Some(0)).with_limits(false, false, true);
+        assert_eq!(layout.stack_limit(), 32);
+        assert_eq!(layout.size(), 40);
+    }
+}
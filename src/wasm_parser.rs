@@ -1,8 +1,13 @@
-use anyhow::Result;
+use std::collections::HashMap;
+
+use anyhow::{Result, anyhow};
 use wasmparser::{
-    FuncType, GlobalType, MemoryType, Operator, Parser, Payload, TableType, TypeRef, ValType,
+    ExternalKind, FuncToValidate, FuncType, GlobalType, KnownCustom, MemoryType, Name, NameMap,
+    NameSectionReader, Operator, Parser, Payload, TableType, TypeRef, ValType, ValidPayload,
+    Validator, ValidatorResources,
 };
 
+#[derive(Default)]
 pub struct WasmModule {
     pub functions: Vec<Function>,
     pub start_func_idx: Option<u32>,
@@ -10,16 +15,68 @@ pub struct WasmModule {
     pub has_assert_eq32_import: bool,
     pub has_assert_eq64_import: bool,
     pub import_count: u32,
+    /// Function imports in index order.
+    pub imported_functions: Vec<ImportedFunction>,
+    /// Number of imported globals, which come before `globals` in the
+    /// global index space.
+    pub imported_global_count: u32,
+    /// Globals defined by the module.
     pub globals: Vec<WasmGlobal>,
     pub tables: Vec<TableType>,
     pub function_types: Vec<FuncType>,
     pub element_segments: Vec<ElementSegment>,
+    pub exports: Vec<Export>,
+    pub names: Names,
+    pub dwarf: DwarfSections,
+}
+
+/// Names from the custom `name` section, keyed by index.
+#[derive(Default)]
+pub struct Names {
+    pub functions: HashMap<u32, String>,
+    /// Local names per function index; parameters come first, as in
+    /// `local.get`.
+    pub locals: HashMap<u32, HashMap<u32, String>>,
+    pub globals: HashMap<u32, String>,
 }
 
+/// Raw DWARF custom sections, keyed by name such as `.debug_line`, left in
+/// the module by the toolchain that produced it.
+#[derive(Default)]
+pub struct DwarfSections {
+    /// Offset of the code section contents, which DWARF addresses in Wasm
+    /// are relative to.
+    pub code_section_offset: usize,
+    pub sections: HashMap<String, Vec<u8>>,
+}
+
+pub struct ImportedFunction {
+    pub module: String,
+    pub name: String,
+    pub func_type: FuncType,
+}
+
+pub struct Export {
+    pub name: String,
+    pub kind: ExternalKind,
+    pub index: u32,
+}
+
+#[derive(Clone)]
 pub struct ElementSegment {
-    pub table_index: u32,
-    pub offset: u32,
-    pub function_indices: Vec<u32>,
+    pub kind: ElementSegmentKind,
+    /// The function each item refers to, or `None` for a null reference.
+    pub items: Vec<Option<u32>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ElementSegmentKind {
+    /// Copied into a table at instantiation.
+    Active { table_index: u32, offset: u32 },
+    /// Only used by `table.init`.
+    Passive,
+    /// Only declares functions that `ref.func` may refer to.
+    Declared,
 }
 
 pub struct WasmGlobal {
@@ -36,6 +93,9 @@ pub struct Function {
 pub struct FunctionBody {
     pub locals: Vec<ValType>,
     pub operators: Vec<Operator<'static>>,
+    /// Byte offset of each operator in the module binary, parallel to
+    /// `operators`. Empty for bodies built by hand.
+    pub offsets: Vec<usize>,
 }
 
 impl WasmModule {
@@ -45,15 +105,28 @@ impl WasmModule {
         let mut func_types = Vec::new();
         let mut func_bodies = Vec::new();
         let mut import_count = 0;
+        let mut imported_functions = Vec::new();
+        let mut imported_global_count = 0;
         let mut memories = Vec::new();
         let mut has_assert_eq32_import = false;
         let mut has_assert_eq64_import = false;
         let mut globals = Vec::new();
         let mut tables = Vec::new();
         let mut element_segments = Vec::new();
+        let mut exports = Vec::new();
+        let mut names = Names::default();
+        let mut dwarf = DwarfSections::default();
 
+        let mut validator = Validator::new();
         for payload in Parser::new(0).parse_all(wasm_bytes) {
-            match payload? {
+            let payload = payload?;
+            if let ValidPayload::Func(func, body) = validator
+                .payload(&payload)
+                .map_err(|e| anyhow!("Invalid module: {}", e))?
+            {
+                validate_function(func, &body)?;
+            }
+            match payload {
                 Payload::TypeSection(types) => {
                     for rec_group in types {
                         for sub_type in rec_group?.into_types() {
@@ -68,8 +141,19 @@ impl WasmModule {
                 Payload::ImportSection(imports) => {
                     for import in imports {
                         let import = import?;
-                        if matches!(import.ty, TypeRef::Func(_)) {
+                        if let TypeRef::Global(_) = import.ty {
+                            imported_global_count += 1;
+                        }
+                        if let TypeRef::Func(type_idx) = import.ty {
                             import_count += 1;
+                            imported_functions.push(ImportedFunction {
+                                module: import.module.to_string(),
+                                name: import.name.to_string(),
+                                func_type: func_types
+                                    .get(type_idx as usize)
+                                    .cloned()
+                                    .ok_or(anyhow!("Invalid type index: {}", type_idx))?,
+                            });
                             match import.name {
                                 "assert_eq32" => has_assert_eq32_import = true,
                                 "assert_eq64" => has_assert_eq64_import = true,
@@ -89,11 +173,22 @@ impl WasmModule {
                                 body: FunctionBody {
                                     locals: Vec::new(),
                                     operators: Vec::new(),
+                                    offsets: Vec::new(),
                                 },
                             });
                         }
                     }
                 }
+                Payload::ExportSection(export_section) => {
+                    for export in export_section {
+                        let export = export?;
+                        exports.push(Export {
+                            name: export.name.to_string(),
+                            kind: export.kind,
+                            index: export.index,
+                        });
+                    }
+                }
                 Payload::StartSection { func, .. } => {
                     start_func_idx = Some(func);
                 }
@@ -118,48 +213,38 @@ impl WasmModule {
                 }
                 Payload::ElementSection(element_section) => {
                     for element in element_section {
-                        let element = element?;
-                        if let wasmparser::ElementKind::Active {
-                            table_index,
-                            offset_expr,
-                        } = element.kind
-                        {
-                            let mut offset = 0u32;
-                            for op in offset_expr.get_operators_reader() {
-                                if let wasmparser::Operator::I32Const { value } = op? {
-                                    offset = value as u32;
-                                    break;
-                                }
-                            }
-
-                            let mut function_indices = Vec::new();
-                            match element.items {
-                                wasmparser::ElementItems::Functions(reader) => {
-                                    for func_idx in reader {
-                                        function_indices.push(func_idx?);
-                                    }
-                                }
-                                _ => {
-                                    eprintln!("Unsupported element items type");
-                                    continue;
-                                }
-                            }
-
-                            element_segments.push(ElementSegment {
-                                table_index: table_index.unwrap_or(0),
-                                offset,
-                                function_indices,
-                            });
-                        }
+                        element_segments.push(read_element_segment(element?)?);
                     }
                 }
+                Payload::CodeSectionStart { range, .. } => {
+                    dwarf.code_section_offset = range.start;
+                }
                 Payload::CodeSectionEntry(body) => {
                     func_bodies.push(body);
                 }
+                Payload::CustomSection(section) if section.name().starts_with(".debug_") => {
+                    dwarf
+                        .sections
+                        .insert(section.name().to_string(), section.data().to_vec());
+                }
+                Payload::CustomSection(section) => {
+                    // A malformed name section must not make the module invalid.
+                    if let KnownCustom::Name(reader) = section.as_known()
+                        && let Err(e) = read_names(reader, &mut names)
+                    {
+                        eprintln!("Ignoring malformed name section: {e}");
+                    }
+                }
                 _ => {}
             }
         }
 
+        if let Some(start_idx) = start_func_idx
+            && let Some(func) = functions.iter_mut().find(|f| f.idx == start_idx)
+        {
+            func.name = Some("_start".to_string());
+        }
+
         for (idx, body) in func_bodies.into_iter().enumerate() {
             if idx < functions.len() {
                 let mut locals = Vec::new();
@@ -172,9 +257,10 @@ impl WasmModule {
                 }
 
                 let mut operators = Vec::new();
+                let mut offsets = Vec::new();
                 let operators_reader = body.get_operators_reader()?;
-                for op in operators_reader {
-                    let op = op?;
+                for op in operators_reader.into_iter_with_offsets() {
+                    let (op, offset) = op?;
                     let owned_op = match op {
                         Operator::I32Const { value } => Operator::I32Const { value },
                         Operator::I64Const { value } => Operator::I64Const { value },
@@ -289,6 +375,7 @@ impl WasmModule {
                         Operator::Return => Operator::Return,
                         Operator::End => Operator::End,
                         Operator::Drop => Operator::Drop,
+                        Operator::Nop => Operator::Nop,
                         Operator::Call { function_index } => Operator::Call { function_index },
                         Operator::If { blockty } => Operator::If { blockty },
                         Operator::Else => Operator::Else,
@@ -309,8 +396,29 @@ impl WasmModule {
                         Operator::I32TruncF64U => Operator::I32TruncF64U,
                         Operator::F64PromoteF32 => Operator::F64PromoteF32,
                         Operator::F32DemoteF64 => Operator::F32DemoteF64,
+                        Operator::F32ConvertI64S => Operator::F32ConvertI64S,
+                        Operator::F32ConvertI64U => Operator::F32ConvertI64U,
+                        Operator::F64ConvertI64S => Operator::F64ConvertI64S,
+                        Operator::F64ConvertI64U => Operator::F64ConvertI64U,
+                        Operator::I64TruncF32S => Operator::I64TruncF32S,
+                        Operator::I64TruncF32U => Operator::I64TruncF32U,
+                        Operator::I64TruncF64S => Operator::I64TruncF64S,
+                        Operator::I64TruncF64U => Operator::I64TruncF64U,
+                        Operator::I32TruncSatF32S => Operator::I32TruncSatF32S,
+                        Operator::I32TruncSatF32U => Operator::I32TruncSatF32U,
+                        Operator::I32TruncSatF64S => Operator::I32TruncSatF64S,
+                        Operator::I32TruncSatF64U => Operator::I32TruncSatF64U,
+                        Operator::I64TruncSatF32S => Operator::I64TruncSatF32S,
+                        Operator::I64TruncSatF32U => Operator::I64TruncSatF32U,
+                        Operator::I64TruncSatF64S => Operator::I64TruncSatF64S,
+                        Operator::I64TruncSatF64U => Operator::I64TruncSatF64U,
+                        Operator::I32ReinterpretF32 => Operator::I32ReinterpretF32,
+                        Operator::I64ReinterpretF64 => Operator::I64ReinterpretF64,
+                        Operator::F32ReinterpretI32 => Operator::F32ReinterpretI32,
+                        Operator::F64ReinterpretI64 => Operator::F64ReinterpretI64,
                         // Phase 1: select + ビットカウント系命令
                         Operator::Select => Operator::Select,
+                        Operator::TypedSelect { ty } => Operator::TypedSelect { ty },
                         Operator::I32Clz => Operator::I32Clz,
                         Operator::I32Ctz => Operator::I32Ctz,
                         Operator::I32Popcnt => Operator::I32Popcnt,
@@ -343,13 +451,210 @@ impl WasmModule {
                             Operator::MemoryCopy { src_mem, dst_mem }
                         }
                         Operator::MemoryFill { mem } => Operator::MemoryFill { mem },
+                        // Threads: shared-memory atomics
+                        Operator::MemoryAtomicNotify { memarg } => {
+                            Operator::MemoryAtomicNotify { memarg }
+                        }
+                        Operator::MemoryAtomicWait32 { memarg } => {
+                            Operator::MemoryAtomicWait32 { memarg }
+                        }
+                        Operator::MemoryAtomicWait64 { memarg } => {
+                            Operator::MemoryAtomicWait64 { memarg }
+                        }
+                        Operator::AtomicFence => Operator::AtomicFence,
+                        Operator::I32AtomicLoad { memarg } => Operator::I32AtomicLoad { memarg },
+                        Operator::I64AtomicLoad { memarg } => Operator::I64AtomicLoad { memarg },
+                        Operator::I32AtomicLoad8U { memarg } => {
+                            Operator::I32AtomicLoad8U { memarg }
+                        }
+                        Operator::I32AtomicLoad16U { memarg } => {
+                            Operator::I32AtomicLoad16U { memarg }
+                        }
+                        Operator::I64AtomicLoad8U { memarg } => {
+                            Operator::I64AtomicLoad8U { memarg }
+                        }
+                        Operator::I64AtomicLoad16U { memarg } => {
+                            Operator::I64AtomicLoad16U { memarg }
+                        }
+                        Operator::I64AtomicLoad32U { memarg } => {
+                            Operator::I64AtomicLoad32U { memarg }
+                        }
+                        Operator::I32AtomicStore { memarg } => Operator::I32AtomicStore { memarg },
+                        Operator::I64AtomicStore { memarg } => Operator::I64AtomicStore { memarg },
+                        Operator::I32AtomicStore8 { memarg } => {
+                            Operator::I32AtomicStore8 { memarg }
+                        }
+                        Operator::I32AtomicStore16 { memarg } => {
+                            Operator::I32AtomicStore16 { memarg }
+                        }
+                        Operator::I64AtomicStore8 { memarg } => {
+                            Operator::I64AtomicStore8 { memarg }
+                        }
+                        Operator::I64AtomicStore16 { memarg } => {
+                            Operator::I64AtomicStore16 { memarg }
+                        }
+                        Operator::I64AtomicStore32 { memarg } => {
+                            Operator::I64AtomicStore32 { memarg }
+                        }
+                        Operator::I32AtomicRmwAdd { memarg } => {
+                            Operator::I32AtomicRmwAdd { memarg }
+                        }
+                        Operator::I64AtomicRmwAdd { memarg } => {
+                            Operator::I64AtomicRmwAdd { memarg }
+                        }
+                        Operator::I32AtomicRmw8AddU { memarg } => {
+                            Operator::I32AtomicRmw8AddU { memarg }
+                        }
+                        Operator::I32AtomicRmw16AddU { memarg } => {
+                            Operator::I32AtomicRmw16AddU { memarg }
+                        }
+                        Operator::I64AtomicRmw8AddU { memarg } => {
+                            Operator::I64AtomicRmw8AddU { memarg }
+                        }
+                        Operator::I64AtomicRmw16AddU { memarg } => {
+                            Operator::I64AtomicRmw16AddU { memarg }
+                        }
+                        Operator::I64AtomicRmw32AddU { memarg } => {
+                            Operator::I64AtomicRmw32AddU { memarg }
+                        }
+                        Operator::I32AtomicRmwSub { memarg } => {
+                            Operator::I32AtomicRmwSub { memarg }
+                        }
+                        Operator::I64AtomicRmwSub { memarg } => {
+                            Operator::I64AtomicRmwSub { memarg }
+                        }
+                        Operator::I32AtomicRmw8SubU { memarg } => {
+                            Operator::I32AtomicRmw8SubU { memarg }
+                        }
+                        Operator::I32AtomicRmw16SubU { memarg } => {
+                            Operator::I32AtomicRmw16SubU { memarg }
+                        }
+                        Operator::I64AtomicRmw8SubU { memarg } => {
+                            Operator::I64AtomicRmw8SubU { memarg }
+                        }
+                        Operator::I64AtomicRmw16SubU { memarg } => {
+                            Operator::I64AtomicRmw16SubU { memarg }
+                        }
+                        Operator::I64AtomicRmw32SubU { memarg } => {
+                            Operator::I64AtomicRmw32SubU { memarg }
+                        }
+                        Operator::I32AtomicRmwAnd { memarg } => {
+                            Operator::I32AtomicRmwAnd { memarg }
+                        }
+                        Operator::I64AtomicRmwAnd { memarg } => {
+                            Operator::I64AtomicRmwAnd { memarg }
+                        }
+                        Operator::I32AtomicRmw8AndU { memarg } => {
+                            Operator::I32AtomicRmw8AndU { memarg }
+                        }
+                        Operator::I32AtomicRmw16AndU { memarg } => {
+                            Operator::I32AtomicRmw16AndU { memarg }
+                        }
+                        Operator::I64AtomicRmw8AndU { memarg } => {
+                            Operator::I64AtomicRmw8AndU { memarg }
+                        }
+                        Operator::I64AtomicRmw16AndU { memarg } => {
+                            Operator::I64AtomicRmw16AndU { memarg }
+                        }
+                        Operator::I64AtomicRmw32AndU { memarg } => {
+                            Operator::I64AtomicRmw32AndU { memarg }
+                        }
+                        Operator::I32AtomicRmwOr { memarg } => Operator::I32AtomicRmwOr { memarg },
+                        Operator::I64AtomicRmwOr { memarg } => Operator::I64AtomicRmwOr { memarg },
+                        Operator::I32AtomicRmw8OrU { memarg } => {
+                            Operator::I32AtomicRmw8OrU { memarg }
+                        }
+                        Operator::I32AtomicRmw16OrU { memarg } => {
+                            Operator::I32AtomicRmw16OrU { memarg }
+                        }
+                        Operator::I64AtomicRmw8OrU { memarg } => {
+                            Operator::I64AtomicRmw8OrU { memarg }
+                        }
+                        Operator::I64AtomicRmw16OrU { memarg } => {
+                            Operator::I64AtomicRmw16OrU { memarg }
+                        }
+                        Operator::I64AtomicRmw32OrU { memarg } => {
+                            Operator::I64AtomicRmw32OrU { memarg }
+                        }
+                        Operator::I32AtomicRmwXor { memarg } => {
+                            Operator::I32AtomicRmwXor { memarg }
+                        }
+                        Operator::I64AtomicRmwXor { memarg } => {
+                            Operator::I64AtomicRmwXor { memarg }
+                        }
+                        Operator::I32AtomicRmw8XorU { memarg } => {
+                            Operator::I32AtomicRmw8XorU { memarg }
+                        }
+                        Operator::I32AtomicRmw16XorU { memarg } => {
+                            Operator::I32AtomicRmw16XorU { memarg }
+                        }
+                        Operator::I64AtomicRmw8XorU { memarg } => {
+                            Operator::I64AtomicRmw8XorU { memarg }
+                        }
+                        Operator::I64AtomicRmw16XorU { memarg } => {
+                            Operator::I64AtomicRmw16XorU { memarg }
+                        }
+                        Operator::I64AtomicRmw32XorU { memarg } => {
+                            Operator::I64AtomicRmw32XorU { memarg }
+                        }
+                        Operator::I32AtomicRmwXchg { memarg } => {
+                            Operator::I32AtomicRmwXchg { memarg }
+                        }
+                        Operator::I64AtomicRmwXchg { memarg } => {
+                            Operator::I64AtomicRmwXchg { memarg }
+                        }
+                        Operator::I32AtomicRmw8XchgU { memarg } => {
+                            Operator::I32AtomicRmw8XchgU { memarg }
+                        }
+                        Operator::I32AtomicRmw16XchgU { memarg } => {
+                            Operator::I32AtomicRmw16XchgU { memarg }
+                        }
+                        Operator::I64AtomicRmw8XchgU { memarg } => {
+                            Operator::I64AtomicRmw8XchgU { memarg }
+                        }
+                        Operator::I64AtomicRmw16XchgU { memarg } => {
+                            Operator::I64AtomicRmw16XchgU { memarg }
+                        }
+                        Operator::I64AtomicRmw32XchgU { memarg } => {
+                            Operator::I64AtomicRmw32XchgU { memarg }
+                        }
+                        Operator::I32AtomicRmwCmpxchg { memarg } => {
+                            Operator::I32AtomicRmwCmpxchg { memarg }
+                        }
+                        Operator::I64AtomicRmwCmpxchg { memarg } => {
+                            Operator::I64AtomicRmwCmpxchg { memarg }
+                        }
+                        Operator::I32AtomicRmw8CmpxchgU { memarg } => {
+                            Operator::I32AtomicRmw8CmpxchgU { memarg }
+                        }
+                        Operator::I32AtomicRmw16CmpxchgU { memarg } => {
+                            Operator::I32AtomicRmw16CmpxchgU { memarg }
+                        }
+                        Operator::I64AtomicRmw8CmpxchgU { memarg } => {
+                            Operator::I64AtomicRmw8CmpxchgU { memarg }
+                        }
+                        Operator::I64AtomicRmw16CmpxchgU { memarg } => {
+                            Operator::I64AtomicRmw16CmpxchgU { memarg }
+                        }
+                        Operator::I64AtomicRmw32CmpxchgU { memarg } => {
+                            Operator::I64AtomicRmw32CmpxchgU { memarg }
+                        }
                         Operator::I32Extend8S => Operator::I32Extend8S,
+                        Operator::I32Extend16S => Operator::I32Extend16S,
+                        Operator::I64Extend8S => Operator::I64Extend8S,
+                        Operator::I64Extend16S => Operator::I64Extend16S,
+                        Operator::I64Extend32S => Operator::I64Extend32S,
                         Operator::Unreachable => Operator::Unreachable,
                         Operator::RefNull { hty } => Operator::RefNull { hty },
                         Operator::RefIsNull => Operator::RefIsNull,
+                        Operator::RefFunc { function_index } => {
+                            Operator::RefFunc { function_index }
+                        }
                         Operator::BrTable { .. } => {
-                            eprintln!("BrTable operator not fully supported yet");
-                            continue;
+                            return Err(anyhow!(
+                                "{}: br_table is not supported",
+                                operator_location(&functions[idx], &names, offset)
+                            ));
                         }
                         Operator::CallIndirect {
                             type_index,
@@ -358,22 +663,39 @@ impl WasmModule {
                             type_index,
                             table_index,
                         },
+                        Operator::TableGet { table } => Operator::TableGet { table },
+                        Operator::TableSet { table } => Operator::TableSet { table },
+                        Operator::TableSize { table } => Operator::TableSize { table },
+                        Operator::TableGrow { table } => Operator::TableGrow { table },
+                        Operator::TableFill { table } => Operator::TableFill { table },
+                        Operator::TableCopy {
+                            dst_table,
+                            src_table,
+                        } => Operator::TableCopy {
+                            dst_table,
+                            src_table,
+                        },
+                        Operator::TableInit { elem_index, table } => {
+                            Operator::TableInit { elem_index, table }
+                        }
+                        Operator::ElemDrop { elem_index } => Operator::ElemDrop { elem_index },
+                        // The module is valid, so the compiler would lower the
+                        // rest of the body wrongly without this operator.
                         _ => {
-                            eprintln!("Unsupported operator: {op:?}");
-                            continue;
+                            return Err(anyhow!(
+                                "{}: Unsupported operator: {:?}",
+                                operator_location(&functions[idx], &names, offset),
+                                op
+                            ));
                         }
                     };
                     operators.push(owned_op);
+                    offsets.push(offset);
                 }
 
                 functions[idx].body.locals = locals;
                 functions[idx].body.operators = operators;
-            }
-        }
-
-        if let Some(start_idx) = start_func_idx {
-            if let Some(func) = functions.iter_mut().find(|f| f.idx == start_idx) {
-                func.name = Some("_start".to_string());
+                functions[idx].body.offsets = offsets;
             }
         }
 
@@ -384,14 +706,131 @@ impl WasmModule {
             has_assert_eq32_import,
             has_assert_eq64_import,
             import_count: import_count as u32,
+            imported_functions,
+            imported_global_count,
             globals,
             tables,
             function_types: func_types,
             element_segments,
+            exports,
+            names,
+            dwarf,
         })
     }
 }
 
+impl WasmModule {
+    /// The global at `index` in the global index space, if the module
+    /// defines it rather than importing it.
+    pub fn defined_global(&self, index: u32) -> Option<&WasmGlobal> {
+        let index = index.checked_sub(self.imported_global_count)?;
+        self.globals.get(index as usize)
+    }
+}
+
+/// Where an operator of `function` is, in the form the compiler reports its
+/// errors in.
+fn operator_location(function: &Function, names: &Names, offset: usize) -> String {
+    let name = names
+        .functions
+        .get(&function.idx)
+        .or(function.name.as_ref());
+    match name {
+        Some(name) => format!("func {} ({name}) at offset 0x{offset:x}", function.idx),
+        None => format!("func {} at offset 0x{offset:x}", function.idx),
+    }
+}
+
+fn read_element_segment(element: wasmparser::Element) -> Result<ElementSegment> {
+    let kind = match element.kind {
+        wasmparser::ElementKind::Active {
+            table_index,
+            offset_expr,
+        } => {
+            let mut reader = offset_expr.get_operators_reader();
+            let offset = match (reader.read()?, reader.read()?) {
+                (Operator::I32Const { value }, Operator::End) => value as u32,
+                (op, _) => {
+                    return Err(anyhow!("Unsupported element segment offset: {:?}", op));
+                }
+            };
+            ElementSegmentKind::Active {
+                table_index: table_index.unwrap_or(0),
+                offset,
+            }
+        }
+        wasmparser::ElementKind::Passive => ElementSegmentKind::Passive,
+        wasmparser::ElementKind::Declared => ElementSegmentKind::Declared,
+    };
+
+    let mut items = Vec::new();
+    match element.items {
+        wasmparser::ElementItems::Functions(reader) => {
+            for func_idx in reader {
+                items.push(Some(func_idx?));
+            }
+        }
+        wasmparser::ElementItems::Expressions(_, reader) => {
+            for expr in reader {
+                let mut reader = expr?.get_operators_reader();
+                let item = match reader.read()? {
+                    Operator::RefFunc { function_index } => Some(function_index),
+                    Operator::RefNull { .. } => None,
+                    op => return Err(anyhow!("Unsupported element expression: {:?}", op)),
+                };
+                items.push(item);
+            }
+        }
+    }
+
+    Ok(ElementSegment { kind, items })
+}
+
+fn read_names(reader: NameSectionReader, names: &mut Names) -> Result<()> {
+    for name in reader {
+        match name? {
+            Name::Function(map) => read_name_map(map, &mut names.functions)?,
+            Name::Local(map) => {
+                for function in map {
+                    let function = function?;
+                    let locals = names.locals.entry(function.index).or_default();
+                    read_name_map(function.names, locals)?;
+                }
+            }
+            Name::Global(map) => read_name_map(map, &mut names.globals)?,
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn read_name_map(map: NameMap, names: &mut HashMap<u32, String>) -> Result<()> {
+    for naming in map {
+        let naming = naming?;
+        names.insert(naming.index, naming.name.to_string());
+    }
+    Ok(())
+}
+
+/// Type-checks a function body before any code is generated for it, so
+/// malformed operand stacks are reported instead of reaching the compiler.
+fn validate_function(
+    func: FuncToValidate<ValidatorResources>,
+    body: &wasmparser::FunctionBody,
+) -> Result<()> {
+    let index = func.index;
+    func.into_validator(Default::default())
+        .validate(body)
+        .map_err(|e| {
+            anyhow!(
+                "Invalid function {} at offset 0x{:x}: {}",
+                index,
+                e.offset(),
+                e.message()
+            )
+        })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -450,6 +889,117 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_table_operators() {
+        // (module
+        //   (table 2 funcref)
+        //   (func
+        //     (drop (table.get 0 (i32.const 0)))
+        //     (table.set 0 (i32.const 0) (ref.null func))
+        //     (drop (table.size 0))
+        //     (drop (table.grow 0 (ref.null func) (i32.const 1)))
+        //     (table.fill 0 (i32.const 0) (ref.null func) (i32.const 1))
+        //     (table.copy 0 0 (i32.const 0) (i32.const 1) (i32.const 1))
+        //     (table.init 0 0 (i32.const 0) (i32.const 0) (i32.const 0))
+        //     (elem.drop 0))
+        //   (elem func 0))
+        let wasm_bytes = vec![
+            0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, 0x01, 0x04, 0x01, 0x60, 0x00, 0x00,
+            0x03, 0x02, 0x01, 0x00, 0x04, 0x04, 0x01, 0x70, 0x00, 0x02, 0x09, 0x05, 0x01, 0x01,
+            0x00, 0x01, 0x00, 0x0a, 0x3b, 0x01, 0x39, 0x00, 0x41, 0x00, 0x25, 0x00, 0x1a, 0x41,
+            0x00, 0xd0, 0x70, 0x26, 0x00, 0xfc, 0x10, 0x00, 0x1a, 0xd0, 0x70, 0x41, 0x01, 0xfc,
+            0x0f, 0x00, 0x1a, 0x41, 0x00, 0xd0, 0x70, 0x41, 0x01, 0xfc, 0x11, 0x00, 0x41, 0x00,
+            0x41, 0x01, 0x41, 0x01, 0xfc, 0x0e, 0x00, 0x00, 0x41, 0x00, 0x41, 0x00, 0x41, 0x00,
+            0xfc, 0x0c, 0x00, 0x00, 0xfc, 0x0d, 0x00, 0x0b,
+        ];
+        let module = WasmModule::parse(&wasm_bytes).unwrap();
+
+        let table_operators: Vec<&Operator> = module.functions[0]
+            .body
+            .operators
+            .iter()
+            .filter(|op| {
+                !matches!(
+                    op,
+                    Operator::I32Const { .. } | Operator::RefNull { .. } | Operator::Drop
+                )
+            })
+            .collect();
+        assert_eq!(
+            table_operators,
+            [
+                &Operator::TableGet { table: 0 },
+                &Operator::TableSet { table: 0 },
+                &Operator::TableSize { table: 0 },
+                &Operator::TableGrow { table: 0 },
+                &Operator::TableFill { table: 0 },
+                &Operator::TableCopy {
+                    dst_table: 0,
+                    src_table: 0,
+                },
+                &Operator::TableInit {
+                    elem_index: 0,
+                    table: 0,
+                },
+                &Operator::ElemDrop { elem_index: 0 },
+                &Operator::End,
+            ]
+        );
+        assert_eq!(
+            module.functions[0].body.operators.len(),
+            module.functions[0].body.offsets.len()
+        );
+    }
+
+    #[test]
+    fn test_unsupported_operators_are_errors() {
+        // (module (func (block (br_table 0 (i32.const 0)))))
+        let br_table = [
+            0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, 0x01, 0x04, 0x01, 0x60, 0x00, 0x00,
+            0x03, 0x02, 0x01, 0x00, 0x0a, 0x0c, 0x01, 0x0a, 0x00, 0x02, 0x40, 0x41, 0x00, 0x0e,
+            0x00, 0x00, 0x0b, 0x0b,
+        ];
+        let error = WasmModule::parse(&br_table).err().unwrap();
+        assert_eq!(
+            error.to_string(),
+            "func 0 at offset 0x1b: br_table is not supported"
+        );
+
+        // (module (func return_call 0))
+        let return_call = [
+            0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, 0x01, 0x04, 0x01, 0x60, 0x00, 0x00,
+            0x03, 0x02, 0x01, 0x00, 0x0a, 0x06, 0x01, 0x04, 0x00, 0x12, 0x00, 0x0b,
+        ];
+        let error = WasmModule::parse(&return_call).err().unwrap();
+        assert_eq!(
+            error.to_string(),
+            "func 0 at offset 0x17: Unsupported operator: ReturnCall { function_index: 0 }"
+        );
+
+        // (module (func return_call 0) (start 0))
+        let start_return_call = [
+            0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, 0x01, 0x04, 0x01, 0x60, 0x00, 0x00,
+            0x03, 0x02, 0x01, 0x00, 0x08, 0x01, 0x00, 0x0a, 0x06, 0x01, 0x04, 0x00, 0x12, 0x00,
+            0x0b,
+        ];
+        let error = WasmModule::parse(&start_return_call).err().unwrap();
+        assert_eq!(
+            error.to_string(),
+            "func 0 (_start) at offset 0x1a: Unsupported operator: ReturnCall { function_index: 0 }"
+        );
+
+        // (module (func nop))
+        let nop = [
+            0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, 0x01, 0x04, 0x01, 0x60, 0x00, 0x00,
+            0x03, 0x02, 0x01, 0x00, 0x0a, 0x05, 0x01, 0x03, 0x00, 0x01, 0x0b,
+        ];
+        let module = WasmModule::parse(&nop).unwrap();
+        assert_eq!(
+            module.functions[0].body.operators,
+            vec![Operator::Nop, Operator::End]
+        );
+    }
+
     #[test]
     fn test_start_function_detection() {
         let wasm_bytes = vec![
@@ -469,4 +1019,133 @@ mod tests {
             .find(|f| f.name.as_ref() == Some(&"_start".to_string()));
         assert!(start_function.is_some());
     }
+
+    #[test]
+    fn test_parse_exports() {
+        let wasm_bytes = vec![
+            0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, 0x01, 0x05, 0x01, 0x60, 0x00, 0x01,
+            0x7f, 0x03, 0x02, 0x01, 0x00, 0x07, 0x07, 0x01, 0x03, 0x61, 0x64, 0x64, 0x00, 0x00,
+            0x0a, 0x06, 0x01, 0x04, 0x00, 0x41, 0x2a, 0x0b,
+        ];
+        let module = WasmModule::parse(&wasm_bytes).unwrap();
+
+        assert_eq!(module.exports.len(), 1);
+        assert_eq!(module.exports[0].name, "add");
+        assert_eq!(module.exports[0].kind, ExternalKind::Func);
+        assert_eq!(module.exports[0].index, 0);
+    }
+
+    #[test]
+    fn test_imported_globals_come_first() {
+        // (import "env" "g" (global i32)) (global (mut i64) (i64.const 0))
+        // exported as "host" and "own"
+        let wasm_bytes = vec![
+            0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, 0x02, 0x0a, 0x01, 0x03, 0x65, 0x6e,
+            0x76, 0x01, 0x67, 0x03, 0x7f, 0x00, 0x06, 0x06, 0x01, 0x7e, 0x01, 0x42, 0x00, 0x0b,
+            0x07, 0x0e, 0x02, 0x04, 0x68, 0x6f, 0x73, 0x74, 0x03, 0x00, 0x03, 0x6f, 0x77, 0x6e,
+            0x03, 0x01,
+        ];
+        let module = WasmModule::parse(&wasm_bytes).unwrap();
+
+        assert_eq!(module.imported_global_count, 1);
+        assert_eq!(module.globals.len(), 1);
+        assert!(module.defined_global(module.exports[0].index).is_none());
+        let own = module.defined_global(module.exports[1].index).unwrap();
+        assert_eq!(own.global_type.content_type, ValType::I64);
+    }
+
+    #[test]
+    fn test_parse_shared_memory_atomics() {
+        let wasm_bytes = vec![
+            0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, 0x01, 0x04, 0x01, 0x60, 0x00, 0x00,
+            0x03, 0x02, 0x01, 0x00, 0x05, 0x04, 0x01, 0x03, 0x01, 0x02, 0x0a, 0x10, 0x01, 0x0e,
+            0x00, 0x41, 0x00, 0x41, 0x01, 0xfe, 0x1e, 0x02, 0x00, 0x1a, 0xfe, 0x03, 0x00, 0x0b,
+        ];
+        let module = WasmModule::parse(&wasm_bytes).unwrap();
+
+        assert!(module.memories[0].shared);
+        assert_eq!(module.memories[0].maximum, Some(2));
+        let operators = &module.functions[0].body.operators;
+        assert!(matches!(operators[2], Operator::I32AtomicRmwAdd { memarg } if memarg.align == 2));
+        assert!(matches!(operators[4], Operator::AtomicFence));
+    }
+
+    #[test]
+    fn test_reject_operand_type_mismatch() {
+        // (func (result i32) i64.const 1 i32.const 2 i32.add)
+        let wasm_bytes = vec![
+            0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, 0x01, 0x05, 0x01, 0x60, 0x00, 0x01,
+            0x7f, 0x03, 0x02, 0x01, 0x00, 0x0a, 0x09, 0x01, 0x07, 0x00, 0x42, 0x01, 0x41, 0x02,
+            0x6a, 0x0b,
+        ];
+        let error = WasmModule::parse(&wasm_bytes).err().unwrap().to_string();
+
+        assert!(
+            error.starts_with("Invalid function 0 at offset 0x1c:"),
+            "{error}"
+        );
+        assert!(error.contains("expected i32, found i64"), "{error}");
+    }
+
+    #[test]
+    fn test_parse_names_and_offsets() {
+        // (func $my_kernel i32.const 1 drop) with a function name subsection
+        let wasm_bytes = vec![
+            0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, 0x01, 0x04, 0x01, 0x60, 0x00, 0x00,
+            0x03, 0x02, 0x01, 0x00, 0x0a, 0x07, 0x01, 0x05, 0x00, 0x41, 0x01, 0x1a, 0x0b, 0x00,
+            0x13, 0x04, 0x6e, 0x61, 0x6d, 0x65, 0x01, 0x0c, 0x01, 0x00, 0x09, 0x6d, 0x79, 0x5f,
+            0x6b, 0x65, 0x72, 0x6e, 0x65, 0x6c,
+        ];
+        let module = WasmModule::parse(&wasm_bytes).unwrap();
+
+        assert_eq!(module.names.functions[&0], "my_kernel");
+        assert_eq!(module.functions[0].body.offsets, vec![0x17, 0x19, 0x1a]);
+        assert_eq!(module.dwarf.code_section_offset, 0x14);
+    }
+
+    #[test]
+    fn test_parse_local_and_global_names() {
+        // (func (param $n i32) (local $sum i32)) (global $counter i32 (i32.const 0))
+        let wasm_bytes = vec![
+            0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, 0x01, 0x05, 0x01, 0x60, 0x01, 0x7f,
+            0x00, 0x03, 0x02, 0x01, 0x00, 0x06, 0x06, 0x01, 0x7f, 0x00, 0x41, 0x00, 0x0b, 0x0a,
+            0x06, 0x01, 0x04, 0x01, 0x01, 0x7f, 0x0b, 0x00, 0x1e, 0x04, 0x6e, 0x61, 0x6d, 0x65,
+            0x02, 0x0b, 0x01, 0x00, 0x02, 0x00, 0x01, 0x6e, 0x01, 0x03, 0x73, 0x75, 0x6d, 0x07,
+            0x0a, 0x01, 0x00, 0x07, 0x63, 0x6f, 0x75, 0x6e, 0x74, 0x65, 0x72,
+        ];
+        let module = WasmModule::parse(&wasm_bytes).unwrap();
+
+        assert_eq!(module.names.locals[&0][&0], "n");
+        assert_eq!(module.names.locals[&0][&1], "sum");
+        assert_eq!(module.names.globals[&0], "counter");
+    }
+
+    #[test]
+    fn test_parse_element_segment_kinds() {
+        // (table 2 funcref) (func $f)
+        // (elem (i32.const 0) $f)
+        // (elem funcref (ref.func $f) (ref.null func))
+        // (elem declare func $f)
+        let wasm_bytes = vec![
+            0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, 0x01, 0x04, 0x01, 0x60, 0x00, 0x00,
+            0x03, 0x02, 0x01, 0x00, 0x04, 0x04, 0x01, 0x70, 0x00, 0x02, 0x09, 0x14, 0x03, 0x00,
+            0x41, 0x00, 0x0b, 0x01, 0x00, 0x05, 0x70, 0x02, 0xd2, 0x00, 0x0b, 0xd0, 0x70, 0x0b,
+            0x03, 0x00, 0x01, 0x00, 0x0a, 0x04, 0x01, 0x02, 0x00, 0x0b,
+        ];
+        let module = WasmModule::parse(&wasm_bytes).unwrap();
+        let segments = &module.element_segments;
+
+        assert_eq!(
+            segments[0].kind,
+            ElementSegmentKind::Active {
+                table_index: 0,
+                offset: 0
+            }
+        );
+        assert_eq!(segments[0].items, vec![Some(0)]);
+        assert_eq!(segments[1].kind, ElementSegmentKind::Passive);
+        assert_eq!(segments[1].items, vec![Some(0), None]);
+        assert_eq!(segments[2].kind, ElementSegmentKind::Declared);
+        assert_eq!(segments[2].items, vec![Some(0)]);
+    }
 }
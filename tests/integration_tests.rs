@@ -1,10 +1,19 @@
 use std::fs;
+use std::path::Path;
 use std::process::Command;
 
 fn run(args: &[&str]) -> std::process::Output {
+    let cache_dir = std::env::temp_dir().join("auto-parallel-wasm-test-cache");
+    run_with_cache_dir(args, &cache_dir)
+}
+
+/// Runs the CLI with its compilation cache in `cache_dir`, keeping tests out
+/// of the user's cache.
+fn run_with_cache_dir(args: &[&str], cache_dir: &Path) -> std::process::Output {
     Command::new("cargo")
         .args(["run", "--quiet", "--"])
         .args(args)
+        .env("AUTO_PARALLEL_WASM_CACHE_DIR", cache_dir)
         .output()
         .expect("Failed to execute cargo run")
 }
@@ -13,7 +22,7 @@ fn wat_to_wasm(wat_path: &str) -> String {
     let wasm_file = format!("/tmp/test_wasm_{:?}.wasm", std::thread::current().id());
 
     let output = Command::new("wat2wasm")
-        .args([wat_path, "-o", &wasm_file])
+        .args([wat_path, "--enable-threads", "-o", &wasm_file])
         .output();
 
     let output = match output {
@@ -49,7 +58,7 @@ fn test_compile(wat_path: &str) {
 fn test_ir(wat_path: &str, expected_ir_path: &str) {
     let wasm_file = wat_to_wasm(wat_path);
 
-    let output = run(&["ir", &wasm_file]);
+    let output = run(&["ir", "--stable-names", &wasm_file]);
     assert!(output.status.success(), "IR generation should succeed");
 
     let actual_ir = String::from_utf8_lossy(&output.stderr);
@@ -108,3 +117,1048 @@ fn test_e2e() {
         test_jit(&wat_path);
     }
 }
+
+#[test]
+fn test_optimization_levels() {
+    let (wat_path, _) = test_path("local_variables");
+    let wasm_file = wat_to_wasm(&wat_path);
+    let output_file = format!("/tmp/test_output_{:?}.o", std::thread::current().id());
+
+    for level in ["-O0", "-O1", "-O2", "-O3", "-Os"] {
+        let output = run(&["exec", level, &wasm_file]);
+        assert!(
+            output.status.success(),
+            "JIT execution at {level} should succeed"
+        );
+
+        let output = run(&["compile", level, &wasm_file, &output_file]);
+        assert!(
+            output.status.success(),
+            "Compilation at {level} should succeed"
+        );
+        fs::remove_file(&output_file).ok();
+    }
+
+    let output = run(&["ir", "-O2", &wasm_file]);
+    assert!(output.status.success(), "IR generation should succeed");
+    let ir = String::from_utf8_lossy(&output.stderr);
+    assert!(!ir.contains("alloca"), "-O2 IR should be optimized:\n{ir}");
+
+    fs::remove_file(&wasm_file).ok();
+}
+
+#[test]
+fn test_compile_target_selection() {
+    let (wat_path, _) = test_path("basic_arithmetic");
+    let wasm_file = wat_to_wasm(&wat_path);
+    let output_file = format!("/tmp/test_target_{:?}.o", std::thread::current().id());
+
+    let output = run(&[
+        "compile",
+        "--target",
+        "aarch64-unknown-linux-gnu",
+        "--reloc",
+        "pic",
+        &wasm_file,
+        &output_file,
+    ]);
+    assert!(output.status.success(), "Cross compilation should succeed");
+
+    let object = fs::read(&output_file).unwrap();
+    assert_eq!(&object[..4], b"\x7fELF", "Output should be an ELF object");
+    let e_machine = u16::from_le_bytes([object[18], object[19]]);
+    assert_eq!(e_machine, 183, "Output should target aarch64 (EM_AARCH64)");
+
+    // The host cc cannot link for another target.
+    for emit in ["so", "exe"] {
+        let output = Command::new("cargo")
+            .args(["run", "--quiet", "--", "compile", "--emit", emit])
+            .args([
+                "--target",
+                "aarch64-unknown-linux-gnu",
+                &wasm_file,
+                &output_file,
+            ])
+            .env_remove("CC")
+            .output()
+            .expect("Failed to execute command");
+        assert!(!output.status.success(), "--emit {emit} should need CC");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("set CC"), "{stderr}");
+    }
+
+    let output = run(&["compile", "--cpu", "native", &wasm_file, &output_file]);
+    assert!(
+        output.status.success(),
+        "Native CPU compilation should succeed"
+    );
+
+    let output = run(&["exec", "--cpu", "native", &wasm_file]);
+    assert!(!output.status.success(), "--cpu should be rejected by exec");
+    let output = run(&["exec", "--features", "+avx2", &wasm_file]);
+    assert!(
+        !output.status.success(),
+        "--features should be rejected by exec"
+    );
+
+    fs::remove_file(&wasm_file).ok();
+    fs::remove_file(&output_file).ok();
+}
+
+#[test]
+fn test_emit_kinds() {
+    let (wat_path, _) = test_path("exports");
+    let wasm_file = wat_to_wasm(&wat_path);
+    let output_file = format!("/tmp/test_emit_{:?}", std::thread::current().id());
+
+    let output = run(&["compile", "--emit", "asm", &wasm_file, &output_file]);
+    assert!(output.status.success(), "Assembly output should succeed");
+    let asm = fs::read_to_string(&output_file).unwrap();
+    assert!(asm.contains("add:"), "Assembly should define the export");
+
+    let output = run(&["compile", "--emit", "bc", &wasm_file, &output_file]);
+    assert!(output.status.success(), "Bitcode output should succeed");
+    assert_eq!(&fs::read(&output_file).unwrap()[..4], b"BC\xc0\xde");
+
+    let output = run(&["compile", "--emit", "ll", &wasm_file, &output_file]);
+    assert!(output.status.success(), "IR output should succeed");
+    let ir = fs::read_to_string(&output_file).unwrap();
+    assert!(ir.contains("define i32 @add(ptr %vmctx, i32 %0, i32 %1)"));
+
+    let output = run(&["compile", "--emit", "so", &wasm_file, &output_file]);
+    assert!(
+        output.status.success(),
+        "Shared library output should succeed"
+    );
+    let object = fs::read(&output_file).unwrap();
+    let e_type = u16::from_le_bytes([object[16], object[17]]);
+    assert_eq!(e_type, 3, "Output should be a shared object (ET_DYN)");
+    let symbols = Command::new("nm")
+        .args(["-D", "--defined-only", &output_file])
+        .output()
+        .expect("Failed to execute nm");
+    assert!(String::from_utf8_lossy(&symbols.stdout).contains(" add\n"));
+
+    let output = run(&["compile", "--emit", "exe", &wasm_file, &output_file]);
+    assert!(output.status.success(), "Executable output should succeed");
+    let status = Command::new(&output_file)
+        .status()
+        .expect("Failed to run compiled executable");
+    assert!(status.success(), "Compiled executable should exit cleanly");
+
+    for args in [
+        &["exec", "--emit", "exe", &wasm_file][..],
+        &["ir", "--emit", "asm", &wasm_file],
+        &["exec", "--emit-header", "/tmp/unused.h", &wasm_file],
+    ] {
+        let output = run(args);
+        assert!(!output.status.success(), "{args:?} should be rejected");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("only supported by compile"), "{stderr}");
+    }
+
+    fs::remove_file(&wasm_file).ok();
+    fs::remove_file(&output_file).ok();
+}
+
+#[test]
+fn test_c_header() {
+    let (wat_path, _) = test_path("c_header");
+    let wasm_file = wat_to_wasm(&wat_path);
+    let id = format!("{:?}", std::thread::current().id());
+    let dir = std::env::temp_dir().join(format!("test_c_header_{}", id.replace(['(', ')'], "")));
+    fs::create_dir_all(&dir).unwrap();
+    let library = dir.join("libkernel.so");
+    let header = dir.join("kernel.h");
+    let source = dir.join("main.c");
+    let program = dir.join("main");
+
+    let output = run(&[
+        "compile",
+        "--emit",
+        "so",
+        "--emit-header",
+        header.to_str().unwrap(),
+        &wasm_file,
+        library.to_str().unwrap(),
+    ]);
+    assert!(output.status.success(), "Compilation should succeed");
+
+    let header_text = fs::read_to_string(&header).unwrap();
+    assert!(
+        header_text.contains("int32_t add(wasm_instance_t *instance, int32_t p0, int32_t p1);")
+    );
+    assert!(header_text.contains("void bump(wasm_instance_t *instance);"));
+
+    fs::write(
+        &source,
+        r#"#include "kernel.h"
+int main(void) {
+    wasm_instance_t *first = wasm_instance_new();
+    wasm_instance_t *second = wasm_instance_new();
+    if (add(first, 2, 3) != 5) return 1;
+    bump(first);
+    bump(first);
+    if (counter_get(first) != 2) return 2;
+    if (counter_get(second) != 0) return 3;
+    memory_base(first)[0] = 7;
+    if (memory_base(second)[0] != 0) return 4;
+    if (memory_size(first) != 65536) return 5;
+    wasm_instance_free(first);
+    wasm_instance_free(second);
+    return 0;
+}
+"#,
+    )
+    .unwrap();
+
+    let status = Command::new("cc")
+        .arg("-o")
+        .arg(&program)
+        .arg(&source)
+        .arg(&library)
+        .arg(format!("-Wl,-rpath,{}", dir.display()))
+        .status()
+        .expect("Failed to run cc");
+    assert!(
+        status.success(),
+        "C program using the header should compile"
+    );
+
+    let status = Command::new(&program).status().unwrap();
+    assert!(
+        status.success(),
+        "C program should observe the module exports"
+    );
+
+    fs::remove_file(&wasm_file).ok();
+    fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn test_memory_grow() {
+    let (wat_path, _) = test_path("memory_grow");
+    test_compile(&wat_path);
+    test_jit(&wat_path);
+}
+
+#[test]
+fn test_atomics() {
+    let (wat_path, _) = test_path("atomics");
+    test_compile(&wat_path);
+    test_jit(&wat_path);
+
+    let wasm_file = wat_to_wasm(&wat_path);
+    let output_file = format!("/tmp/test_atomics_{:?}", std::thread::current().id());
+    let output = run(&["compile", "--emit", "exe", &wasm_file, &output_file]);
+    assert!(output.status.success(), "Executable output should succeed");
+    let status = Command::new(&output_file)
+        .status()
+        .expect("Failed to run compiled executable");
+    assert!(
+        status.success(),
+        "Atomics should behave the same in AOT code"
+    );
+
+    fs::remove_file(&wasm_file).ok();
+    fs::remove_file(&output_file).ok();
+}
+
+#[test]
+fn test_table_instructions() {
+    let (wat_path, _) = test_path("table_ops");
+    test_compile(&wat_path);
+    test_jit(&wat_path);
+
+    let wasm_file = wat_to_wasm(&wat_path);
+    let output_file = format!("/tmp/test_table_ops_{:?}", std::thread::current().id());
+    let output = run(&["compile", "--emit", "exe", &wasm_file, &output_file]);
+    assert!(output.status.success(), "Executable output should succeed");
+    let status = Command::new(&output_file)
+        .status()
+        .expect("Failed to run compiled executable");
+    assert!(
+        status.success(),
+        "Table instructions should behave the same in AOT code"
+    );
+
+    fs::remove_file(&wasm_file).ok();
+    fs::remove_file(&output_file).ok();
+}
+
+#[test]
+fn test_loop_ir() {
+    let (wat_path, _) = test_path("for_loop");
+    let wasm_file = wat_to_wasm(&wat_path);
+
+    let output = run(&["loop-ir", &wasm_file]);
+    assert!(output.status.success(), "Loop IR generation should succeed");
+
+    let text = String::from_utf8_lossy(&output.stdout);
+    assert!(text.contains(";; induction l0 from 0 step 1"));
+    assert!(text.contains(";; trip count 3"));
+
+    fs::remove_file(&wasm_file).ok();
+}
+
+#[test]
+fn test_zero_initialized_locals() {
+    let (wat_path, _) = test_path("zero_locals");
+    let wasm_file = wat_to_wasm(&wat_path);
+
+    for level in ["-O0", "-O2"] {
+        let output = run(&["exec", level, &wasm_file]);
+        assert!(
+            output.status.success(),
+            "Locals should read as zero at {level}"
+        );
+    }
+
+    fs::remove_file(&wasm_file).ok();
+}
+
+#[test]
+fn test_select() {
+    let (wat_path, _) = test_path("dynamic_select");
+    let wasm_file = wat_to_wasm(&wat_path);
+
+    for level in ["-O0", "-O2"] {
+        let output = run(&["exec", level, &wasm_file]);
+        assert!(
+            output.status.success(),
+            "select should pick its first operand for a non-zero condition at {level}"
+        );
+    }
+
+    fs::remove_file(&wasm_file).ok();
+}
+
+#[test]
+fn test_out_of_bounds_traps() {
+    use std::os::unix::process::ExitStatusExt;
+
+    let (wat_path, _) = test_path("out_of_bounds");
+    let wasm_file = wat_to_wasm(&wat_path);
+
+    // At the default level and with optimizations, which must not remove
+    // the trap.
+    for args in [&["exec"][..], &["exec", "-O2"]] {
+        let output = run(&[args, &[wasm_file.as_str()]].concat());
+        assert!(
+            output.status.signal().is_some(),
+            "An out-of-bounds load should trap with {args:?}: {:?}",
+            output.status
+        );
+    }
+
+    fs::remove_file(&wasm_file).ok();
+}
+
+extern "C" fn assert_eq32_host(actual: i32, expected: i32) {
+    assert_eq!(actual, expected);
+}
+
+extern "C" fn assert_eq64_host(actual: i64, expected: i64) {
+    assert_eq!(actual, expected);
+}
+
+#[test]
+fn test_integer_division() {
+    use auto_parallel_wasm::{CompilerOptions, Engine, Imports, Instance, Module, OptLevel, Trap};
+
+    let (wat_path, _) = test_path("integer_division");
+    let wasm_file = wat_to_wasm(&wat_path);
+
+    for level in ["-O0", "-O1", "-O2", "-O3", "-Os"] {
+        let output = run(&["exec", level, &wasm_file]);
+        assert!(
+            output.status.success(),
+            "Division should follow Wasm semantics at {level}"
+        );
+    }
+
+    let engine = Engine::new(CompilerOptions {
+        opt_level: OptLevel::O2,
+        ..CompilerOptions::default()
+    });
+    let module = Module::from_bytes(&engine, &fs::read(&wasm_file).unwrap()).unwrap();
+    let mut imports = Imports::new();
+    imports.func(
+        "env",
+        "assert_eq32",
+        assert_eq32_host as extern "C" fn(i32, i32),
+    );
+    imports.func(
+        "env",
+        "assert_eq64",
+        assert_eq64_host as extern "C" fn(i64, i64),
+    );
+    let instance = Instance::new(&module, &imports).unwrap();
+    let div_s = instance.get_typed_func::<(i32, i32), i32>("div_s").unwrap();
+    let rem_s = instance.get_typed_func::<(i32, i32), i32>("rem_s").unwrap();
+    let error = div_s.call((1, 0)).unwrap_err();
+    assert_eq!(
+        error.downcast_ref::<Trap>(),
+        Some(&Trap::IntegerDivideByZero)
+    );
+    let error = div_s.call((i32::MIN, -1)).unwrap_err();
+    assert_eq!(error.downcast_ref::<Trap>(), Some(&Trap::IntegerOverflow));
+    let error = rem_s.call((1, 0)).unwrap_err();
+    assert_eq!(
+        error.downcast_ref::<Trap>(),
+        Some(&Trap::IntegerDivideByZero)
+    );
+    assert_eq!(rem_s.call((i32::MIN, -1)).unwrap(), 0);
+
+    fs::remove_file(&wasm_file).ok();
+}
+
+#[test]
+fn test_conversions() {
+    use auto_parallel_wasm::{CompilerOptions, Engine, Imports, Instance, Module, OptLevel, Trap};
+
+    let (wat_path, _) = test_path("conversions");
+    let wasm_file = wat_to_wasm(&wat_path);
+
+    for level in ["-O0", "-O1", "-O2", "-O3", "-Os"] {
+        let output = run(&["exec", level, &wasm_file]);
+        assert!(
+            output.status.success(),
+            "Conversions should follow Wasm semantics at {level}"
+        );
+    }
+
+    let engine = Engine::new(CompilerOptions {
+        opt_level: OptLevel::O2,
+        ..CompilerOptions::default()
+    });
+    let module = Module::from_bytes(&engine, &fs::read(&wasm_file).unwrap()).unwrap();
+    let mut imports = Imports::new();
+    imports.func(
+        "env",
+        "assert_eq32",
+        assert_eq32_host as extern "C" fn(i32, i32),
+    );
+    imports.func(
+        "env",
+        "assert_eq64",
+        assert_eq64_host as extern "C" fn(i64, i64),
+    );
+    let instance = Instance::new(&module, &imports).unwrap();
+    let trunc = instance
+        .get_typed_func::<(f64,), i64>("trunc_f64_s")
+        .unwrap();
+    assert_eq!(trunc.call((-1.5,)).unwrap(), -1);
+    let error = trunc.call((f64::NAN,)).unwrap_err();
+    assert_eq!(
+        error.downcast_ref::<Trap>(),
+        Some(&Trap::InvalidConversionToInteger)
+    );
+    let error = trunc.call((9.3e18,)).unwrap_err();
+    assert_eq!(error.downcast_ref::<Trap>(), Some(&Trap::IntegerOverflow));
+
+    fs::remove_file(&wasm_file).ok();
+}
+
+#[test]
+fn test_traps_at_every_level() {
+    use std::os::unix::process::ExitStatusExt;
+
+    // The first trap ends the process, so each trapping body runs from the
+    // start function of its own module.
+    let bodies = [
+        (
+            "i32.div_s by zero",
+            "i32.const 1 i32.const 0 i32.div_s drop",
+        ),
+        (
+            "i32.div_u by zero",
+            "i32.const 1 i32.const 0 i32.div_u drop",
+        ),
+        (
+            "i32.rem_s by zero",
+            "i32.const 1 i32.const 0 i32.rem_s drop",
+        ),
+        (
+            "i32.rem_u by zero",
+            "i32.const 1 i32.const 0 i32.rem_u drop",
+        ),
+        (
+            "i64.div_s by zero",
+            "i64.const 1 i64.const 0 i64.div_s drop",
+        ),
+        (
+            "i64.rem_u by zero",
+            "i64.const 1 i64.const 0 i64.rem_u drop",
+        ),
+        (
+            "i32.div_s overflow",
+            "i32.const -2147483648 i32.const -1 i32.div_s drop",
+        ),
+        (
+            "i64.div_s overflow",
+            "i64.const -9223372036854775808 i64.const -1 i64.div_s drop",
+        ),
+        (
+            "i32.trunc_f32_s of NaN",
+            "f32.const nan i32.trunc_f32_s drop",
+        ),
+        ("i32.trunc_f64_u of -1", "f64.const -1 i32.trunc_f64_u drop"),
+        (
+            "i32.trunc_f64_s overflow",
+            "f64.const 2147483648 i32.trunc_f64_s drop",
+        ),
+        (
+            "i64.trunc_f32_s overflow",
+            "f32.const 9223372036854775808 i64.trunc_f32_s drop",
+        ),
+        (
+            "i64.trunc_f64_u overflow",
+            "f64.const 18446744073709551616 i64.trunc_f64_u drop",
+        ),
+        ("unreachable", "unreachable"),
+        (
+            "call_indirect of a null entry",
+            "i32.const 0 call_indirect (type $t)",
+        ),
+        (
+            "call_indirect out of bounds",
+            "i32.const 1 call_indirect (type $t)",
+        ),
+    ];
+    let id = format!("{:?}", std::thread::current().id()).replace(['(', ')'], "");
+    let wat_path = format!("/tmp/test_traps_{id}.wat");
+
+    for (name, body) in bodies {
+        fs::write(
+            &wat_path,
+            format!(
+                "(module (type $t (func)) (table 1 funcref) (func $main {body}) (start $main))"
+            ),
+        )
+        .unwrap();
+        let wasm_file = wat_to_wasm(&wat_path);
+        for level in ["-O0", "-O1", "-O2", "-O3", "-Os"] {
+            let output = run(&["exec", level, &wasm_file]);
+            assert!(
+                output.status.signal().is_some(),
+                "{name} should trap at {level}: {:?}",
+                output.status
+            );
+        }
+        fs::remove_file(&wasm_file).ok();
+    }
+    fs::remove_file(&wat_path).ok();
+}
+
+#[test]
+fn test_jobs_do_not_change_results() {
+    // A chain of functions spanning several partitions of 256 functions,
+    // each mixing its argument and calling the next, plus indirect calls
+    // into the middle of the chain.
+    let count = 700;
+    let mut wat = String::from(
+        "(module (import \"env\" \"assert_eq32\" (func $assert_eq32 (param i32 i32)))\n",
+    );
+    wat.push_str("(type $t (func (param i32) (result i32)))\n");
+    wat.push_str("(table 2 funcref) (elem (i32.const 0) $f300 $f650)\n");
+    for i in 0..count {
+        let next = if i + 1 < count {
+            format!("call $f{}", i + 1)
+        } else {
+            String::new()
+        };
+        wat.push_str(&format!(
+            "(func $f{i} (export \"f{i}\") (param i32) (result i32) \
+             local.get 0 i32.const {i} i32.xor i32.const 3 i32.mul {next})\n"
+        ));
+    }
+    let chain =
+        |start: i32, value: i32| (start..count).fold(value, |value, i| (value ^ i).wrapping_mul(3));
+    wat.push_str(&format!(
+        "(func $main \
+         i32.const 7 call $f0 i32.const {} call $assert_eq32 \
+         i32.const 5 i32.const 0 call_indirect (type $t) i32.const {} call $assert_eq32 \
+         i32.const 9 i32.const 1 call_indirect (type $t) i32.const {} call $assert_eq32)\n\
+         (start $main))\n",
+        chain(0, 7),
+        chain(300, 5),
+        chain(650, 9)
+    ));
+
+    let id = format!("{:?}", std::thread::current().id()).replace(['(', ')'], "");
+    let wat_path = format!("/tmp/test_jobs_{id}.wat");
+    fs::write(&wat_path, wat).unwrap();
+    let wasm_file = wat_to_wasm(&wat_path);
+
+    for level in ["-O0", "-O2"] {
+        for jobs in ["1", "4"] {
+            let output = run(&["exec", level, "--no-cache", "--jobs", jobs, &wasm_file]);
+            assert!(
+                output.status.success(),
+                "exec {level} --jobs {jobs} failed: {}",
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+
+        let outputs: Vec<Vec<u8>> = ["1", "4"]
+            .iter()
+            .map(|jobs| {
+                let object_file = format!("/tmp/test_jobs_{id}_{jobs}.o");
+                let output = run(&["compile", level, "--jobs", jobs, &wasm_file, &object_file]);
+                assert!(output.status.success(), "compile --jobs {jobs} failed");
+                let object = fs::read(&object_file).unwrap();
+                fs::remove_file(&object_file).ok();
+                object
+            })
+            .collect();
+        assert!(
+            outputs[0] == outputs[1],
+            "Objects compiled at {level} with --jobs 1 and --jobs 4 differ"
+        );
+    }
+
+    fs::remove_file(&wasm_file).ok();
+    fs::remove_file(&wat_path).ok();
+}
+
+#[test]
+fn test_debug_info() {
+    let (wat_path, _) = test_path("local_variables");
+    let wasm_file = wat_to_wasm(&wat_path);
+    let output_file = format!("/tmp/test_debug_info_{:?}", std::thread::current().id());
+
+    let output = run(&["ir", "-g", &wasm_file]);
+    assert!(output.status.success(), "IR generation should succeed");
+    let ir = String::from_utf8_lossy(&output.stderr);
+    assert!(ir.contains("!DISubprogram(name: \"_start\""), "{ir}");
+    assert!(ir.contains("!DILocation(line: "), "{ir}");
+
+    let output = run(&["compile", "-g", "--emit", "exe", &wasm_file, &output_file]);
+    assert!(
+        output.status.success(),
+        "Compilation with -g should succeed"
+    );
+    let status = Command::new(&output_file)
+        .status()
+        .expect("Failed to run compiled executable");
+    assert!(status.success(), "Debug info should not change behavior");
+
+    fs::remove_file(&wasm_file).ok();
+    fs::remove_file(&output_file).ok();
+}
+
+#[test]
+fn test_compilation_cache() {
+    let (wat_path, _) = test_path("call_indirect_basic");
+    let wasm_file = wat_to_wasm(&wat_path);
+    let cache_dir = std::env::temp_dir().join(format!(
+        "auto-parallel-wasm-cache-test-{:?}",
+        std::thread::current().id()
+    ));
+    fs::remove_dir_all(&cache_dir).ok();
+    let cache = |args: &[&str]| {
+        let output = run_with_cache_dir(args, &cache_dir);
+        assert!(output.status.success(), "cache {args:?} should succeed");
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    };
+
+    for _ in 0..2 {
+        let output = run_with_cache_dir(&["exec", &wasm_file], &cache_dir);
+        assert!(output.status.success(), "JIT execution should succeed");
+    }
+    assert!(cache(&["cache", "list"]).contains("1 entries"));
+
+    let output = run_with_cache_dir(&["exec", "-O2", &wasm_file], &cache_dir);
+    assert!(output.status.success(), "JIT execution should succeed");
+    let output = run_with_cache_dir(&["exec", "--no-cache", "-O1", &wasm_file], &cache_dir);
+    assert!(output.status.success(), "JIT execution should succeed");
+    assert!(cache(&["cache", "list"]).contains("2 entries"));
+
+    assert!(cache(&["cache", "prune", "0"]).contains("Removed 2 entries"));
+    let output = run_with_cache_dir(&["exec", &wasm_file], &cache_dir);
+    assert!(output.status.success(), "JIT execution should succeed");
+    assert!(cache(&["cache", "clear"]).contains("Removed 1 entries"));
+    assert!(cache(&["cache", "list"]).contains("0 entries"));
+
+    fs::remove_file(&wasm_file).ok();
+    fs::remove_dir_all(&cache_dir).ok();
+}
+
+static LAST_LOGGED: std::sync::atomic::AtomicI32 = std::sync::atomic::AtomicI32::new(0);
+
+extern "C" fn log_host(value: i32) {
+    LAST_LOGGED.store(value, std::sync::atomic::Ordering::SeqCst);
+}
+
+extern "C" fn scale_host(value: f64) -> f64 {
+    value * 2.5
+}
+
+#[test]
+fn test_embedding_api() {
+    use auto_parallel_wasm::{Engine, Imports, Instance, Module, Trap, Val};
+
+    let (wat_path, _) = test_path("embedding");
+    let wasm_file = wat_to_wasm(&wat_path);
+    let engine = Engine::default();
+    let module = Module::from_bytes(&engine, &fs::read(&wasm_file).unwrap()).unwrap();
+    fs::remove_file(&wasm_file).ok();
+
+    let mut imports = Imports::new();
+    imports.func("env", "log", log_host as extern "C" fn(i32));
+    let error = Instance::new(&module, &imports).err().unwrap();
+    assert!(error.to_string().contains("env::scale"), "{error}");
+    imports.func("env", "scale", log_host as extern "C" fn(i32));
+    assert!(Instance::new(&module, &imports).is_err());
+    imports.func("env", "scale", scale_host as extern "C" fn(f64) -> f64);
+
+    let instance = Instance::new(&module, &imports).unwrap();
+    let add = instance.get_typed_func::<(i32, i32), i32>("add").unwrap();
+    assert_eq!(add.call((2, 3)).unwrap(), 5);
+    assert!(instance.get_typed_func::<i32, i32>("add").is_err());
+    assert_eq!(instance.get_func("add").unwrap().ty().params().len(), 2);
+    let scaled = instance.get_typed_func::<f64, f64>("scaled").unwrap();
+    assert_eq!(scaled.call(2.0).unwrap(), 5.0);
+
+    // The start function ran when instantiating.
+    let counter = instance.get_global("counter").unwrap();
+    assert_eq!(counter.get(), Val::I32(10));
+    let bump = instance.get_typed_func::<(), i32>("bump").unwrap();
+    assert_eq!(bump.call(()).unwrap(), 11);
+    assert_eq!(LAST_LOGGED.load(std::sync::atomic::Ordering::SeqCst), 11);
+    counter.set(Val::I32(41)).unwrap();
+    assert_eq!(bump.call(()).unwrap(), 42);
+    assert!(counter.set(Val::I64(0)).is_err());
+    let limit = instance.get_global("limit").unwrap();
+    assert!(!limit.ty().mutable);
+    assert!(limit.set(Val::I64(1)).is_err());
+
+    let memory = instance.get_memory("memory").unwrap();
+    assert_eq!(memory.data_size(), 65536);
+    let store = instance.get_typed_func::<(i32, i32), ()>("store").unwrap();
+    store.call((16, 0x01020304)).unwrap();
+    let mut bytes = [0; 4];
+    memory.read(16, &mut bytes).unwrap();
+    assert_eq!(bytes, [4, 3, 2, 1]);
+    memory.write(65532, &[1, 2, 3, 4]).unwrap();
+    assert!(memory.write(65533, &[1, 2, 3, 4]).is_err());
+
+    // Wasm accesses are bounds-checked against the memory size, including
+    // the last bytes of the access.
+    store.call((65532, 7)).unwrap();
+    for address in [65533, 65536, -1] {
+        let error = store.call((address, 7)).unwrap_err();
+        assert_eq!(error.downcast_ref::<Trap>(), Some(&Trap::MemoryOutOfBounds));
+    }
+
+    let add = instance.get_func("add").unwrap();
+    assert_eq!(
+        add.call(&[Val::I32(5), Val::I32(6)]).unwrap(),
+        Some(Val::I32(11))
+    );
+    assert!(add.call(&[Val::I32(5)]).is_err());
+
+    // Traps return through the calling Wasm functions and leave the
+    // instance usable.
+    let checked_inc = instance.get_typed_func::<i32, i32>("checked_inc").unwrap();
+    let error = checked_inc.call(1).unwrap_err();
+    assert_eq!(error.downcast_ref::<Trap>(), Some(&Trap::Unreachable));
+    assert_eq!(checked_inc.call(0).unwrap(), 1);
+    let check = instance.get_func("check").unwrap();
+    assert!(check.call(&[Val::I32(2)]).is_err());
+    assert_eq!(check.call(&[Val::I32(0)]).unwrap(), Some(Val::I32(0)));
+
+    // Instances do not share state.
+    let other = Instance::new(&module, &imports).unwrap();
+    assert_eq!(other.get_global("counter").unwrap().get(), Val::I32(10));
+    assert!(other.get_func("missing").is_none());
+}
+
+#[test]
+fn test_embedding_across_threads() {
+    use auto_parallel_wasm::{Engine, Imports, Instance, Module, Val};
+
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Engine>();
+    assert_send_sync::<Module>();
+
+    let (wat_path, _) = test_path("embedding");
+    let wasm_file = wat_to_wasm(&wat_path);
+    let wasm_bytes = fs::read(&wasm_file).unwrap();
+    fs::remove_file(&wasm_file).ok();
+    let engine = Engine::default();
+    let module = Module::from_bytes(&engine, &wasm_bytes).unwrap();
+
+    std::thread::scope(|scope| {
+        for thread in 0..8 {
+            let module = &module;
+            scope.spawn(move || {
+                let mut imports = Imports::new();
+                imports.func("env", "log", log_host as extern "C" fn(i32));
+                imports.func("env", "scale", scale_host as extern "C" fn(f64) -> f64);
+                let instance = Instance::new(module, &imports).unwrap();
+                let add = instance.get_typed_func::<(i32, i32), i32>("add").unwrap();
+                let bump = instance.get_typed_func::<(), i32>("bump").unwrap();
+                for i in 0..1000 {
+                    assert_eq!(add.call((thread, i)).unwrap(), thread + i);
+                    bump.call(()).unwrap();
+                }
+                let counter = instance.get_global("counter").unwrap();
+                assert_eq!(counter.get(), Val::I32(1010));
+            });
+        }
+        // Modules also compile on several threads at once, and the last
+        // clone may be dropped on a thread other than the one compiling it.
+        for _ in 0..4 {
+            scope.spawn(|| {
+                let module = Module::from_bytes(&engine, &wasm_bytes).unwrap();
+                let clone = module.clone();
+                drop(module);
+                std::thread::spawn(move || drop(clone)).join().unwrap();
+            });
+        }
+    });
+}
+
+#[test]
+fn test_unsupported_operator_errors() {
+    use auto_parallel_wasm::{Engine, Module};
+
+    let (wat_path, _) = test_path("br_table");
+    let wasm_file = wat_to_wasm(&wat_path);
+    let output = run(&["exec", &wasm_file]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("func 0 at offset 0x") && stderr.contains("br_table is not supported"),
+        "{stderr}"
+    );
+
+    let error = Module::from_bytes(&Engine::default(), &fs::read(&wasm_file).unwrap())
+        .err()
+        .unwrap();
+    assert!(
+        error.to_string().contains("br_table is not supported"),
+        "{error}"
+    );
+    fs::remove_file(&wasm_file).ok();
+}
+
+#[test]
+fn test_c_api_example() {
+    let (wat_path, _) = test_path("embedding");
+    let wasm_file = wat_to_wasm(&wat_path);
+    let status = Command::new("cargo")
+        .args(["build", "--lib", "--quiet"])
+        .status()
+        .expect("Failed to run cargo build");
+    assert!(status.success(), "Building the C library should succeed");
+
+    // Test binaries live in target/<profile>/deps, next to the library.
+    let lib_dir = std::env::current_exe()
+        .unwrap()
+        .parent()
+        .unwrap()
+        .parent()
+        .unwrap()
+        .to_path_buf();
+    let id = format!("{:?}", std::thread::current().id());
+    let program = std::env::temp_dir().join(format!("test_c_api_{}", id.replace(['(', ')'], "")));
+    let status = Command::new("cc")
+        .arg("-o")
+        .arg(&program)
+        .arg("examples/c/embed.c")
+        .arg("-Iinclude")
+        .arg(format!("-L{}", lib_dir.display()))
+        .arg("-lauto_parallel_wasm")
+        .arg(format!("-Wl,-rpath,{}", lib_dir.display()))
+        .status()
+        .expect("Failed to run cc");
+    assert!(status.success(), "The C example should compile");
+
+    let output = Command::new(&program)
+        .arg(&wasm_file)
+        .output()
+        .expect("Failed to run the C example");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        output.status.success(),
+        "The C example should succeed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        stdout,
+        "add(2, 3) = 5\n\
+         scaled(1.25) = 2.5\n\
+         log: 11\n\
+         bump() = 11\n\
+         check(1) trapped: wasm trap: unreachable executed\n\
+         memory: 65536 bytes, round trip ok\n\
+         out of bounds read: Memory access out of bounds: 4 bytes at offset 65534\n"
+    );
+    fs::remove_file(&program).ok();
+}
+
+#[test]
+fn test_exec_limits() {
+    let (wat_path, _) = test_path("for_loop");
+    let wasm_file = wat_to_wasm(&wat_path);
+    // One unit of fuel for entering the start function and one per
+    // iteration of its three-iteration loop.
+    let output = run(&["exec", "--fuel", "4", &wasm_file]);
+    assert!(
+        output.status.success(),
+        "Four units of fuel should be enough"
+    );
+    let output = run(&["exec", "--fuel", "3", &wasm_file]);
+    assert!(
+        !output.status.success(),
+        "Three units of fuel should not be"
+    );
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("all fuel consumed"), "{stderr}");
+
+    let (wat_path, _) = test_path("infinite_loop");
+    let wasm_file = wat_to_wasm(&wat_path);
+    let output = run(&["exec", "--fuel", "1000000", &wasm_file]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("all fuel consumed"), "{stderr}");
+    let output = run(&["exec", "--timeout", "200ms", &wasm_file]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("execution interrupted"), "{stderr}");
+
+    let (wat_path, _) = test_path("runaway_recursion");
+    let wasm_file = wat_to_wasm(&wat_path);
+    let output = run(&["exec", "--max-stack", "256K", &wasm_file]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("call stack exhausted"), "{stderr}");
+
+    let output = run(&["compile", "--fuel", "10", &wasm_file, "/tmp/unused.o"]);
+    assert!(
+        !output.status.success(),
+        "--fuel should be rejected by compile"
+    );
+    fs::remove_file(&wasm_file).ok();
+}
+
+#[test]
+fn test_exec_profile() {
+    let (wat_path, _) = test_path("for_loop");
+    let wasm_file = wat_to_wasm(&wat_path);
+    let output = run(&["exec", "--profile", "--perf-map", &wasm_file]);
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let count_of = |location: &str| {
+        stderr
+            .lines()
+            .find(|line| line.contains(location))
+            .and_then(|line| line.split_whitespace().next())
+            .and_then(|count| count.parse::<u64>().ok())
+    };
+    assert_eq!(count_of("$_start: loop at 0x"), Some(3), "{stderr}");
+    assert_eq!(count_of("$_start: calls"), Some(1), "{stderr}");
+
+    let perf_map = stderr
+        .lines()
+        .find_map(|line| line.strip_prefix("perf map: "))
+        .expect("perf map path should be printed");
+    let contents = fs::read_to_string(perf_map).unwrap();
+    assert!(
+        contents.lines().any(|line| line.ends_with(" $_start")),
+        "{contents}"
+    );
+    // Sizes come from the symbol table, so functions never overlap.
+    let ranges: Vec<(u64, u64)> = contents
+        .lines()
+        .map(|line| {
+            let mut fields = line.split_whitespace();
+            let mut hex = || u64::from_str_radix(fields.next().unwrap(), 16).unwrap();
+            (hex(), hex())
+        })
+        .collect();
+    assert!(ranges.iter().all(|&(_, size)| size > 0), "{contents}");
+    assert!(
+        ranges
+            .windows(2)
+            .all(|pair| pair[0].0 + pair[0].1 <= pair[1].0),
+        "{contents}"
+    );
+    fs::remove_file(perf_map).ok();
+
+    let output = run(&["exec", "--profile", "--lazy", &wasm_file]);
+    assert!(!output.status.success());
+    fs::remove_file(&wasm_file).ok();
+}
+
+#[test]
+fn test_embedding_limits() {
+    use auto_parallel_wasm::{CompilerOptions, Engine, Imports, Instance, Module, Trap};
+    use std::thread;
+    use std::time::Duration;
+
+    let (wat_path, _) = test_path("limits");
+    let wasm_file = wat_to_wasm(&wat_path);
+    let bytes = fs::read(&wasm_file).unwrap();
+    fs::remove_file(&wasm_file).ok();
+
+    let unlimited = Module::from_bytes(&Engine::default(), &bytes).unwrap();
+    let instance = Instance::new(&unlimited, &Imports::new()).unwrap();
+    assert!(instance.set_fuel(10).is_err());
+    assert_eq!(instance.fuel(), None);
+    assert!(instance.interrupt_handle().is_none());
+
+    let engine = Engine::new(CompilerOptions {
+        consume_fuel: true,
+        interruptible: true,
+        ..CompilerOptions::default()
+    });
+    let module = Module::from_bytes(&engine, &bytes).unwrap();
+    let instance = Instance::new(&module, &Imports::new()).unwrap();
+    let count = instance.get_typed_func::<i32, i32>("count").unwrap();
+
+    // Entering `count` costs one unit of fuel, and each loop iteration one.
+    instance.set_fuel(100).unwrap();
+    assert_eq!(count.call(10).unwrap(), 10);
+    assert_eq!(instance.fuel(), Some(89));
+    instance.set_fuel(11).unwrap();
+    assert_eq!(count.call(10).unwrap(), 10);
+    assert_eq!(instance.fuel(), Some(0));
+    instance.set_fuel(10).unwrap();
+    let error = count.call(10).unwrap_err();
+    assert_eq!(error.downcast_ref::<Trap>(), Some(&Trap::OutOfFuel));
+    assert_eq!(instance.fuel(), Some(0));
+
+    instance.set_fuel(u64::MAX).unwrap();
+    let handle = instance.interrupt_handle().unwrap();
+    let watchdog = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(50));
+        handle.interrupt();
+    });
+    let spin = instance.get_typed_func::<(), ()>("spin").unwrap();
+    let error = spin.call(()).unwrap_err();
+    assert_eq!(error.downcast_ref::<Trap>(), Some(&Trap::Interrupted));
+    watchdog.join().unwrap();
+    assert_eq!(count.call(3).unwrap(), 3);
+    let engine = Engine::new(CompilerOptions {
+        check_stack: true,
+        ..CompilerOptions::default()
+    });
+    let module = Module::from_bytes(&engine, &bytes).unwrap();
+    let instance = Instance::new(&module, &Imports::new()).unwrap();
+    let deep = instance.get_typed_func::<i32, i32>("deep").unwrap();
+    instance.set_max_stack(64 << 10).unwrap();
+    assert_eq!(deep.call(100).unwrap(), 100);
+    let error = deep.call(10_000_000).unwrap_err();
+    assert_eq!(error.downcast_ref::<Trap>(), Some(&Trap::StackOverflow));
+    assert_eq!(deep.call(100).unwrap(), 100);
+    assert!(
+        Instance::new(&unlimited, &Imports::new())
+            .unwrap()
+            .set_max_stack(1 << 20)
+            .is_err()
+    );
+}
@@ -6,7 +6,7 @@ declare void @assert_eq32(i32 %0, i32 %1)
 
 declare void @assert_eq64(i64 %0, i64 %1)
 
-define void @_start() {
+define internal void @_start(ptr %vmctx) {
 entry:
   call void @assert_eq64(i64 42, i64 42)
   call void @assert_eq64(i64 9223372036854775807, i64 9223372036854775807)
@@ -16,8 +16,33 @@ entry:
   ret void
 }
 
+declare ptr @calloc(i64 %0, i64 %1)
+
+declare void @free(ptr %0)
+
+define ptr @wasm_instance_new() {
+entry:
+  %vmctx = call ptr @calloc(i64 1, i64 16)
+  %vmctx_failed = icmp eq ptr %vmctx, null
+  br i1 %vmctx_failed, label %out_of_memory, label %vmctx_allocated
+
+out_of_memory:                                    ; preds = %entry
+  ret ptr null
+
+vmctx_allocated:                                  ; preds = %entry
+  ret ptr %vmctx
+}
+
+define void @wasm_instance_free(ptr %vmctx) {
+entry:
+  call void @free(ptr %vmctx)
+  ret void
+}
+
 define i32 @main() {
 entry:
-  call void @_start()
+  %vmctx = call ptr @wasm_instance_new()
+  call void @_start(ptr %vmctx)
+  call void @wasm_instance_free(ptr %vmctx)
   ret i32 0
-}
\ No newline at end of file
+}
@@ -2,57 +2,71 @@
 source_filename = "wasm_aot"
 target datalayout = "e-m:e-p270:32:32-p271:32:32-p272:64:64-i64:64-i128:128-f80:128-n8:16:32:64-S128"
 
-@table_0 = global [15 x ptr] [ptr @func_1, ptr @func_2, ptr @func_3, ptr @func_4, ptr @func_5, ptr @func_6, ptr @func_7, ptr @func_8, ptr @func_9, ptr @func_10, ptr @func_11, ptr @func_12, ptr @func_13, ptr @func_14, ptr @func_15]
+@func_1.funcref = private constant { ptr, i32 } { ptr @func_1, i32 0 }
+@func_2.funcref = private constant { ptr, i32 } { ptr @func_2, i32 1 }
+@func_3.funcref = private constant { ptr, i32 } { ptr @func_3, i32 2 }
+@func_4.funcref = private constant { ptr, i32 } { ptr @func_4, i32 3 }
+@func_5.funcref = private constant { ptr, i32 } { ptr @func_5, i32 4 }
+@func_6.funcref = private constant { ptr, i32 } { ptr @func_6, i32 5 }
+@func_7.funcref = private constant { ptr, i32 } { ptr @func_7, i32 6 }
+@func_8.funcref = private constant { ptr, i32 } { ptr @func_8, i32 7 }
+@func_9.funcref = private constant { ptr, i32 } { ptr @func_9, i32 8 }
+@func_10.funcref = private constant { ptr, i32 } { ptr @func_10, i32 9 }
+@func_11.funcref = private constant { ptr, i32 } { ptr @func_11, i32 10 }
+@func_12.funcref = private constant { ptr, i32 } { ptr @func_12, i32 11 }
+@func_13.funcref = private constant { ptr, i32 } { ptr @func_13, i32 12 }
+@func_14.funcref = private constant { ptr, i32 } { ptr @func_14, i32 13 }
+@func_15.funcref = private constant { ptr, i32 } { ptr @func_15, i32 14 }
 
 declare void @assert_eq32(i32 %0, i32 %1)
 
 declare void @assert_eq64(i64 %0, i64 %1)
 
-define void @func_1() {
+define internal void @func_1(ptr %vmctx) {
 entry:
   ret void
 }
 
-define void @func_2(i32 %0) {
+define internal void @func_2(ptr %vmctx, i32 %0) {
 entry:
   ret void
 }
 
-define i32 @func_3(i32 %0, i32 %1) {
+define internal i32 @func_3(ptr %vmctx, i32 %0, i32 %1) {
 entry:
   %add = add i32 %0, %1
   ret i32 %add
 }
 
-define i32 @func_4(i32 %0) {
+define internal i32 @func_4(ptr %vmctx, i32 %0) {
 entry:
   %mul = mul i32 %0, 10
   ret i32 %mul
 }
 
-define void @func_5(i32 %0, i32 %1, i32 %2) {
+define internal void @func_5(ptr %vmctx, i32 %0, i32 %1, i32 %2) {
 entry:
   ret void
 }
 
-define i32 @func_6(i32 %0, i32 %1, i32 %2) {
+define internal i32 @func_6(ptr %vmctx, i32 %0, i32 %1, i32 %2) {
 entry:
   %add = add i32 %0, %1
   %add1 = add i32 %add, %2
   ret i32 %add1
 }
 
-define void @func_7(i32 %0, i32 %1) {
+define internal void @func_7(ptr %vmctx, i32 %0, i32 %1) {
 entry:
   ret void
 }
 
-define void @func_8(i32 %0, i32 %1, i32 %2, i32 %3) {
+define internal void @func_8(ptr %vmctx, i32 %0, i32 %1, i32 %2, i32 %3) {
 entry:
   ret void
 }
 
-define i32 @func_9(i32 %0, i32 %1, i32 %2, i32 %3) {
+define internal i32 @func_9(ptr %vmctx, i32 %0, i32 %1, i32 %2, i32 %3) {
 entry:
   %add = add i32 %0, %1
   %add1 = add i32 %add, %2
@@ -60,17 +74,17 @@ entry:
   ret i32 %add2
 }
 
-define i32 @func_10() {
+define internal i32 @func_10(ptr %vmctx) {
 entry:
   ret i32 42
 }
 
-define void @func_11(i32 %0, i32 %1, i32 %2, i32 %3, i32 %4) {
+define internal void @func_11(ptr %vmctx, i32 %0, i32 %1, i32 %2, i32 %3, i32 %4) {
 entry:
   ret void
 }
 
-define i32 @func_12(i32 %0, i32 %1, i32 %2, i32 %3, i32 %4) {
+define internal i32 @func_12(ptr %vmctx, i32 %0, i32 %1, i32 %2, i32 %3, i32 %4) {
 entry:
   %add = add i32 %0, %1
   %add1 = add i32 %add, %2
@@ -79,7 +93,7 @@ entry:
   ret i32 %add3
 }
 
-define i32 @func_13(i32 %0, i32 %1, i32 %2, i32 %3, i32 %4, i32 %5) {
+define internal i32 @func_13(ptr %vmctx, i32 %0, i32 %1, i32 %2, i32 %3, i32 %4, i32 %5) {
 entry:
   %add = add i32 %0, %1
   %add1 = add i32 %add, %2
@@ -89,12 +103,12 @@ entry:
   ret i32 %add4
 }
 
-define void @func_14(i32 %0, i32 %1, i32 %2, i32 %3, i32 %4, i32 %5, i32 %6) {
+define internal void @func_14(ptr %vmctx, i32 %0, i32 %1, i32 %2, i32 %3, i32 %4, i32 %5, i32 %6) {
 entry:
   ret void
 }
 
-define i32 @func_15(i32 %0, i32 %1, i32 %2, i32 %3, i32 %4, i32 %5, i32 %6, i32 %7, i32 %8, i32 %9, i32 %10) {
+define internal i32 @func_15(ptr %vmctx, i32 %0, i32 %1, i32 %2, i32 %3, i32 %4, i32 %5, i32 %6, i32 %7, i32 %8, i32 %9, i32 %10) {
 entry:
   %add = add i32 %0, %1
   %add1 = add i32 %add, %2
@@ -109,154 +123,356 @@ entry:
   ret i32 %add9
 }
 
-define void @_start() {
+define internal void @_start(ptr %vmctx) {
 entry:
-  br i1 true, label %valid_call, label %trap
+  %table_base_ptr = getelementptr inbounds i8, ptr %vmctx, i64 16
+  %table_base = load ptr, ptr %table_base_ptr, align 8
+  %table_size_ptr = getelementptr inbounds i8, ptr %vmctx, i64 24
+  %table_size = load i32, ptr %table_size_ptr, align 4
+  %bounds_check = icmp ult i32 0, %table_size
+  br i1 %bounds_check, label %valid_call, label %trap
 
 valid_call:                                       ; preds = %entry
-  %func_ptr = load ptr, ptr @table_0, align 8
-  %null_check = icmp eq ptr %func_ptr, null
-  br i1 %null_check, label %trap, label %do_call
+  %func_ref_ptr = getelementptr ptr, ptr %table_base, i32 0
+  %func_ref = load ptr, ptr %func_ref_ptr, align 8
+  %null_check = icmp eq ptr %func_ref, null
+  br i1 %null_check, label %trap, label %check_type
 
-trap:                                             ; preds = %valid_call, %entry
+trap:                                             ; preds = %check_type, %valid_call, %entry
+  call void @llvm.trap()
   unreachable
 
 after_call:                                       ; preds = %do_call
-  br i1 true, label %valid_call1, label %trap2
-
-do_call:                                          ; preds = %valid_call
-  call void %func_ptr()
+  %table_base_ptr1 = getelementptr inbounds i8, ptr %vmctx, i64 16
+  %table_base2 = load ptr, ptr %table_base_ptr1, align 8
+  %table_size_ptr3 = getelementptr inbounds i8, ptr %vmctx, i64 24
+  %table_size4 = load i32, ptr %table_size_ptr3, align 4
+  %bounds_check5 = icmp ult i32 1, %table_size4
+  br i1 %bounds_check5, label %valid_call6, label %trap7
+
+check_type:                                       ; preds = %valid_call
+  %signature_ptr = getelementptr inbounds { ptr, i32 }, ptr %func_ref, i32 0, i32 1
+  %signature = load i32, ptr %signature_ptr, align 4
+  %signature_check = icmp eq i32 %signature, 0
+  br i1 %signature_check, label %do_call, label %trap
+
+do_call:                                          ; preds = %check_type
+  %func_ptr = load ptr, ptr %func_ref, align 8
+  call void %func_ptr(ptr %vmctx)
   br label %after_call
 
-valid_call1:                                      ; preds = %after_call
-  %func_ptr4 = load ptr, ptr getelementptr inbounds ([15 x ptr], ptr @table_0, i32 0, i32 1), align 8
-  %null_check5 = icmp eq ptr %func_ptr4, null
-  br i1 %null_check5, label %trap2, label %do_call6
+valid_call6:                                      ; preds = %after_call
+  %func_ref_ptr9 = getelementptr ptr, ptr %table_base2, i32 1
+  %func_ref10 = load ptr, ptr %func_ref_ptr9, align 8
+  %null_check11 = icmp eq ptr %func_ref10, null
+  br i1 %null_check11, label %trap7, label %check_type12
 
-trap2:                                            ; preds = %valid_call1, %after_call
+trap7:                                            ; preds = %check_type12, %valid_call6, %after_call
+  call void @llvm.trap()
   unreachable
 
-after_call3:                                      ; preds = %do_call6
-  br i1 true, label %valid_call7, label %trap8
-
-do_call6:                                         ; preds = %valid_call1
-  call void %func_ptr4(i32 100)
-  br label %after_call3
-
-valid_call7:                                      ; preds = %after_call3
-  %func_ptr10 = load ptr, ptr getelementptr inbounds ([15 x ptr], ptr @table_0, i32 0, i32 2), align 8
-  %null_check11 = icmp eq ptr %func_ptr10, null
-  br i1 %null_check11, label %trap8, label %do_call12
-
-trap8:                                            ; preds = %valid_call7, %after_call3
+after_call8:                                      ; preds = %do_call13
+  %table_base_ptr18 = getelementptr inbounds i8, ptr %vmctx, i64 16
+  %table_base19 = load ptr, ptr %table_base_ptr18, align 8
+  %table_size_ptr20 = getelementptr inbounds i8, ptr %vmctx, i64 24
+  %table_size21 = load i32, ptr %table_size_ptr20, align 4
+  %bounds_check22 = icmp ult i32 2, %table_size21
+  br i1 %bounds_check22, label %valid_call23, label %trap24
+
+check_type12:                                     ; preds = %valid_call6
+  %signature_ptr14 = getelementptr inbounds { ptr, i32 }, ptr %func_ref10, i32 0, i32 1
+  %signature15 = load i32, ptr %signature_ptr14, align 4
+  %signature_check16 = icmp eq i32 %signature15, 1
+  br i1 %signature_check16, label %do_call13, label %trap7
+
+do_call13:                                        ; preds = %check_type12
+  %func_ptr17 = load ptr, ptr %func_ref10, align 8
+  call void %func_ptr17(ptr %vmctx, i32 100)
+  br label %after_call8
+
+valid_call23:                                     ; preds = %after_call8
+  %func_ref_ptr26 = getelementptr ptr, ptr %table_base19, i32 2
+  %func_ref27 = load ptr, ptr %func_ref_ptr26, align 8
+  %null_check28 = icmp eq ptr %func_ref27, null
+  br i1 %null_check28, label %trap24, label %check_type29
+
+trap24:                                           ; preds = %check_type29, %valid_call23, %after_call8
+  call void @llvm.trap()
   unreachable
 
-after_call9:                                      ; preds = %do_call12
+after_call25:                                     ; preds = %do_call30
   call void @assert_eq32(i32 %indirect_call, i32 12)
-  br i1 true, label %valid_call13, label %trap14
-
-do_call12:                                        ; preds = %valid_call7
-  %indirect_call = call i32 %func_ptr10(i32 5, i32 7)
-  br label %after_call9
-
-valid_call13:                                     ; preds = %after_call9
-  %func_ptr16 = load ptr, ptr getelementptr inbounds ([15 x ptr], ptr @table_0, i32 0, i32 3), align 8
-  %null_check17 = icmp eq ptr %func_ptr16, null
-  br i1 %null_check17, label %trap14, label %do_call18
-
-trap14:                                           ; preds = %valid_call13, %after_call9
+  %table_base_ptr35 = getelementptr inbounds i8, ptr %vmctx, i64 16
+  %table_base36 = load ptr, ptr %table_base_ptr35, align 8
+  %table_size_ptr37 = getelementptr inbounds i8, ptr %vmctx, i64 24
+  %table_size38 = load i32, ptr %table_size_ptr37, align 4
+  %bounds_check39 = icmp ult i32 3, %table_size38
+  br i1 %bounds_check39, label %valid_call40, label %trap41
+
+check_type29:                                     ; preds = %valid_call23
+  %signature_ptr31 = getelementptr inbounds { ptr, i32 }, ptr %func_ref27, i32 0, i32 1
+  %signature32 = load i32, ptr %signature_ptr31, align 4
+  %signature_check33 = icmp eq i32 %signature32, 2
+  br i1 %signature_check33, label %do_call30, label %trap24
+
+do_call30:                                        ; preds = %check_type29
+  %func_ptr34 = load ptr, ptr %func_ref27, align 8
+  %indirect_call = call i32 %func_ptr34(ptr %vmctx, i32 5, i32 7)
+  br label %after_call25
+
+valid_call40:                                     ; preds = %after_call25
+  %func_ref_ptr43 = getelementptr ptr, ptr %table_base36, i32 3
+  %func_ref44 = load ptr, ptr %func_ref_ptr43, align 8
+  %null_check45 = icmp eq ptr %func_ref44, null
+  br i1 %null_check45, label %trap41, label %check_type46
+
+trap41:                                           ; preds = %check_type46, %valid_call40, %after_call25
+  call void @llvm.trap()
   unreachable
 
-after_call15:                                     ; preds = %do_call18
-  call void @assert_eq32(i32 %indirect_call19, i32 50)
-  br i1 true, label %valid_call20, label %trap21
-
-do_call18:                                        ; preds = %valid_call13
-  %indirect_call19 = call i32 %func_ptr16(i32 5)
-  br label %after_call15
+after_call42:                                     ; preds = %do_call47
+  call void @assert_eq32(i32 %indirect_call52, i32 50)
+  %table_base_ptr53 = getelementptr inbounds i8, ptr %vmctx, i64 16
+  %table_base54 = load ptr, ptr %table_base_ptr53, align 8
+  %table_size_ptr55 = getelementptr inbounds i8, ptr %vmctx, i64 24
+  %table_size56 = load i32, ptr %table_size_ptr55, align 4
+  %bounds_check57 = icmp ult i32 4, %table_size56
+  br i1 %bounds_check57, label %valid_call58, label %trap59
+
+check_type46:                                     ; preds = %valid_call40
+  %signature_ptr48 = getelementptr inbounds { ptr, i32 }, ptr %func_ref44, i32 0, i32 1
+  %signature49 = load i32, ptr %signature_ptr48, align 4
+  %signature_check50 = icmp eq i32 %signature49, 3
+  br i1 %signature_check50, label %do_call47, label %trap41
+
+do_call47:                                        ; preds = %check_type46
+  %func_ptr51 = load ptr, ptr %func_ref44, align 8
+  %indirect_call52 = call i32 %func_ptr51(ptr %vmctx, i32 5)
+  br label %after_call42
 
-valid_call20:                                     ; preds = %after_call15
-  %func_ptr23 = load ptr, ptr getelementptr inbounds ([15 x ptr], ptr @table_0, i32 0, i32 4), align 8
-  %null_check24 = icmp eq ptr %func_ptr23, null
-  br i1 %null_check24, label %trap21, label %do_call25
+valid_call58:                                     ; preds = %after_call42
+  %func_ref_ptr61 = getelementptr ptr, ptr %table_base54, i32 4
+  %func_ref62 = load ptr, ptr %func_ref_ptr61, align 8
+  %null_check63 = icmp eq ptr %func_ref62, null
+  br i1 %null_check63, label %trap59, label %check_type64
 
-trap21:                                           ; preds = %valid_call20, %after_call15
+trap59:                                           ; preds = %check_type64, %valid_call58, %after_call42
+  call void @llvm.trap()
   unreachable
 
-after_call22:                                     ; preds = %do_call25
-  br i1 true, label %valid_call26, label %trap27
-
-do_call25:                                        ; preds = %valid_call20
-  call void %func_ptr23(i32 1, i32 2, i32 3)
-  br label %after_call22
-
-valid_call26:                                     ; preds = %after_call22
-  %func_ptr29 = load ptr, ptr getelementptr inbounds ([15 x ptr], ptr @table_0, i32 0, i32 5), align 8
-  %null_check30 = icmp eq ptr %func_ptr29, null
-  br i1 %null_check30, label %trap27, label %do_call31
-
-trap27:                                           ; preds = %valid_call26, %after_call22
+after_call60:                                     ; preds = %do_call65
+  %table_base_ptr70 = getelementptr inbounds i8, ptr %vmctx, i64 16
+  %table_base71 = load ptr, ptr %table_base_ptr70, align 8
+  %table_size_ptr72 = getelementptr inbounds i8, ptr %vmctx, i64 24
+  %table_size73 = load i32, ptr %table_size_ptr72, align 4
+  %bounds_check74 = icmp ult i32 5, %table_size73
+  br i1 %bounds_check74, label %valid_call75, label %trap76
+
+check_type64:                                     ; preds = %valid_call58
+  %signature_ptr66 = getelementptr inbounds { ptr, i32 }, ptr %func_ref62, i32 0, i32 1
+  %signature67 = load i32, ptr %signature_ptr66, align 4
+  %signature_check68 = icmp eq i32 %signature67, 4
+  br i1 %signature_check68, label %do_call65, label %trap59
+
+do_call65:                                        ; preds = %check_type64
+  %func_ptr69 = load ptr, ptr %func_ref62, align 8
+  call void %func_ptr69(ptr %vmctx, i32 1, i32 2, i32 3)
+  br label %after_call60
+
+valid_call75:                                     ; preds = %after_call60
+  %func_ref_ptr78 = getelementptr ptr, ptr %table_base71, i32 5
+  %func_ref79 = load ptr, ptr %func_ref_ptr78, align 8
+  %null_check80 = icmp eq ptr %func_ref79, null
+  br i1 %null_check80, label %trap76, label %check_type81
+
+trap76:                                           ; preds = %check_type81, %valid_call75, %after_call60
+  call void @llvm.trap()
   unreachable
 
-after_call28:                                     ; preds = %do_call31
-  call void @assert_eq32(i32 %indirect_call32, i32 6)
-  br i1 true, label %valid_call33, label %trap34
-
-do_call31:                                        ; preds = %valid_call26
-  %indirect_call32 = call i32 %func_ptr29(i32 1, i32 2, i32 3)
-  br label %after_call28
-
-valid_call33:                                     ; preds = %after_call28
-  %func_ptr36 = load ptr, ptr getelementptr inbounds ([15 x ptr], ptr @table_0, i32 0, i32 9), align 8
-  %null_check37 = icmp eq ptr %func_ptr36, null
-  br i1 %null_check37, label %trap34, label %do_call38
+after_call77:                                     ; preds = %do_call82
+  call void @assert_eq32(i32 %indirect_call87, i32 6)
+  %table_base_ptr88 = getelementptr inbounds i8, ptr %vmctx, i64 16
+  %table_base89 = load ptr, ptr %table_base_ptr88, align 8
+  %table_size_ptr90 = getelementptr inbounds i8, ptr %vmctx, i64 24
+  %table_size91 = load i32, ptr %table_size_ptr90, align 4
+  %bounds_check92 = icmp ult i32 9, %table_size91
+  br i1 %bounds_check92, label %valid_call93, label %trap94
+
+check_type81:                                     ; preds = %valid_call75
+  %signature_ptr83 = getelementptr inbounds { ptr, i32 }, ptr %func_ref79, i32 0, i32 1
+  %signature84 = load i32, ptr %signature_ptr83, align 4
+  %signature_check85 = icmp eq i32 %signature84, 5
+  br i1 %signature_check85, label %do_call82, label %trap76
+
+do_call82:                                        ; preds = %check_type81
+  %func_ptr86 = load ptr, ptr %func_ref79, align 8
+  %indirect_call87 = call i32 %func_ptr86(ptr %vmctx, i32 1, i32 2, i32 3)
+  br label %after_call77
+
+valid_call93:                                     ; preds = %after_call77
+  %func_ref_ptr96 = getelementptr ptr, ptr %table_base89, i32 9
+  %func_ref97 = load ptr, ptr %func_ref_ptr96, align 8
+  %null_check98 = icmp eq ptr %func_ref97, null
+  br i1 %null_check98, label %trap94, label %check_type99
+
+trap94:                                           ; preds = %check_type99, %valid_call93, %after_call77
+  call void @llvm.trap()
+  unreachable
 
-trap34:                                           ; preds = %valid_call33, %after_call28
+after_call95:                                     ; preds = %do_call100
+  call void @assert_eq32(i32 %indirect_call105, i32 42)
+  %table_base_ptr106 = getelementptr inbounds i8, ptr %vmctx, i64 16
+  %table_base107 = load ptr, ptr %table_base_ptr106, align 8
+  %table_size_ptr108 = getelementptr inbounds i8, ptr %vmctx, i64 24
+  %table_size109 = load i32, ptr %table_size_ptr108, align 4
+  %bounds_check110 = icmp ult i32 8, %table_size109
+  br i1 %bounds_check110, label %valid_call111, label %trap112
+
+check_type99:                                     ; preds = %valid_call93
+  %signature_ptr101 = getelementptr inbounds { ptr, i32 }, ptr %func_ref97, i32 0, i32 1
+  %signature102 = load i32, ptr %signature_ptr101, align 4
+  %signature_check103 = icmp eq i32 %signature102, 9
+  br i1 %signature_check103, label %do_call100, label %trap94
+
+do_call100:                                       ; preds = %check_type99
+  %func_ptr104 = load ptr, ptr %func_ref97, align 8
+  %indirect_call105 = call i32 %func_ptr104(ptr %vmctx)
+  br label %after_call95
+
+valid_call111:                                    ; preds = %after_call95
+  %func_ref_ptr114 = getelementptr ptr, ptr %table_base107, i32 8
+  %func_ref115 = load ptr, ptr %func_ref_ptr114, align 8
+  %null_check116 = icmp eq ptr %func_ref115, null
+  br i1 %null_check116, label %trap112, label %check_type117
+
+trap112:                                          ; preds = %check_type117, %valid_call111, %after_call95
+  call void @llvm.trap()
   unreachable
 
-after_call35:                                     ; preds = %do_call38
-  call void @assert_eq32(i32 %indirect_call39, i32 42)
-  br i1 true, label %valid_call40, label %trap41
+after_call113:                                    ; preds = %do_call118
+  call void @assert_eq32(i32 %indirect_call123, i32 10)
+  %table_base_ptr124 = getelementptr inbounds i8, ptr %vmctx, i64 16
+  %table_base125 = load ptr, ptr %table_base_ptr124, align 8
+  %table_size_ptr126 = getelementptr inbounds i8, ptr %vmctx, i64 24
+  %table_size127 = load i32, ptr %table_size_ptr126, align 4
+  %bounds_check128 = icmp ult i32 11, %table_size127
+  br i1 %bounds_check128, label %valid_call129, label %trap130
+
+check_type117:                                    ; preds = %valid_call111
+  %signature_ptr119 = getelementptr inbounds { ptr, i32 }, ptr %func_ref115, i32 0, i32 1
+  %signature120 = load i32, ptr %signature_ptr119, align 4
+  %signature_check121 = icmp eq i32 %signature120, 8
+  br i1 %signature_check121, label %do_call118, label %trap112
+
+do_call118:                                       ; preds = %check_type117
+  %func_ptr122 = load ptr, ptr %func_ref115, align 8
+  %indirect_call123 = call i32 %func_ptr122(ptr %vmctx, i32 1, i32 2, i32 3, i32 4)
+  br label %after_call113
+
+valid_call129:                                    ; preds = %after_call113
+  %func_ref_ptr132 = getelementptr ptr, ptr %table_base125, i32 11
+  %func_ref133 = load ptr, ptr %func_ref_ptr132, align 8
+  %null_check134 = icmp eq ptr %func_ref133, null
+  br i1 %null_check134, label %trap130, label %check_type135
+
+trap130:                                          ; preds = %check_type135, %valid_call129, %after_call113
+  call void @llvm.trap()
+  unreachable
 
-do_call38:                                        ; preds = %valid_call33
-  %indirect_call39 = call i32 %func_ptr36()
-  br label %after_call35
+after_call131:                                    ; preds = %do_call136
+  call void @assert_eq32(i32 %indirect_call141, i32 15)
+  ret void
 
-valid_call40:                                     ; preds = %after_call35
-  %func_ptr43 = load ptr, ptr getelementptr inbounds ([15 x ptr], ptr @table_0, i32 0, i32 8), align 8
-  %null_check44 = icmp eq ptr %func_ptr43, null
-  br i1 %null_check44, label %trap41, label %do_call45
+check_type135:                                    ; preds = %valid_call129
+  %signature_ptr137 = getelementptr inbounds { ptr, i32 }, ptr %func_ref133, i32 0, i32 1
+  %signature138 = load i32, ptr %signature_ptr137, align 4
+  %signature_check139 = icmp eq i32 %signature138, 11
+  br i1 %signature_check139, label %do_call136, label %trap130
 
-trap41:                                           ; preds = %valid_call40, %after_call35
-  unreachable
+do_call136:                                       ; preds = %check_type135
+  %func_ptr140 = load ptr, ptr %func_ref133, align 8
+  %indirect_call141 = call i32 %func_ptr140(ptr %vmctx, i32 1, i32 2, i32 3, i32 4, i32 5)
+  br label %after_call131
+}
 
-after_call42:                                     ; preds = %do_call45
-  call void @assert_eq32(i32 %indirect_call46, i32 10)
-  br i1 true, label %valid_call47, label %trap48
+; Function Attrs: cold noreturn nounwind
+declare void @llvm.trap() #0
 
-do_call45:                                        ; preds = %valid_call40
-  %indirect_call46 = call i32 %func_ptr43(i32 1, i32 2, i32 3, i32 4)
-  br label %after_call42
+declare ptr @calloc(i64 %0, i64 %1)
 
-valid_call47:                                     ; preds = %after_call42
-  %func_ptr50 = load ptr, ptr getelementptr inbounds ([15 x ptr], ptr @table_0, i32 0, i32 11), align 8
-  %null_check51 = icmp eq ptr %func_ptr50, null
-  br i1 %null_check51, label %trap48, label %do_call52
+declare void @free(ptr %0)
 
-trap48:                                           ; preds = %valid_call47, %after_call42
-  unreachable
+define ptr @wasm_instance_new() {
+entry:
+  %vmctx = call ptr @calloc(i64 1, i64 32)
+  %vmctx_failed = icmp eq ptr %vmctx, null
+  br i1 %vmctx_failed, label %out_of_memory, label %vmctx_allocated
+
+out_of_memory:                                    ; preds = %entry
+  ret ptr null
+
+vmctx_allocated:                                  ; preds = %entry
+  %table = call ptr @calloc(i64 15, i64 8)
+  %table_failed = icmp eq ptr %table, null
+  br i1 %table_failed, label %alloc_failed, label %table_allocated
+
+alloc_failed:                                     ; preds = %vmctx_allocated
+  call void @wasm_instance_free(ptr %vmctx)
+  ret ptr null
+
+table_allocated:                                  ; preds = %vmctx_allocated
+  %table_base_ptr = getelementptr inbounds i8, ptr %vmctx, i64 16
+  store ptr %table, ptr %table_base_ptr, align 8
+  %table_size_ptr = getelementptr inbounds i8, ptr %vmctx, i64 24
+  store i32 15, ptr %table_size_ptr, align 4
+  %elem_ptr = getelementptr ptr, ptr %table, i64 0
+  store ptr @func_1.funcref, ptr %elem_ptr, align 8
+  %elem_ptr1 = getelementptr ptr, ptr %table, i64 1
+  store ptr @func_2.funcref, ptr %elem_ptr1, align 8
+  %elem_ptr2 = getelementptr ptr, ptr %table, i64 2
+  store ptr @func_3.funcref, ptr %elem_ptr2, align 8
+  %elem_ptr3 = getelementptr ptr, ptr %table, i64 3
+  store ptr @func_4.funcref, ptr %elem_ptr3, align 8
+  %elem_ptr4 = getelementptr ptr, ptr %table, i64 4
+  store ptr @func_5.funcref, ptr %elem_ptr4, align 8
+  %elem_ptr5 = getelementptr ptr, ptr %table, i64 5
+  store ptr @func_6.funcref, ptr %elem_ptr5, align 8
+  %elem_ptr6 = getelementptr ptr, ptr %table, i64 6
+  store ptr @func_7.funcref, ptr %elem_ptr6, align 8
+  %elem_ptr7 = getelementptr ptr, ptr %table, i64 7
+  store ptr @func_8.funcref, ptr %elem_ptr7, align 8
+  %elem_ptr8 = getelementptr ptr, ptr %table, i64 8
+  store ptr @func_9.funcref, ptr %elem_ptr8, align 8
+  %elem_ptr9 = getelementptr ptr, ptr %table, i64 9
+  store ptr @func_10.funcref, ptr %elem_ptr9, align 8
+  %elem_ptr10 = getelementptr ptr, ptr %table, i64 10
+  store ptr @func_11.funcref, ptr %elem_ptr10, align 8
+  %elem_ptr11 = getelementptr ptr, ptr %table, i64 11
+  store ptr @func_12.funcref, ptr %elem_ptr11, align 8
+  %elem_ptr12 = getelementptr ptr, ptr %table, i64 12
+  store ptr @func_13.funcref, ptr %elem_ptr12, align 8
+  %elem_ptr13 = getelementptr ptr, ptr %table, i64 13
+  store ptr @func_14.funcref, ptr %elem_ptr13, align 8
+  %elem_ptr14 = getelementptr ptr, ptr %table, i64 14
+  store ptr @func_15.funcref, ptr %elem_ptr14, align 8
+  ret ptr %vmctx
+}
 
-after_call49:                                     ; preds = %do_call52
-  call void @assert_eq32(i32 %indirect_call53, i32 15)
+define void @wasm_instance_free(ptr %vmctx) {
+entry:
+  %table_base_ptr = getelementptr inbounds i8, ptr %vmctx, i64 16
+  %table_base = load ptr, ptr %table_base_ptr, align 8
+  call void @free(ptr %table_base)
+  call void @free(ptr %vmctx)
   ret void
-
-do_call52:                                        ; preds = %valid_call47
-  %indirect_call53 = call i32 %func_ptr50(i32 1, i32 2, i32 3, i32 4, i32 5)
-  br label %after_call49
 }
 
 define i32 @main() {
 entry:
-  call void @_start()
+  %vmctx = call ptr @wasm_instance_new()
+  call void @_start(ptr %vmctx)
+  call void @wasm_instance_free(ptr %vmctx)
   ret i32 0
 }
+
+attributes #0 = { cold noreturn nounwind }
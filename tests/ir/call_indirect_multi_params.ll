@@ -2,84 +2,180 @@
 source_filename = "wasm_aot"
 target datalayout = "e-m:e-p270:32:32-p271:32:32-p272:64:64-i64:64-i128:128-f80:128-n8:16:32:64-S128"
 
-@table_0 = global [3 x ptr] [ptr @func_1, ptr @func_2, ptr @func_3]
+@func_1.funcref = private constant { ptr, i32 } { ptr @func_1, i32 0 }
+@func_2.funcref = private constant { ptr, i32 } { ptr @func_2, i32 2 }
+@func_3.funcref = private constant { ptr, i32 } { ptr @func_3, i32 1 }
 
 declare void @assert_eq32(i32 %0, i32 %1)
 
 declare void @assert_eq64(i64 %0, i64 %1)
 
-define i32 @func_1(i32 %0, i32 %1) {
+define internal i32 @func_1(ptr %vmctx, i32 %0, i32 %1) {
 entry:
   %add = add i32 %0, %1
   ret i32 %add
 }
 
-define i32 @func_2(i32 %0, i32 %1, i32 %2) {
+define internal i32 @func_2(ptr %vmctx, i32 %0, i32 %1, i32 %2) {
 entry:
   %mul = mul i32 %0, %1
   %add = add i32 %mul, %2
   ret i32 %add
 }
 
-define void @func_3(i32 %0) {
+define internal void @func_3(ptr %vmctx, i32 %0) {
 entry:
   ret void
 }
 
-define void @_start() {
+define internal void @_start(ptr %vmctx) {
 entry:
-  br i1 true, label %valid_call, label %trap
+  %table_base_ptr = getelementptr inbounds i8, ptr %vmctx, i64 16
+  %table_base = load ptr, ptr %table_base_ptr, align 8
+  %table_size_ptr = getelementptr inbounds i8, ptr %vmctx, i64 24
+  %table_size = load i32, ptr %table_size_ptr, align 4
+  %bounds_check = icmp ult i32 0, %table_size
+  br i1 %bounds_check, label %valid_call, label %trap
 
 valid_call:                                       ; preds = %entry
-  %func_ptr = load ptr, ptr @table_0, align 8
-  %null_check = icmp eq ptr %func_ptr, null
-  br i1 %null_check, label %trap, label %do_call
+  %func_ref_ptr = getelementptr ptr, ptr %table_base, i32 0
+  %func_ref = load ptr, ptr %func_ref_ptr, align 8
+  %null_check = icmp eq ptr %func_ref, null
+  br i1 %null_check, label %trap, label %check_type
 
-trap:                                             ; preds = %valid_call, %entry
+trap:                                             ; preds = %check_type, %valid_call, %entry
+  call void @llvm.trap()
   unreachable
 
 after_call:                                       ; preds = %do_call
   call void @assert_eq32(i32 %indirect_call, i32 30)
-  br i1 true, label %valid_call1, label %trap2
-
-do_call:                                          ; preds = %valid_call
-  %indirect_call = call i32 %func_ptr(i32 10, i32 20)
+  %table_base_ptr1 = getelementptr inbounds i8, ptr %vmctx, i64 16
+  %table_base2 = load ptr, ptr %table_base_ptr1, align 8
+  %table_size_ptr3 = getelementptr inbounds i8, ptr %vmctx, i64 24
+  %table_size4 = load i32, ptr %table_size_ptr3, align 4
+  %bounds_check5 = icmp ult i32 1, %table_size4
+  br i1 %bounds_check5, label %valid_call6, label %trap7
+
+check_type:                                       ; preds = %valid_call
+  %signature_ptr = getelementptr inbounds { ptr, i32 }, ptr %func_ref, i32 0, i32 1
+  %signature = load i32, ptr %signature_ptr, align 4
+  %signature_check = icmp eq i32 %signature, 0
+  br i1 %signature_check, label %do_call, label %trap
+
+do_call:                                          ; preds = %check_type
+  %func_ptr = load ptr, ptr %func_ref, align 8
+  %indirect_call = call i32 %func_ptr(ptr %vmctx, i32 10, i32 20)
   br label %after_call
 
-valid_call1:                                      ; preds = %after_call
-  %func_ptr4 = load ptr, ptr getelementptr inbounds ([3 x ptr], ptr @table_0, i32 0, i32 1), align 8
-  %null_check5 = icmp eq ptr %func_ptr4, null
-  br i1 %null_check5, label %trap2, label %do_call6
+valid_call6:                                      ; preds = %after_call
+  %func_ref_ptr9 = getelementptr ptr, ptr %table_base2, i32 1
+  %func_ref10 = load ptr, ptr %func_ref_ptr9, align 8
+  %null_check11 = icmp eq ptr %func_ref10, null
+  br i1 %null_check11, label %trap7, label %check_type12
 
-trap2:                                            ; preds = %valid_call1, %after_call
+trap7:                                            ; preds = %check_type12, %valid_call6, %after_call
+  call void @llvm.trap()
   unreachable
 
-after_call3:                                      ; preds = %do_call6
-  call void @assert_eq32(i32 %indirect_call7, i32 37)
-  br i1 true, label %valid_call8, label %trap9
+after_call8:                                      ; preds = %do_call13
+  call void @assert_eq32(i32 %indirect_call18, i32 37)
+  %table_base_ptr19 = getelementptr inbounds i8, ptr %vmctx, i64 16
+  %table_base20 = load ptr, ptr %table_base_ptr19, align 8
+  %table_size_ptr21 = getelementptr inbounds i8, ptr %vmctx, i64 24
+  %table_size22 = load i32, ptr %table_size_ptr21, align 4
+  %bounds_check23 = icmp ult i32 2, %table_size22
+  br i1 %bounds_check23, label %valid_call24, label %trap25
+
+check_type12:                                     ; preds = %valid_call6
+  %signature_ptr14 = getelementptr inbounds { ptr, i32 }, ptr %func_ref10, i32 0, i32 1
+  %signature15 = load i32, ptr %signature_ptr14, align 4
+  %signature_check16 = icmp eq i32 %signature15, 2
+  br i1 %signature_check16, label %do_call13, label %trap7
+
+do_call13:                                        ; preds = %check_type12
+  %func_ptr17 = load ptr, ptr %func_ref10, align 8
+  %indirect_call18 = call i32 %func_ptr17(ptr %vmctx, i32 5, i32 6, i32 7)
+  br label %after_call8
+
+valid_call24:                                     ; preds = %after_call8
+  %func_ref_ptr27 = getelementptr ptr, ptr %table_base20, i32 2
+  %func_ref28 = load ptr, ptr %func_ref_ptr27, align 8
+  %null_check29 = icmp eq ptr %func_ref28, null
+  br i1 %null_check29, label %trap25, label %check_type30
+
+trap25:                                           ; preds = %check_type30, %valid_call24, %after_call8
+  call void @llvm.trap()
+  unreachable
 
-do_call6:                                         ; preds = %valid_call1
-  %indirect_call7 = call i32 %func_ptr4(i32 5, i32 6, i32 7)
-  br label %after_call3
+after_call26:                                     ; preds = %do_call31
+  ret void
 
-valid_call8:                                      ; preds = %after_call3
-  %func_ptr11 = load ptr, ptr getelementptr inbounds ([3 x ptr], ptr @table_0, i32 0, i32 2), align 8
-  %null_check12 = icmp eq ptr %func_ptr11, null
-  br i1 %null_check12, label %trap9, label %do_call13
+check_type30:                                     ; preds = %valid_call24
+  %signature_ptr32 = getelementptr inbounds { ptr, i32 }, ptr %func_ref28, i32 0, i32 1
+  %signature33 = load i32, ptr %signature_ptr32, align 4
+  %signature_check34 = icmp eq i32 %signature33, 1
+  br i1 %signature_check34, label %do_call31, label %trap25
 
-trap9:                                            ; preds = %valid_call8, %after_call3
-  unreachable
+do_call31:                                        ; preds = %check_type30
+  %func_ptr35 = load ptr, ptr %func_ref28, align 8
+  call void %func_ptr35(ptr %vmctx, i32 42)
+  br label %after_call26
+}
 
-after_call10:                                     ; preds = %do_call13
-  ret void
+; Function Attrs: cold noreturn nounwind
+declare void @llvm.trap() #0
+
+declare ptr @calloc(i64 %0, i64 %1)
 
-do_call13:                                        ; preds = %valid_call8
-  call void %func_ptr11(i32 42)
-  br label %after_call10
+declare void @free(ptr %0)
+
+define ptr @wasm_instance_new() {
+entry:
+  %vmctx = call ptr @calloc(i64 1, i64 32)
+  %vmctx_failed = icmp eq ptr %vmctx, null
+  br i1 %vmctx_failed, label %out_of_memory, label %vmctx_allocated
+
+out_of_memory:                                    ; preds = %entry
+  ret ptr null
+
+vmctx_allocated:                                  ; preds = %entry
+  %table = call ptr @calloc(i64 3, i64 8)
+  %table_failed = icmp eq ptr %table, null
+  br i1 %table_failed, label %alloc_failed, label %table_allocated
+
+alloc_failed:                                     ; preds = %vmctx_allocated
+  call void @wasm_instance_free(ptr %vmctx)
+  ret ptr null
+
+table_allocated:                                  ; preds = %vmctx_allocated
+  %table_base_ptr = getelementptr inbounds i8, ptr %vmctx, i64 16
+  store ptr %table, ptr %table_base_ptr, align 8
+  %table_size_ptr = getelementptr inbounds i8, ptr %vmctx, i64 24
+  store i32 3, ptr %table_size_ptr, align 4
+  %elem_ptr = getelementptr ptr, ptr %table, i64 0
+  store ptr @func_1.funcref, ptr %elem_ptr, align 8
+  %elem_ptr1 = getelementptr ptr, ptr %table, i64 1
+  store ptr @func_2.funcref, ptr %elem_ptr1, align 8
+  %elem_ptr2 = getelementptr ptr, ptr %table, i64 2
+  store ptr @func_3.funcref, ptr %elem_ptr2, align 8
+  ret ptr %vmctx
+}
+
+define void @wasm_instance_free(ptr %vmctx) {
+entry:
+  %table_base_ptr = getelementptr inbounds i8, ptr %vmctx, i64 16
+  %table_base = load ptr, ptr %table_base_ptr, align 8
+  call void @free(ptr %table_base)
+  call void @free(ptr %vmctx)
+  ret void
 }
 
 define i32 @main() {
 entry:
-  call void @_start()
+  %vmctx = call ptr @wasm_instance_new()
+  call void @_start(ptr %vmctx)
+  call void @wasm_instance_free(ptr %vmctx)
   ret i32 0
 }
+
+attributes #0 = { cold noreturn nounwind }
@@ -0,0 +1,116 @@
+(module
+  (import "env" "assert_eq32" (func $assert_eq32 (param i32 i32)))
+  (import "env" "assert_eq64" (func $assert_eq64 (param i64 i64)))
+
+  (memory 1 2 shared)
+
+  (func $start_func
+    ;; Read-modify-write returns the previous value
+    i32.const 0
+    i32.const 5
+    i32.atomic.rmw.add
+    i32.const 0
+    call $assert_eq32
+
+    i32.const 0
+    i32.const 3
+    i32.atomic.rmw.sub
+    i32.const 5
+    call $assert_eq32
+
+    ;; Narrow forms only touch their own bytes
+    i32.const 0
+    i32.const 0x1ff
+    i32.atomic.rmw8.xchg_u
+    i32.const 2
+    call $assert_eq32
+
+    i32.const 0
+    i32.atomic.load
+    i32.const 0xff
+    call $assert_eq32
+
+    ;; Compare-and-exchange only stores on a match
+    i32.const 0
+    i32.const 1
+    i32.const 7
+    i32.atomic.rmw.cmpxchg
+    i32.const 0xff
+    call $assert_eq32
+
+    i32.const 0
+    i32.const 0xff
+    i32.const 7
+    i32.atomic.rmw.cmpxchg
+    i32.const 0xff
+    call $assert_eq32
+
+    i32.const 0
+    i32.atomic.load
+    i32.const 7
+    call $assert_eq32
+
+    i32.const 8
+    i64.const 0x100000000
+    i64.atomic.store
+    i32.const 8
+    i64.const 1
+    i64.atomic.rmw.or
+    i64.const 0x100000000
+    call $assert_eq64
+    i32.const 8
+    i64.const 1
+    i64.atomic.rmw32.and_u
+    i64.const 1
+    call $assert_eq64
+    atomic.fence
+
+    ;; Nobody is waiting, so notify wakes no one
+    i32.const 0
+    i32.const 1
+    memory.atomic.notify
+    i32.const 0
+    call $assert_eq32
+
+    ;; Wait returns "not-equal" (1) or "timed-out" (2) without a notify
+    i32.const 0
+    i32.const 8
+    i64.const -1
+    memory.atomic.wait32
+    i32.const 1
+    call $assert_eq32
+
+    i32.const 0
+    i32.const 7
+    i64.const 1000000
+    memory.atomic.wait32
+    i32.const 2
+    call $assert_eq32
+
+    i32.const 8
+    i64.const 0x100000001
+    i64.const 1000000
+    memory.atomic.wait64
+    i32.const 2
+    call $assert_eq32
+
+    ;; Shared memory grows in place up to its maximum
+    i32.const 1
+    memory.grow
+    i32.const 1
+    call $assert_eq32
+
+    i32.const 70000
+    i32.const 42
+    i32.atomic.rmw.add
+    i32.const 0
+    call $assert_eq32
+
+    i32.const 1
+    memory.grow
+    i32.const -1
+    call $assert_eq32
+  )
+
+  (start $start_func)
+)
@@ -0,0 +1,16 @@
+(module
+  ;; br_table is valid Wasm that the compiler cannot lower yet
+  (func $dispatch (param i32)
+    block
+      local.get 0
+      br_table 0 0
+    end
+  )
+
+  (func $_start
+    i32.const 1
+    call $dispatch
+  )
+
+  (start $_start)
+)
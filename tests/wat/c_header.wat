@@ -0,0 +1,17 @@
+(module
+  (memory (export "memory") 1)
+  (global $counter (export "counter") (mut i32) (i32.const 0))
+
+  (func (export "add") (param i32 i32) (result i32)
+    local.get 0
+    local.get 1
+    i32.add
+  )
+
+  (func (export "bump")
+    global.get $counter
+    i32.const 1
+    i32.add
+    global.set $counter
+  )
+)
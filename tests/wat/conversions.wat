@@ -0,0 +1,120 @@
+(module
+  (import "env" "assert_eq32" (func $assert_eq32 (param i32 i32)))
+  (import "env" "assert_eq64" (func $assert_eq64 (param i64 i64)))
+
+  (func $trunc_f64_s (export "trunc_f64_s") (param f64) (result i64)
+    local.get 0
+    i64.trunc_f64_s
+  )
+
+  (func $trunc_sat_f32_u (param f32) (result i32)
+    local.get 0
+    i32.trunc_sat_f32_u
+  )
+
+  (func $trunc_sat_f64_s (param f64) (result i64)
+    local.get 0
+    i64.trunc_sat_f64_s
+  )
+
+  (func $extend8_s (param i64) (result i64)
+    local.get 0
+    i64.extend8_s
+  )
+
+  (func $main
+    nop
+
+    ;; i64 <-> float conversions
+    f64.const -42.9
+    call $trunc_f64_s
+    i64.const -42
+    call $assert_eq64
+
+    f32.const 4294967296
+    i64.trunc_f32_u
+    i64.const 4294967296
+    call $assert_eq64
+
+    f64.const -9223372036854775808
+    call $trunc_f64_s
+    i64.const -9223372036854775808
+    call $assert_eq64
+
+    i64.const -1
+    f64.convert_i64_u
+    f64.const 18446744073709551616
+    f64.eq
+    i32.const 1
+    call $assert_eq32
+
+    i64.const -3
+    f32.convert_i64_s
+    i32.trunc_f32_s
+    i32.const -3
+    call $assert_eq32
+
+    ;; Saturating truncation clamps and maps NaN to 0
+    f32.const -1
+    call $trunc_sat_f32_u
+    i32.const 0
+    call $assert_eq32
+
+    f32.const nan
+    call $trunc_sat_f32_u
+    i32.const 0
+    call $assert_eq32
+
+    f64.const 1e300
+    call $trunc_sat_f64_s
+    i64.const 9223372036854775807
+    call $assert_eq64
+
+    f64.const -1e300
+    i32.trunc_sat_f64_s
+    i32.const -2147483648
+    call $assert_eq32
+
+    ;; Reinterpretation keeps the bits
+    f32.const -2
+    i32.reinterpret_f32
+    i32.const 0xc0000000
+    call $assert_eq32
+
+    i64.const 0x3ff0000000000000
+    f64.reinterpret_i64
+    i64.reinterpret_f64
+    i64.const 0x3ff0000000000000
+    call $assert_eq64
+
+    i32.const 0x3f800000
+    f32.reinterpret_i32
+    f32.const 1
+    f32.eq
+    i32.const 1
+    call $assert_eq32
+
+    ;; Sign extension of the low bits
+    i64.const 0x180
+    call $extend8_s
+    i64.const -128
+    call $assert_eq64
+
+    i32.const 0x18000
+    i32.extend16_s
+    i32.const -32768
+    call $assert_eq32
+
+    i64.const 0x7fff
+    i64.extend16_s
+    i64.const 32767
+    call $assert_eq64
+
+    i64.const 0x80000000
+    i64.extend32_s
+    i64.const -2147483648
+    call $assert_eq64
+  )
+
+  (start $main)
+)
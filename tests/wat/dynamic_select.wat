@@ -1,16 +1,48 @@
 (module
+  (import "env" "assert_eq32" (func $assert_eq32 (param i32 i32)))
+  (import "env" "assert_eq64" (func $assert_eq64 (param i64 i64)))
+
+  ;; `select` yields its first operand when the condition is non-zero.
   (func $test_select (param $cond i32) (result i32)
     i32.const 10
     i32.const 20
     local.get $cond
     select
   )
-  
+
+  (func $test_select64 (param $cond i32) (result i64)
+    i64.const 10
+    i64.const 20
+    local.get $cond
+    select (result i64)
+  )
+
   (func $main
     i32.const 1
     call $test_select
-    drop
+    i32.const 10
+    call $assert_eq32
+
+    i32.const -1
+    call $test_select
+    i32.const 10
+    call $assert_eq32
+
+    i32.const 0
+    call $test_select
+    i32.const 20
+    call $assert_eq32
+
+    i32.const 1
+    call $test_select64
+    i64.const 10
+    call $assert_eq64
+
+    i32.const 0
+    call $test_select64
+    i64.const 20
+    call $assert_eq64
   )
-  
+
   (start $main)
-)
\ No newline at end of file
+)
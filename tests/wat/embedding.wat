@@ -0,0 +1,59 @@
+(module
+  (import "env" "log" (func $log (param i32)))
+  (import "env" "scale" (func $scale (param f64) (result f64)))
+
+  (memory (export "memory") 1)
+  (global $counter (export "counter") (mut i32) (i32.const 0))
+  (global (export "limit") i64 (i64.const 0))
+
+  (func (export "add") (param i32 i32) (result i32)
+    local.get 0
+    local.get 1
+    i32.add
+  )
+
+  (func (export "scaled") (param f64) (result f64)
+    local.get 0
+    call $scale
+  )
+
+  ;; Increments the counter, logs it and returns it
+  (func (export "bump") (result i32)
+    global.get $counter
+    i32.const 1
+    i32.add
+    global.set $counter
+    global.get $counter
+    call $log
+    global.get $counter
+  )
+
+  (func (export "store") (param i32 i32)
+    local.get 0
+    local.get 1
+    i32.store
+  )
+
+  ;; Traps when its argument is non-zero
+  (func $check (export "check") (param i32) (result i32)
+    local.get 0
+    if
+      unreachable
+    end
+    local.get 0
+  )
+
+  (func (export "checked_inc") (param i32) (result i32)
+    local.get 0
+    call $check
+    i32.const 1
+    i32.add
+  )
+
+  (func $init
+    i32.const 10
+    global.set $counter
+  )
+
+  (start $init)
+)
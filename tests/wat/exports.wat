@@ -0,0 +1,19 @@
+(module
+  (import "env" "assert_eq32" (func $assert_eq32 (param i32 i32)))
+
+  (func $add (export "add") (param i32 i32) (result i32)
+    local.get 0
+    local.get 1
+    i32.add
+  )
+
+  (func $start_func
+    i32.const 2
+    i32.const 3
+    call $add
+    i32.const 5
+    call $assert_eq32
+  )
+
+  (start $start_func)
+)
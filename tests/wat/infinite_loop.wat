@@ -0,0 +1,13 @@
+(module
+  (func $spin
+    loop $forever
+      br $forever
+    end
+  )
+
+  (func $main
+    call $spin
+  )
+
+  (start $main)
+)
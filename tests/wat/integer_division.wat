@@ -0,0 +1,58 @@
+(module
+  (import "env" "assert_eq32" (func $assert_eq32 (param i32 i32)))
+  (import "env" "assert_eq64" (func $assert_eq64 (param i64 i64)))
+
+  (func $div_s (export "div_s") (param i32 i32) (result i32)
+    local.get 0
+    local.get 1
+    i32.div_s
+  )
+
+  (func $rem_s (export "rem_s") (param i32 i32) (result i32)
+    local.get 0
+    local.get 1
+    i32.rem_s
+  )
+
+  (func $rem_s64 (param i64 i64) (result i64)
+    local.get 0
+    local.get 1
+    i64.rem_s
+  )
+
+  (func $main
+    ;; Signed division truncates towards zero
+    i32.const -7
+    i32.const 2
+    call $div_s
+    i32.const -3
+    call $assert_eq32
+
+    i32.const -2147483648
+    i32.const 1
+    call $div_s
+    i32.const -2147483648
+    call $assert_eq32
+
+    ;; A remainder by -1 is 0, even for INT_MIN
+    i32.const -2147483648
+    i32.const -1
+    call $rem_s
+    i32.const 0
+    call $assert_eq32
+
+    i64.const -9223372036854775808
+    i64.const -1
+    call $rem_s64
+    i64.const 0
+    call $assert_eq64
+
+    i32.const -7
+    i32.const 2
+    call $rem_s
+    i32.const -1
+    call $assert_eq32
+  )
+
+  (start $main)
+)
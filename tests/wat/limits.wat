@@ -0,0 +1,39 @@
+(module
+  ;; Counts to $n, running the loop header $n times
+  (func (export "count") (param $n i32) (result i32)
+    (local $i i32)
+    loop $next
+      local.get $i
+      i32.const 1
+      i32.add
+      local.set $i
+      local.get $i
+      local.get $n
+      i32.lt_u
+      br_if $next
+    end
+    local.get $i
+  )
+
+  ;; Recurses $n calls deep
+  (func $deep (export "deep") (param $n i32) (result i32)
+    local.get $n
+    i32.eqz
+    if
+      i32.const 0
+      return
+    end
+    local.get $n
+    i32.const 1
+    i32.sub
+    call $deep
+    i32.const 1
+    i32.add
+  )
+
+  (func $spin (export "spin")
+    loop $forever
+      br $forever
+    end
+  )
+)
@@ -0,0 +1,42 @@
+(module
+  (import "env" "assert_eq32" (func $assert_eq32 (param i32 i32)))
+
+  (memory 1 2)
+  (global $last (mut i32) (i32.const 0))
+
+  (func $start_func
+    ;; Grow by one page; the previous size is returned
+    i32.const 1
+    memory.grow
+    i32.const 1
+    call $assert_eq32
+
+    memory.size
+    i32.const 2
+    call $assert_eq32
+
+    ;; The maximum is two pages
+    i32.const 1
+    memory.grow
+    i32.const -1
+    call $assert_eq32
+
+    ;; New pages are zeroed and usable
+    i32.const 70000
+    i32.load
+    i32.const 0
+    call $assert_eq32
+
+    i32.const 70000
+    i32.const 42
+    i32.store
+    i32.const 70000
+    i32.load
+    global.set $last
+    global.get $last
+    i32.const 42
+    call $assert_eq32
+  )
+
+  (start $start_func)
+)
@@ -0,0 +1,21 @@
+(module
+  (memory 1)
+
+  (func $load (param $addr i32) (result i32)
+    local.get $addr
+    i32.load
+  )
+
+  ;; The last four bytes of memory load fine, but a load two bytes before
+  ;; the end runs past it and traps
+  (func $main
+    i32.const 65532
+    call $load
+    drop
+    i32.const 65534
+    call $load
+    drop
+  )
+
+  (start $main)
+)
@@ -0,0 +1,15 @@
+(module
+  (func $recurse (param $n i32)
+    local.get $n
+    i32.const 1
+    i32.add
+    call $recurse
+  )
+
+  (func $main
+    i32.const 0
+    call $recurse
+  )
+
+  (start $main)
+)
@@ -0,0 +1,89 @@
+(module
+  (import "env" "assert_eq32" (func $assert_eq32 (param i32 i32)))
+
+  (type $ret_i32 (func (result i32)))
+
+  (table $small 1 funcref)
+  (table $growable 0 8 funcref)
+
+  (func $seven (result i32)
+    i32.const 7
+  )
+
+  (func $nine (result i32)
+    i32.const 9
+  )
+
+  (elem (table $small) (i32.const 0) func $seven)
+  (elem $passive funcref (ref.func $nine) (ref.null func))
+  (elem declare func $seven)
+
+  (func $test_tables
+    ;; table.grow returns the old size, or -1 past the maximum
+    ref.null func
+    i32.const 4
+    table.grow $growable
+    i32.const 0
+    call $assert_eq32
+
+    ref.null func
+    i32.const 5
+    table.grow $growable
+    i32.const -1
+    call $assert_eq32
+
+    table.size $growable
+    i32.const 4
+    call $assert_eq32
+
+    ;; table.init from a passive segment, then drop it
+    i32.const 1
+    i32.const 0
+    i32.const 2
+    table.init $growable $passive
+    elem.drop $passive
+
+    i32.const 1
+    call_indirect $growable (type $ret_i32)
+    i32.const 9
+    call $assert_eq32
+
+    i32.const 2
+    table.get $growable
+    ref.is_null
+    i32.const 1
+    call $assert_eq32
+
+    ;; table.copy across tables and table.set/table.get
+    i32.const 0
+    i32.const 0
+    i32.const 1
+    table.copy $growable $small
+    i32.const 0
+    call_indirect $growable (type $ret_i32)
+    i32.const 7
+    call $assert_eq32
+
+    i32.const 3
+    i32.const 0
+    table.get $growable
+    table.set $growable
+    i32.const 3
+    call_indirect $growable (type $ret_i32)
+    i32.const 7
+    call $assert_eq32
+
+    ;; table.fill clears a range
+    i32.const 0
+    ref.null func
+    i32.const 4
+    table.fill $growable
+    i32.const 3
+    table.get $growable
+    ref.is_null
+    i32.const 1
+    call $assert_eq32
+  )
+
+  (start $test_tables)
+)
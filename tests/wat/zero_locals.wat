@@ -0,0 +1,68 @@
+(module
+  (import "env" "assert_eq32" (func $assert_eq32 (param i32 i32)))
+  (import "env" "assert_eq64" (func $assert_eq64 (param i64 i64)))
+
+  ;; Accumulates into locals that are never written before the loop, so
+  ;; the result depends on them starting at zero.
+  (func $sum (param $n i32) (result i64)
+    (local $i i32)
+    (local $total i64)
+    block
+      loop
+        local.get $i
+        local.get $n
+        i32.ge_s
+        br_if 1
+        local.get $total
+        i64.const 10
+        i64.add
+        local.set $total
+        local.get $i
+        i32.const 1
+        i32.add
+        local.set $i
+        br 0
+      end
+    end
+    local.get $total
+  )
+
+  (func $_start
+    (local $a i32)
+    (local $b i64)
+    (local $c f32)
+    (local $d f64)
+    (local $e externref)
+    local.get $a
+    i32.const 0
+    call $assert_eq32
+
+    local.get $b
+    i64.const 0
+    call $assert_eq64
+
+    local.get $c
+    f32.const 0
+    f32.eq
+    i32.const 1
+    call $assert_eq32
+
+    local.get $d
+    f64.const 0
+    f64.eq
+    i32.const 1
+    call $assert_eq32
+
+    local.get $e
+    ref.is_null
+    i32.const 1
+    call $assert_eq32
+
+    i32.const 4
+    call $sum
+    i64.const 40
+    call $assert_eq64
+  )
+
+  (start $_start)
+)